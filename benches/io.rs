@@ -0,0 +1,67 @@
+//! Compares `bundle --io sync` with `--io async`:
+//!
+//!     cargo bench --features async-io --bench io
+//!
+//! A local disk shows little difference; the async path pays off where every file
+//! costs a round trip. Set `SHEAFY_BENCH_DIR` to a directory on an NFS or SMB mount to
+//! generate the test tree there.
+
+use sheafy::bundle::run_bundle;
+use sheafy::cli::{BundleArgs, IoMode};
+use sheafy::config::{Config, SheafyConfig};
+use std::{fs, path::Path, time::Instant};
+
+const FILES: usize = 2000;
+const ROUNDS: usize = 5;
+
+fn main() {
+    let parent = std::env::var_os("SHEAFY_BENCH_DIR");
+    let source = match &parent {
+        Some(dir) => tempfile::tempdir_in(dir),
+        None => tempfile::tempdir(),
+    }
+    .expect("Failed to create the bench tree");
+    for i in 0..FILES {
+        let dir = source.path().join(format!("dir{}", i % 50));
+        fs::create_dir_all(&dir).unwrap();
+        let line = format!("fn f{}() -> usize {{ {} }}\n", i, i);
+        fs::write(dir.join(format!("file{}.rs", i)), line.repeat(40)).unwrap();
+    }
+    let out = tempfile::tempdir().unwrap();
+    println!(
+        "{} files in {} ({} rounds each)",
+        FILES,
+        source.path().display(),
+        ROUNDS
+    );
+    for io in [IoMode::Sync, IoMode::Async] {
+        let mut best = f64::MAX;
+        for _ in 0..ROUNDS {
+            let started = Instant::now();
+            bundle(source.path(), &out.path().join("bundle.md"), io);
+            best = best.min(started.elapsed().as_secs_f64());
+        }
+        println!(
+            "--io {:<5}  best of {}: {:.3}s",
+            format!("{:?}", io).to_lowercase(),
+            ROUNDS,
+            best
+        );
+    }
+}
+
+fn bundle(source: &Path, output: &Path, io: IoMode) {
+    let config = Config {
+        sheafy: SheafyConfig {
+            working_dir: Some(source.to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let args = BundleArgs {
+        output: Some(output.to_string_lossy().into_owned()),
+        io,
+        ..Default::default()
+    };
+    run_bundle(config, args).expect("bundle failed");
+}
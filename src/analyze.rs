@@ -0,0 +1,349 @@
+//! `sheafy init --analyze`: scan the project and write a sheafy.toml tailored to it
+//! instead of the generic template.
+//!
+//! The scan respects .gitignore (like `bundle` does by default), then looks for the
+//! things that usually make a first bundle noisy: generated or vendored directories,
+//! lock files, minified and `@generated` sources, binaries and very large files. It also
+//! puts READMEs, manifests and entry points first and adds fence hints for extensions
+//! the built-in table does not know.
+
+use crate::config::{Config, CONFIG_FILENAME, DEFAULT_BUNDLE_NAME};
+use anyhow::Result;
+use ignore::WalkBuilder;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Text files larger than this are suggested for ignoring.
+const LARGE_FILE_BYTES: u64 = 512 * 1024;
+
+/// Directory names that hold build output, dependencies or caches.
+const GENERATED_DIRS: &[&str] = &[
+    "target",
+    "node_modules",
+    "dist",
+    "build",
+    "out",
+    "vendor",
+    "__pycache__",
+    ".venv",
+    "venv",
+    ".tox",
+    ".mypy_cache",
+    ".pytest_cache",
+    ".next",
+    ".nuxt",
+    ".gradle",
+    "coverage",
+];
+
+const LOCK_FILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "Pipfile.lock",
+    "composer.lock",
+    "Gemfile.lock",
+    "go.sum",
+    "bun.lockb",
+];
+
+/// Files worth reading first, in this order, when they exist.
+const PRIORITY_CANDIDATES: &[&str] = &[
+    "README.md",
+    "README",
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "src/main.rs",
+    "src/lib.rs",
+    "main.go",
+    "main.py",
+    "src/index.ts",
+    "src/index.js",
+    "index.js",
+];
+
+/// Fence languages for common extensions missing from the built-in table.
+const EXTRA_LANGUAGE_HINTS: &[(&str, &str)] = &[
+    ("vue", "vue"),
+    ("svelte", "svelte"),
+    ("jsx", "jsx"),
+    ("tsx", "tsx"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("cc", "cpp"),
+    ("cs", "csharp"),
+    ("ex", "elixir"),
+    ("exs", "elixir"),
+    ("hs", "haskell"),
+    ("lua", "lua"),
+    ("dart", "dart"),
+    ("zig", "zig"),
+    ("proto", "protobuf"),
+    ("graphql", "graphql"),
+    ("r", "r"),
+    ("pl", "perl"),
+    ("ps1", "powershell"),
+    ("ini", "ini"),
+    ("cfg", "ini"),
+    ("xml", "xml"),
+    ("scss", "scss"),
+    ("less", "less"),
+    ("tf", "hcl"),
+    ("gradle", "groovy"),
+    ("mk", "makefile"),
+];
+
+#[derive(Debug, Default)]
+struct Analysis {
+    file_count: usize,
+    languages: BTreeMap<&'static str, usize>,
+    generated_dirs: BTreeSet<String>,
+    lock_files: BTreeSet<String>,
+    generated_files: BTreeSet<String>,
+    binary_extensions: BTreeSet<String>,
+    binary_files: BTreeSet<String>,
+    large_files: Vec<(String, u64)>,
+    unknown_extensions: BTreeMap<String, usize>,
+    priority: Vec<String>,
+}
+
+pub fn run_init_analyze() -> Result<()> {
+    let root = std::env::current_dir()?;
+    // Fail before scanning a possibly large tree
+    Config::confirm_overwrite()?;
+    status!("Analyzing project in {}...", root.display());
+    let analysis = analyze(&root);
+    let content = render(&analysis);
+    Config::create(&content)?;
+
+    status!(
+        "Scanned {} file(s); detected languages: {}",
+        analysis.file_count,
+        language_summary(&analysis)
+    );
+    status!(
+        "Suggested {} ignore pattern(s) and {} priority pattern(s).",
+        analysis.generated_dirs.len()
+            + analysis.lock_files.len()
+            + analysis.generated_files.len()
+            + analysis.binary_extensions.len()
+            + analysis.binary_files.len()
+            + analysis.large_files.len(),
+        analysis.priority.len()
+    );
+    status!("Created tailored config file at {}", CONFIG_FILENAME);
+    Ok(())
+}
+
+fn analyze(root: &Path) -> Analysis {
+    let mut analysis = Analysis::default();
+    let generated_dirs = Arc::new(Mutex::new(BTreeSet::new()));
+
+    let mut builder = WalkBuilder::new(root);
+    let seen_dirs = Arc::clone(&generated_dirs);
+    builder.filter_entry(move |entry| {
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        let name = entry.file_name().to_string_lossy();
+        if is_dir && entry.depth() > 0 && GENERATED_DIRS.contains(&name.as_ref()) {
+            seen_dirs.lock().unwrap().insert(format!("{}/", name));
+            return false;
+        }
+        true
+    });
+
+    for entry in builder.build().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let Ok(rel_path) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let rel_str = rel_path
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        if rel_str == CONFIG_FILENAME || rel_str == DEFAULT_BUNDLE_NAME {
+            continue;
+        }
+        analysis.file_count += 1;
+        let name = entry.file_name().to_string_lossy();
+        let extension = rel_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+
+        if LOCK_FILES.contains(&name.as_ref()) {
+            analysis.lock_files.insert(name.into_owned());
+            continue;
+        }
+        if name.ends_with(".min.js") || name.ends_with(".min.css") {
+            let pattern = format!("*.min.{}", extension.as_deref().unwrap_or_default());
+            analysis.generated_files.insert(pattern);
+            continue;
+        }
+
+        let Ok(bytes) = fs::read(entry.path()) else {
+            continue;
+        };
+        if crate::sniff::is_binary(&bytes) {
+            match extension {
+                Some(ext) => analysis.binary_extensions.insert(format!("*.{}", ext)),
+                None => analysis.binary_files.insert(format!("/{}", rel_str)),
+            };
+            continue;
+        }
+        let content = String::from_utf8_lossy(&bytes);
+        let head: String = content.lines().take(5).collect::<Vec<_>>().join("\n");
+        if head.contains("@generated") || head.contains("DO NOT EDIT") {
+            analysis.generated_files.insert(format!("/{}", rel_str));
+            continue;
+        }
+        if bytes.len() as u64 > LARGE_FILE_BYTES {
+            analysis
+                .large_files
+                .push((format!("/{}", rel_str), bytes.len() as u64));
+            continue;
+        }
+
+        let language = crate::sniff::language_hint(rel_path, &content);
+        if !language.is_empty() {
+            *analysis.languages.entry(language).or_default() += 1;
+        } else if let Some(ext) = extension.filter(|ext| ext != "txt") {
+            *analysis.unknown_extensions.entry(ext).or_default() += 1;
+        }
+    }
+
+    analysis.generated_dirs = std::mem::take(&mut *generated_dirs.lock().unwrap());
+    analysis.priority = PRIORITY_CANDIDATES
+        .iter()
+        .filter(|candidate| root.join(candidate).is_file())
+        .map(|candidate| candidate.to_string())
+        .collect();
+    analysis
+}
+
+fn language_summary(analysis: &Analysis) -> String {
+    if analysis.languages.is_empty() {
+        return "none".to_string();
+    }
+    let mut languages: Vec<_> = analysis.languages.iter().collect();
+    languages.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    languages
+        .iter()
+        .map(|(language, count)| format!("{} ({})", language, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Renders the analysis as a sheafy.toml, with a comment explaining each group of patterns.
+fn render(analysis: &Analysis) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# Generated by `sheafy init --analyze` from {} file(s).",
+        analysis.file_count
+    );
+    let _ = writeln!(out, "# Detected languages: {}", language_summary(analysis));
+    out.push_str("[sheafy]\n");
+    let _ = writeln!(out, "bundle_name = \"{}\"", DEFAULT_BUNDLE_NAME);
+    out.push_str("use_gitignore = true\n");
+
+    // gitignore syntax has no trailing comments, so sizes go on their own line
+    let large_files: Vec<String> = analysis
+        .large_files
+        .iter()
+        .map(|(path, len)| format!("# {} KiB\n{}", len / 1024, path))
+        .collect();
+    let groups: [(&str, Vec<String>); 5] = [
+        (
+            "Generated or vendored directories",
+            analysis.generated_dirs.iter().cloned().collect(),
+        ),
+        ("Lock files", analysis.lock_files.iter().cloned().collect()),
+        (
+            "Minified or generated sources",
+            analysis.generated_files.iter().cloned().collect(),
+        ),
+        (
+            "Binary files",
+            analysis
+                .binary_extensions
+                .iter()
+                .chain(&analysis.binary_files)
+                .cloned()
+                .collect(),
+        ),
+        ("Large files (over 512 KiB)", large_files),
+    ];
+    if groups.iter().any(|(_, patterns)| !patterns.is_empty()) {
+        out.push_str("\nignore_patterns = \"\"\"\n");
+        for (title, patterns) in groups.iter().filter(|(_, p)| !p.is_empty()) {
+            let _ = writeln!(out, "# {}", title);
+            for pattern in patterns {
+                let _ = writeln!(out, "{}", toml_escape(pattern));
+            }
+        }
+        out.push_str("\"\"\"\n");
+    } else {
+        out.push_str("\n# ignore_patterns = \"\"\"\n# *.log\n# \"\"\"\n");
+    }
+
+    if !analysis.priority.is_empty() {
+        out.push_str("\n# Bundled first, so readers see the overview before the details\n");
+        out.push_str("priority_patterns = \"\"\"\n");
+        for path in &analysis.priority {
+            let _ = writeln!(out, "{}", toml_escape(path));
+        }
+        out.push_str("\"\"\"\n");
+    }
+
+    if !analysis.unknown_extensions.is_empty() {
+        out.push_str("\n# Fence languages for extensions without a built-in hint\n");
+        out.push_str("[sheafy.language_hints]\n");
+        for (ext, count) in &analysis.unknown_extensions {
+            let known = EXTRA_LANGUAGE_HINTS.iter().find(|(e, _)| e == ext);
+            let key = toml_key(ext);
+            match known {
+                Some((_, language)) => {
+                    let _ = writeln!(out, "{} = \"{}\"  # {} file(s)", key, language, count);
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "# {} = \"{}\"  # {} file(s)",
+                        key,
+                        toml_escape(ext),
+                        count
+                    );
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Escapes text for a TOML basic string.
+fn toml_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quotes a TOML key unless it is a bare key.
+fn toml_key(key: &str) -> String {
+    if key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        key.to_string()
+    } else {
+        format!("\"{}\"", toml_escape(key))
+    }
+}
@@ -0,0 +1,537 @@
+//! `--format tar.gz` and `--format zip`: the selected files as a conventional archive.
+//!
+//! The same selection that makes a Markdown bundle (ignore rules, `ignore_patterns`,
+//! focus, limits, ...) can produce a plain project snapshot. Archives hold every file
+//! byte for byte, binary ones included, with its executable bit; Markdown-only settings
+//! (stubs, handlers, previews, prompts) don't apply. With `--reproducible` every entry
+//! gets the time from `SOURCE_DATE_EPOCH` (or 1970), so the archive is byte-identical
+//! for the same tree. `sheafy restore` extracts `.tar`, `.tar.gz` and `.zip` archives,
+//! also those made by other tools, recognizing them by content rather than name.
+
+use crate::deflate;
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const BLOCK: usize = 512;
+
+/// Bytes [`sniff`] needs to recognize every kind
+pub const SNIFF_LEN: usize = 262;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+/// One file of an archive.
+#[derive(Debug)]
+pub struct Entry {
+    /// Relative, with `/` separators
+    pub path: String,
+    pub content: Vec<u8>,
+    /// Unix permission bits, when the archive records them
+    pub mode: Option<u32>,
+}
+
+/// The kind of archive a file starting with `head` is, if it is one.
+pub fn sniff(head: &[u8]) -> Option<ArchiveKind> {
+    if head.starts_with(&[0x1F, 0x8B]) {
+        Some(ArchiveKind::TarGz)
+    } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        Some(ArchiveKind::Zip)
+    } else if head.get(257..262) == Some(b"ustar") {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    }
+}
+
+/// Writes `files` (relative to `working_dir`) as an archive to `output`; returns the
+/// files it holds.
+pub fn write_archive(
+    kind: ArchiveKind,
+    working_dir: &Path,
+    files: &[PathBuf],
+    output: &Path,
+    reproducible: bool,
+) -> Result<Vec<PathBuf>> {
+    let fixed_time = reproducible.then(|| {
+        std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0)
+    });
+    let mut entries = Vec::new();
+    let mut archived = Vec::new();
+    for rel_path in files {
+        let path = rel_path
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        let full_path = working_dir.join(rel_path);
+        let (content, metadata) = match fs::read(&full_path).and_then(|content| {
+            let metadata = fs::metadata(&full_path)?;
+            Ok((content, metadata))
+        }) {
+            Ok(read) => read,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Could not open file '{}': {}. Skipping.",
+                    full_path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        status!("{}", tr!("bundle.adding", path));
+        let mtime = fixed_time.unwrap_or_else(|| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs())
+        });
+        entries.push((
+            Entry {
+                path,
+                content,
+                mode: Some(file_mode(&metadata)),
+            },
+            mtime,
+        ));
+        archived.push(rel_path.clone());
+    }
+
+    let bytes = match kind {
+        ArchiveKind::Tar => tar(&entries)?,
+        ArchiveKind::TarGz => gzip(&tar(&entries)?, fixed_time.unwrap_or(0)),
+        ArchiveKind::Zip => zip(&entries)?,
+    };
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
+    }
+    fs::write(output, bytes)
+        .with_context(|| format!("Failed to write output file: {}", output.display()))?;
+    Ok(archived)
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0o644
+}
+
+/// The files of the archive `bytes`.
+pub fn read_archive(kind: ArchiveKind, bytes: &[u8]) -> Result<Vec<Entry>> {
+    match kind {
+        ArchiveKind::Tar => untar(bytes),
+        ArchiveKind::TarGz => untar(&gunzip(bytes)?),
+        ArchiveKind::Zip => unzip(bytes),
+    }
+}
+
+fn tar(entries: &[(Entry, u64)]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for (entry, mtime) in entries {
+        let (prefix, name) = match split_ustar_path(&entry.path) {
+            Some(split) => split,
+            None => {
+                // Too long for the header: a pax extended header carries the path
+                let record = pax_record("path", &entry.path);
+                write_tar_header(
+                    &mut out,
+                    "",
+                    "pax_header",
+                    record.len() as u64,
+                    0o644,
+                    *mtime,
+                    b'x',
+                )?;
+                write_tar_data(&mut out, record.as_bytes());
+                ("", "long_path")
+            }
+        };
+        write_tar_header(
+            &mut out,
+            prefix,
+            name,
+            entry.content.len() as u64,
+            entry.mode.unwrap_or(0o644),
+            *mtime,
+            b'0',
+        )?;
+        write_tar_data(&mut out, &entry.content);
+    }
+    out.resize(out.len() + 2 * BLOCK, 0);
+    Ok(out)
+}
+
+/// `path` as the ustar prefix and name fields, if it fits them.
+fn split_ustar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+/// A pax record: `<length> <key>=<value>\n`, where the length counts itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = format!(" {}={}\n", key, value);
+    let mut len = rest.len() + 1;
+    while (len.to_string().len() + rest.len()) != len {
+        len = len.to_string().len() + rest.len();
+    }
+    format!("{}{}", len, rest)
+}
+
+fn write_tar_header(
+    out: &mut Vec<u8>,
+    prefix: &str,
+    name: &str,
+    size: u64,
+    mode: u32,
+    mtime: u64,
+    kind: u8,
+) -> Result<()> {
+    if size >= 8 << 30 {
+        bail!("'{}' is too large for a tar archive (8 GiB at most)", name);
+    }
+    let mut header = [0u8; BLOCK];
+    let mut field = |start: usize, len: usize, value: &[u8]| {
+        header[start..start + value.len().min(len)].copy_from_slice(&value[..value.len().min(len)]);
+    };
+    field(0, 100, name.as_bytes());
+    field(100, 8, format!("{:07o}\0", mode).as_bytes());
+    field(108, 8, b"0000000\0");
+    field(116, 8, b"0000000\0");
+    field(124, 12, format!("{:011o}\0", size).as_bytes());
+    field(
+        136,
+        12,
+        format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes(),
+    );
+    field(156, 1, &[kind]);
+    field(257, 8, b"ustar\x0000");
+    field(345, 155, prefix.as_bytes());
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    out.extend_from_slice(&header);
+    Ok(())
+}
+
+fn write_tar_data(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(data);
+    out.resize(out.len().next_multiple_of(BLOCK), 0);
+}
+
+fn untar(bytes: &[u8]) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    // Path from a pax or GNU long name header, for the next entry
+    let mut long_path: Option<String> = None;
+    while pos + BLOCK <= bytes.len() {
+        let header = &bytes[pos..pos + BLOCK];
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let checksum: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u32)
+            .sum();
+        if octal(&header[148..156]) != Some(checksum as u64) {
+            bail!("Invalid tar header at offset {}", pos);
+        }
+        let size = octal(&header[124..136])
+            .with_context(|| format!("Invalid tar header at offset {}", pos))?
+            as usize;
+        let data = bytes
+            .get(pos + BLOCK..pos + BLOCK + size)
+            .context("The tar archive ends inside a file")?;
+        pos += BLOCK + size.next_multiple_of(BLOCK);
+        let kind = header[156];
+        match kind {
+            b'x' => {
+                long_path = pax_path(data).or(long_path);
+                continue;
+            }
+            b'L' => {
+                long_path = Some(cstr(data));
+                continue;
+            }
+            b'0' | 0 | b'7' => {}
+            // Directories are created as needed; links and the like are not restored
+            b'5' | b'g' => continue,
+            _ => {
+                let name = long_path.take().unwrap_or_else(|| cstr(&header[..100]));
+                eprintln!("Warning: '{}' is not a regular file; skipping it.", name);
+                continue;
+            }
+        }
+        let path = long_path.take().unwrap_or_else(|| {
+            let name = cstr(&header[..100]);
+            let prefix = cstr(&header[345..500]);
+            if prefix.is_empty() || &header[257..262] != b"ustar" {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            }
+        });
+        entries.push(Entry {
+            path: path.trim_start_matches("./").to_string(),
+            content: data.to_vec(),
+            mode: octal(&header[100..108]).map(|mode| mode as u32 & 0o7777),
+        });
+    }
+    Ok(entries)
+}
+
+/// The NUL-terminated text at the start of `field`.
+fn cstr(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn octal(field: &[u8]) -> Option<u64> {
+    let text = cstr(field);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// The `path` of pax extended header records.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let mut rest = data;
+    let mut path = None;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|b| *b == b' ')?;
+        let len: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = std::str::from_utf8(rest.get(space + 1..len)?).ok()?;
+        let (key, value) = record.strip_suffix('\n')?.split_once('=')?;
+        if key == "path" {
+            path = Some(value.to_string());
+        }
+        rest = &rest[len..];
+    }
+    path
+}
+
+fn gzip(data: &[u8], mtime: u64) -> Vec<u8> {
+    let mut out = vec![0x1F, 0x8B, 8, 0];
+    out.extend_from_slice(&(mtime as u32).to_le_bytes());
+    // No extra flags; unknown operating system
+    out.extend_from_slice(&[0, 0xFF]);
+    out.extend_from_slice(&deflate::compress(data));
+    out.extend_from_slice(&deflate::crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Decompresses gzip data, of all its members.
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = bytes;
+    while rest.len() >= 18 && rest.starts_with(&[0x1F, 0x8B]) {
+        if rest[2] != 8 {
+            bail!("Unsupported gzip compression method {}", rest[2]);
+        }
+        let flags = rest[3];
+        let mut pos = 10;
+        if flags & 0x04 != 0 {
+            let len = u16::from_le_bytes([rest[pos], rest[pos + 1]]) as usize;
+            pos += 2 + len;
+        }
+        for flag in [0x08, 0x10] {
+            // File name and comment, NUL-terminated
+            if flags & flag != 0 {
+                pos += rest[pos..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .context("Invalid gzip header")?
+                    + 1;
+            }
+        }
+        if flags & 0x02 != 0 {
+            pos += 2;
+        }
+        let (data, used) = deflate::decompress(rest.get(pos..).context("Invalid gzip header")?)
+            .context("Invalid gzip data")?;
+        let trailer = rest
+            .get(pos + used..pos + used + 8)
+            .context("The gzip data ends too early")?;
+        if u32::from_le_bytes(trailer[..4].try_into().unwrap()) != deflate::crc32(&data) {
+            bail!("The gzip data is corrupt (CRC mismatch)");
+        }
+        out.extend_from_slice(&data);
+        rest = &rest[pos + used + 8..];
+    }
+    if out.is_empty() && !bytes.is_empty() && rest.len() == bytes.len() {
+        bail!("Not gzip data");
+    }
+    Ok(out)
+}
+
+fn zip(entries: &[(Entry, u64)]) -> Result<Vec<u8>> {
+    if entries.len() > u16::MAX as usize {
+        bail!("Too many files for a zip archive ({} at most)", u16::MAX);
+    }
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (entry, mtime) in entries {
+        let compressed = deflate::compress(&entry.content);
+        let (method, data) = if compressed.len() < entry.content.len() {
+            (8u16, compressed.as_slice())
+        } else {
+            (0, entry.content.as_slice())
+        };
+        let offset = out.len();
+        if offset > u32::MAX as usize || entry.content.len() > u32::MAX as usize {
+            bail!("The zip archive would exceed 4 GiB; use --format tar.gz");
+        }
+        let (time, date) = crate::timestamp::dos_datetime(*mtime);
+        let name = entry.path.as_bytes();
+        // Fields shared by the local and the central header, from "version needed" on
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        // Names are UTF-8
+        common.extend_from_slice(&0x0800u16.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&deflate::crc32(&entry.content).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(entry.content.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        out.extend_from_slice(&0x0403_4B50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name);
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4B50u32.to_le_bytes());
+        // Made by Unix, so the permissions below count
+        central.extend_from_slice(&(3u16 << 8 | 20).to_le_bytes());
+        central.extend_from_slice(&common);
+        // Comment length, disk number, internal attributes
+        central.extend_from_slice(&[0; 6]);
+        let mode = 0o100000 | entry.mode.unwrap_or(0o644);
+        central.extend_from_slice(&(mode << 16).to_le_bytes());
+        central.extend_from_slice(&(offset as u32).to_le_bytes());
+        central.extend_from_slice(name);
+    }
+    let central_offset = out.len();
+    if central_offset + central.len() > u32::MAX as usize {
+        bail!("The zip archive would exceed 4 GiB; use --format tar.gz");
+    }
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4B50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(central_offset as u32).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    Ok(out)
+}
+
+fn unzip(bytes: &[u8]) -> Result<Vec<Entry>> {
+    let u16_at = |pos: usize| -> Result<u16> {
+        Ok(u16::from_le_bytes(
+            bytes
+                .get(pos..pos + 2)
+                .context("The zip archive is truncated")?
+                .try_into()?,
+        ))
+    };
+    let u32_at = |pos: usize| -> Result<u32> {
+        Ok(u32::from_le_bytes(
+            bytes
+                .get(pos..pos + 4)
+                .context("The zip archive is truncated")?
+                .try_into()?,
+        ))
+    };
+    // The end of central directory record, before a comment of up to 64 KiB
+    let end = (0..=bytes.len().saturating_sub(22))
+        .rev()
+        .take(u16::MAX as usize + 1)
+        .find(|pos| bytes[*pos..].starts_with(b"PK\x05\x06"))
+        .context("Not a zip archive (no end of central directory)")?;
+    let count = u16_at(end + 10)? as usize;
+    let mut pos = u32_at(end + 16)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(pos)? != 0x0201_4B50 {
+            bail!("Invalid zip central directory at offset {}", pos);
+        }
+        let made_by = u16_at(pos + 4)?;
+        let flags = u16_at(pos + 8)?;
+        let method = u16_at(pos + 10)?;
+        let crc = u32_at(pos + 16)?;
+        let compressed_size = u32_at(pos + 20)? as usize;
+        let size = u32_at(pos + 24)? as usize;
+        let name_len = u16_at(pos + 28)? as usize;
+        let extra_len = u16_at(pos + 30)? as usize;
+        let comment_len = u16_at(pos + 32)? as usize;
+        let attributes = u32_at(pos + 38)?;
+        let local = u32_at(pos + 42)? as usize;
+        let name = bytes
+            .get(pos + 46..pos + 46 + name_len)
+            .context("The zip archive is truncated")?;
+        let path = String::from_utf8_lossy(name).into_owned();
+        pos += 46 + name_len + extra_len + comment_len;
+        if path.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            bail!("'{}' is encrypted; sheafy cannot extract it", path);
+        }
+        if u32_at(local)? != 0x0403_4B50 {
+            bail!("Invalid zip local header for '{}'", path);
+        }
+        let start = local + 30 + u16_at(local + 26)? as usize + u16_at(local + 28)? as usize;
+        let data = bytes
+            .get(start..start + compressed_size)
+            .context("The zip archive is truncated")?;
+        let content = match method {
+            0 => data.to_vec(),
+            8 => {
+                deflate::decompress(data)
+                    .with_context(|| format!("Invalid compressed data for '{}'", path))?
+                    .0
+            }
+            _ => bail!(
+                "'{}' uses zip compression method {}, which sheafy cannot read",
+                path,
+                method
+            ),
+        };
+        if content.len() != size || deflate::crc32(&content) != crc {
+            bail!("'{}' is corrupt in the zip archive (CRC mismatch)", path);
+        }
+        let mode =
+            (made_by >> 8 == 3 && attributes >> 16 != 0).then_some(attributes >> 16 & 0o7777);
+        entries.push(Entry {
+            path,
+            content,
+            mode,
+        });
+    }
+    Ok(entries)
+}
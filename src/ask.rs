@@ -0,0 +1,260 @@
+//! `sheafy ask`: sends the bundle to a model as one prompt and restores the reply.
+//!
+//! The project is bundled with the task (`--prompt` or `--prompt-file`), sent to the
+//! Anthropic Messages API or an OpenAI-compatible Chat Completions API, and the reply is
+//! streamed to stdout as it arrives. It is also kept in `.sheafy/last-reply.md`, and with
+//! `--apply` restored like any bundle. Requests go through `curl`, with the API key
+//! passed on curl's stdin rather than its command line.
+
+use crate::cli::{AskArgs, Provider, RestoreArgs};
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Where the last reply is kept, in `.sheafy/`
+pub const REPLY_FILENAME: &str = "last-reply.md";
+
+const DEFAULT_MAX_TOKENS: u32 = 16000;
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Asks for replies that `sheafy restore` can apply.
+const SYSTEM_PROMPT: &str = "You are given a project as a Markdown bundle: each file is a \
+`## path` heading followed by a code fence holding the whole file. Carry out the task \
+described in the bundle. For every file you create or change, reply with a `## path` \
+heading followed by a code fence holding the complete new content of the file, never a \
+diff or an excerpt. Keep explanations short and outside the code fences.";
+
+/// The `[sheafy.ask]` settings with command line overrides and defaults applied.
+struct Api {
+    provider: Provider,
+    model: String,
+    url: String,
+    key_env: String,
+    max_tokens: u32,
+    system: String,
+}
+
+impl Api {
+    fn resolve(config: &Config, args: &AskArgs) -> Result<Self> {
+        let ask = &config.sheafy.ask;
+        let model = args
+            .model
+            .clone()
+            .or_else(|| ask.model.clone())
+            .context("No model given: pass --model or set `model` in [sheafy.ask]")?;
+        let provider = args
+            .provider
+            .or(ask.provider)
+            .unwrap_or(if model.starts_with("claude") {
+                Provider::Anthropic
+            } else {
+                Provider::Openai
+            });
+        let (default_base, path, default_key_env) = match provider {
+            Provider::Anthropic => (
+                "https://api.anthropic.com",
+                "/v1/messages",
+                "ANTHROPIC_API_KEY",
+            ),
+            Provider::Openai => (
+                "https://api.openai.com/v1",
+                "/chat/completions",
+                "OPENAI_API_KEY",
+            ),
+        };
+        let base = ask.base_url.as_deref().unwrap_or(default_base);
+        Ok(Self {
+            provider,
+            model,
+            url: format!("{}{}", base.trim_end_matches('/'), path),
+            key_env: ask
+                .api_key_env
+                .clone()
+                .unwrap_or_else(|| default_key_env.to_string()),
+            max_tokens: ask.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            system: ask
+                .system
+                .clone()
+                .unwrap_or_else(|| SYSTEM_PROMPT.to_string()),
+        })
+    }
+
+    fn body(&self, bundle: &str) -> Value {
+        match self.provider {
+            Provider::Anthropic => json!({
+                "model": self.model,
+                "max_tokens": self.max_tokens,
+                "stream": true,
+                "system": self.system,
+                "messages": [{ "role": "user", "content": bundle }],
+            }),
+            Provider::Openai => json!({
+                "model": self.model,
+                "max_tokens": self.max_tokens,
+                "stream": true,
+                "messages": [
+                    { "role": "system", "content": self.system },
+                    { "role": "user", "content": bundle },
+                ],
+            }),
+        }
+    }
+
+    /// Request headers, as lines of a curl config file.
+    fn curl_config(&self, key: &str) -> String {
+        let mut headers = match self.provider {
+            Provider::Anthropic => vec![
+                format!("x-api-key: {}", key),
+                format!("anthropic-version: {}", ANTHROPIC_VERSION),
+            ],
+            Provider::Openai => vec![format!("Authorization: Bearer {}", key)],
+        };
+        headers.push("Content-Type: application/json".to_string());
+        headers
+            .iter()
+            .map(|header| format!("header = \"{}\"\n", header.replace('"', "\\\"")))
+            .collect()
+    }
+
+    /// The text a server-sent event adds to the reply, or the error it reports.
+    fn event_text(&self, event: &Value) -> Result<Option<String>> {
+        if let Some(error) = event.get("error") {
+            bail!(
+                "The API reported an error: {}",
+                error["message"].as_str().unwrap_or(&error.to_string())
+            );
+        }
+        let text = match self.provider {
+            Provider::Anthropic => event["delta"]["text"].as_str(),
+            Provider::Openai => event["choices"][0]["delta"]["content"].as_str(),
+        };
+        Ok(text.map(str::to_string))
+    }
+}
+
+pub fn run_ask(config: Config, args: AskArgs) -> Result<()> {
+    if args.bundle.prompt.is_none() && args.bundle.prompt_file.is_none() {
+        bail!("`sheafy ask` needs a task: pass --prompt or --prompt-file");
+    }
+    let api = Api::resolve(&config, &args)?;
+    let key = std::env::var(&api.key_env)
+        .ok()
+        .filter(|key| !key.is_empty())
+        .with_context(|| format!("Set {} to your API key", api.key_env))?;
+    let working_dir = config.get_working_dir()?;
+    let counter = crate::tokens::Counter::from_config(&config, &working_dir)?;
+
+    let summary = crate::bundle::run_bundle(config, args.bundle)?;
+    let bundle = fs::read_to_string(&summary.output)
+        .with_context(|| format!("Failed to read bundle: {}", summary.output.display()))?;
+    status!(
+        "Asking {} ({} bytes, {}{} tokens)...\n",
+        api.model,
+        bundle.len(),
+        if counter.is_exact() { "" } else { "about " },
+        counter.count(&bundle)
+    );
+
+    let reply = stream_reply(&api, &key, &bundle)?;
+    let reply_path = save_reply(&working_dir, &reply)?;
+    status!("\nReply saved to '{}'.", reply_path.display());
+
+    if args.apply {
+        let config = Config::load().context("Failed to load configuration")?;
+        crate::restore::run_restore(
+            &config,
+            &RestoreArgs {
+                input_file: Some(reply_path.to_string_lossy().into_owned()),
+                allow_new_paths: args.allow_new_paths,
+                ..Default::default()
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Sends the request and prints the reply as it streams in; returns the whole reply.
+fn stream_reply(api: &Api, key: &str, bundle: &str) -> Result<String> {
+    let body_file = tempfile::NamedTempFile::new().context("Failed to create request file")?;
+    fs::write(body_file.path(), api.body(bundle).to_string())
+        .context("Failed to write request file")?;
+    let mut child = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--no-buffer",
+            "--fail-with-body",
+        ])
+        .args(["--config", "-", "--data-binary"])
+        .arg(format!("@{}", body_file.path().display()))
+        .arg(&api.url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl; `sheafy ask` needs it on PATH")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(api.curl_config(key).as_bytes())
+        .context("Failed to pass the request headers to curl")?;
+
+    let mut reply = String::new();
+    // Lines that are not events, such as the JSON body of an HTTP error
+    let mut other = String::new();
+    let mut stdout = std::io::stdout();
+    let reader = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    for line in reader.lines() {
+        let line = line.context("Failed to read the API response")?;
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            if !line.starts_with("event:") && !line.trim().is_empty() {
+                other.push_str(&line);
+                other.push('\n');
+            }
+            continue;
+        };
+        if data == "[DONE]" {
+            continue;
+        }
+        let event: Value = match serde_json::from_str(data) {
+            Ok(event) => event,
+            Err(_) => continue,
+        };
+        if let Some(text) = api.event_text(&event)? {
+            print!("{}", text);
+            stdout.flush().ok();
+            reply.push_str(&text);
+        }
+    }
+    let exit = child.wait().context("Failed to wait for curl")?;
+    if !exit.success() {
+        let detail = serde_json::from_str::<Value>(&other)
+            .ok()
+            .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or_else(|| other.trim().to_string());
+        bail!("Request to {} failed ({}): {}", api.url, exit, detail);
+    }
+    if reply.is_empty() {
+        bail!("The API sent an empty reply");
+    }
+    if !reply.ends_with('\n') {
+        println!();
+        reply.push('\n');
+    }
+    Ok(reply)
+}
+
+fn save_reply(working_dir: &std::path::Path, reply: &str) -> Result<PathBuf> {
+    let dir = crate::state::state_dir(working_dir);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let path = dir.join(REPLY_FILENAME);
+    fs::write(&path, reply).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
@@ -0,0 +1,110 @@
+//! `sheafy bundle --baseline previous.md`: leave out the files a previous bundle
+//! already holds with the same content, so each round of an iterative session only
+//! sends what changed. The unchanged files are listed by name in the new bundle.
+//!
+//! The baseline is read the way restore reads it (header prefixes, handlers, rewritten
+//! links, missing final newlines), so a file counts as unchanged exactly when restoring
+//! the baseline would write the bytes it has now.
+
+use crate::config::Config;
+use crate::parser::BundleReader;
+use crate::restore::{RestoreOptions, PATCH_MARKER, READONLY_MARKER};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+/// Removes from `files` those whose content equals their section in `baseline`, and
+/// returns them in bundle order. The baseline itself is dropped too.
+pub fn drop_unchanged(
+    config: &Config,
+    working_dir: &Path,
+    baseline: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<PathBuf>> {
+    let contents = read_baseline(config, working_dir, baseline)
+        .with_context(|| format!("Failed to read baseline bundle: {}", baseline.display()))?;
+    let baseline_rel = crate::paths::absolute(baseline, working_dir)
+        .strip_prefix(working_dir)
+        .ok()
+        .map(Path::to_path_buf);
+    let mut unchanged = Vec::new();
+    files.retain(|file| {
+        if baseline_rel.as_ref() == Some(file) {
+            return false;
+        }
+        let path = file
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        let same = contents
+            .get(&crate::header::normalize(&path))
+            .is_some_and(|content| {
+                fs::read(working_dir.join(file)).is_ok_and(|bytes| bytes == content.as_bytes())
+            });
+        if same {
+            unchanged.push(file.clone());
+        }
+        !same
+    });
+    status!(
+        "Baseline {}: {} file(s) unchanged and left out, {} new or changed.",
+        baseline.display(),
+        unchanged.len(),
+        files.len()
+    );
+    Ok(unchanged)
+}
+
+/// Prose line for the bundle listing the files left out (outside any section, so
+/// restore skips it).
+pub fn note(baseline: &Path, unchanged: &[PathBuf]) -> String {
+    let names: Vec<String> = unchanged
+        .iter()
+        .map(|file| {
+            format!(
+                "`{}`",
+                file.to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/")
+            )
+        })
+        .collect();
+    format!(
+        "> Unchanged since {} and not repeated here: {}.",
+        baseline.file_name().map_or_else(
+            || baseline.display().to_string(),
+            |name| name.to_string_lossy().into_owned()
+        ),
+        names.join(", ")
+    )
+}
+
+/// Restored content of every whole-file section of the baseline, by working tree path.
+fn read_baseline(
+    config: &Config,
+    working_dir: &Path,
+    baseline: &Path,
+) -> Result<HashMap<String, String>> {
+    let file = File::open(baseline)?;
+    let bundle = BundleReader::new(BufReader::new(file))?;
+    if bundle
+        .markers()
+        .iter()
+        .any(|m| m == PATCH_MARKER || m == READONLY_MARKER)
+    {
+        bail!("it holds patches or excerpts, not whole files");
+    }
+    let options = RestoreOptions::from_config(config, working_dir)?;
+    let mut contents = HashMap::new();
+    for section in bundle {
+        let section = options.map_path(section?);
+        if section.preview || section.stub.is_some() || section.path.is_empty() {
+            continue;
+        }
+        let path = section.path.clone();
+        contents.insert(path, section.into_file_content(&options)?);
+    }
+    Ok(contents)
+}
@@ -1,262 +1,2002 @@
-use crate::config::{Config, DEFAULT_BUNDLE_NAME};
+use crate::archive::ArchiveKind;
+use crate::cli::{AutoOrder, BundleArgs, BundleFormat, HeaderStyle, MinifiedPolicy};
+use crate::config::{
+    ByteSize, Config, LongLinePolicy, SubmodulePolicy, DEFAULT_BUNDLE_NAME, DEFAULT_MAX_FILES,
+};
+use crate::error::SheafyError;
+use crate::handlers::Handlers;
+use crate::limits::Limited;
+use crate::links::Anchors;
+use crate::long_lines::LongLines;
+use crate::parser::{fence_for, BundleReader};
+use crate::readahead::FileReader;
+use crate::restore::{
+    HeaderPrefixes, CHECKSUMS_HEADING, HANDLER_PREFIX, LINKS_PREFIX, NO_EOL_MARKER, PART_PREFIX,
+    PATCH_MARKER, PREVIEW_PREFIX, PROMPT_END_MARKER, PROMPT_MARKER, READONLY_MARKER,
+    SAME_AS_PREFIX, STATS_PREFIX, UNMAPPED_MARKER, VERSION_MARKER,
+};
+use crate::scopes::Scopes;
+use crate::script::Shell;
+use crate::state::State;
+use crate::stub::Stubs;
+use crate::summarize::Summarizers;
+use crate::tags::{GroupHeadings, Tags};
+use crate::tokens::Counter;
 use anyhow::{bail, Context, Result};
-use ignore::{WalkBuilder}; // Import OverrideBuilder
+use ignore::{gitignore::Gitignore, DirEntry, WalkBuilder, WalkState};
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{self, File},
-    io::{BufWriter, Read, Write},
-    path::PathBuf,
+    io::{self, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
 };
 
-fn invert_patern(pattern: &str) -> String {
-    // if starts with !, remove it, otherwise add!
-    if pattern.starts_with('!') {
-        pattern[1..].to_string()
-    } else {
-        format!("!{}", pattern)
+/// Stands for the task prompt in `prologue` and `epilogue`.
+pub const PROMPT_PLACEHOLDER: &str = "{{prompt}}";
+
+/// Result of a bundle run, reported back to callers such as the daemon.
+#[derive(Debug, Default)]
+pub struct BundleSummary {
+    pub output: PathBuf,
+    /// Files written to the bundle, relative to the working directory.
+    pub files: Vec<PathBuf>,
+}
+
+/// Files selected for bundling together with the paths they were resolved against.
+#[derive(Debug)]
+pub struct Selection {
+    pub working_dir: PathBuf,
+    pub output_path: PathBuf,
+    /// Paths relative to `working_dir`, in bundle order.
+    pub files: Vec<PathBuf>,
+    /// Whether git ignore rules applied to the walk
+    pub use_gitignore: bool,
+    /// Files left out by `limits`, per pattern
+    pub limited: Vec<Limited>,
+    /// Files left out because `--baseline` already holds them unchanged
+    pub unchanged: Vec<PathBuf>,
+    /// Project-wide and directory-scoped settings
+    pub scopes: Scopes,
+    /// Outputs of the config's profiles and the watch session, left out like `output_path`
+    pub other_outputs: Vec<PathBuf>,
+}
+
+/// Walks done earlier in this process, by walk key, so that bundling several profiles
+/// walks the tree once for all that walk it alike.
+#[derive(Default)]
+pub struct SharedWalks(HashMap<String, Vec<PathBuf>>);
+
+impl SharedWalks {
+    /// Number of walks done.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 }
 
 pub fn run_bundle(
     config: Config, // Pass loaded config
-    // REMOVED: cli_filters: Option<Vec<String>>,
-    cli_output: Option<String>,
-    cli_use_git: bool,
-    cli_no_git: bool,
-) -> Result<()> {
+    args: BundleArgs,
+) -> Result<BundleSummary> {
+    run_bundle_sharing(config, args, &mut SharedWalks::default())
+}
+
+/// [`run_bundle`], reusing the walks in `walks` and adding its own.
+pub fn run_bundle_sharing(
+    config: Config,
+    args: BundleArgs,
+    walks: &mut SharedWalks,
+) -> Result<BundleSummary> {
+    if args.format == BundleFormat::Patch && args.since.is_none() {
+        bail!("--format patch requires --since <ref>");
+    }
+    let shell = match args.format {
+        BundleFormat::Shell => Some(Shell::Posix),
+        BundleFormat::Powershell => Some(Shell::PowerShell),
+        _ => None,
+    };
+    let archive = match args.format {
+        BundleFormat::TarGz => Some(ArchiveKind::TarGz),
+        BundleFormat::Zip => Some(ArchiveKind::Zip),
+        _ => None,
+    };
+    if args.append && (args.format != BundleFormat::Markdown || args.context_lines.is_some()) {
+        bail!("--append adds whole files to a Markdown bundle; it needs --format markdown");
+    }
+    if args.check && (shell.is_some() || archive.is_some()) {
+        bail!("--check compares Markdown bundles; it needs --format markdown or patch");
+    }
+    if (shell.is_some() || archive.is_some()) && args.context_lines.is_some() {
+        bail!("Scripts and archives hold whole files; --context-lines needs --format markdown");
+    }
+    // Blank lines before each section; strict parsers want more than one
+    let section_separator = match config.sheafy.section_spacing.unwrap_or(1) {
+        spacing @ 1..=2 => "\n".repeat(spacing),
+        other => bail!("section_spacing must be 1 or 2, got {}", other),
+    };
+    // Read before changing into the working directory, so relative paths mean what they say
+    let prompt = read_prompt(&args)?;
     // Use working_dir already determined in main.rs
     let working_dir = config
         .get_working_dir()
         .context("Failed to get working directory for bundling")?;
-    let output_filename = cli_output
-        .or(config.sheafy.bundle_name)
-        .unwrap_or_else(|| DEFAULT_BUNDLE_NAME.to_string());
-    let output_path = PathBuf::from(&output_filename);
     let env_wd = std::env::current_dir()?;
     std::env::set_current_dir(working_dir.clone())?;
     defer! {
         std::env::set_current_dir(env_wd).unwrap();
     }
-    // Ensure output path is absolute for comparison, handle potential creation errors
-    let absolute_output_path = if output_path.is_absolute() {
-        output_path.clone()
+
+    let scan_started = Instant::now();
+    let walk = crate::timings::span("walk");
+    let selection = select_files_sharing(&config, &args, walks)?;
+    drop(walk);
+    let scan_time = scan_started.elapsed();
+    // With nothing changed since the baseline, the bundle still says so
+    if selection.files.is_empty() && selection.unchanged.is_empty() {
+        if args.show_excluded {
+            crate::excluded::report_excluded(&config, &args, &selection);
+        }
+        return Ok(BundleSummary {
+            output: selection.output_path,
+            files: selection.files,
+        });
+    }
+    let Selection {
+        working_dir,
+        output_path: absolute_output_path,
+        files: matched_files,
+        limited,
+        unchanged,
+        ..
+    } = &selection;
+
+    if shell.is_some() || archive.is_some() {
+        status!("{}", tr!("bundle.creating", absolute_output_path.display()));
+        let bundled_files = match (shell, archive) {
+            (Some(shell), _) => crate::script::write_script(
+                shell,
+                working_dir,
+                matched_files,
+                absolute_output_path,
+            )?,
+            (None, Some(kind)) => crate::archive::write_archive(
+                kind,
+                working_dir,
+                matched_files,
+                absolute_output_path,
+                args.reproducible,
+            )?,
+            (None, None) => unreachable!("checked above"),
+        };
+        status!(
+            "{}",
+            tr!(
+                "bundle.done",
+                absolute_output_path.display(),
+                bundled_files.len()
+            )
+        );
+        if let Err(e) = State::record_bundled(working_dir, &bundled_files) {
+            eprintln!("Warning: Could not record the bundled paths: {:#}", e);
+        }
+        return Ok(BundleSummary {
+            output: absolute_output_path.clone(),
+            files: bundled_files,
+        });
+    }
+
+    // Patch bundles always carry the diff, even of files matching a stub pattern
+    let stubs = match args.format {
+        BundleFormat::Markdown => Stubs::from_config(&config, &args.stubs, working_dir)?,
+        BundleFormat::Patch
+        | BundleFormat::Shell
+        | BundleFormat::Powershell
+        | BundleFormat::TarGz
+        | BundleFormat::Zip => None,
+    };
+
+    // Excerpt and patch bundles are much smaller than their sources, so only guard whole files
+    if let Some(max_size) = config.sheafy.max_bundle_size {
+        if args.context_lines.is_none() && args.format == BundleFormat::Markdown {
+            let sized: Vec<PathBuf> = matched_files
+                .iter()
+                .filter(|rel_path| !stubs.as_ref().is_some_and(|s| s.matches(rel_path)))
+                .cloned()
+                .collect();
+            check_bundle_size(working_dir, &sized, max_size)?;
+        }
+    }
+
+    if args.check {
+        status!("Checking {}", absolute_output_path.display());
     } else {
-        working_dir.join(&output_path)
+        status!("{}", tr!("bundle.creating", absolute_output_path.display()));
     }
-    .canonicalize() // Try to canonicalize *before* creating the file
-    .or_else(|_| -> anyhow::Result<PathBuf> {
-        // If canonicalize fails (e.g., file doesn't exist yet), keep the joined path
-        if output_path.is_absolute() {
-            Ok(output_path.clone())
-        } else {
-            Ok(working_dir.join(&output_path))
+    // Create parent directory if it doesn't exist
+    if let Some(parent_dir) = absolute_output_path.parent() {
+        if !parent_dir.exists() && !args.check {
+            status!("{}", tr!("bundle.creating_dir", parent_dir.display()));
+            fs::create_dir_all(parent_dir).with_context(|| {
+                format!(
+                    "Failed to create output directory: {}",
+                    parent_dir.display()
+                )
+            })?;
+        }
+    }
+
+    // Header paths already in the bundle being appended to; None when writing a new one
+    let (output_file, appending) = if args.append {
+        open_for_append(absolute_output_path)?
+    } else if args.check {
+        let file = tempfile::tempfile().context("Failed to create a temporary file")?;
+        (file, None)
+    } else {
+        let file = File::create(absolute_output_path).with_context(|| {
+            format!(
+                "Failed to create output file: {}",
+                absolute_output_path.display()
+            )
+        })?;
+        (file, None)
+    };
+    let mut writer = BufWriter::new(output_file);
+    let mut bundled_files = Vec::new();
+    let mut throttle = config
+        .sheafy
+        .io_throttle_mb_s
+        .filter(|rate| *rate > 0.0)
+        .map(Throttle::new);
+    // Only whole-file sections can be shared; excerpts and diffs are always written out
+    let dedup = (args.dedup || config.sheafy.dedup.unwrap_or(false)) && args.since.is_none();
+    // File content -> header path of the section that holds it
+    let mut seen_contents: HashMap<String, String> = HashMap::new();
+    // Header path -> SHA-256 of the section's content, for the checksums section
+    let whole_files = args.format == BundleFormat::Markdown && args.context_lines.is_none();
+    if args.checksums && !whole_files {
+        eprintln!("Warning: Checksums need whole files; leaving them out of this bundle.");
+    }
+    let mut checksums = ((args.checksums || config.sheafy.checksums.unwrap_or(false))
+        && whole_files)
+        .then(BTreeMap::new);
+    if checksums.is_some() && appending.is_some() {
+        eprintln!(
+            "Warning: Checksums cover a whole bundle; leaving them out of the appended sections."
+        );
+        checksums = None;
+    }
+    let handlers = Handlers::from_config(&config, working_dir)?;
+    let summarizers = Summarizers::from_config(&config, working_dir)?;
+    let long_lines = LongLines::from_config(&config, working_dir)?;
+    let preview_rows = args.csv_preview_rows.or(config.sheafy.csv_preview_rows);
+    let minified_policy = args.minified.or(config.sheafy.minified).unwrap_or_default();
+    // Minified files left out, with their sizes, for the note at the end
+    let mut minified_skipped: Vec<(PathBuf, u64)> = Vec::new();
+    let mut split_size = args.split_size.or(config.sheafy.split_size);
+    if split_size.is_some() && args.header_style != HeaderStyle::Heading {
+        eprintln!(
+            "Warning: Splitting files into parts needs `## path` headings; writing whole files."
+        );
+        split_size = None;
+    }
+    let file_stats = args.file_stats || config.sheafy.file_stats.unwrap_or(false);
+    // Bundles of the whole project are measured for `.sheafy/metrics.jsonl`
+    let mut metrics = (args.since.is_none() && args.files_from.is_none() && !args.append)
+        .then(crate::stats::Metrics::default);
+    // Only set up when counts are written: reading a rank file takes a moment
+    let token_counter = (file_stats || metrics.is_some())
+        .then(|| Counter::from_config(&config, working_dir))
+        .transpose()?;
+    let prefixes = HeaderPrefixes::from_config(&config);
+    // An appended bundle's sections go at its end, not under group headings
+    let tags = Tags::from_config(&config, working_dir)?.filter(|_| appending.is_none());
+    let mut groups = GroupHeadings::new(tags.as_ref());
+    let mut link_anchors =
+        (args.link_anchors || config.sheafy.link_anchors.unwrap_or(false)) && args.since.is_none();
+    if link_anchors && args.header_style != HeaderStyle::Heading {
+        eprintln!("Warning: Link anchors need `## path` headings; leaving links unchanged.");
+        link_anchors = false;
+    }
+    // The task prompt goes where `{{prompt}}` is, or else right after the prologue
+    let placed = [&config.sheafy.prologue, &config.sheafy.epilogue]
+        .iter()
+        .any(|text| {
+            text.as_deref()
+                .is_some_and(|t| t.contains(PROMPT_PLACEHOLDER))
+        });
+    let fill = |text: &Option<String>| {
+        text.as_deref()
+            .map(|t| t.replace(PROMPT_PLACEHOLDER, prompt.as_deref().unwrap_or("")))
+    };
+    let mut prologue = fill(&config.sheafy.prologue);
+    let epilogue = fill(&config.sheafy.epilogue);
+    if let Some(prompt) = prompt.as_deref().filter(|_| !placed) {
+        let prologue = prologue.get_or_insert_with(String::new);
+        if !prologue.is_empty() && !prologue.ends_with('\n') {
+            prologue.push('\n');
+        }
+        prologue.push_str(prompt);
+    }
+
+    // Anchors are assigned in document order, so the prologue's headings come first.
+    // Files that will be skipped get none: links to them are left as they are.
+    let anchors = link_anchors.then(|| {
+        let mut anchors = Anchors::default();
+        anchors.add_headings(prologue.as_deref().unwrap_or(""));
+        let mut groups = GroupHeadings::new(tags.as_ref());
+        for rel_path in matched_files.iter().filter(|rel_path| {
+            writes_section(
+                working_dir,
+                rel_path,
+                stubs.as_ref(),
+                &summarizers,
+                minified_policy,
+                &long_lines,
+            )
+        }) {
+            if let Some(heading) = groups.heading_for(rel_path) {
+                anchors.add_headings(&heading);
+            }
+            let rel_path = rel_path
+                .to_string_lossy()
+                .replace(std::path::MAIN_SEPARATOR, "/");
+            anchors.add_section(&prefixes.to_header(&rel_path));
+        }
+        anchors
+    });
+
+    // A bundle appended to keeps its own front matter, markers and prologue
+    if appending.is_none() {
+        write_front_matter(
+            &mut writer,
+            working_dir,
+            matched_files,
+            &selection.scopes,
+            (!args.reproducible).then_some(scan_time),
+            &args.template_vars,
+            args.readonly,
+        )?;
+        writeln!(writer, "{}", VERSION_MARKER)?;
+        // Bundles that don't carry whole files are marked so restore won't treat them as such
+        if args.format == BundleFormat::Patch {
+            writeln!(writer, "{}", PATCH_MARKER)?;
+        } else if args.context_lines.is_some() {
+            writeln!(writer, "{}", READONLY_MARKER)?;
+        }
+        if let Some(marker) = crate::update::args_marker(&args)? {
+            writeln!(writer, "{}", marker)?;
+        }
+
+        if let Some(prologue) = &prologue {
+            writer.write_all(prologue.as_bytes())?;
+            if !prologue.ends_with('\n') {
+                // Ensure newline after prologue
+                writeln!(writer)?;
+            }
+        }
+    }
+
+    let mut reader = FileReader::new(args.io, working_dir, matched_files)?;
+    for rel_path in matched_files {
+        let rel_path_str = rel_path
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/"); // Use consistent / separator in header
+                                                      // In NFC, as restore reads heading paths
+        let header_path = crate::header::normalize(&prefixes.to_header(&rel_path_str));
+        if appending
+            .as_ref()
+            .is_some_and(|existing| existing.contains(&header_path))
+        {
+            status!("  Already in the bundle: {}", header_path);
+            continue;
+        }
+        status!("{}", tr!("bundle.adding", header_path));
+        // A header restore would map to another file is marked to be taken as is
+        let unmapped = !prefixes.is_reversible(&rel_path_str);
+
+        // Read from the original absolute path constructed relative to working_dir
+        let full_read_path = working_dir.join(rel_path);
+        let read = crate::timings::span("read");
+        let bytes = match reader.read(rel_path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Could not open file '{}': {}. Skipping.",
+                    full_read_path.display(),
+                    e
+                );
+                continue; // Skip this file
+            }
+        };
+        drop(read);
+        let transform = crate::timings::span("transform");
+        if let Some(stubs) = stubs.as_ref().filter(|s| s.matches(rel_path)) {
+            let stub = stubs.stub(&header_path, &bytes);
+            status!("    (stub of {})", ByteSize(stub.size));
+            groups.start(&mut writer, rel_path, &section_separator)?;
+            writeln!(
+                writer,
+                "{}## {}",
+                section_separator,
+                crate::header::escape(&header_path)
+            )?;
+            writeln!(writer, "{}", stub.note())?;
+            if unmapped {
+                writeln!(writer, "{}", UNMAPPED_MARKER)?;
+            }
+            bundled_files.push(rel_path.clone());
+            continue;
+        }
+        if crate::sniff::is_binary(&bytes) {
+            eprintln!(
+                "Warning: Skipping binary file '{}'; add it to stub_patterns to record it without its content.",
+                full_read_path.display()
+            );
+            continue;
+        }
+        let file_content = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Could not read file '{}': {}. Skipping.",
+                    full_read_path.display(),
+                    e
+                );
+                continue; // Skip this file
+            }
+        };
+        if let Some(throttle) = throttle.as_mut() {
+            throttle.consume(file_content.len());
+        }
+
+        // Determine language hint for ``` block (extension, well-known name or shebang)
+        let lang_hint = selection
+            .scopes
+            .language_hint(rel_path)
+            .unwrap_or_else(|| crate::sniff::language_hint(rel_path, &file_content));
+
+        if let Some(since) = &args.since {
+            if args.format == BundleFormat::Patch {
+                let diff = crate::git::diff_file(
+                    working_dir,
+                    since,
+                    rel_path,
+                    args.context_lines.unwrap_or(3),
+                )?;
+                groups.start(&mut writer, rel_path, &section_separator)?;
+                writeln!(
+                    writer,
+                    "{}## {}",
+                    section_separator,
+                    crate::header::escape(&header_path)
+                )?;
+                let fence = fence_for(&diff);
+                writeln!(writer, "{}diff", fence)?;
+                writer.write_all(diff.as_bytes())?;
+                if !diff.ends_with('\n') {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "{}", fence)?;
+                bundled_files.push(rel_path.clone());
+                continue;
+            }
+            if let Some(context_lines) = args.context_lines {
+                let diff = crate::git::diff_file(working_dir, since, rel_path, context_lines)?;
+                let hunks = crate::git::parse_hunks(&diff);
+                if hunks.is_empty() {
+                    status!("    (no textual changes in {}, skipping)", header_path);
+                    continue;
+                }
+                groups.start(&mut writer, rel_path, &section_separator)?;
+                writeln!(
+                    writer,
+                    "{}## {}",
+                    section_separator,
+                    crate::header::escape(&header_path)
+                )?;
+                for hunk in hunks {
+                    if hunk.new_len == 0 {
+                        writeln!(writer, "\nLines removed after line {}.", hunk.new_start)?;
+                        continue;
+                    }
+                    let end = hunk.new_start + hunk.new_len - 1;
+                    writeln!(writer, "\nLines {}-{}:", hunk.new_start, end)?;
+                    let fence = fence_for(&hunk.lines.join("\n"));
+                    writeln!(writer, "{}{}", fence, lang_hint)?;
+                    for line in &hunk.lines {
+                        writeln!(writer, "{}", line)?;
+                    }
+                    writeln!(writer, "{}", fence)?;
+                }
+                bundled_files.push(rel_path.clone());
+                continue;
+            }
+        }
+
+        // A summarizer's output is bundled instead of the file; restore skips it
+        let mut summarized_by = None;
+        let summary_language;
+        let (lang_hint, file_content) = match summarizers.for_path(rel_path) {
+            Some(summarizer) => match summarizer.run(&rel_path_str, &file_content) {
+                Ok(summary) => {
+                    status!("    (summarized by `{}`)", summarizer.command);
+                    summarized_by = Some(summarizer.command.as_str());
+                    summary_language = summarizer.language.clone();
+                    (summary_language.as_str(), summary)
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: Summarizer `{}` failed on '{}': {:#}. Bundling the file itself.",
+                        summarizer.command, header_path, e
+                    );
+                    (lang_hint, file_content)
+                }
+            },
+            None => (lang_hint, file_content),
+        };
+
+        // Minified files are named; the policy may also cut them to a preview or drop them
+        let mut minified_chars = None;
+        let minified = crate::minified::detect(&file_content).filter(|_| summarized_by.is_none());
+        let file_content = match minified {
+            Some(shape) => {
+                eprintln!(
+                    "Warning: '{}' looks minified ({}); {}.",
+                    header_path,
+                    shape.describe(),
+                    match minified_policy {
+                        MinifiedPolicy::Include => "bundling it as is",
+                        MinifiedPolicy::Truncate => "keeping a preview of it",
+                        MinifiedPolicy::Skip => "skipping the file",
+                    }
+                );
+                match minified_policy {
+                    MinifiedPolicy::Include => file_content,
+                    MinifiedPolicy::Truncate => {
+                        minified_chars = Some(file_content.chars().count());
+                        crate::minified::preview(&file_content)
+                    }
+                    MinifiedPolicy::Skip => {
+                        minified_skipped.push((rel_path.clone(), file_content.len() as u64));
+                        continue;
+                    }
+                }
+            }
+            None => file_content,
+        };
+
+        // Files with overly long lines are named; the policy may also wrap, cut or drop them
+        let mut long_line_policy = None;
+        if let Some(offenders) = long_lines.find(&file_content) {
+            let policy = long_lines.policy(rel_path);
+            let (line, length) = offenders.longest;
+            eprintln!(
+                "Warning: '{}' has {} line(s) longer than {} characters (line {} has {}); {}.",
+                header_path,
+                offenders.count,
+                long_lines.max,
+                line,
+                length,
+                match policy {
+                    LongLinePolicy::Warn => "bundling it as is",
+                    LongLinePolicy::Wrap => "wrapping them",
+                    LongLinePolicy::Truncate => "cutting them short",
+                    LongLinePolicy::Skip => "skipping the file",
+                }
+            );
+            if policy == LongLinePolicy::Skip {
+                continue;
+            }
+            long_line_policy = Some(policy);
+        }
+        let cut_lines = long_line_policy == Some(LongLinePolicy::Truncate);
+
+        // Large tables are cut down to their header and first rows; restore skips them
+        let mut preview_total = None;
+        let file_content = match preview_rows {
+            Some(rows) if crate::table::is_table(rel_path) => {
+                match crate::table::preview(&file_content, rows) {
+                    Some((preview, total)) => {
+                        status!("    (previewing {} of {} rows)", rows, total);
+                        preview_total = Some((rows, total));
+                        preview
+                    }
+                    None => file_content,
+                }
+            }
+            _ => file_content,
+        };
+
+        // Excerpts and summaries are not the file: never deduplicated, encoded or checksummed
+        let excerpt = preview_total.is_some()
+            || minified_chars.is_some()
+            || cut_lines
+            || summarized_by.is_some();
+        if dedup && !excerpt {
+            if let Some(original) = seen_contents.get(&file_content) {
+                status!("    (same content as {}, writing a reference)", original);
+                groups.start(&mut writer, rel_path, &section_separator)?;
+                writeln!(
+                    writer,
+                    "{}## {}",
+                    section_separator,
+                    crate::header::escape(&header_path)
+                )?;
+                writeln!(writer, "{} {} -->", SAME_AS_PREFIX, original)?;
+                if unmapped {
+                    writeln!(writer, "{}", UNMAPPED_MARKER)?;
+                }
+                if let Some(checksums) = checksums.as_mut() {
+                    if let Some(hash) = checksums.get(original).cloned() {
+                        checksums.insert(header_path.clone(), hash);
+                    }
+                }
+                bundled_files.push(rel_path.clone());
+                continue;
+            }
+            seen_contents.insert(file_content.clone(), header_path.clone());
+        }
+
+        // A file handler replaces the content with its encoded form
+        let mut handler_name = None;
+        let encoded_language;
+        let handler = handlers.for_path(rel_path).filter(|_| !excerpt);
+        let (lang_hint, file_content) = match handler {
+            Some((name, handler)) => match handler.encode(&rel_path_str, &file_content) {
+                Ok(encoded) => {
+                    handler_name = Some(name);
+                    encoded_language = encoded.language;
+                    (encoded_language.as_str(), encoded.content)
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: Handler '{}' failed on '{}': {:#}. Bundling it unchanged.",
+                        name, header_path, e
+                    );
+                    (lang_hint, file_content)
+                }
+            },
+            None => (lang_hint, file_content),
+        };
+        // What restore reads back from the section; excerpts can't be checked
+        if let Some(checksums) = checksums.as_mut().filter(|_| !excerpt) {
+            checksums.insert(
+                header_path.clone(),
+                crate::stub::sha256_hex(file_content.as_bytes()),
+            );
+        }
+
+        // Relative links in Markdown documents point at the sections of their targets
+        let mut link_targets = Default::default();
+        let file_content = match &anchors {
+            Some(anchors) if lang_hint == "markdown" => {
+                let (rewritten, originals) =
+                    crate::links::rewrite_links(&file_content, &header_path, anchors);
+                link_targets = originals;
+                rewritten
+            }
+            _ => file_content,
+        };
+
+        // Long lines are wrapped or cut in the content as written
+        let mut wrap_note = None;
+        let file_content = match long_line_policy {
+            Some(LongLinePolicy::Wrap) if long_lines.find(&file_content).is_some() => {
+                let (wrapped, note) = long_lines.wrap(&file_content);
+                wrap_note = Some(note);
+                wrapped
+            }
+            Some(LongLinePolicy::Truncate) => long_lines.truncate(&file_content),
+            _ => file_content,
+        };
+
+        // Write file block to Markdown
+        drop(transform);
+        let _write = crate::timings::span("write");
+        groups.start(&mut writer, rel_path, &section_separator)?;
+        writer.write_all(section_separator.as_bytes())?;
+        let fence = fence_for(&file_content);
+        let parts = match split_size {
+            Some(ByteSize(max)) if file_content.len() as u64 > max => {
+                split_lines(&file_content, max as usize)
+            }
+            _ => vec![&*file_content],
+        };
+        if let (Some(metrics), Some(counter)) = (metrics.as_mut(), token_counter.as_ref()) {
+            metrics.add(&header_path, &file_content, counter);
+        }
+        let stats = token_counter
+            .as_ref()
+            .filter(|_| file_stats)
+            .map(|counter| {
+                format!(
+                    "{} lines={} bytes={} tokens={} -->",
+                    STATS_PREFIX,
+                    file_content.lines().count(),
+                    file_content.len(),
+                    counter.count(&file_content)
+                )
+            });
+        let (last_part, earlier_parts) = parts.split_last().expect("at least one part");
+        if !earlier_parts.is_empty() {
+            status!("    (split into {} parts)", parts.len());
+        }
+        if let Some(stats) = stats
+            .as_ref()
+            .filter(|_| args.header_style != HeaderStyle::Heading)
+        {
+            // Without a heading, the stats go right above the fence
+            writeln!(writer, "{}", stats)?;
+        }
+        match args.header_style {
+            HeaderStyle::Heading => {
+                writeln!(writer, "## {}", crate::header::escape(&header_path))?;
+                if let Some(stats) = &stats {
+                    writeln!(writer, "{}", stats)?;
+                }
+                for (index, part) in earlier_parts.iter().enumerate() {
+                    writeln!(writer, "{} {}/{} -->", PART_PREFIX, index + 1, parts.len())?;
+                    writeln!(writer, "{}{}", fence, lang_hint)?;
+                    writer.write_all(part.as_bytes())?;
+                    writeln!(writer, "{}\n", fence)?;
+                }
+                if !earlier_parts.is_empty() {
+                    writeln!(
+                        writer,
+                        "{} {}/{} -->",
+                        PART_PREFIX,
+                        parts.len(),
+                        parts.len()
+                    )?;
+                }
+                writeln!(writer, "{}{}", fence, lang_hint)?;
+            }
+            HeaderStyle::Title if lang_hint.is_empty() => {
+                writeln!(writer, "{}text title=\"{}\"", fence, header_path)?
+            }
+            HeaderStyle::Title => {
+                writeln!(writer, "{}{} title=\"{}\"", fence, lang_hint, header_path)?
+            }
+            HeaderStyle::Pandoc if lang_hint.is_empty() => {
+                writeln!(writer, "{}{{filename=\"{}\"}}", fence, header_path)?
+            }
+            HeaderStyle::Pandoc => writeln!(
+                writer,
+                "{}{{.{} filename=\"{}\"}}",
+                fence, lang_hint, header_path
+            )?,
+        }
+        writer.write_all(last_part.as_bytes())?;
+        let missing_eol = !last_part.ends_with('\n');
+        if missing_eol {
+            // Ensure code block ends with newline
+            writeln!(writer)?;
+        }
+        writeln!(writer, "{}", fence)?;
+        if missing_eol {
+            writeln!(writer, "{}", NO_EOL_MARKER)?;
+        }
+        if let Some(note) = &wrap_note {
+            writeln!(writer, "{}", note)?;
+        }
+        if cut_lines {
+            writeln!(
+                writer,
+                "{} long lines cut at {} characters -->",
+                PREVIEW_PREFIX, long_lines.max
+            )?;
+        }
+        if let Some(total) = minified_chars {
+            writeln!(
+                writer,
+                "{} first {} of {} characters of a minified file -->",
+                PREVIEW_PREFIX,
+                crate::minified::PREVIEW_CHARS,
+                total
+            )?;
+        }
+        if let Some((rows, total)) = preview_total {
+            writeln!(
+                writer,
+                "{} first {} of {} rows -->",
+                PREVIEW_PREFIX, rows, total
+            )?;
+        }
+        if let Some(command) = summarized_by {
+            writeln!(
+                writer,
+                "{} summary by `{}`, not the file itself -->",
+                PREVIEW_PREFIX, command
+            )?;
+        }
+        if let Some(name) = handler_name {
+            writeln!(writer, "{} {} -->", HANDLER_PREFIX, name)?;
+        }
+        if !link_targets.is_empty() {
+            writeln!(
+                writer,
+                "{} {} -->",
+                LINKS_PREFIX,
+                serde_json::to_string(&link_targets)?
+            )?;
+        }
+        if unmapped {
+            writeln!(writer, "{}", UNMAPPED_MARKER)?;
+        }
+        bundled_files.push(rel_path.clone());
+    }
+
+    if !limited.is_empty() || !unchanged.is_empty() || !minified_skipped.is_empty() {
+        writer.write_all(section_separator.as_bytes())?;
+        for l in limited {
+            writeln!(writer, "{}", l.note())?;
+        }
+        if !minified_skipped.is_empty() {
+            writeln!(writer, "{}", crate::minified::note(&minified_skipped))?;
+        }
+        if let Some(baseline) = args.baseline.as_deref().filter(|_| !unchanged.is_empty()) {
+            writeln!(
+                writer,
+                "{}",
+                crate::baseline::note(Path::new(baseline), unchanged)
+            )?;
+        }
+    }
+
+    if let Some(checksums) = &checksums {
+        write_checksums(&mut writer, &section_separator, checksums)?;
+    }
+
+    if let Some(epilogue) = epilogue.as_ref().filter(|_| appending.is_none()) {
+        if !epilogue.starts_with('\n') {
+            // Ensure newline before epilogue
+            writeln!(writer)?;
+        }
+        writer.write_all(epilogue.as_bytes())?;
+        if !epilogue.ends_with('\n') {
+            // Ensure newline after epilogue
+            writeln!(writer)?;
+        }
+    }
+
+    let write = crate::timings::span("write");
+    writer.flush()?; // Ensure buffer is written
+    drop(write);
+    if args.check {
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.rewind()?;
+        let mut regenerated = String::new();
+        file.read_to_string(&mut regenerated)?;
+        check_fresh(absolute_output_path, &regenerated)?;
+        return Ok(BundleSummary {
+            output: absolute_output_path.clone(),
+            files: bundled_files,
+        });
+    }
+    if let Some(existing) = &appending {
+        status!(
+            "Appended {} section(s) to a bundle of {}.",
+            bundled_files.len(),
+            existing.len()
+        );
+    }
+    status!(
+        "{}",
+        tr!(
+            "bundle.done",
+            absolute_output_path.display(),
+            matched_files.len()
+        )
+    );
+    if args.show_excluded {
+        crate::excluded::report_excluded(&config, &args, &selection);
+    }
+    if let Some(max_chars) = args.copy_chunks {
+        let text = fs::read_to_string(absolute_output_path).with_context(|| {
+            format!("Failed to read bundle: {}", absolute_output_path.display())
+        })?;
+        crate::clipboard::copy_in_chunks(&text, max_chars)?;
+    }
+    // Lets restore tell the files of this project from paths it never bundled
+    let known: Vec<PathBuf> = bundled_files.iter().chain(unchanged).cloned().collect();
+    if let Err(e) = State::record_bundled(working_dir, &known) {
+        eprintln!("Warning: Could not record the bundled paths: {:#}", e);
+    }
+    if let (Some(mut metrics), Some(counter)) = (metrics, token_counter) {
+        metrics.tokenizer = counter.name();
+        if let Err(e) = crate::stats::record(working_dir, metrics, absolute_output_path) {
+            eprintln!("Warning: Could not record the bundle metrics: {:#}", e);
+        }
+    }
+
+    Ok(BundleSummary {
+        output: absolute_output_path.clone(),
+        files: bundled_files,
+    })
+}
+
+/// Front matter keys that change from run to run without the files changing.
+const VOLATILE_KEYS: [&str; 4] = ["generator:", "created:", "root:", "scan_ms:"];
+
+/// `bundle --check`: compares the bundle just made with the one at `path`, apart from
+/// the volatile front matter lines and line endings, and lists the sections that differ.
+fn check_fresh(path: &Path, regenerated: &str) -> Result<()> {
+    let committed = match fs::read_to_string(path) {
+        Ok(committed) => committed,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(SheafyError::BundleNotFound {
+                path: path.to_path_buf(),
+            }
+            .into())
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read bundle: {}", path.display()))
+        }
+    };
+    if stable_lines(&committed) == stable_lines(regenerated) {
+        status!("{} is up to date.", path.display());
+        return Ok(());
+    }
+    let sections = |text: &str| -> Option<BTreeMap<String, String>> {
+        BundleReader::new(text.as_bytes())
+            .ok()?
+            .map(|section| section.ok().map(|s| (s.path, s.content)))
+            .collect()
+    };
+    let fresh = sections(regenerated).unwrap_or_default();
+    let Some(old) = sections(&committed) else {
+        status!("  {} does not parse as a bundle", path.display());
+        return Err(SheafyError::StaleBundle {
+            path: path.to_path_buf(),
+        }
+        .into());
+    };
+    let mut differing = 0;
+    for (section, content) in &fresh {
+        let status = match old.get(section) {
+            None => "added",
+            Some(old_content) if old_content != content => "changed",
+            Some(_) => continue,
+        };
+        status!("  {:<7} {}", status, section);
+        differing += 1;
+    }
+    for section in old.keys().filter(|section| !fresh.contains_key(*section)) {
+        status!("  {:<7} {}", "removed", section);
+        differing += 1;
+    }
+    if differing == 0 {
+        status!("  The sections match; the front matter, prologue, epilogue or notes differ.");
+    }
+    Err(SheafyError::StaleBundle {
+        path: path.to_path_buf(),
+    }
+    .into())
+}
+
+/// The lines of a bundle without the volatile front matter lines.
+fn stable_lines(bundle: &str) -> Vec<&str> {
+    let mut in_front_matter = false;
+    bundle
+        .lines()
+        .enumerate()
+        .filter(|(index, line)| {
+            if *line == "---" && (*index == 0 || in_front_matter) {
+                in_front_matter = *index == 0;
+                return true;
+            }
+            !(in_front_matter && VOLATILE_KEYS.iter().any(|key| line.starts_with(key)))
+        })
+        .map(|(_, line)| line)
+        .collect()
+}
+
+/// Opens the bundle at `path` for `--append`, creating it if need be, and locks it until
+/// the file is closed so that concurrent runs append one after another. Returns the
+/// header paths of the sections it already holds, or None if it is empty.
+fn open_for_append(path: &Path) -> Result<(File, Option<HashSet<String>>)> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open output file: {}", path.display()))?;
+    file.lock()
+        .with_context(|| format!("Failed to lock output file: {}", path.display()))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .with_context(|| format!("Failed to read bundle: {}", path.display()))?;
+    if content.is_empty() {
+        return Ok((file, None));
+    }
+    let bundle = BundleReader::new(content.as_bytes())
+        .with_context(|| format!("Failed to parse bundle: {}", path.display()))?;
+    if bundle
+        .markers()
+        .iter()
+        .any(|m| m == PATCH_MARKER || m == READONLY_MARKER)
+    {
+        return Err(SheafyError::NotWholeFiles {
+            path: path.to_path_buf(),
+        })
+        .context("Only bundles of whole files can be appended to");
+    }
+    let existing = bundle
+        .map(|section| section.map(|s| s.path))
+        .collect::<Result<HashSet<_>>>()
+        .with_context(|| format!("Failed to parse bundle: {}", path.display()))?;
+    Ok((file, Some(existing)))
+}
+
+/// Writes the `## sheafy:checksums` section: one `<sha256>  <path>` line per file, as
+/// `sha256sum` prints them.
+fn write_checksums(
+    writer: &mut impl Write,
+    section_separator: &str,
+    checksums: &BTreeMap<String, String>,
+) -> Result<()> {
+    let lines: String = checksums
+        .iter()
+        .map(|(path, hash)| format!("{}  {}\n", hash, path))
+        .collect();
+    let fence = fence_for(&lines);
+    writeln!(writer, "{}## {}", section_separator, CHECKSUMS_HEADING)?;
+    writeln!(writer, "{}text", fence)?;
+    writer.write_all(lines.as_bytes())?;
+    writeln!(writer, "{}", fence)?;
+    Ok(())
+}
+
+/// Walks the working directory and applies every selection rule (ignore rules,
+/// self-exclusion, focus and change filters) without writing anything.
+pub fn select_files(config: &Config, args: &BundleArgs) -> Result<Selection> {
+    select_files_sharing(config, args, &mut SharedWalks::default())
+}
+
+/// [`select_files`], reusing the walks in `walks` and adding its own.
+pub fn select_files_sharing(
+    config: &Config,
+    args: &BundleArgs,
+    walks: &mut SharedWalks,
+) -> Result<Selection> {
+    let working_dir = config
+        .get_working_dir()
+        .context("Failed to get working directory for bundling")?;
+    let absolute_output_path = bundle_path(config, args.output.as_deref(), &working_dir)?;
+    // Profiles' bundles are never bundled, whichever profile runs
+    let mut other_outputs = Vec::new();
+    if let Some(session) = &args.session {
+        other_outputs.push(absolute_output(session, &working_dir)?);
+    }
+    for (name, profile) in &config.sheafy.profiles {
+        let output = crate::profiles::output(config, name, profile);
+        // A profile whose placeholders can't be expanded here has no output to skip
+        let Ok(output) = absolute_output(&output, &working_dir) else {
+            continue;
+        };
+        if output != absolute_output_path && !other_outputs.contains(&output) {
+            other_outputs.push(output);
         }
-    })?;
+    }
 
-    println!("Output file will be: {}", absolute_output_path.display());
+    status!("{}", tr!("bundle.output", absolute_output_path.display()));
+
+    if let Some(list) = &args.files_from {
+        let mut files = read_file_list(list, &working_dir, &absolute_output_path)?;
+        status!("Bundling the {} file(s) listed in {}.", files.len(), list);
+        let unchanged = match &args.baseline {
+            Some(baseline) => crate::baseline::drop_unchanged(
+                config,
+                &working_dir,
+                &working_dir.join(baseline),
+                &mut files,
+            )?,
+            None => Vec::new(),
+        };
+        return Ok(Selection {
+            scopes: Scopes::from_config(config, &working_dir)?,
+            working_dir,
+            output_path: absolute_output_path,
+            files,
+            use_gitignore: false,
+            limited: Vec::new(),
+            unchanged,
+            other_outputs,
+        });
+    }
 
     let config_git_setting = config.sheafy.use_gitignore.unwrap_or(true);
-    let effective_use_gitignore = match (cli_use_git, cli_no_git) {
+    let effective_use_gitignore = match (args.use_gitignore, args.no_gitignore) {
         (true, true) => bail!("Cannot specify both --use-gitignore and --no-gitignore"),
         (true, false) => true,
         (false, true) => false,
         (false, false) => config_git_setting,
     };
 
-    if effective_use_gitignore {
-        println!("Respecting .gitignore rules.");
+    let parent_gitignore = config.sheafy.parent_gitignore.unwrap_or(true);
+    if effective_use_gitignore && parent_gitignore {
+        status!("Respecting .gitignore rules (including those of parent directories).");
+    } else if effective_use_gitignore {
+        status!("Respecting .gitignore rules below the working directory.");
     } else {
-        println!("Ignoring .gitignore rules.");
+        status!("Ignoring .gitignore rules.");
     }
     // --- End Custom Ignore Pattern Handling ---
 
-    let mut matched_files: Vec<PathBuf> = Vec::new();
-    // Ensure config path is absolute for comparison
-    let config_path_abs = working_dir
-        .join(crate::config::CONFIG_FILENAME)
-        .canonicalize()
-        .ok();
-    let executable_path_abs = std::env::current_exe().ok();
+    let entry_filter = EntryFilter {
+        working_dir: &working_dir,
+        config_paths_abs: config_paths(config, &working_dir),
+        output_path_abs: &absolute_output_path,
+        other_outputs: &other_outputs,
+        executable_path_abs: std::env::current_exe()
+            .ok()
+            .map(|exe| crate::paths::absolute(&exe, &working_dir)),
+        dirs: crate::warm::is_enabled().then(Default::default),
+    };
+    // The daemon reuses the previous walk while the config and the tree are unchanged
+    let max_depth = args.max_depth.or(config.sheafy.max_depth);
+    let mut outputs: Vec<&PathBuf> = other_outputs
+        .iter()
+        .chain([&absolute_output_path])
+        .collect();
+    outputs.sort();
+    let walk_key = format!(
+        "{:?}|{}|{:?}|{:?}|{:?}|{:?}",
+        config.sheafy,
+        effective_use_gitignore,
+        max_depth,
+        outputs,
+        entry_filter.config_paths_abs,
+        crate::warm::outside_ignores(&working_dir)
+    );
+
+    // Files directly in the working directory are at depth 1
+    match max_depth {
+        Some(0) => bail!("max_depth must be at least 1 (1 = only top-level files)"),
+        Some(depth) => status!("Descending at most {} level(s).", depth),
+        None => {}
+    }
+
+    let presets = crate::presets::detect(config, &working_dir);
+    if !presets.is_empty() {
+        let names: Vec<&str> = presets.iter().map(|preset| preset.name).collect();
+        status!(
+            "Detected {} project: applying built-in ignore patterns (disable with `presets = false`).",
+            names.join(" + ")
+        );
+    }
+
+    // Custom ignore patterns, anchored at the working directory
+    let ignore = crate::presets::combined_ignore_patterns(config, &presets)
+        .map(|patterns| Arc::new(crate::presets::ignore_matcher(&working_dir, &patterns)));
+    let walker = Walker {
+        working_dir: &working_dir,
+        max_depth,
+        submodules: config.sheafy.submodules.unwrap_or_default(),
+        ignore: ignore.clone(),
+    };
+    let mut builder = walker.builder(
+        effective_use_gitignore,
+        effective_use_gitignore && parent_gitignore,
+    );
+
+    let jobs = match args.jobs {
+        Some(0) | None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        Some(jobs) => jobs,
+    };
+    status!(
+        "Starting file scan in {} ({} thread(s))...",
+        working_dir.display(),
+        jobs
+    );
+
+    let cached = match walks.0.get(&walk_key) {
+        Some(files) => {
+            status!("Reusing the file list of an earlier scan in this run.");
+            Some(files.clone())
+        }
+        None => {
+            let cached = crate::warm::cached_walk(&walk_key);
+            if cached.is_some() {
+                status!("Nothing changed since the last scan; reusing its file list.");
+            }
+            cached
+        }
+    };
+    // One file past the limit is enough to know the walk went too far
+    let max_files = match args.max_files.or(config.sheafy.max_files) {
+        // No limit, with room for the one past it
+        Some(0) => usize::MAX - 1,
+        Some(max) => max,
+        None => DEFAULT_MAX_FILES,
+    };
+    let walked = cached.is_none();
+    let mut matched_files: Vec<PathBuf> = if let Some(files) = cached {
+        files
+    } else if jobs > 1 {
+        let found = Mutex::new(Vec::new());
+        builder.threads(jobs).build_parallel().run(|| {
+            Box::new(|entry_result| {
+                if let Some(relative_path) = entry_filter.accept(entry_result) {
+                    let mut found = found.lock().unwrap();
+                    found.push(relative_path);
+                    if found.len() > max_files {
+                        return WalkState::Quit;
+                    }
+                }
+                WalkState::Continue
+            })
+        });
+        found.into_inner().unwrap()
+    } else {
+        // Single-threaded walk: deterministic visiting order, easier to debug
+        builder
+            .build()
+            .filter_map(|entry_result| entry_filter.accept(entry_result))
+            .take(max_files + 1)
+            .collect()
+    };
+    // A `!pattern` in ignore_patterns brings back files that .gitignore leaves out
+    if let Some(ignore) = ignore
+        .as_ref()
+        .filter(|ignore| walked && effective_use_gitignore && ignore.num_whitelists() > 0)
+    {
+        let found: HashSet<PathBuf> = matched_files.iter().cloned().collect();
+        let mut builder = walker.builder(false, false);
+        builder.hidden(true);
+        let unignored: Vec<PathBuf> = builder
+            .build()
+            .filter_map(|entry_result| entry_filter.accept(entry_result))
+            .filter(|rel_path| {
+                !found.contains(rel_path)
+                    && ignore
+                        .matched_path_or_any_parents(rel_path, false)
+                        .is_whitelist()
+            })
+            .collect();
+        matched_files.extend(unignored);
+    }
+    if matched_files.len() > max_files {
+        report_biggest_dirs(&matched_files);
+        return Err(SheafyError::TooManyFiles { max: max_files }.into());
+    }
+    if let Some(dirs) = entry_filter.dirs {
+        crate::warm::store_walk(walk_key.clone(), dirs.into_inner().unwrap(), &matched_files);
+    }
+    walks.0.insert(walk_key, matched_files.clone());
+
+    let mut scopes = Scopes::from_config(config, &working_dir)?;
+    scopes.apply(&working_dir, &mut matched_files)?;
+    if let Some(patterns) = crate::profiles::Patterns::from_args(args, &working_dir)? {
+        matched_files.retain(|rel_path| patterns.permits(rel_path));
+    }
+    // Old, renamed and archived bundles are recognized by their marker, whatever their name
+    if !args.include_bundles {
+        matched_files.retain(|rel_path| {
+            let bundle = crate::clean::is_bundle(&working_dir.join(rel_path));
+            if bundle {
+                status!(
+                    "  Skipping sheafy bundle: {} (--include-bundles bundles it)",
+                    rel_path.display()
+                );
+            }
+            !bundle
+        });
+    }
+    if let Some(paths) = &config.sheafy.always_include {
+        add_always_included(
+            paths,
+            &working_dir,
+            &absolute_output_path,
+            &mut matched_files,
+        );
+    }
+    let mut selection = Selection {
+        working_dir,
+        output_path: absolute_output_path,
+        files: Vec::new(),
+        use_gitignore: effective_use_gitignore,
+        limited: Vec::new(),
+        unchanged: Vec::new(),
+        scopes,
+        other_outputs,
+    };
+
+    if matched_files.is_empty() {
+        status!(
+            "No files found matching the ignore rules (including .gitignore and custom patterns)."
+        );
+        // Attempt to create an empty output file anyway? Or just exit? Exiting seems fine.
+        return Ok(selection);
+    }
+
+    matched_files.sort(); // Keep sorting for consistent output
+
+    if let Some(package) = &args.package {
+        crate::cargo::select_package(&selection.working_dir, package, &mut matched_files)?;
+    }
+
+    if let Some(focus) = &args.focus {
+        status!(
+            "Resolving references from focus file {} (depth {})...",
+            focus,
+            args.depth
+        );
+        let selected = crate::deps::collect_focus_set(
+            &selection.working_dir,
+            Path::new(focus),
+            args.depth,
+            &matched_files,
+        )?;
+        matched_files = selected.into_iter().collect();
+    }
+
+    if let Some(since) = &args.since {
+        let changed: HashSet<PathBuf> = crate::git::changed_files(&selection.working_dir, since)?
+            .into_iter()
+            .collect();
+        matched_files.retain(|p| changed.contains(p));
+        status!(
+            "Restricting bundle to {} file(s) changed since {}.",
+            matched_files.len(),
+            since
+        );
+        if matched_files.is_empty() {
+            status!("No files changed since {}.", since);
+        }
+    }
 
-    let mut builder = WalkBuilder::new(&working_dir);
-    builder.standard_filters(effective_use_gitignore);
+    if let Some(baseline) = &args.baseline {
+        selection.unchanged = crate::baseline::drop_unchanged(
+            config,
+            &selection.working_dir,
+            &selection.working_dir.join(baseline),
+            &mut matched_files,
+        )?;
+    }
 
-    // Apply custom ignore patterns
-    let tmp_ignore_file = tempfile::NamedTempFile::new().unwrap();
-    if let Some(patterns) = &config.sheafy.ignore_patterns {
-        if !patterns.trim().is_empty() {
-            tmp_ignore_file
-                .as_file()
-                .write_all(patterns.as_bytes())
-                .unwrap();
-            builder.add_custom_ignore_filename(tmp_ignore_file.path().to_str().unwrap());
+    match args
+        .auto_order
+        .or(config.sheafy.auto_order)
+        .unwrap_or_default()
+    {
+        AutoOrder::Alphabetical => {}
+        AutoOrder::Smart => crate::order::smart_order(&selection.working_dir, &mut matched_files),
+        AutoOrder::RecentFirst => {
+            crate::order::recent_first(&selection.working_dir, &mut matched_files)
         }
     }
+    if selection.scopes.has_priorities() {
+        // Files are ranked by the first pattern they match; the rest keep their order
+        let unranked = usize::MAX;
+        matched_files
+            .sort_by_cached_key(|rel_path| selection.scopes.priority(rel_path).unwrap_or(unranked));
+    }
+    if let Some(tags) = Tags::from_config(config, &selection.working_dir)? {
+        tags.group(&mut matched_files);
+    }
+    if !config.sheafy.limits.is_empty() {
+        selection.limited = crate::limits::apply_limits(
+            &selection.working_dir,
+            &config.sheafy.limits,
+            &mut matched_files,
+        )?;
+    }
 
-    println!("Starting file scan in {}...", working_dir.display());
+    selection.files = matched_files;
+    Ok(selection)
+}
 
-    for entry_result in builder.build() {
-        println!("ENTRY: {:?}",entry_result);
+/// Whether bundling writes a section for `rel_path`, told before the bundle is written:
+/// stubs get one, while unreadable and binary files and those the minified and long line
+/// policies drop don't. Files with a summarizer are taken to get one without running it.
+fn writes_section(
+    working_dir: &Path,
+    rel_path: &Path,
+    stubs: Option<&Stubs>,
+    summarizers: &Summarizers,
+    minified_policy: MinifiedPolicy,
+    long_lines: &LongLines,
+) -> bool {
+    if stubs.is_some_and(|stubs| stubs.matches(rel_path)) {
+        return true;
+    }
+    let Ok(bytes) = fs::read(working_dir.join(rel_path)) else {
+        return false;
+    };
+    if crate::sniff::is_binary(&bytes) {
+        return false;
+    }
+    let Ok(content) = String::from_utf8(bytes) else {
+        return false;
+    };
+    if summarizers.for_path(rel_path).is_some() {
+        return true;
+    }
+    let content = match crate::minified::detect(&content).map(|_| minified_policy) {
+        Some(MinifiedPolicy::Skip) => return false,
+        Some(MinifiedPolicy::Truncate) => crate::minified::preview(&content),
+        Some(MinifiedPolicy::Include) | None => content,
+    };
+    long_lines.find(&content).is_none() || long_lines.policy(rel_path) != LongLinePolicy::Skip
+}
+
+/// Adds the `always_include` files that the walk or directory settings left out.
+/// Missing files and paths outside the working directory are skipped with a warning.
+fn add_always_included(
+    paths: &[String],
+    working_dir: &Path,
+    output_path: &Path,
+    files: &mut Vec<PathBuf>,
+) {
+    let mut added = 0;
+    for path in paths {
+        let rel_path: PathBuf = Path::new(path.trim())
+            .components()
+            .filter(|c| !matches!(c, std::path::Component::CurDir))
+            .collect();
+        if rel_path.is_absolute()
+            || rel_path
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            eprintln!(
+                "Warning: always_include path '{}' is outside the working directory; skipping it.",
+                path
+            );
+            continue;
+        }
+        let abs_path = working_dir.join(&rel_path);
+        if !abs_path.is_file() {
+            eprintln!(
+                "Warning: always_include file '{}' does not exist; skipping it.",
+                path
+            );
+            continue;
+        }
+        if abs_path == output_path || files.contains(&rel_path) {
+            continue;
+        }
+        files.push(rel_path);
+        added += 1;
+    }
+    if added > 0 {
+        status!("Including {} file(s) from always_include.", added);
+    }
+}
+
+/// Reads a `--files-from` list (`-` for stdin): one path per line, relative to the
+/// working directory or absolute inside it. Order is kept; duplicates and the output
+/// file are dropped.
+fn read_file_list(list: &str, working_dir: &Path, output_path: &Path) -> Result<Vec<PathBuf>> {
+    let text = if list == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read file list from stdin")?
+    } else {
+        fs::read_to_string(working_dir.join(list))
+            .with_context(|| format!("Failed to read file list: {}", list))?
+    };
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let path = Path::new(line);
+        let rel_path = if path.is_absolute() {
+            path.strip_prefix(working_dir)
+                .with_context(|| {
+                    format!(
+                        "Listed file '{}' is outside {}",
+                        line,
+                        working_dir.display()
+                    )
+                })?
+                .to_path_buf()
+        } else {
+            path.components()
+                .filter(|c| !matches!(c, std::path::Component::CurDir))
+                .collect()
+        };
+        let abs_path = working_dir.join(&rel_path);
+        if !abs_path.is_file() {
+            bail!("Listed file '{}' does not exist or is not a file", line);
+        }
+        if abs_path == output_path {
+            eprintln!("Warning: Skipping '{}', the bundle being written.", line);
+            continue;
+        }
+        if seen.insert(rel_path.clone()) {
+            files.push(rel_path);
+        }
+    }
+    Ok(files)
+}
+
+/// Builds the walks of a bundle's selection.
+struct Walker<'a> {
+    working_dir: &'a Path,
+    max_depth: Option<usize>,
+    submodules: SubmodulePolicy,
+    /// `ignore_patterns` and the presets' patterns
+    ignore: Option<Arc<Gitignore>>,
+}
+
+impl Walker<'_> {
+    /// A walk of the working directory with sheafy's filters: its state directory, the
+    /// `submodules` policy and the ignore patterns, and `.gitignore` rules if `gitignore`.
+    fn builder(&self, gitignore: bool, parents: bool) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(self.working_dir);
+        builder
+            .standard_filters(gitignore)
+            .parents(parents)
+            .max_depth(self.max_depth);
+        let submodules = self.submodules;
+        let ignore = self.ignore.clone();
+        let root = self.working_dir.to_path_buf();
+        builder.filter_entry(move |entry| {
+            // sheafy's own state is never part of the project
+            if entry.depth() == 1 && entry.file_name() == crate::state::STATE_DIR {
+                return false;
+            }
+            if entry.depth() == 0 {
+                return true;
+            }
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            if ignore
+                .as_ref()
+                .is_some_and(|ignore| ignore.matched(entry.path(), is_dir).is_ignore())
+            {
+                return false;
+            }
+            if !is_dir {
+                return true;
+            }
+            match submodules {
+                SubmodulePolicy::Include => true,
+                SubmodulePolicy::Skip => !is_nested_repo(entry.path()),
+                // Keep the files at the top of a nested repository, nothing below
+                SubmodulePolicy::Shallow => entry
+                    .path()
+                    .parent()
+                    .is_none_or(|parent| parent == root || !is_nested_repo(parent)),
+            }
+        });
+        builder
+    }
+}
+
+/// The config files a bundle leaves out: the one the settings were read from and the
+/// working directory's own `sheafy.toml`. None with `include_config = true`.
+pub(crate) fn config_paths(config: &Config, working_dir: &Path) -> Vec<PathBuf> {
+    if config.sheafy.include_config.unwrap_or(false) {
+        return Vec::new();
+    }
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let mut paths: Vec<PathBuf> = config
+        .path
+        .iter()
+        .map(|path| crate::paths::absolute(path, &current_dir))
+        .collect();
+    let own = crate::paths::absolute(Path::new(crate::config::CONFIG_FILENAME), working_dir);
+    if !paths.iter().any(|path| crate::paths::same(path, &own)) {
+        paths.push(own);
+    }
+    paths
+}
+
+/// A directory with its own `.git` (a directory for nested clones, a file for submodules).
+fn is_nested_repo(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+/// Whether the `submodules` policy leaves out `rel_path`, a file below `working_dir`.
+pub(crate) fn in_skipped_repo(
+    policy: SubmodulePolicy,
+    working_dir: &Path,
+    rel_path: &Path,
+) -> bool {
+    // Shallow keeps the files directly in a nested repository
+    let skip = match policy {
+        SubmodulePolicy::Include => return false,
+        SubmodulePolicy::Skip => 1,
+        SubmodulePolicy::Shallow => 2,
+    };
+    rel_path
+        .ancestors()
+        .skip(skip)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .any(|dir| is_nested_repo(&working_dir.join(dir)))
+}
+
+/// Per-entry checks shared by the sequential and parallel walkers.
+struct EntryFilter<'a> {
+    working_dir: &'a Path,
+    config_paths_abs: Vec<PathBuf>,
+    output_path_abs: &'a Path,
+    other_outputs: &'a [PathBuf],
+    executable_path_abs: Option<PathBuf>,
+    /// Directories entered, recorded for the daemon's walk cache
+    dirs: Option<Mutex<Vec<PathBuf>>>,
+}
+
+impl EntryFilter<'_> {
+    /// Returns the path relative to the working directory if the entry should be bundled.
+    fn accept(&self, entry_result: Result<DirEntry, ignore::Error>) -> Option<PathBuf> {
         let entry = match entry_result {
             Ok(entry) => entry,
             Err(e) => {
                 eprintln!("Warning: Skipping path due to error: {}", e);
-                continue;
+                return None;
             }
         };
         let path = entry.path();
 
         // Skip directories
-        if !entry.file_type().map_or(false, |ft| ft.is_file()) {
-            continue;
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            if let Some(dirs) = self
+                .dirs
+                .as_ref()
+                .filter(|_| entry.file_type().is_some_and(|ft| ft.is_dir()))
+            {
+                dirs.lock().unwrap().push(path.to_path_buf());
+            }
+            return None;
         }
 
-        // Attempt to get absolute path for comparison
-        let absolute_path = path.canonicalize().ok();
+        // Spelled like the paths it is compared with
+        let absolute_path = crate::paths::absolute(path, self.working_dir);
+        let is = |other: &Path| crate::paths::same(&absolute_path, other);
 
         // Skip the config file itself
-        if config_path_abs.as_ref().map_or(false, |config_abs| {
-            absolute_path.as_ref() == Some(config_abs)
-        }) {
-            // println!("Skipping config file: {:?}", path); // Debugging
-            continue;
+        if self.config_paths_abs.iter().any(|config| is(config)) {
+            return None;
         }
 
-        // Skip the output file itself
-        if absolute_path.as_ref() == Some(&absolute_output_path) {
-            // println!("Skipping output file: {:?}", path); // Debugging
-            continue;
+        // Skip the output file itself, and those of the profiles and the session
+        if is(self.output_path_abs) || self.other_outputs.iter().any(|output| is(output)) {
+            return None;
         }
 
         // Skip the executable itself
-        if executable_path_abs
-            .as_ref()
-            .map_or(false, |exec_abs| absolute_path.as_ref() == Some(exec_abs))
-        {
-            // println!("Skipping executable file: {:?}", path); // Debugging
-            continue;
+        if self.executable_path_abs.as_deref().is_some_and(is) {
+            return None;
         }
 
-        if let Some(relative_path) = pathdiff::diff_paths(path, &working_dir) {
-            matched_files.push(relative_path);
+        if let Some(relative_path) = pathdiff::diff_paths(path, self.working_dir) {
+            Some(relative_path)
         } else {
             // Fallback, though diff_paths should ideally work for files found by WalkBuilder within working_dir
             eprintln!(
                 "Warning: Could not determine relative path for {:?}. Using absolute path.",
                 path
             );
-            matched_files.push(path.to_path_buf());
+            Some(path.to_path_buf())
         }
     }
+}
 
-    if matched_files.is_empty() {
-        println!(
-            "No files found matching the ignore rules (including .gitignore and custom patterns)."
-        );
-        // Attempt to create an empty output file anyway? Or just exit? Exiting seems fine.
-        return Ok(());
-    }
+/// Limits read throughput to a fixed number of bytes per second.
+struct Throttle {
+    bytes_per_sec: f64,
+    started: Instant,
+    consumed: u64,
+}
 
-    matched_files.sort(); // Keep sorting for consistent output
+impl Throttle {
+    fn new(mb_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec: mb_per_sec * 1024.0 * 1024.0,
+            started: Instant::now(),
+            consumed: 0,
+        }
+    }
 
-    println!(
-        "\nCreating Markdown bundle: {}",
-        absolute_output_path.display()
-    );
-    // Create parent directory if it doesn't exist
-    if let Some(parent_dir) = absolute_output_path.parent() {
-        if !parent_dir.exists() {
-            println!("Creating output directory: {}", parent_dir.display());
-            fs::create_dir_all(parent_dir).with_context(|| {
-                format!(
-                    "Failed to create output directory: {}",
-                    parent_dir.display()
-                )
-            })?;
+    /// Records `bytes` read and sleeps until the average rate is back under the limit.
+    fn consume(&mut self, bytes: usize) {
+        self.consumed += bytes as u64;
+        let expected = Duration::from_secs_f64(self.consumed as f64 / self.bytes_per_sec);
+        let elapsed = self.started.elapsed();
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
         }
     }
+}
 
-    let output_file = File::create(&absolute_output_path).with_context(|| {
-        format!(
-            "Failed to create output file: {}",
-            absolute_output_path.display()
-        )
-    })?;
-    let mut writer = BufWriter::new(output_file);
+/// The bundle a command writes or reads: `name`, else `bundle_name` from the config or
+/// the default, with its placeholders expanded. Restore finds what bundle wrote this way.
+pub fn bundle_path(config: &Config, name: Option<&str>, working_dir: &Path) -> Result<PathBuf> {
+    let name = name
+        .or(config.sheafy.bundle_name.as_deref())
+        .unwrap_or(DEFAULT_BUNDLE_NAME);
+    absolute_output(name, working_dir)
+}
 
-    if let Some(prologue) = config.sheafy.prologue {
-        writer.write_all(prologue.as_bytes())?;
-        if !prologue.ends_with('\n') {
-            // Ensure newline after prologue
-            writeln!(writer)?;
-        }
+/// The absolute path an output filename (with placeholders) is written to, canonical
+/// when it exists already, for comparing with the walked files.
+pub fn absolute_output(output_filename: &str, working_dir: &Path) -> Result<PathBuf> {
+    let output_filename = expand_output_template(output_filename, working_dir)?;
+    Ok(crate::paths::absolute(
+        Path::new(&output_filename),
+        working_dir,
+    ))
+}
+
+/// Expands `{project}`, `{branch}`, `{hash}` and `{date}` in an output path. Git is
+/// only consulted when a git placeholder is used.
+fn expand_output_template(template: &str, working_dir: &Path) -> Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        let value = match &rest[start + 1..start + len] {
+            "project" => working_dir
+                .file_name()
+                .map_or_else(|| "project".to_string(), |n| n.to_string_lossy().into_owned()),
+            // Branch names like `feature/x` would otherwise create subdirectories
+            "branch" => crate::git::current_branch(working_dir)
+                .context("{branch} in the output path needs a git repository")?
+                .unwrap_or_else(|| "detached".to_string())
+                .replace(['/', '\\'], "-"),
+            "hash" => crate::git::short_hash(working_dir)
+                .context("{hash} in the output path needs a git repository with a commit")?,
+            "date" => crate::timestamp::iso_date(crate::timestamp::now_unix()),
+            other => bail!(
+                "Unknown placeholder {{{}}} in output path '{}' (expected {{project}}, {{branch}}, {{hash}} or {{date}})",
+                other,
+                template
+            ),
+        };
+        expanded.push_str(&value);
+        rest = &rest[start + len + 1..];
     }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
-    for rel_path in &matched_files {
-        let header_path = rel_path
+/// Writes the YAML front matter: the generator, and what downstream tools need to budget
+/// for the bundle without parsing it (file count, source bytes, per-language totals).
+/// `scan_time` is `None` for reproducible bundles, which also leave out the creation
+/// time and root.
+fn write_front_matter(
+    writer: &mut impl Write,
+    working_dir: &Path,
+    files: &[PathBuf],
+    scopes: &Scopes,
+    scan_time: Option<Duration>,
+    template_vars: &[String],
+    readonly: bool,
+) -> Result<()> {
+    writeln!(writer, "---")?;
+    writeln!(writer, "generator: sheafy {}", env!("CARGO_PKG_VERSION"))?;
+    if scan_time.is_some() {
+        let now = crate::timestamp::now_unix();
+        writeln!(writer, "created: {}", crate::timestamp::rfc3339(now))?;
+        let root = working_dir
             .to_string_lossy()
-            .replace(std::path::MAIN_SEPARATOR, "/"); // Use consistent / separator in header
-        println!("  Adding: {}", header_path);
-
-        let mut file_content = String::new();
-        // Read from the original absolute path constructed relative to working_dir
-        let full_read_path = working_dir.join(rel_path);
-        match File::open(&full_read_path) {
-            Ok(mut f) => {
-                if let Err(e) = f.read_to_string(&mut file_content) {
-                    eprintln!(
-                        "Warning: Could not read file '{}': {}. Skipping.",
-                        full_read_path.display(),
-                        e
-                    );
-                    continue; // Skip this file
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "Warning: Could not open file '{}': {}. Skipping.",
-                    full_read_path.display(),
-                    e
-                );
-                continue; // Skip this file
-            }
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        writeln!(writer, "root: \"{}\"", root)?;
+    }
+    // Language -> (files, bytes); the sizes are of the sources, before any handler runs
+    let mut languages: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for file in files {
+        let language = scopes
+            .language_hint(file)
+            .unwrap_or_else(|| crate::sniff::language_hint(file, ""));
+        let language = if language.is_empty() {
+            "text"
+        } else {
+            language
+        };
+        let size = fs::metadata(working_dir.join(file)).map_or(0, |m| m.len());
+        let totals = languages.entry(language).or_default();
+        totals.0 += 1;
+        totals.1 += size;
+    }
+    writeln!(writer, "files: {}", files.len())?;
+    writeln!(
+        writer,
+        "source_bytes: {}",
+        languages.values().map(|(_, bytes)| bytes).sum::<u64>()
+    )?;
+    if let Some(scan_time) = scan_time {
+        writeln!(writer, "scan_ms: {}", scan_time.as_millis())?;
+    }
+    if !template_vars.is_empty() {
+        writeln!(
+            writer,
+            "{}: [{}]",
+            crate::vars::FRONT_MATTER_KEY,
+            template_vars.join(", ")
+        )?;
+    }
+    if readonly {
+        writeln!(writer, "readonly: true")?;
+    }
+    if !languages.is_empty() {
+        writeln!(writer, "languages:")?;
+        for (language, (count, bytes)) in &languages {
+            // Hints from the config may hold characters YAML would misread
+            let key = if language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                language.to_string()
+            } else {
+                serde_json::to_string(language)?
+            };
+            writeln!(
+                writer,
+                "  {}: {{ files: {}, bytes: {} }}",
+                key, count, bytes
+            )?;
         }
+    }
+    writeln!(writer, "---")?;
+    Ok(())
+}
 
-        // Determine language hint for ``` block
-        let lang_hint = rel_path
-            .extension()
-            .and_then(|os| os.to_str())
-            .map(crate::restore::get_language_hint) // Use existing helper
-            .unwrap_or("");
+/// The `--prompt`/`--prompt-file` instructions as a block that restore skips.
+fn read_prompt(args: &BundleArgs) -> Result<Option<String>> {
+    let text = match (&args.prompt, &args.prompt_file) {
+        (Some(text), _) => text.clone(),
+        (None, Some(file)) if file == "-" => {
+            std::io::read_to_string(std::io::stdin()).context("Failed to read prompt from stdin")?
+        }
+        (None, Some(file)) => fs::read_to_string(file)
+            .with_context(|| format!("Failed to read prompt file: {}", file))?,
+        (None, None) => return Ok(None),
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        bail!("The task prompt is empty");
+    }
+    Ok(Some(format!(
+        "\n{}\n## Task\n\n{}\n{}\n",
+        PROMPT_MARKER, text, PROMPT_END_MARKER
+    )))
+}
 
-        // Write file block to Markdown
-        writeln!(writer, "\n## {}", header_path)?; // Add a newline before header for better separation
-        writeln!(writer, "```{}", lang_hint)?;
-        writer.write_all(file_content.as_bytes())?;
-        if !file_content.ends_with('\n') {
-            // Ensure code block ends with newline
-            writeln!(writer)?;
+/// Prints the directories (two levels deep at most) holding the most of `files`, with
+/// ignore patterns for them, after a scan stopped at `max_files`.
+fn report_biggest_dirs(files: &[PathBuf]) {
+    let mut by_dir: HashMap<PathBuf, usize> = HashMap::new();
+    for rel_path in files {
+        let mut dir = PathBuf::new();
+        for component in rel_path
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .take(2)
+        {
+            dir.push(component);
+            *by_dir.entry(dir.clone()).or_default() += 1;
+        }
+    }
+    let mut dirs: Vec<_> = by_dir.into_iter().collect();
+    dirs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if dirs.is_empty() {
+        return;
+    }
+    eprintln!(
+        "Directories with the most files (of the first {} found):",
+        files.len()
+    );
+    for (dir, count) in dirs.iter().take(8) {
+        eprintln!("  {:>7}  {}/", count, crate::paths::to_slash(dir));
+    }
+    let top_level: Vec<_> = dirs
+        .iter()
+        .filter(|(dir, _)| dir.components().count() == 1)
+        .take(3)
+        .collect();
+    if !top_level.is_empty() {
+        eprintln!("Suggested ignore_patterns to add to sheafy.toml (or raise max_files):");
+        for (dir, count) in top_level {
+            eprintln!(
+                "  {:<24} # {} file(s)",
+                format!("{}/", crate::paths::to_slash(dir)),
+                count
+            );
         }
-        writeln!(writer, "```")?; // Removed extra newline after ```
     }
+}
 
-    if let Some(epilogue) = config.sheafy.epilogue {
-        if !epilogue.starts_with('\n') {
-            // Ensure newline before epilogue
-            writeln!(writer)?;
+/// Aborts with a list of the largest contributors if the projected bundle size exceeds `max_size`.
+fn check_bundle_size(working_dir: &Path, files: &[PathBuf], max_size: ByteSize) -> Result<()> {
+    // Heading, fences and blank lines add a small fixed overhead per section
+    const SECTION_OVERHEAD: u64 = 16;
+    let mut sizes: Vec<(u64, &PathBuf)> = files
+        .iter()
+        .map(|rel_path| {
+            let len = fs::metadata(working_dir.join(rel_path)).map_or(0, |m| m.len());
+            (
+                len + rel_path.as_os_str().len() as u64 + SECTION_OVERHEAD,
+                rel_path,
+            )
+        })
+        .collect();
+    let projected: u64 = sizes.iter().map(|(len, _)| len).sum();
+    if projected <= max_size.0 {
+        return Ok(());
+    }
+
+    sizes.sort_by_key(|(len, _)| std::cmp::Reverse(*len));
+    eprintln!("Largest files:");
+    for (len, rel_path) in sizes.iter().take(10) {
+        eprintln!(
+            "  {:>10}  {}",
+            ByteSize(*len).to_string(),
+            rel_path.display()
+        );
+    }
+
+    // Suggest the top-level directories and extensions that contribute the most
+    let mut by_pattern: HashMap<String, (u64, usize)> = HashMap::new();
+    for (len, rel_path) in &sizes {
+        let mut components = rel_path.components();
+        let first = components.next();
+        if components.next().is_some() {
+            if let Some(dir) = first {
+                let entry = by_pattern
+                    .entry(format!("{}/", dir.as_os_str().to_string_lossy()))
+                    .or_default();
+                entry.0 += len;
+                entry.1 += 1;
+            }
         }
-        writer.write_all(epilogue.as_bytes())?;
-        if !epilogue.ends_with('\n') {
-            // Ensure newline after epilogue
-            writeln!(writer)?;
+        if let Some(ext) = rel_path.extension() {
+            let entry = by_pattern
+                .entry(format!("*.{}", ext.to_string_lossy()))
+                .or_default();
+            entry.0 += len;
+            entry.1 += 1;
+        }
+    }
+    let mut suggestions: Vec<_> = by_pattern.into_iter().collect();
+    suggestions.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+    if !suggestions.is_empty() {
+        eprintln!("Suggested ignore_patterns to add to sheafy.toml:");
+        for (pattern, (len, count)) in suggestions.iter().take(5) {
+            eprintln!(
+                "  {:<24} # {} across {} file(s)",
+                pattern,
+                ByteSize(*len),
+                count
+            );
         }
     }
 
-    writer.flush()?; // Ensure buffer is written
-    println!(
-        "\nSuccessfully created '{}' with {} file(s).",
-        absolute_output_path.display(),
-        matched_files.len()
-    );
+    Err(SheafyError::BundleTooLarge {
+        size: projected,
+        max: max_size.0,
+    }
+    .into())
+}
 
-    Ok(())
+/// Splits `content` at line ends into parts of at most `max` bytes (a longer line
+/// makes a part of its own). Joined, the parts are `content` again.
+fn split_lines(content: &str, max: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for line in content.split_inclusive('\n') {
+        if end > start && end - start + line.len() > max {
+            parts.push(&content[start..end]);
+            start = end;
+        }
+        end += line.len();
+    }
+    parts.push(&content[start..end]);
+    parts
 }
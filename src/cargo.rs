@@ -0,0 +1,192 @@
+//! Workspace-aware selection for `sheafy bundle --package`: one member crate of a
+//! Cargo workspace, the workspace manifest, and the crates it depends on by path.
+//!
+//! Members are the `Cargo.toml` files among the walked candidates whose directory
+//! matches `[workspace] members` (and not `exclude`), so ignore rules still apply.
+//! Path dependencies are followed transitively, including `workspace = true` entries
+//! that resolve to a path in `[workspace.dependencies]`.
+
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+use toml::{Table, Value};
+
+const MANIFEST: &str = "Cargo.toml";
+
+/// Keeps the files of package `name` and of its path dependencies, plus the workspace
+/// manifest. `files` are relative to `working_dir`, the workspace root.
+pub fn select_package(working_dir: &Path, name: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+    let root = read_manifest(&working_dir.join(MANIFEST))
+        .context("--package needs a Cargo workspace in the working directory")?;
+    let Some(workspace) = root.get("workspace").and_then(Value::as_table) else {
+        bail!("--package needs a Cargo workspace, but Cargo.toml has no [workspace] table");
+    };
+    let members = globs(working_dir, workspace.get("members"))?;
+    let excluded = globs(working_dir, workspace.get("exclude"))?;
+
+    // Package directory -> name, for the root package and every member found
+    let mut packages: BTreeMap<PathBuf, String> = BTreeMap::new();
+    if let Some(root_name) = package_name(&root) {
+        packages.insert(PathBuf::new(), root_name);
+    }
+    for file in files.iter() {
+        let Some(dir) = file.parent().filter(|_| file.ends_with(MANIFEST)) else {
+            continue;
+        };
+        if dir.as_os_str().is_empty()
+            || !members.matched(dir, true).is_ignore()
+            || excluded.matched(dir, true).is_ignore()
+        {
+            continue;
+        }
+        if let Some(member_name) = package_name(&read_manifest(&working_dir.join(file))?) {
+            packages.insert(dir.to_path_buf(), member_name);
+        }
+    }
+    let Some(start) = packages
+        .iter()
+        .find(|(_, package)| package.as_str() == name)
+        .map(|(dir, _)| dir.clone())
+    else {
+        let names: Vec<&str> = packages.values().map(String::as_str).collect();
+        bail!(
+            "No workspace member named '{}' (members: {})",
+            name,
+            names.join(", ")
+        );
+    };
+
+    let workspace_deps = workspace.get("dependencies").and_then(Value::as_table);
+    let mut selected: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(dir) = queue.pop_front() {
+        if !selected.insert(dir.clone()) {
+            continue;
+        }
+        let manifest = if dir.as_os_str().is_empty() {
+            root.clone()
+        } else {
+            read_manifest(&working_dir.join(&dir).join(MANIFEST))?
+        };
+        for (dep, spec) in dependency_specs(&manifest) {
+            let path = match spec.get("path").and_then(Value::as_str) {
+                Some(path) => dir.join(path),
+                None if spec.get("workspace").and_then(Value::as_bool) == Some(true) => {
+                    match workspace_deps
+                        .and_then(|deps| deps.get(dep))
+                        .and_then(|spec| spec.get("path"))
+                        .and_then(Value::as_str)
+                    {
+                        Some(path) => PathBuf::from(path),
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
+            match resolve(&path) {
+                Some(dep_dir) => {
+                    packages
+                        .entry(dep_dir.clone())
+                        .or_insert_with(|| dep.to_string());
+                    queue.push_back(dep_dir);
+                }
+                None => eprintln!(
+                    "Warning: Path dependency '{}' of '{}' is outside the working directory; not bundled.",
+                    dep,
+                    dir.join(MANIFEST).display()
+                ),
+            }
+        }
+    }
+
+    let names: Vec<&str> = selected
+        .iter()
+        .filter_map(|dir| packages.get(dir).map(String::as_str))
+        .collect();
+    status!(
+        "Bundling package '{}' with {} crate(s) in scope: {}",
+        name,
+        selected.len(),
+        names.join(", ")
+    );
+    // A file belongs to the innermost package directory containing it
+    files.retain(|file| {
+        file == Path::new(MANIFEST)
+            || packages
+                .keys()
+                .filter(|dir| file.starts_with(dir))
+                .max_by_key(|dir| dir.components().count())
+                .is_some_and(|owner| selected.contains(owner))
+    });
+    Ok(())
+}
+
+fn read_manifest(path: &Path) -> Result<Table> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    text.parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn package_name(manifest: &Table) -> Option<String> {
+    manifest
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Workspace `members`/`exclude` globs as a matcher for directories relative to the root.
+fn globs(working_dir: &Path, patterns: Option<&Value>) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(working_dir);
+    for pattern in patterns
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+    {
+        builder
+            .add_line(None, &format!("/{}", pattern.trim_matches('/')))
+            .with_context(|| format!("Invalid workspace member pattern: '{}'", pattern))?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Every dependency entry given as a table, from all dependency sections including
+/// `[target.'cfg(..)'.dependencies]`.
+fn dependency_specs(manifest: &Table) -> Vec<(&str, &Table)> {
+    const SECTIONS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let targets = manifest
+        .get("target")
+        .and_then(Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values().filter_map(Value::as_table));
+    std::iter::once(manifest)
+        .chain(targets)
+        .flat_map(|table| SECTIONS.iter().filter_map(|s| table.get(*s)?.as_table()))
+        .flat_map(|deps| deps.iter())
+        .filter_map(|(name, spec)| Some((name.as_str(), spec.as_table()?)))
+        .collect()
+}
+
+/// Resolves `.` and `..` lexically; None if the path leaves the working directory.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => out.push(part),
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,110 @@
+//! `sheafy clean`: remove artifacts sheafy generated in the working directory.
+//!
+//! Bundles are recognised by their leading `<!-- sheafy:vN -->` marker rather than by
+//! name, so hand-written Markdown (and unmarked v1 bundles) is never touched. In
+//! `.sheafy/`, only what sheafy regenerates (the last `ask` reply, a stale daemon
+//! socket) is removed; the bundled paths and the metrics history are kept.
+
+use crate::config::Config;
+use crate::restore::format_version;
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// Directories never searched for bundles.
+const SKIPPED_DIRS: &[&str] = &[".git", ".sheafy", "node_modules", "target"];
+
+pub fn run_clean(config: &Config, dry_run: bool) -> Result<()> {
+    let working_dir = config.get_working_dir()?;
+    let mut artifacts = find_artifacts(&working_dir);
+    artifacts.extend(crate::state::generated_entries(&working_dir));
+    if artifacts.is_empty() {
+        status!("Nothing to clean in {}.", working_dir.display());
+        return Ok(());
+    }
+
+    for path in &artifacts {
+        let display = path.strip_prefix(&working_dir).unwrap_or(path).display();
+        if dry_run {
+            status!("  Would remove: {}", display);
+        } else {
+            status!("  Removing: {}", display);
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            removed.with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    if dry_run {
+        status!(
+            "\n{} file(s) would be removed. Run without --dry-run to delete them.",
+            artifacts.len()
+        );
+    } else {
+        status!("\nRemoved {} file(s).", artifacts.len());
+        // Drop `.sheafy/` once nothing user-authored is left in it
+        let state_dir = crate::state::state_dir(&working_dir);
+        if fs::read_dir(&state_dir).is_ok_and(|mut entries| entries.next().is_none()) {
+            fs::remove_dir(&state_dir)
+                .with_context(|| format!("Failed to remove {}", state_dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Every generated file under `working_dir`, sorted. Ignore rules are not applied
+/// because bundles are commonly gitignored.
+fn find_artifacts(working_dir: &Path) -> Vec<PathBuf> {
+    let mut builder = WalkBuilder::new(working_dir);
+    builder.standard_filters(false).filter_entry(|entry| {
+        !(entry.depth() > 0
+            && entry.file_type().is_some_and(|t| t.is_dir())
+            && SKIPPED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()))
+    });
+    let mut artifacts: Vec<PathBuf> = builder
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+        .filter(|path| is_generated(path))
+        .collect();
+    artifacts.sort();
+    artifacts
+}
+
+/// Bundles and conflict reports both start with the version marker; restore reports
+/// go by their name.
+fn is_generated(path: &Path) -> bool {
+    if path
+        .file_name()
+        .is_some_and(|name| name == crate::restore::REPORT_FILENAME)
+    {
+        return true;
+    }
+    is_bundle(path)
+}
+
+/// Whether `path` is a Markdown file starting with a sheafy version marker (after any
+/// front matter).
+pub fn is_bundle(path: &Path) -> bool {
+    if path.extension().is_none_or(|ext| ext != "md") {
+        return false;
+    }
+    // Only the front matter and leading marker lines are needed
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let head: String = BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .take(32)
+        .collect::<Vec<_>>()
+        .join("\n");
+    format_version(&head) >= 2
+}
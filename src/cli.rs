@@ -1,38 +1,705 @@
-use clap::{ArgAction, Parser, Subcommand};
+use crate::config::ByteSize;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Answer yes to every confirmation question
+    #[arg(short = 'y', long, global = true, action = ArgAction::SetTrue)]
+    pub yes: bool,
+
+    /// Never wait for input: every question takes its default answer (implied when
+    /// stdin is not a terminal; `--yes` still answers yes)
+    #[arg(long, global = true, action = ArgAction::SetTrue)]
+    pub no_input: bool,
+
+    /// Print on stderr how long each phase of the command took (bundle: walk, read,
+    /// transform, write; restore: check, parse, write)
+    #[arg(long, global = true, action = ArgAction::SetTrue)]
+    pub timings: bool,
+
+    /// Write the phases of the command as a profile for https://www.speedscope.app
+    #[arg(long, global = true, value_name = "FILE")]
+    pub profile_out: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Initialize a new sheafy project with default config
-    Init,
+    Init {
+        /// Scan the project and write a tailored config (ignore and priority patterns, language hints)
+        #[arg(long, action = ArgAction::SetTrue)]
+        analyze: bool,
+    },
     /// Bundles project files into a single Markdown file
-    Bundle {
-        // REMOVED: filters argument
-        // /// Comma-separated list of file extensions to include (e.g., rs,py,txt). Overrides config.
-        // #[arg(short, long, value_delimiter = ',')]
-        // filters: Option<Vec<String>>,
-
-        /// Output Markdown filename. Overrides config.
+    Bundle(Box<BundleArgs>),
+    /// Restores files from a Markdown bundle file, overwriting existing files
+    Restore(RestoreArgs),
+    /// Regenerates an existing bundle in place, preserving `<!-- sheafy:note -->` annotations
+    Update {
+        /// The bundle to update (defaults to `bundle_name` from config)
+        input_file: Option<String>,
+    },
+    /// Checks that a bundle can be restored and reports its format version and features
+    Verify {
+        /// The bundle to check (defaults to `bundle_name` from config)
+        input_file: Option<String>,
+    },
+    /// Prints how a bundle is parsed: headings, fences and notes with their line numbers,
+    /// and which sections were accepted or rejected and why
+    Inspect {
+        /// The bundle to inspect (defaults to `bundle_name` from config)
+        input_file: Option<String>,
+    },
+    /// Removes generated bundles and conflict reports from the working directory
+    Clean {
+        /// List what would be removed without deleting anything
+        #[arg(long, action = ArgAction::SetTrue)]
+        dry_run: bool,
+    },
+    /// Renders a bundle as a self-contained HTML page (or PDF) with a table of contents,
+    /// line numbers and syntax highlighting
+    Render {
+        /// The bundle to render (defaults to `bundle_name` from config)
+        input_file: Option<String>,
+        /// Output file (defaults to the bundle name with an .html or .pdf extension)
         #[arg(short, long)]
         output: Option<String>,
-
-        /// Force use of .gitignore rules (overrides config if set to false).
+        /// Write a PDF instead of HTML (implied by an `.pdf` output name)
         #[arg(long, action = ArgAction::SetTrue)]
-        use_gitignore: bool,
+        pdf: bool,
+    },
+    /// Inspects the sheafy.toml configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Runs a long-lived JSON-RPC server for editor integrations
+    Daemon {
+        /// Speak newline-delimited JSON-RPC 2.0 over stdin/stdout
+        #[arg(long, required_unless_present = "socket", conflicts_with = "socket")]
+        stdio: bool,
+        /// Listen on a unix socket (default `.sheafy/daemon.sock`), keeping scan results
+        /// warm between requests; `sheafy bundle` uses the daemon while it runs
+        #[arg(long, value_name = "PATH", num_args = 0..=1)]
+        socket: Option<Option<String>>,
+        /// With `--socket`, check every file's SHA-256 before reusing its cached
+        /// statistics, not only those modified around when they were cached
+        #[arg(long, action = ArgAction::SetTrue, requires = "socket")]
+        paranoid: bool,
+        /// With `--socket`, print the cache hits and misses after each connection
+        #[arg(short, long, action = ArgAction::SetTrue, requires = "socket")]
+        verbose: bool,
+    },
+    /// Checks the config, git, the working directory, clipboard tools and the terminal
+    Doctor,
+    /// Prints the files a bundle would hold as a graph: directories as clusters, and
+    /// optionally the imports between files
+    Graph(GraphArgs),
+    /// Bundles the project with a task, sends it to an LLM API and streams the reply;
+    /// `--apply` restores the files in it
+    Ask(Box<AskArgs>),
+    /// Creates a project from a template bundle of the `template_registry`, filling in
+    /// its template variables
+    New(NewArgs),
+    /// Shows the size of a bundle per directory, compares two bundles, or lists the
+    /// sizes recorded in `.sheafy/metrics.jsonl`
+    Stats(StatsArgs),
+    /// Picks the files to bundle in a terminal UI, with live size and token totals, or
+    /// previews what restoring a bundle would change
+    Tui(TuiArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// The bundle to measure (defaults to `bundle_name` from config); with `--compare`,
+    /// the older and the newer bundle
+    pub bundles: Vec<String>,
+
+    /// Show how each directory grew from the first bundle to the second.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub compare: bool,
+
+    /// List the bundle sizes recorded in `.sheafy/metrics.jsonl`, oldest first.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["bundles", "compare"])]
+    pub history: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct NewArgs {
+    /// The template: a bundle name in the registry (without `.md`) or a path to a bundle
+    pub template: String,
+
+    /// The directory to create; its name is the default for the `name` variable
+    pub dir: String,
+
+    /// Value of a template variable (`NAME=VALUE`). May be repeated.
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct AskArgs {
+    /// Model to ask. Overrides `model` in `[sheafy.ask]`.
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// API to call. Overrides `provider` in `[sheafy.ask]`; without either, `claude-*`
+    /// models go to Anthropic and others to OpenAI.
+    #[arg(long, value_enum)]
+    pub provider: Option<Provider>,
+
+    /// Restore the files in the reply once it is complete.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub apply: bool,
+
+    /// With `--apply`, write paths that no bundle of this project has held.
+    #[arg(long, action = ArgAction::SetTrue, requires = "apply")]
+    pub allow_new_paths: bool,
 
-        /// Force *disabling* .gitignore rules (overrides config and --use-gitignore).
+    #[command(flatten)]
+    pub bundle: BundleArgs,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// The Messages API (`/v1/messages`)
+    Anthropic,
+    /// The Chat Completions API (`/chat/completions`), also served by many local and
+    /// hosted model servers
+    Openai,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct TuiArgs {
+    /// List the sections of a bundle (defaults to `bundle_name` from config) with a diff
+    /// of what restoring each would change, instead of picking files to bundle
+    #[arg(long, value_name = "BUNDLE", num_args = 0..=1)]
+    pub restore: Option<Option<String>>,
+
+    /// What the restore view compares the bundle with: the working tree, or the last
+    /// commit (`head`)
+    #[arg(long, value_enum, default_value_t = DiffBase::Worktree, requires = "restore")]
+    pub diff_base: DiffBase,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct GraphArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+
+    /// Add edges for references between files (Rust modules and paths, Python imports,
+    /// relative TS/JS imports)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub imports: bool,
+
+    /// Write the graph to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT (`sheafy graph | dot -Tsvg > files.svg`)
+    #[default]
+    Dot,
+    /// Nodes (files, directories) and edges (`contains`, `imports`)
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Reports invalid, unused and shadowed `ignore_patterns`
+    Lint,
+    /// Prints the effective config: the user-wide config overridden by sheafy.toml
+    Show {
+        /// Print each setting on its own line with the file it came from
         #[arg(long, action = ArgAction::SetTrue)]
-        no_gitignore: bool,
+        origin: bool,
     },
-    /// Restores files from a Markdown bundle file, overwriting existing files
-    Restore {
-        /// The Markdown file to restore from
-        input_file: Option<String>,
+    /// Prints the effective value of one setting (`bundle_name`, `sheafy.restore.allowed_paths`)
+    Get { key: String },
+    /// Sets one setting in sheafy.toml, keeping the file's comments and layout
+    Set {
+        key: String,
+        /// A TOML value (`true`, `3`, `["src/**"]`); anything else is taken as a string
+        value: String,
+        /// Change the user-wide config instead
+        #[arg(long, action = ArgAction::SetTrue)]
+        global: bool,
     },
+    /// Opens sheafy.toml in $VISUAL or $EDITOR and checks it afterwards
+    Edit {
+        /// Edit the user-wide config instead
+        #[arg(long, action = ArgAction::SetTrue)]
+        global: bool,
+    },
+}
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RestoreArgs {
+    /// The Markdown file to restore from
+    pub input_file: Option<String>,
+
+    /// Restore every `*.md` bundle in this directory, in filename order (later bundles win).
+    #[arg(long, value_name = "DIR", conflicts_with = "input_file")]
+    pub all: Option<String>,
+
+    /// What to do when a file exists with different content. `skip` leaves it alone and
+    /// records both versions in `sheafy-conflicts.md`.
+    #[arg(long, value_enum, default_value_t = OnConflict::Overwrite)]
+    pub on_conflict: OnConflict,
+
+    /// Write nothing; exit with an error (listing the paths) unless every file already matches the bundle.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "all")]
+    pub check: bool,
+
+    /// What `--check` compares the bundle with: the files in the working tree, or the
+    /// files as of the last commit (`head`), leaving uncommitted changes out of it.
+    #[arg(long, value_enum, default_value_t = DiffBase::Worktree, requires = "check")]
+    pub diff_base: DiffBase,
+
+    /// Write every file directly into DIR under a flattened name (`src/main.rs` becomes
+    /// `src__main.rs`), plus a `sheafy-map.json` mapping the names back to their paths.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["all", "check"])]
+    pub flat: Option<String>,
+
+    /// Only write files git already tracks (plus those matching `restore_allow`); if the
+    /// bundle holds any other path, nothing is restored.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["check", "flat"])]
+    pub tracked_only: bool,
+
+    /// Write paths that no bundle of this project has held (per `.sheafy/state.json`).
+    /// Without it, such a bundle is refused as likely wrong or hallucinated.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["check", "flat"])]
+    pub allow_new_paths: bool,
+
+    /// What to do with a file whose code fence is still open when the bundle ends, as
+    /// when LLM output is cut off mid-file. `partial` writes it as `<path>.partial`.
+    #[arg(long, value_enum, default_value_t = OnTruncated::Fail)]
+    pub on_truncated: OnTruncated,
+
+    /// Restore into a temporary copy of the tree first and run the check command for
+    /// each language in the bundle (`cargo check`, `tsc --noEmit`, ...); restore
+    /// nothing if one fails.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["check", "flat"])]
+    pub validate: bool,
+
+    /// Skip validation even if `validate = true` in `[sheafy.restore]`.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "validate")]
+    pub no_validate: bool,
+
+    /// What to do with stub sections, which record a file's size and hash but not its
+    /// content: `skip` them, or create an empty `placeholder` where the file is missing.
+    #[arg(long, value_enum, default_value_t = OnStub::Skip)]
+    pub stubs: OnStub,
+
+    /// Stop at the first file that cannot be written. By default the other files are
+    /// still restored and the failures are listed at the end.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "check")]
+    pub stop_on_error: bool,
+
+    /// Check every section against the bundle's `## sheafy:checksums` section (see
+    /// `bundle --checksums`); restore nothing if one was corrupted or edited.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["check", "flat"])]
+    pub verify_checksums: bool,
+
+    /// Write `sheafy-restore-report.json`: each section, the file it was restored to,
+    /// what was done (created, overwritten, unchanged, skipped, error) and the file's
+    /// SHA-256 before and after.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["check", "flat"])]
+    pub report: bool,
+
+    /// Drop the first N components of every path before restoring it, like `tar
+    /// --strip-components`; sections with no more than N components are skipped.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub strip_components: usize,
+
+    /// Restore every path under DIR (after `--strip-components`), for bundles made
+    /// from a directory further down.
+    #[arg(long, value_name = "DIR")]
+    pub prefix: Option<String>,
+
+    /// Restore a bundle even though its front matter declares it read-only
+    /// (`readonly: true` or `restore: false`).
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub force_readonly: bool,
+
+    /// Write files exactly as bundled, instead of in the line endings and byte order
+    /// mark of the files they replace.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub as_bundled: bool,
+
+    /// Value of a template variable: `{{NAME}}` in the paths and contents of a bundle
+    /// declaring it (front matter `vars: [NAME, ...]`) becomes VALUE. May be repeated.
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Replace the existing file with the bundled version
+    #[default]
+    Overwrite,
+    /// Keep the existing file and report the conflict
+    Skip,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffBase {
+    /// The files as they are on disk
+    #[default]
+    Worktree,
+    /// The files as of the last commit
+    Head,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTruncated {
+    /// Stop with an error naming the truncated file
+    #[default]
+    Fail,
+    /// Restore the other files and leave the truncated one out
+    Skip,
+    /// Write what the bundle holds of the file to `<path>.partial`
+    Partial,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnStub {
+    /// Leave the file alone
+    #[default]
+    Skip,
+    /// Create an empty file if none exists; an existing one is kept
+    Placeholder,
+}
+
+#[derive(Args, Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BundleArgs {
+    // REMOVED: filters argument
+    // /// Comma-separated list of file extensions to include (e.g., rs,py,txt). Overrides config.
+    // #[arg(short, long, value_delimiter = ',')]
+    // filters: Option<Vec<String>>,
+    /// Output Markdown filename. Overrides config.
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Bundle with the settings of this `[sheafy.profiles.<name>]` table; options given
+    /// on the command line still win.
+    #[arg(long, value_name = "NAME")]
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Bundle every profile in the config, each to its own output, walking the tree once.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["output", "profile"])]
+    #[serde(skip)]
+    pub all_profiles: bool,
+
+    /// Keep running, and bundle again whenever a file changes.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "all_profiles")]
+    #[serde(skip)]
+    pub watch: bool,
+
+    /// Add the sections of files the output doesn't hold yet to its end instead of
+    /// replacing it, holding a lock on it meanwhile, so several runs can build one bundle.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["all_profiles", "watch"])]
+    #[serde(skip)]
+    pub append: bool,
+
+    /// Make the bundle in memory and compare it with the output file instead of writing
+    /// it; exit with an error listing the sections that differ if the file is stale.
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["all_profiles", "watch", "append", "copy_chunks"])]
+    #[serde(skip)]
+    pub check: bool,
+
+    /// With `--watch`, also append each round's changes as diffs to FILE, for pasting
+    /// just the delta into an ongoing chat.
+    #[arg(long, value_name = "FILE", requires = "watch")]
+    pub session: Option<String>,
+
+    /// Force use of .gitignore rules (overrides config if set to false).
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub use_gitignore: bool,
+
+    /// Force *disabling* .gitignore rules (overrides config and --use-gitignore).
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub no_gitignore: bool,
+
+    /// Only bundle this file plus the files it references (Rust `mod`/`use`, Python and JS/TS imports).
+    #[arg(long)]
+    pub focus: Option<String>,
+
+    /// Number of reference hops to follow from the focus file.
+    #[arg(long, default_value_t = 1, requires = "focus")]
+    pub depth: usize,
+
+    /// Bundle exactly the files listed in this file (one path per line, `-` for stdin),
+    /// in that order, instead of walking the working directory.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["focus", "since", "show_excluded", "max_depth", "package"])]
+    pub files_from: Option<String>,
+
+    /// Only bundle this member crate of the Cargo workspace, the workspace manifest and
+    /// the crates it depends on by path.
+    #[arg(short, long, value_name = "NAME")]
+    pub package: Option<String>,
+
+    /// Only bundle files matching PATTERN (gitignore syntax); may be repeated.
+    #[arg(long = "include", value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Leave out files matching PATTERN (gitignore syntax), on top of `ignore_patterns`;
+    /// may be repeated.
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Only bundle files changed since this git ref (commit, branch or tag).
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Leave out files whose content is the same as in this earlier bundle (relative to
+    /// the working directory); they are listed by name instead.
+    #[arg(long, value_name = "FILE", conflicts_with = "since")]
+    pub baseline: Option<String>,
+
+    /// Bundle only the changed hunks plus N lines of context instead of whole files (read-only bundle).
+    #[arg(long, value_name = "N", requires = "since")]
+    pub context_lines: Option<usize>,
+
+    /// Only descend this many directory levels (1 = just the files in the working directory).
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Stop the scan once it finds more than N files (overrides `max_files`; 0 = no limit).
+    #[arg(long, value_name = "N")]
+    pub max_files: Option<usize>,
+
+    /// Number of directory-walking threads (0 = one per CPU). `--jobs 1` walks single-threaded.
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// How files are read: `sync`, one after another, or `async`, many at once while the
+    /// bundle is written (faster on network filesystems; needs the `async-io` feature).
+    #[arg(long, value_enum, default_value_t = IoMode::Sync)]
+    pub io: IoMode,
+
+    /// Emit identical file contents once; later copies become `<!-- sheafy:same-as path -->` references.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub dedup: bool,
+
+    /// End the bundle with a `## sheafy:checksums` section listing each file's SHA-256,
+    /// for `restore --verify-checksums`.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub checksums: bool,
+
+    /// Declare the bundle a template with these variables (comma-separated): restore
+    /// fills `{{NAME}}` in paths and contents with values from `--var NAME=VALUE`.
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    pub template_vars: Vec<String>,
+
+    /// Declare the bundle read-only (`readonly: true` in the front matter), for reference
+    /// and context bundles: restore refuses it unless given `--force-readonly`.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub readonly: bool,
+
+    /// Bundle Markdown files that are themselves sheafy bundles (recognized by their
+    /// version marker), which are left out by default.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub include_bundles: bool,
+
+    /// Point relative links in bundled Markdown documents at the heading anchors of the
+    /// sections holding their targets (restore writes the original links back).
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub link_anchors: bool,
+
+    /// Bundle only the header and first N rows of CSV/TSV files longer than that
+    /// (restore leaves those files alone).
+    #[arg(long, value_name = "N")]
+    pub csv_preview_rows: Option<usize>,
+
+    /// Split files larger than SIZE (e.g. `200KB`) into several code fences under one
+    /// heading, marked `part 1/N`..., for chat UIs that truncate long blocks.
+    #[arg(long, value_name = "SIZE")]
+    pub split_size: Option<ByteSize>,
+
+    /// Bundle files matching PATTERN (gitignore syntax) as stubs: path, size and SHA-256
+    /// but no content. Adds to `stub_patterns`; may be repeated.
+    #[arg(long = "stub", value_name = "PATTERN")]
+    pub stubs: Vec<String>,
+
+    /// After bundling, copy the bundle to the clipboard in chunks of at most N characters,
+    /// the next one on each Enter, for chat UIs that cap message length.
+    #[arg(long, value_name = "N")]
+    pub copy_chunks: Option<usize>,
+
+    /// Order of the files: `alphabetical`, `smart` (manifests, READMEs and entry
+    /// points first, then the modules they reference) or `recent-first` (most recently
+    /// changed first). Overrides `auto_order`.
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub auto_order: Option<AutoOrder>,
+
+    /// Note each file's lines, bytes and estimated tokens under its heading, as
+    /// `<!-- sheafy:stats lines=120 bytes=4312 tokens=1078 -->`.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub file_stats: bool,
+
+    /// What to do with files that look minified or obfuscated: `include` them, keep a
+    /// short `truncate`d preview, or `skip` them. Overrides `minified`.
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub minified: Option<MinifiedPolicy>,
+
+    /// After bundling, list the files that were left out and the rule that excluded each.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub show_excluded: bool,
+
+    /// Where each file's path is written: a `## path` heading, or the fence info string
+    /// (`title` for mkdocs-material, `pandoc` for pandoc's `filename` attribute).
+    #[arg(long, value_enum, default_value_t = HeaderStyle::Heading)]
+    pub header_style: HeaderStyle,
+
+    /// Leave the creation time and absolute paths out of the front matter, so bundling
+    /// the same tree twice produces byte-identical output.
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub reproducible: bool,
+
+    /// Task instructions to put in the bundle, at `{{prompt}}` in the prologue or
+    /// epilogue, or else right after the prologue.
+    #[arg(long, value_name = "TEXT", conflicts_with = "prompt_file")]
+    pub prompt: Option<String>,
+
+    /// Like `--prompt`, with the instructions read from FILE (`-` for stdin).
+    #[arg(long, value_name = "FILE")]
+    pub prompt_file: Option<String>,
+
+    /// Bundle format. `patch` emits git diffs against --since that restore can apply.
+    #[arg(long, value_enum, default_value_t = BundleFormat::Markdown)]
+    pub format: BundleFormat,
+}
+
+impl Default for BundleArgs {
+    fn default() -> Self {
+        Self {
+            output: None,
+            profile: None,
+            all_profiles: false,
+            watch: false,
+            append: false,
+            check: false,
+            session: None,
+            use_gitignore: false,
+            no_gitignore: false,
+            focus: None,
+            depth: 1,
+            since: None,
+            baseline: None,
+            files_from: None,
+            package: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            context_lines: None,
+            max_depth: None,
+            max_files: None,
+            jobs: None,
+            io: IoMode::Sync,
+            dedup: false,
+            checksums: false,
+            template_vars: Vec::new(),
+            readonly: false,
+            include_bundles: false,
+            link_anchors: false,
+            csv_preview_rows: None,
+            split_size: None,
+            stubs: Vec::new(),
+            copy_chunks: None,
+            auto_order: None,
+            file_stats: false,
+            minified: None,
+            show_excluded: false,
+            header_style: HeaderStyle::Heading,
+            reproducible: false,
+            prompt: None,
+            prompt_file: None,
+            format: BundleFormat::Markdown,
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoOrder {
+    /// Alphabetical by path
+    #[default]
+    Alphabetical,
+    /// Manifests, READMEs and entry points first, then the modules they reference
+    Smart,
+    /// Most recently changed first: by last commit, or modification time for files with
+    /// uncommitted changes and outside git
+    #[serde(rename = "recent-first")]
+    RecentFirst,
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MinifiedPolicy {
+    /// Bundle the file as is and name it in a warning
+    #[default]
+    Include,
+    /// Keep the start of the file as a preview; restore skips it
+    Truncate,
+    /// Leave the file out and list it at the end of the bundle
+    Skip,
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IoMode {
+    /// Read each file when it is written
+    #[default]
+    Sync,
+    /// Read files concurrently ahead of the writer
+    Async,
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleFormat {
+    /// Whole files (or excerpts with --context-lines) in fenced code blocks
+    #[default]
+    Markdown,
+    /// Unified diffs against the --since ref, applied by restore with `git apply`
+    Patch,
+    /// A POSIX shell script that recreates the files when run; restore reads it too
+    Shell,
+    /// The same as a PowerShell script
+    Powershell,
+    /// A gzip-compressed tar archive of the files; restore extracts it
+    #[value(name = "tar.gz")]
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    /// A zip archive of the files; restore extracts it
+    Zip,
+}
+
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderStyle {
+    /// `## src/main.rs` above the code fence
+    #[default]
+    Heading,
+    /// ```rust title="src/main.rs"
+    Title,
+    /// ```{.rust filename="src/main.rs"}
+    Pandoc,
 }
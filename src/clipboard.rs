@@ -0,0 +1,183 @@
+//! Copying a bundle to the clipboard, whole or in chunks (`bundle --copy-chunks N`).
+//!
+//! Chat UIs cap the length of a message. In chunk mode the bundle is cut into pieces of
+//! at most N characters, preferably at line ends, and each piece after the first is
+//! copied when Enter is pressed, so they can be pasted one message at a time. Every
+//! piece but the last ends with a note that more follows, and every piece but the
+//! first starts with one saying which part it is.
+
+use crate::prompt;
+use anyhow::{bail, Context, Result};
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    process::{Command, Stdio},
+};
+
+pub struct Tool {
+    pub program: &'static str,
+    pub args: &'static [&'static str],
+    /// Where the tool applies, for `sheafy doctor`
+    pub platform: &'static str,
+}
+
+/// Clipboard tools, in order of preference.
+pub const TOOLS: &[Tool] = &[
+    Tool {
+        program: "pbcopy",
+        args: &[],
+        platform: "macOS",
+    },
+    Tool {
+        program: "wl-copy",
+        args: &[],
+        platform: "Wayland",
+    },
+    Tool {
+        program: "xclip",
+        args: &["-selection", "clipboard"],
+        platform: "X11",
+    },
+    Tool {
+        program: "xsel",
+        args: &["--clipboard", "--input"],
+        platform: "X11",
+    },
+    Tool {
+        program: "clip.exe",
+        args: &[],
+        platform: "Windows/WSL",
+    },
+];
+
+pub fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| {
+            let candidate = dir.join(program);
+            candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+        })
+    })
+}
+
+fn find_tool() -> Result<&'static Tool> {
+    TOOLS.iter().find(|tool| on_path(tool.program)).context(
+        "No clipboard tool found on PATH (pbcopy, wl-copy, xclip, xsel or clip.exe); \
+         run `sheafy doctor` for details",
+    )
+}
+
+fn copy(tool: &Tool, text: &str) -> Result<()> {
+    let mut child = Command::new(tool.program)
+        .args(tool.args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", tool.program))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes())
+        .with_context(|| format!("Failed to write to {}", tool.program))?;
+    let exit = child.wait()?;
+    if !exit.success() {
+        bail!("{} exited with {}", tool.program, exit);
+    }
+    Ok(())
+}
+
+fn head(part: usize, total: usize) -> String {
+    format!("[Part {}/{} of the bundle, continued]\n", part, total)
+}
+
+fn tail(part: usize, total: usize) -> String {
+    format!(
+        "\n[Part {}/{}: the bundle continues in the next message; wait for it before replying]\n",
+        part, total
+    )
+}
+
+/// Cuts `text` into pieces whose marked-up form is at most `max_chars` characters,
+/// breaking after the last newline in a piece when there is one.
+pub fn chunks(text: &str, max_chars: usize) -> Result<Vec<String>> {
+    if text.chars().count() <= max_chars {
+        return Ok(vec![text.to_string()]);
+    }
+    // Markers grow with the part numbers, so size them for a generous total
+    let overhead = head(9999, 9999).chars().count() + tail(9999, 9999).chars().count();
+    if max_chars <= overhead * 2 {
+        bail!(
+            "--copy-chunks {} is too small to hold the continuation markers; use at least {}",
+            max_chars,
+            overhead * 2 + 1
+        );
+    }
+    let budget = max_chars - overhead;
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = match rest.char_indices().nth(budget) {
+            None => rest.len(),
+            Some((limit, _)) => match rest[..limit].rfind('\n') {
+                Some(newline) if newline > 0 => newline + 1,
+                _ => limit,
+            },
+        };
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    let total = pieces.len();
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, piece)| {
+            let mut chunk = String::new();
+            if i > 0 {
+                chunk.push_str(&head(i + 1, total));
+            }
+            chunk.push_str(piece);
+            if i + 1 < total {
+                chunk.push_str(&tail(i + 1, total));
+            }
+            chunk
+        })
+        .collect())
+}
+
+/// Copies `text` to the clipboard in chunks of at most `max_chars` characters, waiting
+/// for Enter before each chunk after the first.
+pub fn copy_in_chunks(text: &str, max_chars: usize) -> Result<()> {
+    let tool = find_tool()?;
+    let chunks = chunks(text, max_chars)?;
+    let total = chunks.len();
+    if total > 1 && !prompt::is_interactive() {
+        bail!(
+            "The bundle needs {} chunks of {} characters; copying them one by one needs a terminal",
+            total,
+            max_chars
+        );
+    }
+    let stdin = io::stdin();
+    for (i, chunk) in chunks.iter().enumerate() {
+        if i > 0 {
+            eprint!(
+                "Press Enter to copy chunk {}/{} (Ctrl-C to stop)... ",
+                i + 1,
+                total
+            );
+            io::stderr().flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                bail!("Stopped at chunk {}/{} (end of input)", i + 1, total);
+            }
+        }
+        copy(tool, chunk)?;
+        status!(
+            "Copied chunk {}/{} ({} characters) to the clipboard with {}.",
+            i + 1,
+            total,
+            chunk.chars().count(),
+            tool.program
+        );
+    }
+    Ok(())
+}
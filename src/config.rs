@@ -1,6 +1,9 @@
+use crate::cli::{AutoOrder, BundleArgs, MinifiedPolicy, Provider};
+use crate::error::SheafyError;
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
     io::Write,
     path::{Path, PathBuf},
@@ -8,6 +11,8 @@ use std::{
 
 pub const CONFIG_FILENAME: &str = "sheafy.toml";
 pub const DEFAULT_BUNDLE_NAME: &str = "project_bundle.md";
+/// Files the bundle scan may find before it stops, unless `max_files` is set
+pub const DEFAULT_MAX_FILES: usize = 5000;
 // Updated default config content
 pub const DEFAULT_CONFIG_CONTENT: &str = r#"[sheafy]
 # Output filename for bundle command
@@ -19,6 +24,21 @@ bundle_name = "project_bundle.md"
 # Whether to respect .gitignore files (default: true)
 use_gitignore = true
 
+# Optional: when the working directory is inside a git repository, .gitignore files of
+# the directories above it (up to the repository root) apply too (default: true)
+# parent_gitignore = false
+
+# Optional: bundle the config file itself, e.g. when the model should see these settings
+# (default: false)
+# include_config = true
+
+# Optional: built-in ignore patterns for the detected project type (package.json,
+# pyproject.toml, Cargo.toml): node_modules/, .venv/, __pycache__/, dist/, target/, ... (default: true)
+# presets = false
+
+# Optional: language of sheafy's messages, "en" (default) or "zh"; SHEAFY_LANG overrides it
+# lang = "zh"
+
 # Optional: Add custom ignore patterns (multi-line string, gitignore syntax)
 # These patterns are applied *in addition* to .gitignore rules (if enabled).
 # Example: ignore all '.log' files and the 'temp/' directory
@@ -27,6 +47,109 @@ use_gitignore = true
 # temp/
 # """
 
+# Optional: files to bundle even if .gitignore, the hidden-file filter, presets or
+# ignore_patterns would leave them out (paths relative to the working directory)
+# always_include = ["LICENSE", ".github/workflows/ci.yml", ".env.example"]
+
+# Optional: cap read throughput while bundling (MiB/s), e.g. on network filesystems
+# io_throttle_mb_s = 50
+
+# Optional: abort before writing if the bundle would exceed this size
+# max_bundle_size = "10MB"
+
+# Optional: write identical files once, referencing the first copy (same as `--dedup`)
+# dedup = true
+
+# Optional: end bundles with a `## sheafy:checksums` section for `restore --verify-checksums`
+# (same as `--checksums`)
+# checksums = true
+
+# Optional: bundle only the header and first rows of longer CSV/TSV files (same as `--csv-preview-rows`)
+# csv_preview_rows = 20
+
+# Optional: write files larger than this as several code fences (parts) under one heading,
+# for chat UIs that cut off very long code blocks; restore joins them (same as `--split-size`)
+# split_size = "200KB"
+
+# Optional: note each file's lines, bytes and estimated tokens under its heading (same as `--file-stats`)
+# file_stats = true
+
+# Optional: how tokens are counted (file stats, `sheafy ask`, daemon stats): "estimate"
+# (default), or exactly with "cl100k" (GPT-4), "o200k" (GPT-4o) or "llama3", given the
+# model's .tiktoken rank file, e.g. from
+# https://openaipublic.blob.core.windows.net/encodings/cl100k_base.tiktoken; without
+# tokenizer_file it is looked for in ~/.config/sheafy/tokenizers/<name>.tiktoken
+# tokenizer = "cl100k"
+# tokenizer_file = "tokenizers/cl100k_base.tiktoken"
+
+# Optional: point relative links in bundled Markdown docs at their bundle sections (same as `--link-anchors`)
+# link_anchors = true
+
+# Optional: rewrite the paths in section headers; restore maps them back.
+# `header_strip_prefix` is removed first, then `header_add_prefix` is put in front.
+# header_strip_prefix = "packages/app"
+# header_add_prefix = "my-crate"
+
+# Optional: only descend this many directory levels (1 = top-level files only, same as `--max-depth`)
+# max_depth = 3
+
+# Optional: stop the scan once it finds more than this many files (default 5000, 0 = no
+# limit); the directories with the most files and ignore patterns for them are printed
+# max_files = 20000
+
+# Optional: end every restored file with a newline, even files that had none
+# normalize_eof_newline = true
+
+# Optional: paths `restore --tracked-only` may create although git doesn't track them
+# restore_allow = """
+# src/generated/
+# *.snap
+# """
+
+# Optional: blank lines between sections, 1 (default) or 2 for strict Markdown parsers
+# section_spacing = 2
+
+# Optional: "smart" puts manifests, READMEs and entry points first, then the modules they
+# reference, then the rest alphabetically; "recent-first" puts the files changed last
+# first (default "alphabetical"; same as `--auto-order`)
+# auto_order = "smart"
+
+# Optional: bundle files matching these patterns first, in pattern order
+# priority_patterns = """
+# README.md
+# Cargo.toml
+# src/main.rs
+# """
+
+# Optional: bundle files matching these patterns as stubs (path, size and SHA-256, no
+# content), e.g. images and other large assets; {path} in stub_url becomes the file's path
+# stub_patterns = """
+# *.png
+# assets/
+# """
+# stub_url = "https://raw.githubusercontent.com/me/project/main/{path}"
+
+# Optional: lines longer than max_line_length characters (default 10000) are reported;
+# long_lines sets what happens to such files per pattern (the strictest match wins):
+# "warn" (default), "wrap" (restore joins the lines again), "truncate" (restore skips
+# the file) or "skip"
+# max_line_length = 5000
+# long_lines = { "*.min.js" = "skip", "*.b64" = "truncate", "*" = "wrap" }
+
+# Optional: what happens to files that look minified or obfuscated (a few huge lines,
+# hardly any whitespace): "include" (default), "truncate" (a preview restore skips) or
+# "skip"; either way they are named in a warning
+# minified = "skip"
+
+# Optional: cap how much the files matching a pattern (gitignore syntax) may contribute,
+# as a number of files or a size; files past the cap are left out and listed in a note
+# limits = { "*.snap" = "5 files", "*.sql" = "100KB" }
+
+# Optional: group the bundle's sections under a `# Tag` heading per tag, tags in
+# tag_order first and the rest by name, untagged files last under `# Other`
+# tags = { core = ["src/**"], tests = ["tests/**"] }
+# tag_order = ["core", "tests"]
+
 # Optional prologue text to include at start of bundle
 # prologue = """
 # # Project Bundle
@@ -40,6 +163,91 @@ use_gitignore = true
 #
 # Generated by sheafy
 # """
+
+# Optional: code fence language per file extension (overrides built-in hints)
+# [sheafy.language_hints]
+# vue = "vue"
+
+# Optional: files of nested git repositories (submodules, vendored clones):
+# "include" (default), "skip", or "shallow" (only the files at their top level)
+# submodules = "skip"
+
+# Optional: how Jupyter notebooks are bundled: "flatten" (readable cells, default) or "raw" (JSON)
+# notebooks = "raw"
+
+# Optional: file handlers transform content when bundling and reverse it on restore.
+# `notebook` is built in and used for *.ipynb by default; other handlers run
+# `encode`/`decode` commands that read stdin and print the result.
+# [sheafy.handlers.sops]
+# patterns = "secrets/*.yaml"
+# encode = "sops --decrypt --input-type yaml --output-type yaml /dev/stdin"
+# decode = "sops --encrypt --input-type yaml --output-type yaml /dev/stdin"
+# language = "yaml"
+
+# Optional: bundle a summary of some files instead of the files themselves. The command
+# reads a file on stdin and prints its summary; restore skips summarized sections.
+# [[sheafy.summarize]]
+# pattern = "docs/**/*.md"
+# command = "llm summarize"
+# language = "markdown"
+
+# Optional: settings for the files below a directory, on top of the ones above. Patterns
+# are relative to the directory. A sheafy.toml in the directory itself can hold the same
+# keys in its [sheafy] table.
+# [sheafy.dir."tests/fixtures"]
+# ignore_patterns = """
+# *.snap
+# """
+# priority_patterns = "README.md"
+# language_hints = { tpl = "html" }
+
+# Optional: where `sheafy new <template> <dir>` finds <template>.md bundles, a directory or
+# a git URL; best set in the user-wide config
+# template_registry = "https://github.com/me/sheafy-templates.git"
+
+# Optional: named bundles for different audiences. A profile holds `sheafy bundle` options
+# by their long names (`-` becomes `_`), plus include/exclude patterns (gitignore syntax).
+# `sheafy bundle --profile docs` uses one; `sheafy bundle --all-profiles` writes them all
+# in one walk of the tree. Without `output`, a profile writes project_bundle-<name>.md.
+# [sheafy.profiles.docs]
+# output = "docs_bundle.md"
+# include = ["docs/**", "README.md"]
+# exclude = ["docs/archive/"]
+# [sheafy.profiles.backend]
+# include = ["src/server/**"]
+# file_stats = true
+
+# Optional: the only paths restore may write, e.g. when applying generated bundles in CI.
+# A bundle with other paths is refused ("fail", default) or those entries are skipped ("skip").
+# [sheafy.restore]
+# allowed_paths = ["src/**", "tests/**"]
+# on_disallowed = "skip"
+# Restore into a temporary copy first and run a check per language there (same as
+# `restore --validate`); nothing is restored if one fails. Presets: rust = "cargo check
+# --quiet", typescript = "tsc --noEmit", python = "python3 -m compileall -q .".
+# validate = true
+# Write sheafy-restore-report.json after every restore, as `restore --report` does
+# report = true
+# Existing files are written over in their own line endings (LF or CRLF) and byte order
+# mark; false writes them exactly as bundled, as `restore --as-bundled` does
+# match_existing = false
+# [sheafy.restore.checks]
+# python = "ruff check ."
+# typescript = ""  # no check
+# Values for the {{name}} placeholders of template bundles (those whose front matter
+# declares `vars: [...]`); `restore --var name=value` overrides them.
+# [sheafy.restore.vars]
+# author = "Jane Doe"
+
+# Optional: the LLM API `sheafy ask` sends bundles to. The key is read from the
+# environment variable named by api_key_env (default ANTHROPIC_API_KEY or OPENAI_API_KEY);
+# base_url points at another server speaking the same API.
+# [sheafy.ask]
+# provider = "anthropic"
+# model = "claude-sonnet-4-5"
+# api_key_env = "ANTHROPIC_API_KEY"
+# base_url = "https://api.anthropic.com"
+# max_tokens = 16000
 "#;
 
 #[derive(Deserialize, Debug, Default)]
@@ -48,43 +256,623 @@ pub struct SheafyConfig {
     pub bundle_name: Option<String>,
     pub working_dir: Option<String>,
     pub use_gitignore: Option<bool>,
+    /// Also apply the ignore files of enclosing directories up to the repository root
+    pub parent_gitignore: Option<bool>,
+    /// Bundle the config file itself, which is left out by default
+    pub include_config: Option<bool>,
     pub prologue: Option<String>,
     pub epilogue: Option<String>,
     // ADDED: ignore_patterns field
     pub ignore_patterns: Option<String>,
+    /// Files bundled even if an ignore rule or the hidden-file filter leaves them out
+    pub always_include: Option<Vec<String>>,
+    /// Built-in ignore patterns for detected project types (node, python, rust)
+    pub presets: Option<bool>,
+    /// Language of messages (`en`, `zh`); the `SHEAFY_LANG` variable takes precedence
+    pub lang: Option<String>,
+    /// Upper bound for file read throughput while bundling, in MiB per second
+    pub io_throttle_mb_s: Option<f64>,
+    /// Abort bundling if the projected bundle would be larger than this
+    pub max_bundle_size: Option<ByteSize>,
+    /// Emit identical file contents once and reference them from the other sections
+    pub dedup: Option<bool>,
+    /// End bundles with a section listing each file's SHA-256
+    pub checksums: Option<bool>,
+    /// Rewrite relative links in Markdown documents to the anchors of bundle sections
+    pub link_anchors: Option<bool>,
+    /// Bundle only the header and this many rows of CSV/TSV files
+    pub csv_preview_rows: Option<usize>,
+    /// Files larger than this are written as several code fences under one heading
+    pub split_size: Option<ByteSize>,
+    /// Note each file's lines, bytes and estimated tokens under its heading
+    pub file_stats: Option<bool>,
+    /// Removed from the start of paths in section headers (restore adds it back)
+    pub header_strip_prefix: Option<String>,
+    /// Put in front of paths in section headers, after stripping (restore removes it)
+    pub header_add_prefix: Option<String>,
+    /// How many directory levels to descend; 1 bundles only top-level files
+    pub max_depth: Option<usize>,
+    /// Abort the scan once it finds more files than this (default 5000, 0 = no limit)
+    pub max_files: Option<usize>,
+    /// Blank lines written before each section: 1 (default) or 2
+    pub section_spacing: Option<usize>,
+    /// Restore every file with a final newline, even if the original had none
+    pub normalize_eof_newline: Option<bool>,
+    /// Paths `restore --tracked-only` may write although git doesn't track them
+    /// (gitignore syntax, one per line)
+    pub restore_allow: Option<String>,
+    /// How files are ordered before `priority_patterns` apply
+    pub auto_order: Option<AutoOrder>,
+    /// Files matching these patterns (gitignore syntax, one per line) are bundled first
+    pub priority_patterns: Option<String>,
+    /// Files matching these patterns (gitignore syntax, one per line) are bundled as stubs
+    pub stub_patterns: Option<String>,
+    /// Where a stubbed file can be fetched, with `{path}` standing for its path
+    pub stub_url: Option<String>,
+    /// Lines longer than this many characters are reported (default 10000)
+    pub max_line_length: Option<usize>,
+    /// What happens to files with long lines, per pattern (gitignore syntax)
+    #[serde(default)]
+    pub long_lines: BTreeMap<String, LongLinePolicy>,
+    /// What happens to files that look minified or obfuscated
+    pub minified: Option<MinifiedPolicy>,
+    /// How tokens are counted for file stats and budgets
+    pub tokenizer: Option<Tokenizer>,
+    /// The `.tiktoken` rank file of `tokenizer`, relative to the working directory
+    pub tokenizer_file: Option<String>,
+    /// Caps on the files or bytes each pattern (gitignore syntax) may contribute
+    #[serde(default)]
+    pub limits: BTreeMap<String, Limit>,
+    /// Tags by name, each with the patterns (gitignore syntax) of the files it covers
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<String>>,
+    /// Order of the tag groups in the bundle; tags not listed follow by name
+    pub tag_order: Option<Vec<String>>,
+    /// Code fence language per file extension, overriding the built-in table
+    #[serde(default)]
+    pub language_hints: BTreeMap<String, String>,
+    /// File handlers by name, tried in name order
+    #[serde(default)]
+    pub handlers: BTreeMap<String, HandlerConfig>,
+    /// External summarizers, tried in order
+    #[serde(default)]
+    pub summarize: Vec<SummarizeConfig>,
+    /// How Jupyter notebooks are bundled
+    pub notebooks: Option<NotebookMode>,
+    /// Whether files of nested repositories (submodules, vendored clones) are bundled
+    pub submodules: Option<SubmodulePolicy>,
+    /// The `[sheafy.restore]` table
+    #[serde(default)]
+    pub restore: RestoreConfig,
+    /// The `[sheafy.ask]` table
+    #[serde(default)]
+    pub ask: AskConfig,
+    /// `[sheafy.dir."path"]` tables: settings for the files below a directory
+    #[serde(default)]
+    pub dir: BTreeMap<String, DirConfig>,
+    /// `[sheafy.profiles.<name>]` tables: `sheafy bundle` options under a name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, BundleArgs>,
+    /// Where `sheafy new` finds `<template>.md` bundles: a directory or a git URL
+    pub template_registry: Option<String>,
+}
+
+/// Settings for the files below one directory, from a `[sheafy.dir."path"]` table or the
+/// `[sheafy]` table of a `sheafy.toml` in that directory.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct DirConfig {
+    /// More files to leave out, relative to the directory (gitignore syntax)
+    pub ignore_patterns: Option<String>,
+    /// Files to bundle first, ranked after the project-wide `priority_patterns`
+    pub priority_patterns: Option<String>,
+    /// Code fence language per file extension, over those of enclosing directories
+    #[serde(default)]
+    pub language_hints: BTreeMap<String, String>,
+}
+
+/// Guardrails for `sheafy restore`, in `[sheafy.restore]`.
+#[derive(Deserialize, Debug, Default)]
+pub struct RestoreConfig {
+    /// The only paths restore may write (gitignore syntax, e.g. `src/**`)
+    pub allowed_paths: Option<Vec<String>>,
+    /// What restore does with a bundle entry outside `allowed_paths`
+    #[serde(default)]
+    pub on_disallowed: OnDisallowed,
+    /// Restore into a temporary copy and run `checks` there first (`--validate`)
+    pub validate: Option<bool>,
+    /// Always write `sheafy-restore-report.json` (`--report`)
+    pub report: Option<bool>,
+    /// Write over existing files in their own line endings and byte order mark
+    /// (default true; `--as-bundled` turns it off)
+    pub match_existing: Option<bool>,
+    /// Check command per fence language, over the built-in presets; `""` disables one
+    #[serde(default)]
+    pub checks: BTreeMap<String, String>,
+    /// Values of the template variables bundles declare (`--var` wins)
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+}
+
+/// The LLM API of `sheafy ask`, in `[sheafy.ask]`.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct AskConfig {
+    pub provider: Option<Provider>,
+    pub model: Option<String>,
+    /// Environment variable holding the API key
+    pub api_key_env: Option<String>,
+    /// API root, for proxies and compatible servers
+    pub base_url: Option<String>,
+    /// Upper bound on the length of the reply, in tokens
+    pub max_tokens: Option<u32>,
+    /// Instructions sent as the system prompt, replacing the built-in ones
+    pub system: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnDisallowed {
+    /// Restore nothing and list the offending paths
+    #[default]
+    Fail,
+    /// Restore the other files and warn about each skipped one
+    Skip,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmodulePolicy {
+    /// Walk nested repositories like any other directory
+    #[default]
+    Include,
+    /// Leave out every directory that has its own `.git`
+    Skip,
+    /// Only the files at the top of a nested repository (README, manifests)
+    Shallow,
+}
+
+/// What happens to a file with lines longer than `max_line_length`, mildest first.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LongLinePolicy {
+    /// Bundle the file as is and name it in a warning
+    #[default]
+    Warn,
+    /// Break the long lines into several; restore joins them again
+    Wrap,
+    /// Cut the long lines short; restore skips the file
+    Truncate,
+    /// Leave the file out
+    Skip,
+}
+
+/// How tokens are counted.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    /// About four characters per token, one per CJK character
+    #[default]
+    Estimate,
+    /// GPT-4 and GPT-3.5
+    Cl100k,
+    /// GPT-4o and later OpenAI models
+    O200k,
+    /// Llama 3
+    Llama3,
+}
+
+impl Tokenizer {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tokenizer::Estimate => "estimate",
+            Tokenizer::Cl100k => "cl100k",
+            Tokenizer::O200k => "o200k",
+            Tokenizer::Llama3 => "llama3",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotebookMode {
+    /// Readable `# %%` cells, rebuilt into a notebook on restore
+    #[default]
+    Flatten,
+    /// The notebook JSON as is
+    Raw,
+}
+
+/// A `[sheafy.handlers.<name>]` table.
+#[derive(Deserialize, Debug, Default)]
+pub struct HandlerConfig {
+    /// Files the handler applies to (gitignore syntax, one per line)
+    pub patterns: String,
+    /// Command turning file content (stdin) into bundled text (stdout); omit for built-ins
+    pub encode: Option<String>,
+    /// Command reversing `encode`
+    pub decode: Option<String>,
+    /// Code fence language of the encoded text
+    pub language: Option<String>,
+}
+
+/// A `[[sheafy.summarize]]` entry.
+#[derive(Deserialize, Debug, Default)]
+pub struct SummarizeConfig {
+    /// Files summarized (gitignore syntax)
+    pub pattern: String,
+    /// Command turning file content (stdin) into its summary (stdout)
+    pub command: String,
+    /// Code fence language of the summary
+    pub language: Option<String>,
+}
+
+/// A size given either as a plain number of bytes or as a string like "10MB" or "512 KiB".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: f64 = number
+            .parse()
+            .with_context(|| format!("Invalid size: '{}'", s))?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" | "kib" => 1024,
+            "m" | "mb" | "mib" => 1024 * 1024,
+            "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+            other => bail!("Unknown size unit '{}' in '{}'", other, s),
+        };
+        Ok(ByteSize((number * multiplier as f64) as u64))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(ByteSize(bytes)),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+        let mut value = self.0 as f64;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} B", self.0)
+        } else {
+            write!(f, "{:.1} {}", value, UNITS[unit])
+        }
+    }
+}
+
+/// A `limits` entry: a number of files ("5 files") or a size ("100KB").
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Files(usize),
+    Bytes(ByteSize),
+}
+
+impl std::str::FromStr for Limit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s.strip_suffix("files").or_else(|| s.strip_suffix("file")) {
+            Some(count) => Ok(Limit::Files(
+                count
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid file count: '{}'", s))?,
+            )),
+            None => Ok(Limit::Bytes(s.parse()?)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Files(count) => write!(f, "{} file(s)", count),
+            Limit::Bytes(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+/// Where user-wide config defaults live: `$XDG_CONFIG_HOME/sheafy/config.toml`, by
+/// default `~/.config/sheafy/config.toml` (`%APPDATA%\sheafy\config.toml` on Windows).
+pub fn global_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("APPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".config"))
+            }
+        })?;
+    Some(base.join("sheafy").join("config.toml"))
+}
+
+/// Which config file an effective value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Built in (the template `sheafy init` writes)
+    Default,
+    /// The user-wide config
+    Global,
+    /// The project's `sheafy.toml`
+    Project,
+    /// Both: `ignore_patterns` of the project extend the global ones
+    Both,
+    /// An environment variable (`SHEAFY_LANG`)
+    Env,
+}
+
+/// The effective config with the provenance of its values (`sheafy config show`).
+pub struct Layered {
+    pub config: Config,
+    /// The merged TOML the config was read from
+    pub merged: toml::Table,
+    /// Origin of every value, by dotted key (`sheafy.language_hints.vue`)
+    pub origins: BTreeMap<String, Origin>,
+    /// The global config file, if there is one
+    pub global_path: Option<PathBuf>,
+}
+
+impl Layered {
+    /// `sheafy config show`: the effective settings as TOML, or with `origin` one
+    /// `key = value` line per setting, commented with the file it came from.
+    pub fn show(&self, origin: bool) -> Result<()> {
+        let global = self
+            .global_path
+            .as_ref()
+            .map_or_else(|| "none".to_string(), |path| path.display().to_string());
+        let project = if Path::new(CONFIG_FILENAME).exists() {
+            CONFIG_FILENAME
+        } else {
+            "none"
+        };
+        status!("# Global config: {}", global);
+        status!("# Project config: {}", project);
+        if !origin {
+            status!("{}", toml::to_string_pretty(&self.merged)?.trim_end());
+            return Ok(());
+        }
+        for (key, from) in &self.origins {
+            let Some(value) = lookup(&self.merged, key) else {
+                continue;
+            };
+            let from = match from {
+                Origin::Default => "default".to_string(),
+                Origin::Env => "SHEAFY_LANG".to_string(),
+                Origin::Global => global.clone(),
+                Origin::Project => CONFIG_FILENAME.to_string(),
+                Origin::Both => format!("{} + {}", global, CONFIG_FILENAME),
+            };
+            // One line per setting: multi-line strings are written escaped
+            let value = match value {
+                toml::Value::String(text) => serde_json::to_string(text)?,
+                value => value.to_string(),
+            };
+            status!("{} = {}  # {}", key, value, from);
+        }
+        Ok(())
+    }
+}
+
+/// A key as typed on the command line, with the implied `sheafy.` table
+/// (`bundle_name` is `sheafy.bundle_name`).
+pub fn full_key(key: &str) -> String {
+    if key == "sheafy" || key.starts_with("sheafy.") {
+        key.to_string()
+    } else {
+        format!("sheafy.{}", key)
+    }
+}
+
+impl Layered {
+    /// `sheafy config get`: prints the effective value of `key`, strings unquoted.
+    pub fn get(&self, key: &str) -> Result<()> {
+        let key = full_key(key);
+        match lookup(&self.merged, &key) {
+            Some(toml::Value::String(text)) => println!("{}", text),
+            Some(value) => println!("{}", value),
+            None => bail!("{} is not set in any config file", key),
+        }
+        Ok(())
+    }
+}
+
+/// The value at a dotted key of `table`.
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let (first, rest) = match key.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (key, None),
+    };
+    match (table.get(first)?, rest) {
+        (value, None) => Some(value),
+        (toml::Value::Table(inner), Some(rest)) => lookup(inner, rest),
+        _ => None,
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    toml::from_str(&content).context(SheafyError::ConfigInvalid {
+        paths: vec![path.to_path_buf()],
+    })
+}
+
+/// Lays `upper` (from `origin`) over `lower`: tables merge key by key, other values
+/// replace lower ones, except `sheafy.ignore_patterns`, whose lines are appended.
+fn merge_tables(
+    mut lower: toml::Table,
+    upper: toml::Table,
+    origin: Origin,
+    prefix: &str,
+    origins: &mut BTreeMap<String, Origin>,
+) -> toml::Table {
+    for (key, value) in upper {
+        let path = format!("{}{}", prefix, key);
+        let merged = match (lower.remove(&key), value) {
+            (Some(toml::Value::Table(below)), toml::Value::Table(above)) => toml::Value::Table(
+                merge_tables(below, above, origin, &format!("{}.", path), origins),
+            ),
+            (Some(toml::Value::String(below)), toml::Value::String(above))
+                if path == "sheafy.ignore_patterns" =>
+            {
+                origins.insert(path, Origin::Both);
+                let separator = if below.ends_with('\n') { "" } else { "\n" };
+                toml::Value::String(below + separator + &above)
+            }
+            (_, value) => {
+                origins.remove(&path);
+                origins.retain(|key, _| !key.starts_with(&format!("{}.", path)));
+                mark(&path, &value, origin, origins);
+                value
+            }
+        };
+        lower.insert(key, merged);
+    }
+    lower
+}
+
+/// Records `origin` for `value` and, for a table, every value in it.
+fn mark(path: &str, value: &toml::Value, origin: Origin, origins: &mut BTreeMap<String, Origin>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                mark(&format!("{}.{}", path, key), value, origin, origins);
+            }
+        }
+        _ => {
+            origins.insert(path.to_string(), origin);
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Default)]
 pub struct Config {
     #[serde(default)]
     pub sheafy: SheafyConfig,
+    /// The project config file the settings were read from, if there was one
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 impl Config {
+    /// The effective config: the user-wide defaults (see [`global_config_path`])
+    /// overridden by `sheafy.toml` in the current directory.
     pub fn load() -> Result<Self> {
+        Ok(Self::load_layered()?.config)
+    }
+
+    /// Like [`Self::load`], also telling where each value came from.
+    pub fn load_layered() -> Result<Layered> {
+        let global_path = global_config_path().filter(|path| path.is_file());
+        let global = match &global_path {
+            Some(path) => read_table(path)?,
+            None => toml::Table::new(),
+        };
         let config_path = Path::new(CONFIG_FILENAME);
-        if config_path.exists() {
-            let config_content = fs::read_to_string(config_path)
-                .with_context(|| format!("Failed to read config file: {}", CONFIG_FILENAME))?;
-            toml::from_str(&config_content)
-                .with_context(|| format!("Failed to parse config file: {}", CONFIG_FILENAME))
+        let project = if config_path.exists() {
+            read_table(config_path)?
         } else {
-            Ok(Self::default())
+            toml::Table::new()
+        };
+        let mut origins = BTreeMap::new();
+        let mut merged: toml::Table = toml::from_str(DEFAULT_CONFIG_CONTENT)?;
+        for (key, value) in &merged {
+            mark(key, value, Origin::Default, &mut origins);
+        }
+        merged = merge_tables(merged, global, Origin::Global, "", &mut origins);
+        merged = merge_tables(merged, project, Origin::Project, "", &mut origins);
+        if let Some(lang) = std::env::var("SHEAFY_LANG").ok().filter(|l| !l.is_empty()) {
+            let mut sheafy = toml::Table::new();
+            sheafy.insert("lang".to_string(), toml::Value::String(lang));
+            let env = toml::Table::from_iter([("sheafy".to_string(), toml::Value::Table(sheafy))]);
+            merged = merge_tables(merged, env, Origin::Env, "", &mut origins);
         }
+        let mut config: Self =
+            merged
+                .clone()
+                .try_into()
+                .with_context(|| SheafyError::ConfigInvalid {
+                    paths: global_path
+                        .iter()
+                        .cloned()
+                        .chain([PathBuf::from(CONFIG_FILENAME)])
+                        .collect(),
+                })?;
+        config.path = config_path.exists().then(|| config_path.to_path_buf());
+        crate::i18n::select(config.sheafy.lang.as_deref());
+        Ok(Layered {
+            config,
+            merged,
+            origins,
+            global_path,
+        })
     }
 
     pub fn init() -> Result<()> {
+        Self::confirm_overwrite()?;
+        Self::create(DEFAULT_CONFIG_CONTENT)?;
+        status!("Created default config file at {}", CONFIG_FILENAME);
+        Ok(())
+    }
+
+    /// Fails if a config file exists, unless the user agrees to replace it (`--yes`).
+    pub fn confirm_overwrite() -> Result<()> {
         let config_path = Path::new(CONFIG_FILENAME);
-        if config_path.exists() {
+        if config_path.exists()
+            && !crate::prompt::confirm(
+                &format!(
+                    "Config file {} already exists. Overwrite it?",
+                    config_path.display()
+                ),
+                false,
+            )?
+        {
             bail!("Config file already exists at {}", config_path.display());
         }
+        Ok(())
+    }
 
+    /// Writes `content` as the config file, after [`Self::confirm_overwrite`].
+    pub fn create(content: &str) -> Result<()> {
+        let config_path = Path::new(CONFIG_FILENAME);
         let mut file = File::create(config_path)
             .with_context(|| format!("Failed to create config file: {}", CONFIG_FILENAME))?;
-        file.write_all(DEFAULT_CONFIG_CONTENT.as_bytes())
+        file.write_all(content.as_bytes())
             .with_context(|| format!("Failed to write config file: {}", CONFIG_FILENAME))?;
-
-        println!("Created default config file at {}", config_path.display());
         Ok(())
     }
 
@@ -94,12 +882,13 @@ impl Config {
         if let Some(working_dir) = &self.sheafy.working_dir {
             let working_dir_path = current_dir.join(working_dir);
             if working_dir_path.exists() {
-                Ok(working_dir_path.canonicalize().with_context(|| format!("Failed to canonicalize working directory path: {}", working_dir_path.display()))?) // Canonicalize for consistency
+                // Canonical for consistency, without Windows' verbatim prefix
+                Ok(crate::paths::absolute(&working_dir_path, &current_dir))
             } else {
-                bail!(
-                    "Working directory does not exist: {}",
-                    working_dir_path.display()
-                );
+                Err(SheafyError::WorkingDirNotFound {
+                    path: working_dir_path,
+                }
+                .into())
             }
         } else {
             Ok(current_dir)
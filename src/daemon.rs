@@ -0,0 +1,459 @@
+//! Long-running JSON-RPC mode for editor integrations (`sheafy daemon --stdio`) and
+//! for fast repeated bundling (`sheafy daemon --socket`).
+//!
+//! Each request is a single line of JSON-RPC 2.0 on stdin (or a socket connection);
+//! each response is a single line on stdout (or the connection). Progress messages are
+//! routed to stderr so stdout only ever carries protocol traffic. On a socket, the
+//! daemon keeps scan results and file statistics warm between requests (see
+//! [`crate::warm`]), and `sheafy bundle` hands its work to it when it is running.
+//! Supported methods:
+//! - `bundle`  (params: same keys as the `bundle` CLI flags, e.g. `{"output": "b.md"}`)
+//! - `restore` (params: `{"input_file": "b.md"}` or `{"all": "bundles/"}`, plus
+//!   `"on_conflict": "skip"`)
+//! - `list`    (params: as `bundle`; returns the files that would be bundled)
+//! - `diff`    (params: as `restore`; compares bundle sections with the working tree)
+//! - `stats`   (params: as `bundle`; returns file, byte, line and token totals,
+//!   overall and per language)
+//! - `shutdown`
+//!
+//! Failures with a stable code (see [`crate::error`]) carry it as `error.data.code`.
+
+use crate::{
+    bundle::{self, BundleSummary, Selection},
+    cli::{BundleArgs, RestoreArgs},
+    config::Config,
+    restore,
+    tokens::Counter,
+};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+/// Name of the socket `sheafy daemon --socket` listens on by default, in `.sheafy/`.
+pub const SOCKET_NAME: &str = "daemon.sock";
+/// Set (to anything) to make `sheafy bundle` ignore a running daemon.
+pub const NO_DAEMON_ENV: &str = "SHEAFY_NO_DAEMON";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+/// The client's environment would make a different bundle than the daemon's
+const ENV_MISMATCH: i64 = -32001;
+
+/// Variables besides `SHEAFY_*` that change what a bundle holds: the time reproducible
+/// bundles record and where the user-wide config is found
+const BUNDLE_ENV: &[&str] = &["SOURCE_DATE_EPOCH", "XDG_CONFIG_HOME", "HOME", "APPDATA"];
+
+struct RpcError {
+    code: i64,
+    message: String,
+    /// Stable code of a [`crate::error::SheafyError`], as `error.data.code`
+    sheafy_code: Option<&'static str>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            sheafy_code: None,
+        }
+    }
+}
+
+pub fn run_stdio() -> Result<()> {
+    crate::status::redirect_to_stderr(true);
+    status!("sheafy daemon listening on stdio");
+
+    serve(io::stdin().lock(), io::stdout()).map(|_| ())
+}
+
+/// Answers the requests read from `reader` on `writer` until the input ends or a
+/// `shutdown` request arrives; returns whether one did.
+fn serve(reader: impl BufRead, mut writer: impl Write) -> Result<bool> {
+    for line in reader.lines() {
+        let line = line.context("Failed to read request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, shutdown) = handle_line(&line);
+        if let Some(response) = response {
+            let written = writeln!(writer, "{}", response).and_then(|_| writer.flush());
+            // A client that asks for shutdown may hang up without reading the response
+            if !shutdown {
+                written.context("Failed to write response")?;
+            }
+        }
+        if shutdown {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Where the daemon of the project in the current directory listens by default.
+pub fn default_socket_path() -> Result<PathBuf> {
+    let config = Config::load().context("Failed to load configuration")?;
+    Ok(crate::state::state_dir(&config.get_working_dir()?).join(SOCKET_NAME))
+}
+
+/// The variables of this process's environment that change what a bundle holds.
+fn bundle_env() -> BTreeMap<String, String> {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value)))
+        .filter(|(name, _)| {
+            (name.starts_with("SHEAFY_") && name != NO_DAEMON_ENV)
+                || BUNDLE_ENV.contains(&name.as_str())
+        })
+        .map(|(name, value)| (name, value.to_string_lossy().into_owned()))
+        .collect()
+}
+
+/// Whether `path` is a socket left behind by a daemon that no longer listens on it.
+#[cfg(unix)]
+pub fn is_stale_socket(path: &Path) -> bool {
+    use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+
+    std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket())
+        && UnixStream::connect(path).is_err()
+}
+
+#[cfg(not(unix))]
+pub fn is_stale_socket(_path: &Path) -> bool {
+    false
+}
+
+/// `sheafy daemon --socket`: serves connections one at a time, with warm caches, until
+/// a `shutdown` request. The socket file is removed on shutdown; one left behind by a
+/// daemon that was killed is replaced. `verbose` prints the cache counts after each
+/// connection.
+#[cfg(unix)]
+pub fn run_socket(path: Option<&str>, paranoid: bool, verbose: bool) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_socket_path()?,
+    };
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            bail!("A sheafy daemon is already listening on {}", path.display());
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    crate::warm::enable(paranoid);
+    status!("sheafy daemon listening on {}", path.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Warning: Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        let reader = match stream.try_clone() {
+            Ok(reader) => io::BufReader::new(reader),
+            Err(e) => {
+                eprintln!("Warning: Failed to read from a connection: {}", e);
+                continue;
+            }
+        };
+        // A client that goes away mid-request must not stop the daemon
+        let served = serve(reader, &stream);
+        if let Some(counts) = crate::warm::counts().filter(|_| verbose) {
+            status!("{}", counts.describe());
+        }
+        match served {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => eprintln!("Warning: Connection closed: {:#}", e),
+        }
+    }
+    std::fs::remove_file(&path)
+        .with_context(|| format!("Failed to remove socket {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn run_socket(_path: Option<&str>, _paranoid: bool, _verbose: bool) -> Result<()> {
+    bail!("`sheafy daemon --socket` needs unix domain sockets; use --stdio on this platform")
+}
+
+/// Runs `sheafy bundle` through the daemon listening on the project's default socket.
+/// Returns `None` without a running daemon, when `SHEAFY_NO_DAEMON` is set, when the
+/// arguments need this process's stdin or terminal, or when the daemon's environment
+/// differs in a variable that changes the bundle; the caller then bundles itself.
+#[cfg(unix)]
+pub fn bundle_via_socket(config: &Config, args: &BundleArgs) -> Result<Option<BundleSummary>> {
+    use std::os::unix::net::UnixStream;
+
+    if std::env::var_os(NO_DAEMON_ENV).is_some()
+        || args.files_from.as_deref() == Some("-")
+        || args.prompt_file.as_deref() == Some("-")
+        || args.show_excluded
+        || args.check
+    {
+        return Ok(None);
+    }
+    let path = crate::state::state_dir(&config.get_working_dir()?).join(SOCKET_NAME);
+    let Ok(mut stream) = UnixStream::connect(&path) else {
+        return Ok(None);
+    };
+    // Chunks are copied here, where the clipboard and the terminal are
+    let params = BundleArgs {
+        copy_chunks: None,
+        ..args.clone()
+    };
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "bundle",
+        "params": params,
+        "env": bundle_env(),
+    });
+    writeln!(stream, "{}", request).context("Failed to send the request to the daemon")?;
+    let mut line = String::new();
+    io::BufReader::new(&stream)
+        .read_line(&mut line)
+        .context("Failed to read the daemon's response")?;
+    let response: Value =
+        serde_json::from_str(&line).context("The daemon sent an invalid response")?;
+    if let Some(error) = response.get("error") {
+        if error["code"].as_i64() == Some(ENV_MISMATCH) {
+            status!(
+                "The daemon on {} runs with other SHEAFY_*, SOURCE_DATE_EPOCH or config location variables; bundling here.",
+                path.display()
+            );
+            return Ok(None);
+        }
+        let message = error["message"].as_str().unwrap_or("unknown error");
+        match error["data"]["code"].as_str() {
+            Some(code) => bail!("{} (daemon, error code {})", message, code),
+            None => bail!("{} (daemon)", message),
+        }
+    }
+    let result = &response["result"];
+    let summary = BundleSummary {
+        output: PathBuf::from(result["output"].as_str().unwrap_or_default()),
+        files: result["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(PathBuf::from)
+            .collect(),
+    };
+    status!(
+        "Bundled {} file(s) into '{}' via the daemon on {}.",
+        summary.files.len(),
+        summary.output.display(),
+        path.display()
+    );
+    if let Some(max_chars) = args.copy_chunks {
+        let text = std::fs::read_to_string(&summary.output)
+            .with_context(|| format!("Failed to read bundle: {}", summary.output.display()))?;
+        crate::clipboard::copy_in_chunks(&text, max_chars)?;
+    }
+    Ok(Some(summary))
+}
+
+#[cfg(not(unix))]
+pub fn bundle_via_socket(_config: &Config, _args: &BundleArgs) -> Result<Option<BundleSummary>> {
+    Ok(None)
+}
+
+/// Handles one request line, returning the response (None for notifications)
+/// and whether the daemon should exit.
+fn handle_line(line: &str) -> (Option<Value>, bool) {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return (
+                Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                )),
+                false,
+            )
+        }
+    };
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let error = RpcError::new(INVALID_REQUEST, "Missing method");
+        return (
+            Some(error_response(id.unwrap_or(Value::Null), error)),
+            false,
+        );
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let shutdown = method == "shutdown";
+
+    // Clients send the variables that shape a bundle; with others, they bundle themselves
+    let result = match request.get("env") {
+        Some(env) if *env != json!(bundle_env()) => Err(RpcError::new(
+            ENV_MISMATCH,
+            "The client's environment differs from the daemon's",
+        )),
+        _ => dispatch(method, params),
+    };
+    // Requests without an id are notifications and never get a response
+    let response = id.map(|id| match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    });
+    (response, shutdown)
+}
+
+fn dispatch(method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "bundle" => {
+            let args: BundleArgs = parse_params(params)?;
+            let summary = bundle::run_bundle(load_config()?, args).map_err(server_error)?;
+            Ok(json!({
+                "output": display_path(&summary.output),
+                "files": summary.files.iter().map(|p| display_path(p)).collect::<Vec<_>>(),
+            }))
+        }
+        "list" => {
+            let args: BundleArgs = parse_params(params)?;
+            let selection = bundle::select_files(&load_config()?, &args).map_err(server_error)?;
+            Ok(json!({
+                "working_dir": display_path(&selection.working_dir),
+                "files": selection.files.iter().map(|p| display_path(p)).collect::<Vec<_>>(),
+            }))
+        }
+        "stats" => {
+            let args: BundleArgs = parse_params(params)?;
+            let config = load_config()?;
+            let selection = bundle::select_files(&config, &args).map_err(server_error)?;
+            let counter =
+                Counter::from_config(&config, &selection.working_dir).map_err(server_error)?;
+            stats(&selection, &counter).map_err(server_error)
+        }
+        "restore" => {
+            let args: RestoreArgs = parse_params(params)?;
+            let summary = restore::run_restore(&load_config()?, &args).map_err(server_error)?;
+            Ok(json!({
+                "input": display_path(&summary.input),
+                "restored": summary.restored.iter().map(|p| display_path(p)).collect::<Vec<_>>(),
+                "conflicts": summary.conflicts.iter().map(|c| c.path.as_str()).collect::<Vec<_>>(),
+            }))
+        }
+        "diff" => {
+            let args: RestoreArgs = parse_params(params)?;
+            let entries = restore::diff_bundle(
+                &load_config()?,
+                args.input_file.as_deref(),
+                &args.vars,
+                args.diff_base,
+            )
+            .map_err(server_error)?;
+            Ok(json!({
+                "entries": entries
+                    .into_iter()
+                    .map(|(path, status)| json!({ "path": path, "status": status.as_str() }))
+                    .collect::<Vec<_>>(),
+            }))
+        }
+        "shutdown" => Ok(Value::Null),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+/// Totals for the `stats` method, from the warm per-file statistics.
+fn stats(selection: &Selection, counter: &Counter) -> Result<Value> {
+    // Language -> (files, bytes, tokens)
+    let mut languages: BTreeMap<&str, (usize, u64, usize)> = BTreeMap::new();
+    let (mut bytes, mut lines, mut tokens) = (0, 0, 0);
+    for file in &selection.files {
+        let path = selection.working_dir.join(file);
+        let file_stats = crate::warm::file_stats(&path, counter)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let language = selection
+            .scopes
+            .language_hint(file)
+            .unwrap_or_else(|| crate::sniff::language_hint(file, ""));
+        let totals = languages
+            .entry(if language.is_empty() {
+                "text"
+            } else {
+                language
+            })
+            .or_default();
+        totals.0 += 1;
+        totals.1 += file_stats.bytes;
+        totals.2 += file_stats.tokens;
+        bytes += file_stats.bytes;
+        lines += file_stats.lines;
+        tokens += file_stats.tokens;
+    }
+    Ok(json!({
+        "working_dir": display_path(&selection.working_dir),
+        "cache": crate::warm::counts(),
+        "files": selection.files.len(),
+        "source_bytes": bytes,
+        "lines": lines,
+        "tokens": tokens,
+        "languages": languages
+            .into_iter()
+            .map(|(language, (files, bytes, tokens))| {
+                (language.to_string(), json!({ "files": files, "bytes": bytes, "tokens": tokens }))
+            })
+            .collect::<serde_json::Map<_, _>>(),
+    }))
+}
+
+fn parse_params<T: for<'de> Deserialize<'de> + Default>(params: Value) -> Result<T, RpcError> {
+    if params.is_null() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+/// Config is reloaded for every request so edits to sheafy.toml are picked up.
+fn load_config() -> Result<Config, RpcError> {
+    Config::load()
+        .context("Failed to load configuration")
+        .map_err(server_error)
+}
+
+fn server_error(e: anyhow::Error) -> RpcError {
+    RpcError {
+        sheafy_code: crate::error::code(&e),
+        ..RpcError::new(SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    let mut body = json!({ "code": error.code, "message": error.message });
+    if let Some(code) = error.sheafy_code {
+        body["data"] = json!({ "code": code });
+    }
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": body,
+    })
+}
+
+fn display_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "/")
+}
@@ -0,0 +1,313 @@
+//! Dependency-aware file selection for `sheafy bundle --focus`.
+//!
+//! Starting from a focus file, references are followed up to a configurable
+//! number of hops. Supported reference kinds:
+//! - Rust: `mod foo;` declarations and `crate::`/`super::`/`self::` paths
+//! - Python: `import a.b` and `from .a import b` statements
+//! - TypeScript/JavaScript: relative `import`/`export ... from`/`require()` specifiers
+//!
+//! Resolution is purely lexical and only ever yields files that are already
+//! part of the candidate set produced by the walker, so ignore rules still apply.
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+lazy_static! {
+    static ref RUST_MOD_REGEX: Regex =
+        Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+([A-Za-z_][A-Za-z0-9_]*)\s*;").unwrap();
+    static ref RUST_USE_REGEX: Regex = Regex::new(r"(?s)\buse\s+([^;]+);").unwrap();
+    static ref RUST_USE_ALIAS_REGEX: Regex = Regex::new(r"\s+as\s+[A-Za-z_][A-Za-z0-9_]*").unwrap();
+    static ref RUST_PATH_REGEX: Regex =
+        Regex::new(r"\b((?:crate|super|self)(?:::[A-Za-z_][A-Za-z0-9_]*)+)").unwrap();
+    static ref PY_IMPORT_REGEX: Regex =
+        Regex::new(r"(?m)^\s*import\s+([A-Za-z0-9_.,\s]+?)\s*(?:#.*)?$").unwrap();
+    static ref PY_FROM_REGEX: Regex =
+        Regex::new(r"(?m)^\s*from\s+(\.*[A-Za-z0-9_.]*)\s+import\s+\(?\s*([A-Za-z0-9_,\s]+)")
+            .unwrap();
+    static ref JS_IMPORT_REGEX: Regex =
+        Regex::new(r#"(?:\bfrom\s*|\bimport\s*\(?\s*|\brequire\s*\(\s*)["'](\.{1,2}/[^"']*)["']"#)
+            .unwrap();
+}
+
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Returns the focus file plus every candidate reachable from it within `depth` hops.
+/// All paths are relative to `working_dir`.
+pub fn collect_focus_set(
+    working_dir: &Path,
+    focus: &Path,
+    depth: usize,
+    candidates: &[PathBuf],
+) -> Result<BTreeSet<PathBuf>> {
+    let candidate_set: HashSet<PathBuf> = candidates.iter().map(|p| normalize(p)).collect();
+    let focus = normalize(focus.strip_prefix(working_dir).unwrap_or(focus));
+    if !candidate_set.contains(&focus) {
+        bail!(
+            "Focus file '{}' is not among the files selected for bundling (check the path and ignore rules)",
+            focus.display()
+        );
+    }
+
+    let mut selected = BTreeSet::new();
+    let mut queue = VecDeque::from([(focus.clone(), 0usize)]);
+    selected.insert(focus);
+
+    while let Some((current, hops)) = queue.pop_front() {
+        if hops >= depth {
+            continue;
+        }
+        let content = match fs::read_to_string(working_dir.join(&current)) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Could not read '{}' while resolving references: {}",
+                    current.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        for reference in find_references(working_dir, &current, &content, &candidate_set) {
+            if selected.insert(reference.clone()) {
+                status!(
+                    "  Following reference: {} -> {}",
+                    current.display(),
+                    reference.display()
+                );
+                queue.push_back((reference, hops + 1));
+            }
+        }
+    }
+
+    Ok(selected)
+}
+
+/// The references between `files` (relative to `working_dir`): each file that
+/// references others, with those files. Unreadable files have no references.
+pub fn reference_graph(working_dir: &Path, files: &[PathBuf]) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let candidate_set: HashSet<PathBuf> = files.iter().map(|p| normalize(p)).collect();
+    let mut graph = BTreeMap::new();
+    for file in files {
+        let file = normalize(file);
+        let Ok(content) = fs::read_to_string(working_dir.join(&file)) else {
+            continue;
+        };
+        let references = find_references(working_dir, &file, &content, &candidate_set);
+        if !references.is_empty() {
+            graph.insert(file, references);
+        }
+    }
+    graph
+}
+
+/// Finds the candidate files referenced by `file` (relative to `working_dir`).
+fn find_references(
+    working_dir: &Path,
+    file: &Path,
+    content: &str,
+    candidates: &HashSet<PathBuf>,
+) -> Vec<PathBuf> {
+    let ext = file.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let possible = match ext {
+        "rs" => rust_references(working_dir, file, content),
+        "py" => python_references(file, content),
+        e if JS_EXTENSIONS.contains(&e) => js_references(file, content),
+        _ => Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    possible
+        .into_iter()
+        .map(|p| normalize(&p))
+        .filter(|p| p != file && candidates.contains(p) && seen.insert(p.clone()))
+        .collect()
+}
+
+fn rust_references(working_dir: &Path, file: &Path, content: &str) -> Vec<PathBuf> {
+    let mut refs = Vec::new();
+    let module_dir = rust_module_dir(file);
+
+    for cap in RUST_MOD_REGEX.captures_iter(content) {
+        let name = &cap[1];
+        refs.push(module_dir.join(format!("{}.rs", name)));
+        refs.push(module_dir.join(name).join("mod.rs"));
+    }
+
+    let Some(crate_src) = rust_crate_src(working_dir, file) else {
+        return refs;
+    };
+    let mut paths: Vec<String> = Vec::new();
+    for cap in RUST_USE_REGEX.captures_iter(content) {
+        paths.extend(expand_use_tree(&cap[1]));
+    }
+    paths.extend(
+        RUST_PATH_REGEX
+            .captures_iter(content)
+            .map(|c| c[1].to_string()),
+    );
+
+    for path in paths {
+        let segments: Vec<&str> = path.split("::").map(str::trim).collect();
+        let (base, rest) = match segments.first() {
+            Some(&"crate") => (crate_src.clone(), &segments[1..]),
+            Some(&"self") => (module_dir.clone(), &segments[1..]),
+            Some(&"super") => {
+                let mut base = module_dir.clone();
+                let mut rest = &segments[..];
+                while rest.first() == Some(&"super") {
+                    base = base.parent().map(Path::to_path_buf).unwrap_or_default();
+                    rest = &rest[1..];
+                }
+                (base, rest)
+            }
+            _ => continue,
+        };
+        // The last segments may name items rather than modules, so try every prefix.
+        for len in 1..=rest.len() {
+            let module = rest[..len].iter().collect::<PathBuf>();
+            refs.push(base.join(&module).with_extension("rs"));
+            refs.push(base.join(&module).join("mod.rs"));
+        }
+    }
+    refs
+}
+
+/// Directory holding the child modules declared by a Rust source file.
+fn rust_module_dir(file: &Path) -> PathBuf {
+    let parent = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    match file.file_stem().and_then(|s| s.to_str()) {
+        Some("main" | "lib" | "mod") | None => parent,
+        Some(stem) => parent.join(stem),
+    }
+}
+
+/// Locates the `src` directory of the crate containing `file`, if any.
+fn rust_crate_src(working_dir: &Path, file: &Path) -> Option<PathBuf> {
+    let mut dir = file.parent();
+    while let Some(d) = dir {
+        if working_dir.join(d).join("Cargo.toml").exists() {
+            return Some(d.join("src"));
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+/// Expands a use tree such as `crate::{a, b::{c, d}}` into flat paths.
+fn expand_use_tree(tree: &str) -> Vec<String> {
+    let tree: String = RUST_USE_ALIAS_REGEX
+        .replace_all(tree, "")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let Some(open) = tree.find('{') else {
+        return vec![tree];
+    };
+    let prefix = &tree[..open];
+    let Some(close) = tree.rfind('}') else {
+        return vec![tree];
+    };
+    let mut results = Vec::new();
+    let mut level = 0;
+    let mut start = open + 1;
+    for (i, c) in tree[..=close].char_indices().skip(open + 1) {
+        match c {
+            '{' => level += 1,
+            '}' if level > 0 => level -= 1,
+            ',' | '}' if level == 0 => {
+                let item = &tree[start..i];
+                if !item.is_empty() {
+                    for sub in expand_use_tree(item) {
+                        results.push(format!("{}{}", prefix, sub));
+                    }
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    results
+}
+
+fn python_references(file: &Path, content: &str) -> Vec<PathBuf> {
+    let mut refs = Vec::new();
+    let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let module_candidates = |base: &Path, module: &str| {
+        let rel: PathBuf = module.split('.').filter(|s| !s.is_empty()).collect();
+        vec![
+            base.join(&rel).with_extension("py"),
+            base.join(&rel).join("__init__.py"),
+        ]
+    };
+
+    for cap in PY_IMPORT_REGEX.captures_iter(content) {
+        for module in cap[1].split(',') {
+            let module = module.split_whitespace().next().unwrap_or("");
+            if !module.is_empty() {
+                refs.extend(module_candidates(Path::new(""), module));
+            }
+        }
+    }
+
+    for cap in PY_FROM_REGEX.captures_iter(content) {
+        let module = &cap[1];
+        let dots = module.chars().take_while(|c| *c == '.').count();
+        let base = if dots == 0 {
+            PathBuf::new()
+        } else {
+            let mut base = dir.clone();
+            for _ in 1..dots {
+                base = base.parent().map(Path::to_path_buf).unwrap_or_default();
+            }
+            base
+        };
+        let module = &module[dots..];
+        if !module.is_empty() {
+            refs.extend(module_candidates(&base, module));
+        }
+        // `from pkg import name` may import a submodule rather than an attribute.
+        for name in cap[2].split(',') {
+            let name = name.split_whitespace().next().unwrap_or("");
+            if !name.is_empty() {
+                refs.extend(module_candidates(&base, &format!("{}.{}", module, name)));
+            }
+        }
+    }
+    refs
+}
+
+fn js_references(file: &Path, content: &str) -> Vec<PathBuf> {
+    let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut refs = Vec::new();
+    for cap in JS_IMPORT_REGEX.captures_iter(content) {
+        let target = dir.join(&cap[1]);
+        refs.push(target.clone());
+        for ext in JS_EXTENSIONS {
+            let mut with_ext = target.clone().into_os_string();
+            with_ext.push(format!(".{}", ext));
+            refs.push(PathBuf::from(with_ext));
+            refs.push(target.join(format!("index.{}", ext)));
+        }
+    }
+    refs
+}
+
+/// Lexically normalizes a relative path, resolving `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
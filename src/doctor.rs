@@ -0,0 +1,326 @@
+//! `sheafy doctor`: check the environment sheafy runs in and say how to fix what is off.
+//!
+//! Unlike the other commands it does not need a valid `sheafy.toml`: a config that
+//! fails to parse is one of the things it reports. Each check prints `ok`, `warn` or
+//! `fail` with a one-line remediation; only failures make the command exit non-zero.
+
+use crate::clipboard::{on_path, TOOLS};
+use crate::config::{global_config_path, Config, CONFIG_FILENAME};
+use crate::state::{self, State};
+use anyhow::{bail, Result};
+use ignore::gitignore::GitignoreBuilder;
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, topic: &str, message: &str) {
+        status!("  ok    {:<10} {}", topic, message);
+    }
+
+    fn warn(&mut self, topic: &str, message: &str, fix: &str) {
+        self.warnings += 1;
+        status!("  warn  {:<10} {}", topic, message);
+        status!("        {:<10} fix: {}", "", fix);
+    }
+
+    fn fail(&mut self, topic: &str, message: &str, fix: &str) {
+        self.failures += 1;
+        status!("  fail  {:<10} {}", topic, message);
+        status!("        {:<10} fix: {}", "", fix);
+    }
+}
+
+pub fn run_doctor() -> Result<()> {
+    let mut report = Report::default();
+    status!("Checking sheafy's environment:");
+    let config = check_config(&mut report);
+    let working_dir = config
+        .as_ref()
+        .map(|config| check_working_dir(&mut report, config));
+    check_git(&mut report, working_dir.as_ref().and_then(Option::as_deref));
+    check_clipboard(&mut report);
+    check_terminal(&mut report);
+    if let Some(Some(working_dir)) = &working_dir {
+        check_state(&mut report, working_dir);
+    }
+    report.ok(
+        "features",
+        if cfg!(feature = "async-io") {
+            "async-io built in (bundle --io async)"
+        } else {
+            "async-io not built in; bundle --io async is unavailable"
+        },
+    );
+
+    status!(
+        "\n{} problem(s), {} warning(s).",
+        report.failures,
+        report.warnings
+    );
+    if report.failures > 0 {
+        bail!("sheafy doctor found {} problem(s)", report.failures);
+    }
+    Ok(())
+}
+
+/// Parses `sheafy.toml`, reporting keys sheafy does not know (they are otherwise
+/// silently ignored).
+fn check_config(report: &mut Report) -> Option<Config> {
+    if let Some(global) = global_config_path().filter(|path| path.is_file()) {
+        let parsed = fs::read_to_string(&global)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(toml::from_str::<Config>(&text)?));
+        match parsed {
+            Ok(_) => report.ok(
+                "config",
+                &format!("user-wide defaults from {}", global.display()),
+            ),
+            Err(e) => report.fail(
+                "config",
+                &format!(
+                    "{} does not load: {}",
+                    global.display(),
+                    e.to_string().lines().last().unwrap_or_default().trim()
+                ),
+                "correct the value named above; it applies to every project",
+            ),
+        }
+    }
+    let path = Path::new(CONFIG_FILENAME);
+    if !path.exists() {
+        report.ok("config", "no sheafy.toml; using the defaults");
+        return Some(Config::default());
+    }
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            report.fail(
+                "config",
+                &format!("cannot read {}: {}", CONFIG_FILENAME, e),
+                "check the file's permissions and that it is UTF-8",
+            );
+            return None;
+        }
+    };
+    let mut unknown = Vec::new();
+    let parsed: Result<Config, _> =
+        serde_ignored::deserialize(toml::Deserializer::new(&text), |key| {
+            unknown.push(key.to_string())
+        });
+    let config = match parsed {
+        Ok(config) => config,
+        Err(e) => {
+            let message = e.to_string();
+            report.fail(
+                "config",
+                &format!(
+                    "{} does not parse: {}",
+                    CONFIG_FILENAME,
+                    message.lines().last().unwrap_or_default().trim()
+                ),
+                "correct the value named above, or move the file away and run `sheafy init`",
+            );
+            return None;
+        }
+    };
+    if unknown.is_empty() {
+        report.ok("config", &format!("{} parses", CONFIG_FILENAME));
+    } else {
+        report.warn(
+            "config",
+            &format!("unknown key(s), ignored: {}", unknown.join(", ")),
+            "check the spelling against the commented template `sheafy init` writes",
+        );
+    }
+    check_ignore_patterns(report, &config);
+    Some(config)
+}
+
+fn check_ignore_patterns(report: &mut Report, config: &Config) {
+    let Some(patterns) = config.sheafy.ignore_patterns.as_deref() else {
+        return;
+    };
+    let invalid = patterns
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter(|line| {
+            GitignoreBuilder::new(".")
+                .add_line(None, line)
+                .and_then(|builder| builder.build())
+                .is_err()
+        })
+        .count();
+    if invalid > 0 {
+        report.fail(
+            "config",
+            &format!("{} ignore pattern(s) do not parse", invalid),
+            "run `sheafy config lint` to see which",
+        );
+    }
+}
+
+/// Returns the working directory if it exists.
+fn check_working_dir(report: &mut Report, config: &Config) -> Option<std::path::PathBuf> {
+    let working_dir = match config.get_working_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            report.fail(
+                "workdir",
+                &e.to_string(),
+                "create the directory or correct `working_dir` in sheafy.toml",
+            );
+            return None;
+        }
+    };
+    let shown = working_dir.display().to_string();
+    if let Err(e) = fs::read_dir(&working_dir) {
+        report.fail(
+            "workdir",
+            &format!("{} is not readable: {}", shown, e),
+            "grant read permission on the directory",
+        );
+    } else if let Err(e) = tempfile::NamedTempFile::new_in(&working_dir) {
+        report.warn(
+            "workdir",
+            &format!("{} is not writable: {}", shown, e),
+            "bundle with -o to a writable location; restore needs write access",
+        );
+    } else {
+        report.ok("workdir", &format!("{} is readable and writable", shown));
+    }
+    Some(working_dir)
+}
+
+fn check_git(report: &mut Report, working_dir: Option<&Path>) {
+    let version = match crate::git::version() {
+        Ok(version) => version,
+        Err(_) => {
+            report.warn(
+                "git",
+                "git is not on PATH",
+                "install git; --since, --tracked-only and --format patch need it",
+            );
+            return;
+        }
+    };
+    match working_dir {
+        Some(dir) if crate::git::is_work_tree(dir) => report.ok(
+            "git",
+            &format!("{}, working directory is a repository", version),
+        ),
+        Some(_) => report.ok(
+            "git",
+            &format!(
+                "{}, working directory is not a repository (git features are unavailable)",
+                version
+            ),
+        ),
+        None => report.ok("git", &version),
+    }
+}
+
+fn check_clipboard(report: &mut Report) {
+    let found: Vec<String> = TOOLS
+        .iter()
+        .filter(|tool| on_path(tool.program))
+        .map(|tool| format!("{} ({})", tool.program, tool.platform))
+        .collect();
+    if found.is_empty() {
+        report.warn(
+            "clipboard",
+            "no clipboard tool found on PATH",
+            "install wl-clipboard or xclip (Linux) to copy bundles to the clipboard, \
+             e.g. `xclip -selection clipboard < project_bundle.md`",
+        );
+    } else {
+        report.ok("clipboard", &found.join(", "));
+    }
+}
+
+fn check_terminal(report: &mut Report) {
+    if io::stdin().is_terminal() {
+        report.ok("terminal", "stdin is a terminal; questions are asked");
+    } else {
+        report.ok(
+            "terminal",
+            "stdin is not a terminal; questions take their default answer (pass --yes to agree)",
+        );
+    }
+    if io::stdout().is_terminal() {
+        let term = env::var("TERM").unwrap_or_default();
+        if term.is_empty() || term == "dumb" {
+            report.warn(
+                "terminal",
+                "TERM is unset or `dumb`",
+                "set TERM (e.g. xterm-256color) for full terminal output",
+            );
+        }
+    }
+}
+
+/// `.sheafy/`: state.json must parse, and the directory must be writable if it exists.
+fn check_state(report: &mut Report, working_dir: &Path) {
+    let dir = state::state_dir(working_dir);
+    if !dir.exists() {
+        report.ok("state", "no .sheafy/ directory yet");
+        return;
+    }
+    match State::load(working_dir) {
+        Ok(Some(state)) => report.ok(
+            "state",
+            &format!(
+                "{} records {} bundled path(s)",
+                state::STATE_FILE,
+                state.bundled_paths.len()
+            ),
+        ),
+        Ok(None) => report.ok("state", &format!("no {} yet", state::STATE_FILE)),
+        Err(e) => report.fail(
+            "state",
+            &format!("{:#}", e),
+            &format!(
+                "delete .sheafy/{}; the next bundle records paths again",
+                state::STATE_FILE
+            ),
+        ),
+    }
+    if let Err(e) = tempfile::NamedTempFile::new_in(&dir) {
+        report.fail(
+            "state",
+            &format!(".sheafy/ is not writable: {}", e),
+            "fix the directory's permissions or remove it",
+        );
+    }
+    let generated = state::generated_entries(working_dir);
+    if !generated.is_empty() {
+        let size: u64 = generated.iter().map(|path| disk_usage(path)).sum();
+        report.ok(
+            "cache",
+            &format!(
+                "{} generated entr(ies), {} byte(s); `sheafy clean` removes them",
+                generated.len(),
+                size
+            ),
+        );
+    }
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| disk_usage(&entry.path()))
+            .sum(),
+        Err(_) => fs::metadata(path).map(|meta| meta.len()).unwrap_or(0),
+    }
+}
@@ -0,0 +1,166 @@
+//! Failure kinds with stable codes, for embedders and scripts.
+//!
+//! Errors are still `anyhow::Error`s with context, but where sheafy fails for a reason a
+//! caller may want to act on, the cause or a context layer is a [`SheafyError`]: library users find it
+//! with [`find`] (or `downcast_ref`), the CLI prints its code after the message, and the
+//! daemon returns it as `error.data.code`. Codes are never reused or renumbered; the
+//! catalogue is [`SheafyError::CATALOGUE`] and the README.
+
+use std::{fmt, path::PathBuf};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SheafyError {
+    /// A config file that must exist does not, or cannot be located
+    ConfigNotFound { path: Option<PathBuf> },
+    /// The config files (the user-wide one, then `sheafy.toml`) are not valid TOML or
+    /// have a value of the wrong type
+    ConfigInvalid { paths: Vec<PathBuf> },
+    /// `working_dir` names a directory that does not exist
+    WorkingDirNotFound { path: PathBuf },
+    /// The bundle to read does not exist
+    BundleNotFound { path: PathBuf },
+    /// The bundle is malformed at `line` (1-based)
+    ParseError { line: usize, message: String },
+    /// The bundle was written by a newer sheafy
+    UnsupportedVersion { version: u32, supported: u32 },
+    /// The bundle holds patches or excerpts where whole files are needed
+    NotWholeFiles { path: PathBuf },
+    /// The bundle file is not UTF-8 text, from `line` on
+    NonUtf8File { line: usize },
+    /// The bundle would exceed `max_bundle_size`
+    BundleTooLarge { size: u64, max: u64 },
+    /// The scan found more than `max_files` files
+    TooManyFiles { max: usize },
+    /// The bundle's front matter declares it read-only and `--force-readonly` was not given
+    ReadonlyBundle { path: PathBuf },
+    /// `bundle --check` made a bundle that differs from the one at `path`
+    StaleBundle { path: PathBuf },
+    /// A bundle path is absolute or leads out of the working directory with `..`
+    PathEscape { path: String },
+    /// Bundle paths refused by `--tracked-only`, `allowed_paths` or the known paths
+    PathsRefused { paths: Vec<String>, reason: String },
+    /// A `restore --validate` check failed
+    ValidationFailed { command: String },
+    /// `restore --check` found files that differ from the bundle
+    TreeDiffers { differing: usize, checked: usize },
+    /// Restore could not write some files; the others were restored
+    RestoreIncomplete { failed: usize, restored: usize },
+    /// `restore --verify-checksums` found sections that differ from the bundle's checksums
+    ChecksumMismatch { mismatched: usize, listed: usize },
+}
+
+impl SheafyError {
+    /// Every code with a one-line description, in code order.
+    pub const CATALOGUE: &'static [(&'static str, &'static str)] = &[
+        ("E101", "config file not found"),
+        ("E102", "config file does not load"),
+        ("E103", "working directory not found"),
+        ("E201", "bundle not found"),
+        ("E202", "bundle does not parse"),
+        ("E203", "bundle format newer than this sheafy"),
+        ("E204", "bundle holds patches or excerpts, not whole files"),
+        ("E205", "bundle file is not UTF-8"),
+        ("E206", "bundle would exceed max_bundle_size"),
+        ("E207", "scan found more than max_files files"),
+        ("E208", "bundle is declared read-only"),
+        ("E209", "bundle is out of date"),
+        ("E301", "path escapes the working directory"),
+        ("E302", "paths refused by a restore guard"),
+        ("E303", "restore --validate check failed"),
+        ("E304", "working tree differs from the bundle"),
+        ("E305", "some files could not be restored"),
+        ("E306", "sections do not match the bundle's checksums"),
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ConfigNotFound { .. } => "E101",
+            Self::ConfigInvalid { .. } => "E102",
+            Self::WorkingDirNotFound { .. } => "E103",
+            Self::BundleNotFound { .. } => "E201",
+            Self::ParseError { .. } => "E202",
+            Self::UnsupportedVersion { .. } => "E203",
+            Self::NotWholeFiles { .. } => "E204",
+            Self::NonUtf8File { .. } => "E205",
+            Self::BundleTooLarge { .. } => "E206",
+            Self::TooManyFiles { .. } => "E207",
+            Self::ReadonlyBundle { .. } => "E208",
+            Self::StaleBundle { .. } => "E209",
+            Self::PathEscape { .. } => "E301",
+            Self::PathsRefused { .. } => "E302",
+            Self::ValidationFailed { .. } => "E303",
+            Self::TreeDiffers { .. } => "E304",
+            Self::RestoreIncomplete { .. } => "E305",
+            Self::ChecksumMismatch { .. } => "E306",
+        }
+    }
+}
+
+impl fmt::Display for SheafyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Looked up in the message catalogs, so users paste errors in their language
+        let message = match self {
+            Self::ConfigNotFound { path: Some(path) } => {
+                tr!("error.config_not_found", path.display())
+            }
+            Self::ConfigNotFound { path: None } => tr!("error.config_not_locatable"),
+            Self::ConfigInvalid { paths } => match paths.as_slice() {
+                [path] => tr!("error.config_invalid", path.display()),
+                paths => tr!(
+                    "error.configs_invalid",
+                    paths
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(" and ")
+                ),
+            },
+            Self::WorkingDirNotFound { path } => {
+                tr!("error.working_dir_not_found", path.display())
+            }
+            Self::BundleNotFound { path } => tr!("error.bundle_not_found", path.display()),
+            Self::ParseError { line, message } => tr!("error.parse", line, message),
+            Self::UnsupportedVersion { version, supported } => {
+                tr!("error.unsupported_version", version, supported)
+            }
+            Self::NotWholeFiles { path } => tr!("error.not_whole_files", path.display()),
+            Self::NonUtf8File { line } => tr!("error.non_utf8", line),
+            Self::BundleTooLarge { size, max } => tr!(
+                "error.bundle_too_large",
+                crate::config::ByteSize(*size),
+                crate::config::ByteSize(*max)
+            ),
+            Self::TooManyFiles { max } => tr!("error.too_many_files", max),
+            Self::ReadonlyBundle { path } => tr!("error.readonly", path.display()),
+            Self::StaleBundle { path } => tr!("error.stale", path.display()),
+            Self::PathEscape { path } => tr!("error.path_escape", path),
+            Self::PathsRefused { reason, .. } => reason.clone(),
+            Self::ValidationFailed { command } => tr!("error.validation_failed", command),
+            Self::TreeDiffers { differing, checked } => {
+                tr!("error.tree_differs", differing, checked)
+            }
+            Self::RestoreIncomplete { failed, restored } => {
+                tr!("error.restore_incomplete", failed, restored)
+            }
+            Self::ChecksumMismatch { mismatched, listed } => {
+                tr!("error.checksum_mismatch", mismatched, listed)
+            }
+        };
+        f.write_str(&message)
+    }
+}
+
+impl std::error::Error for SheafyError {}
+
+/// The [`SheafyError`] an error was caused by or given as context, if any.
+pub fn find(error: &anyhow::Error) -> Option<&SheafyError> {
+    error
+        .downcast_ref()
+        .or_else(|| error.chain().find_map(|cause| cause.downcast_ref()))
+}
+
+/// The stable code of an error, if it has one.
+pub fn code(error: &anyhow::Error) -> Option<&'static str> {
+    find(error).map(SheafyError::code)
+}
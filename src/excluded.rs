@@ -0,0 +1,284 @@
+//! `bundle --show-excluded`: lists the files that exist in the working directory but
+//! were left out of the bundle, grouped by the rule that excluded them.
+//!
+//! Each mechanism is peeled off with its own walk (no filters, hidden files, git
+//! ignore rules, project presets, `ignore_patterns`; directory settings are checked per
+//! file), so the reported reason is the first rule that applies. Directories excluded
+//! as a whole are shown once with a file count.
+
+use crate::bundle::Selection;
+use crate::cli::BundleArgs;
+use crate::config::Config;
+use ignore::WalkBuilder;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reason {
+    Hidden,
+    Gitignore,
+    Presets,
+    IgnorePatterns,
+    DirSettings,
+    MaxDepth,
+    Limits,
+    Baseline,
+    SheafyFiles,
+    Submodules,
+    Package,
+    Patterns,
+    Focus,
+    Since,
+    Other,
+}
+
+impl Reason {
+    fn describe(self) -> &'static str {
+        match self {
+            Reason::Hidden => "hidden files",
+            Reason::Gitignore => {
+                ".gitignore rules (including .git/info/exclude and global excludes)"
+            }
+            Reason::Presets => "built-in project presets (presets = false to disable)",
+            Reason::IgnorePatterns => "ignore_patterns in sheafy.toml",
+            Reason::DirSettings => {
+                "ignore_patterns of a directory ([sheafy.dir] or its sheafy.toml)"
+            }
+            Reason::MaxDepth => "max_depth",
+            Reason::Limits => "limits in sheafy.toml",
+            Reason::Baseline => "--baseline (unchanged)",
+            Reason::SheafyFiles => "sheafy's own files (config, output, .sheafy/, executable)",
+            Reason::Submodules => "submodules policy",
+            Reason::Package => "--package",
+            Reason::Patterns => "--include/--exclude (or the profile's include/exclude)",
+            Reason::Focus => "--focus",
+            Reason::Since => "--since",
+            Reason::Other => "other filters",
+        }
+    }
+}
+
+/// Prints the files a bundle run with `selection` left out.
+pub fn report_excluded(config: &Config, args: &BundleArgs, selection: &Selection) {
+    let Selection {
+        working_dir,
+        output_path,
+        files: selected,
+        use_gitignore,
+        limited,
+        unchanged,
+        scopes,
+        other_outputs,
+    } = selection;
+    let use_gitignore = *use_gitignore;
+    let parents = config.sheafy.parent_gitignore.unwrap_or(true);
+    let walk = |hidden, gitignore, patterns| {
+        walk(
+            working_dir,
+            hidden,
+            gitignore,
+            gitignore && parents,
+            patterns,
+        )
+    };
+    let all = walk(false, false, None);
+    let visible = if use_gitignore {
+        walk(true, false, None)
+    } else {
+        all.clone()
+    };
+    let not_ignored = if use_gitignore {
+        walk(true, true, None)
+    } else {
+        visible.clone()
+    };
+    let presets = crate::presets::detect(config, working_dir);
+    let preset_patterns = crate::presets::patterns(&presets);
+    let not_preset = if presets.is_empty() {
+        not_ignored.clone()
+    } else {
+        walk(use_gitignore, use_gitignore, Some(&preset_patterns))
+    };
+    let patterns = crate::presets::combined_ignore_patterns(config, &presets);
+    let walked = match &patterns {
+        Some(patterns) if *patterns != preset_patterns => {
+            walk(use_gitignore, use_gitignore, Some(patterns))
+        }
+        _ => not_preset.clone(),
+    };
+    let selected: HashSet<&PathBuf> = selected.iter().collect();
+    let limited: HashSet<&PathBuf> = limited.iter().flat_map(|l| &l.files).collect();
+    let unchanged: HashSet<&PathBuf> = unchanged.iter().collect();
+    let output_rel = output_path.strip_prefix(working_dir).ok();
+    let other_outputs_rel: Vec<&Path> = other_outputs
+        .iter()
+        .filter_map(|output| output.strip_prefix(working_dir).ok())
+        .collect();
+    let config_paths = crate::bundle::config_paths(config, working_dir);
+    let config_paths_rel: Vec<&Path> = config_paths
+        .iter()
+        .filter_map(|config| config.strip_prefix(working_dir).ok())
+        .collect();
+    let patterns = crate::profiles::Patterns::from_args(args, working_dir)
+        .ok()
+        .flatten();
+    let max_depth = args.max_depth.or(config.sheafy.max_depth);
+    let submodules = config.sheafy.submodules.unwrap_or_default();
+    let executable_rel = std::env::current_exe()
+        .ok()
+        .map(|exe| crate::paths::absolute(&exe, working_dir))
+        .and_then(|exe| exe.strip_prefix(working_dir).ok().map(Path::to_path_buf));
+
+    let mut excluded: BTreeMap<Reason, Vec<&PathBuf>> = BTreeMap::new();
+    for path in &all {
+        if selected.contains(path) {
+            continue;
+        }
+        let reason = if !visible.contains(path) {
+            Reason::Hidden
+        } else if !not_ignored.contains(path) {
+            Reason::Gitignore
+        } else if !not_preset.contains(path) {
+            Reason::Presets
+        } else if !walked.contains(path) {
+            Reason::IgnorePatterns
+        } else if crate::bundle::in_skipped_repo(submodules, working_dir, path) {
+            Reason::Submodules
+        } else if scopes.is_config_file(path) {
+            Reason::SheafyFiles
+        } else if scopes.is_ignored(path) {
+            Reason::DirSettings
+        } else if max_depth.is_some_and(|depth| path.components().count() > depth) {
+            Reason::MaxDepth
+        } else if limited.contains(path) {
+            Reason::Limits
+        } else if unchanged.contains(path) {
+            Reason::Baseline
+        } else if config_paths_rel.contains(&path.as_path())
+            || Some(path.as_path()) == output_rel
+            || other_outputs_rel.contains(&path.as_path())
+            || executable_rel.as_ref() == Some(path)
+            || path.starts_with(crate::state::STATE_DIR)
+            || (!args.include_bundles && crate::clean::is_bundle(&working_dir.join(path)))
+        {
+            Reason::SheafyFiles
+        } else if patterns.as_ref().is_some_and(|p| !p.permits(path)) {
+            Reason::Patterns
+        } else if args.package.is_some() {
+            Reason::Package
+        } else if args.focus.is_some() {
+            Reason::Focus
+        } else if args.since.is_some() {
+            Reason::Since
+        } else {
+            Reason::Other
+        };
+        excluded.entry(reason).or_default().push(path);
+    }
+
+    let total: usize = excluded.values().map(Vec::len).sum();
+    if total == 0 {
+        status!("\nNo files were excluded.");
+        return;
+    }
+    status!("\nExcluded {} file(s):", total);
+    for (reason, paths) in &excluded {
+        status!("  By {}:", reason.describe());
+        for (path, count) in collapse(paths, &all) {
+            if count == 1 {
+                status!("    {}", path);
+            } else {
+                status!("    {}/ ({} files)", path, count);
+            }
+        }
+    }
+}
+
+/// Replaces files with the highest directory whose files were all excluded for the
+/// same reason. Returns display paths with the number of files each stands for.
+fn collapse(paths: &[&PathBuf], all: &HashSet<PathBuf>) -> Vec<(String, usize)> {
+    let totals = count_by_dir(all.iter());
+    let excluded = count_by_dir(paths.iter().copied());
+
+    let mut groups: BTreeMap<String, usize> = BTreeMap::new();
+    for path in paths {
+        let top = path
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .take_while(|dir| excluded.get(dir) == totals.get(dir))
+            .last();
+        let key = top.unwrap_or(path).display().to_string();
+        *groups
+            .entry(key.replace(std::path::MAIN_SEPARATOR, "/"))
+            .or_default() += 1;
+    }
+    groups.into_iter().collect()
+}
+
+/// Number of files below each directory.
+fn count_by_dir<'a>(files: impl Iterator<Item = &'a PathBuf>) -> HashMap<&'a Path, usize> {
+    let mut counts = HashMap::new();
+    for file in files {
+        for dir in file.ancestors().skip(1) {
+            *counts.entry(dir).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Files under `working_dir` (relative paths) with the given filters applied.
+fn walk(
+    working_dir: &Path,
+    hidden: bool,
+    gitignore: bool,
+    parents: bool,
+    patterns: Option<&str>,
+) -> HashSet<PathBuf> {
+    let ignore =
+        patterns.map(|patterns| Arc::new(crate::presets::ignore_matcher(working_dir, patterns)));
+    let files = |gitignore: bool, parents: bool| -> HashSet<PathBuf> {
+        let mut builder = WalkBuilder::new(working_dir);
+        let ignore = ignore.clone();
+        builder
+            .standard_filters(gitignore)
+            .parents(parents)
+            .hidden(hidden)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                entry.file_name() != ".git"
+                    && (entry.depth() == 0
+                        || !ignore
+                            .as_ref()
+                            .is_some_and(|ignore| ignore.matched(entry.path(), is_dir).is_ignore()))
+            });
+        builder
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+            .filter_map(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(working_dir)
+                    .ok()
+                    .map(Path::to_path_buf)
+            })
+            .collect()
+    };
+    let mut walked = files(gitignore, parents);
+    // A `!pattern` brings back files that .gitignore leaves out, as in the bundle's walk
+    if let Some(ignore) = ignore
+        .as_ref()
+        .filter(|ignore| gitignore && ignore.num_whitelists() > 0)
+    {
+        walked.extend(files(false, false).into_iter().filter(|rel_path| {
+            ignore
+                .matched_path_or_any_parents(rel_path, false)
+                .is_whitelist()
+        }));
+    }
+    walked
+}
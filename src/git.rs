@@ -0,0 +1,309 @@
+//! Thin wrappers around the `git` command line used by change-aware features.
+
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+/// Runs git in `dir` and returns its stdout. `ok_codes` lists additional exit
+/// codes that are not failures (e.g. `git diff --no-index` exits 1 on differences).
+fn run_git(dir: &Path, args: &[&str], ok_codes: &[i32]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git (is it installed and on PATH?)")?;
+    let code = output.status.code().unwrap_or(-1);
+    if !output.status.success() && !ok_codes.contains(&code) {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Files (relative to `dir`) that differ from `since`, including untracked files.
+pub fn changed_files(dir: &Path, since: &str) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = run_git(
+        dir,
+        &["diff", "--name-only", "--relative", since, "--"],
+        &[],
+    )?
+    .lines()
+    .chain(untracked_files(dir)?.iter().map(String::as_str))
+    .filter(|l| !l.is_empty())
+    .map(PathBuf::from)
+    .collect();
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn untracked_files(dir: &Path) -> Result<Vec<String>> {
+    Ok(
+        run_git(dir, &["ls-files", "--others", "--exclude-standard"], &[])?
+            .lines()
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Files git tracks below `dir`, relative to it, with `/` separators.
+pub fn tracked_files(dir: &Path) -> Result<Vec<String>> {
+    Ok(run_git(dir, &["ls-files", "-z"], &[])?
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Time (seconds since the epoch) of the last commit touching each file below `dir`,
+/// by path relative to `dir` with `/` separators.
+pub fn commit_times(dir: &Path) -> Result<HashMap<String, i64>> {
+    let log = run_git(
+        dir,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--format=@%ct",
+            "--name-only",
+            "--relative",
+            "--",
+            ".",
+        ],
+        &[],
+    )?;
+    let mut times = HashMap::new();
+    let mut time = 0;
+    for line in log.lines().filter(|line| !line.is_empty()) {
+        match line.strip_prefix('@').and_then(|t| t.parse().ok()) {
+            Some(commit_time) => time = commit_time,
+            // Newest commits come first, so the first time seen is the last change
+            None => {
+                times.entry(line.to_string()).or_insert(time);
+            }
+        }
+    }
+    Ok(times)
+}
+
+/// Returns true if `path` (relative to `dir`) is not known to git.
+pub fn is_untracked(dir: &Path, path: &Path) -> Result<bool> {
+    let path = path.to_string_lossy();
+    let out = run_git(
+        dir,
+        &["ls-files", "--others", "--exclude-standard", "--", &path],
+        &[],
+    )?;
+    Ok(!out.trim().is_empty())
+}
+
+/// Unified diff of a single file against `since` with `context` lines of context.
+/// Untracked files are diffed against /dev/null so they show up as new files.
+pub fn diff_file(dir: &Path, since: &str, path: &Path, context: usize) -> Result<String> {
+    let unified = format!("-U{}", context);
+    let path_str = path.to_string_lossy().replace('\\', "/");
+    if is_untracked(dir, path)? {
+        run_git(
+            dir,
+            &["diff", "--no-index", &unified, "--", "/dev/null", &path_str],
+            &[1],
+        )
+    } else {
+        run_git(
+            dir,
+            &["diff", &unified, "--relative", since, "--", &path_str],
+            &[],
+        )
+    }
+}
+
+/// Unified diff between two files anywhere on disk (`git diff --no-index`); empty when
+/// they are the same.
+pub fn diff_files(old: &Path, new: &Path, context: usize) -> Result<String> {
+    let unified = format!("-U{}", context);
+    let (old, new) = (old.to_string_lossy(), new.to_string_lossy());
+    run_git(
+        Path::new("."),
+        &["diff", "--no-index", &unified, "--", &old, &new],
+        &[1],
+    )
+}
+
+/// Reads files as they are in the last commit, through one `git cat-file --batch`.
+pub struct HeadFiles {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    /// Path of the directory the files are relative to, from the top of the repository
+    prefix: String,
+}
+
+impl HeadFiles {
+    /// Reader of the files below `dir` as of HEAD; `None` if the repository has no
+    /// commit yet.
+    pub fn open(dir: &Path) -> Result<Option<Self>> {
+        // Exits 1 without output on an unborn HEAD
+        if run_git(dir, &["rev-parse", "--verify", "-q", "HEAD"], &[1])?
+            .trim()
+            .is_empty()
+        {
+            return Ok(None);
+        }
+        let mut child = Command::new("git")
+            .args(["cat-file", "--batch"])
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run git (is it installed and on PATH?)")?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Some(Self {
+            child,
+            stdin,
+            stdout,
+            prefix: show_prefix(dir),
+        }))
+    }
+
+    /// Content of the file at `path` (relative to the directory, `/` separators);
+    /// `None` if the commit has no such file.
+    pub fn read(&mut self, path: &str) -> Result<Option<Vec<u8>>> {
+        if path.contains('\n') {
+            return Ok(None);
+        }
+        writeln!(self.stdin, "HEAD:{}{}", self.prefix, path)?;
+        self.stdin.flush()?;
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        // `<object> <type> <size>`, or `<name> missing` (or `ambiguous`)
+        let mut fields = header.trim_end().rsplit(' ');
+        let (Some(size), Some(kind)) = (fields.next(), fields.next()) else {
+            bail!("Unexpected output of git cat-file: {}", header.trim_end());
+        };
+        let Ok(size) = size.parse::<usize>() else {
+            return Ok(None);
+        };
+        // The content, then a newline
+        let mut content = vec![0; size + 1];
+        self.stdout
+            .read_exact(&mut content)
+            .context("Failed to read from git cat-file")?;
+        content.pop();
+        // A directory of that name is no file
+        Ok((kind == "blob").then_some(content))
+    }
+}
+
+impl Drop for HeadFiles {
+    fn drop(&mut self) {
+        // The batch would otherwise wait for more names
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Path of `dir` relative to the top of its repository (`git rev-parse --show-prefix`).
+/// Empty when `dir` is the repository root or not inside a repository.
+pub fn show_prefix(dir: &Path) -> String {
+    run_git(dir, &["rev-parse", "--show-prefix"], &[])
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Applies a patch read from `patch_file` to the files below `dir`.
+pub fn apply_patch(dir: &Path, patch_file: &Path) -> Result<()> {
+    let prefix = show_prefix(dir);
+    let patch = patch_file.to_string_lossy();
+    let mut args = vec!["apply", "--whitespace=nowarn"];
+    let directory = format!("--directory={}", prefix);
+    if !prefix.is_empty() {
+        args.push(&directory);
+    }
+    args.push(&patch);
+    run_git(dir, &args, &[]).map(|_| ())
+}
+
+/// A hunk of a unified diff, described by its range in the new file.
+#[derive(Debug)]
+pub struct Hunk {
+    pub new_start: usize,
+    pub new_len: usize,
+    /// Lines of the new file covered by the hunk (context and added lines).
+    pub lines: Vec<String>,
+}
+
+/// Parses the hunks of a single-file unified diff.
+pub fn parse_hunks(diff: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for line in diff.lines() {
+        if let Some(header) = line.strip_prefix("@@ ") {
+            // @@ -a,b +c,d @@
+            let new_range = header
+                .split_whitespace()
+                .find(|part| part.starts_with('+'))
+                .unwrap_or("+0,0");
+            let mut parts = new_range[1..].splitn(2, ',');
+            let new_start = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+            let new_len = parts.next().and_then(|s| s.parse().ok()).unwrap_or(1);
+            hunks.push(Hunk {
+                new_start,
+                new_len,
+                lines: Vec::new(),
+            });
+        } else if let Some(hunk) = hunks.last_mut() {
+            if let Some(kept) = line.strip_prefix(' ').or_else(|| line.strip_prefix('+')) {
+                hunk.lines.push(kept.to_string());
+            } else if line.is_empty() {
+                // Some tools strip the leading space of empty context lines
+                hunk.lines.push(String::new());
+            }
+        }
+    }
+    hunks
+}
+
+/// Name of the checked-out branch, or None on a detached HEAD.
+pub fn current_branch(dir: &Path) -> Result<Option<String>> {
+    let branch = run_git(dir, &["rev-parse", "--abbrev-ref", "HEAD"], &[])?;
+    let branch = branch.trim();
+    Ok((branch != "HEAD").then(|| branch.to_string()))
+}
+
+/// Abbreviated hash of HEAD.
+pub fn short_hash(dir: &Path) -> Result<String> {
+    Ok(run_git(dir, &["rev-parse", "--short", "HEAD"], &[])?
+        .trim()
+        .to_string())
+}
+
+/// Output of `git --version`, e.g. `git version 2.43.0`.
+pub fn version() -> Result<String> {
+    Ok(run_git(Path::new("."), &["--version"], &[])?
+        .trim()
+        .to_string())
+}
+
+/// Clones the tip of `url` into `dest`, which must not exist or be empty.
+pub fn clone_shallow(url: &str, dest: &Path) -> Result<()> {
+    let dest = dest.to_string_lossy();
+    run_git(
+        Path::new("."),
+        &["clone", "--depth", "1", "--quiet", url, &dest],
+        &[],
+    )
+    .with_context(|| format!("Failed to clone '{}'", url))?;
+    Ok(())
+}
+
+/// Whether `dir` is inside a git work tree.
+pub fn is_work_tree(dir: &Path) -> bool {
+    run_git(dir, &["rev-parse", "--is-inside-work-tree"], &[]).is_ok_and(|out| out.trim() == "true")
+}
@@ -0,0 +1,228 @@
+//! File handlers: transform a file's content when it is bundled and reverse the
+//! transform when it is restored.
+//!
+//! Handlers are configured by name under `[sheafy.handlers.<name>]` with the files they
+//! apply to. A handler is either built in (`notebook`) or an external process: the
+//! `encode`/`decode` commands receive the content on stdin and print the result. The
+//! handler used for a section is recorded in a `<!-- sheafy:handler name -->` note
+//! after it, so restore knows how to turn it back into the original file.
+//!
+//! Jupyter notebooks get the built-in `notebook` handler even without configuration,
+//! unless `notebooks = "raw"` is set.
+
+use crate::config::{Config, HandlerConfig, NotebookMode};
+use crate::notebook::NotebookHandler;
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+/// A bundle-time transform and its inverse.
+pub trait FileHandler {
+    /// Turns file content into the text written to the bundle.
+    fn encode(&self, path: &str, content: &str) -> Result<Encoded>;
+    /// Turns bundled text back into the file content.
+    fn decode(&self, path: &str, bundled: &str) -> Result<String>;
+    /// Puts what `encode` left out of the bundle back into `decoded`, from `existing`,
+    /// the file it is about to replace. Most handlers leave nothing out.
+    fn merge(&self, _existing: &str, decoded: String) -> String {
+        decoded
+    }
+}
+
+/// A file's bundled form.
+pub struct Encoded {
+    pub content: String,
+    /// Code fence language of `content`
+    pub language: String,
+}
+
+/// Handlers shipped with sheafy, usable by name without commands.
+fn builtin(name: &str) -> Option<Box<dyn FileHandler>> {
+    match name {
+        "notebook" => Some(Box::new(NotebookHandler)),
+        _ => None,
+    }
+}
+
+/// Runs user-supplied commands through the shell.
+struct ExternalHandler {
+    name: String,
+    encode: String,
+    decode: String,
+    language: String,
+    working_dir: std::path::PathBuf,
+}
+
+impl ExternalHandler {
+    fn run(&self, command: &str, path: &str, input: &str) -> Result<String> {
+        let output = pipe(command, &self.working_dir, path, input)
+            .with_context(|| format!("Failed to run handler '{}': {}", self.name, command))?;
+        if !output.status.success() {
+            bail!(
+                "Handler '{}' failed on {}: {}",
+                self.name,
+                path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        String::from_utf8(output.stdout)
+            .with_context(|| format!("Handler '{}' printed invalid UTF-8 for {}", self.name, path))
+    }
+}
+
+impl FileHandler for ExternalHandler {
+    fn encode(&self, path: &str, content: &str) -> Result<Encoded> {
+        Ok(Encoded {
+            content: self.run(&self.encode, path, content)?,
+            language: self.language.clone(),
+        })
+    }
+
+    fn decode(&self, path: &str, bundled: &str) -> Result<String> {
+        self.run(&self.decode, path, bundled)
+    }
+}
+
+/// Runs `command` through the shell in `working_dir`, with `input` on its stdin and
+/// `path` in `SHEAFY_PATH`, collecting its output.
+pub(crate) fn pipe(
+    command: &str,
+    working_dir: &Path,
+    path: &str,
+    input: &str,
+) -> std::io::Result<Output> {
+    let mut child = shell(command)
+        .current_dir(working_dir)
+        .env("SHEAFY_PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Feed stdin from a thread so a chatty child can't deadlock on a full stdout pipe
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let feeder = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    let _ = feeder.join();
+    Ok(output)
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", command]);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
+
+/// The configured handlers, in name order.
+#[derive(Default)]
+pub struct Handlers {
+    handlers: Vec<(String, Gitignore, Box<dyn FileHandler>)>,
+    /// Flatten `*.ipynb` files no configured handler claims
+    notebooks: Option<NotebookHandler>,
+}
+
+impl Handlers {
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Self> {
+        let mut handlers = Vec::new();
+        for (name, handler_config) in &config.sheafy.handlers {
+            let handler = build(name, handler_config, working_dir)?;
+            let mut builder = GitignoreBuilder::new(working_dir);
+            for line in handler_config.patterns.lines() {
+                builder.add_line(None, line).with_context(|| {
+                    format!("Invalid pattern '{}' for handler '{}'", line, name)
+                })?;
+            }
+            let matcher = builder
+                .build()
+                .with_context(|| format!("Invalid patterns for handler '{}'", name))?;
+            handlers.push((name.clone(), matcher, handler));
+        }
+        let notebooks = (config.sheafy.notebooks.unwrap_or_default() == NotebookMode::Flatten)
+            .then_some(NotebookHandler);
+        Ok(Self {
+            handlers,
+            notebooks,
+        })
+    }
+
+    /// The first handler whose patterns match `rel_path`, with its name.
+    pub fn for_path(&self, rel_path: &Path) -> Option<(&str, &dyn FileHandler)> {
+        self.handlers
+            .iter()
+            .find(|(_, matcher, _)| {
+                matcher
+                    .matched_path_or_any_parents(rel_path, false)
+                    .is_ignore()
+            })
+            .map(|(name, _, handler)| (name.as_str(), handler.as_ref()))
+            .or_else(|| {
+                let notebook = self.notebooks.as_ref()?;
+                let is_notebook = rel_path.extension().is_some_and(|ext| ext == "ipynb");
+                is_notebook.then_some(("notebook", notebook as &dyn FileHandler))
+            })
+    }
+
+    /// Reverses the handler recorded for a bundled section. Built-in handlers work
+    /// without configuration; external ones must be configured under the same name.
+    pub fn decode(&self, name: &str, path: &str, bundled: &str) -> Result<String> {
+        let decoded = match self.handlers.iter().find(|(n, _, _)| n == name) {
+            Some((_, _, handler)) => handler.decode(path, bundled),
+            None => match builtin(name) {
+                Some(handler) => handler.decode(path, bundled),
+                None => bail!(
+                    "'{}' was bundled with handler '{}', which is not configured in sheafy.toml",
+                    path,
+                    name
+                ),
+            },
+        };
+        decoded.with_context(|| format!("Failed to decode '{}' with handler '{}'", path, name))
+    }
+
+    /// `decoded` with what the handler `name` left out of the bundle taken from
+    /// `existing`; see [`FileHandler::merge`].
+    pub fn merge(&self, name: &str, existing: &str, decoded: String) -> String {
+        match self.handlers.iter().find(|(n, _, _)| n == name) {
+            Some((_, _, handler)) => handler.merge(existing, decoded),
+            None => match builtin(name) {
+                Some(handler) => handler.merge(existing, decoded),
+                None => decoded,
+            },
+        }
+    }
+}
+
+fn build(name: &str, config: &HandlerConfig, working_dir: &Path) -> Result<Box<dyn FileHandler>> {
+    match (&config.encode, &config.decode) {
+        (Some(encode), Some(decode)) => Ok(Box::new(ExternalHandler {
+            name: name.to_string(),
+            encode: encode.clone(),
+            decode: decode.clone(),
+            language: config.language.clone().unwrap_or_default(),
+            working_dir: working_dir.to_path_buf(),
+        })),
+        (None, None) => match builtin(name) {
+            Some(handler) => Ok(handler),
+            None => bail!(
+                "Handler '{}' is not built in; give it `encode` and `decode` commands",
+                name
+            ),
+        },
+        _ => bail!(
+            "Handler '{}' needs both `encode` and `decode` commands",
+            name
+        ),
+    }
+}
@@ -0,0 +1,108 @@
+//! Paths in `## path` section headings.
+//!
+//! A path is written bare (`## src/main.rs`) unless Markdown could read it differently:
+//! whitespace, `#`, backticks, or emphasis, link and HTML syntax. Such a path is
+//! wrapped in a code span instead (``## `my file (v2).rs` ``), which renders verbatim
+//! and keeps its spaces. Both forms are read back. Paths are NFC-normalized when written
+//! and when read, so a heading whose accents were decomposed on the way (macOS file
+//! names, copy and paste) still names the same file; restore finds the file on disk by
+//! its NFC name (see [`crate::paths::from_slash`]).
+//!
+//! Since bundling wraps every ambiguous path, a bare heading with whitespace or
+//! Markdown syntax in it (`## Getting started`) cannot name a file: it is a heading of
+//! prose, such as one in an LLM's reply or in a README pasted around a bundle.
+
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters that make a bare heading path ambiguous in Markdown.
+const SPECIAL: &[char] = &['#', '`', '*', '[', ']', '<', '>', '\\', '|', '~'];
+
+/// The heading text for `path`: the path itself, or a code span holding it.
+pub fn escape(path: &str) -> Cow<'_, str> {
+    if !needs_escape(path) {
+        return Cow::Borrowed(path);
+    }
+    // A backtick string one longer than any run inside the path delimits the span;
+    // CommonMark strips one space of padding on each side
+    let longest_run = path.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let ticks = "`".repeat(longest_run + 1);
+    let pad = if path.starts_with(['`', ' ']) || path.ends_with(['`', ' ']) {
+        " "
+    } else {
+        ""
+    };
+    Cow::Owned(format!("{ticks}{pad}{path}{pad}{ticks}"))
+}
+
+fn needs_escape(path: &str) -> bool {
+    let chars: Vec<char> = path.chars().collect();
+    chars.iter().enumerate().any(|(i, &c)| {
+        c.is_whitespace()
+            || SPECIAL.contains(&c)
+            // `_` inside a word (`my_mod.rs`) is literal; at a word edge it may be emphasis
+            || (c == '_'
+                && !(i > 0
+                    && chars[i - 1].is_alphanumeric()
+                    && chars.get(i + 1).is_some_and(|next| next.is_alphanumeric())))
+    })
+}
+
+/// The path named by the text of a heading (after `##`), in NFC.
+pub fn parse(text: &str) -> String {
+    let text = text.trim();
+    let path = code_span(text).unwrap_or_else(|| strip_closing_sequence(text));
+    path.nfc().collect()
+}
+
+/// The path named by the text of a heading, unless it is bare text that bundling would
+/// have wrapped in a code span; see [`parse`].
+pub fn parse_path(text: &str) -> Option<String> {
+    let text = text.trim();
+    if code_span(text).is_none() {
+        let bare = strip_closing_sequence(text);
+        if bare
+            .chars()
+            .any(|c| c.is_whitespace() || SPECIAL.contains(&c))
+        {
+            return None;
+        }
+    }
+    Some(parse(text))
+}
+
+/// `path` in NFC, as heading paths are read.
+pub fn normalize(path: &str) -> String {
+    path.nfc().collect()
+}
+
+/// The content of `text` if it is a single code span.
+fn code_span(text: &str) -> Option<&str> {
+    let ticks = text.len() - text.trim_start_matches('`').len();
+    if ticks == 0 || text.len() < 2 * ticks {
+        return None;
+    }
+    let inner = text[ticks..].strip_suffix(&text[..ticks])?;
+    // The closing run must be exactly as long as the opening one
+    if inner.ends_with('`') || inner.is_empty() {
+        return None;
+    }
+    let unpadded = inner
+        .strip_prefix(' ')
+        .and_then(|rest| rest.strip_suffix(' '))
+        .filter(|rest| !rest.is_empty());
+    Some(match unpadded {
+        Some(rest) if !inner.trim().is_empty() => rest,
+        _ => inner,
+    })
+}
+
+/// A bare heading without its optional closing `#` sequence (`## src/main.rs ##`).
+fn strip_closing_sequence(text: &str) -> &str {
+    let without = text.trim_end_matches('#');
+    if without.len() < text.len() && (without.is_empty() || without.ends_with([' ', '\t'])) {
+        without.trim_end()
+    } else {
+        text
+    }
+}
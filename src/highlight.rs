@@ -0,0 +1,331 @@
+//! A small line-based syntax highlighter for rendered bundles.
+//!
+//! It only knows comments, strings, numbers and keywords, which is enough to make
+//! code readable in HTML and PDF snapshots without shipping a grammar engine.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+/// Comment and keyword rules for one family of languages.
+struct Syntax {
+    line_comment: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    keywords: &'static [&'static str],
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+    "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while",
+    "with", "yield",
+];
+const JS_KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "from",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "type",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "yield",
+];
+const C_KEYWORDS: &[&str] = &[
+    "abstract",
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extends",
+    "extern",
+    "false",
+    "final",
+    "float",
+    "for",
+    "fun",
+    "func",
+    "go",
+    "if",
+    "implements",
+    "import",
+    "int",
+    "interface",
+    "long",
+    "map",
+    "namespace",
+    "new",
+    "null",
+    "nil",
+    "override",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "short",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "unsigned",
+    "val",
+    "var",
+    "void",
+    "while",
+];
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "return", "then", "until", "while",
+];
+const RUBY_KEYWORDS: &[&str] = &[
+    "begin", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "if", "module",
+    "nil", "require", "rescue", "return", "self", "true", "unless", "until", "while", "yield",
+];
+const SQL_KEYWORDS: &[&str] = &[
+    "and", "as", "by", "create", "delete", "from", "group", "insert", "into", "join", "not",
+    "null", "or", "order", "select", "set", "table", "update", "values", "where", "AND", "AS",
+    "BY", "CREATE", "DELETE", "FROM", "GROUP", "INSERT", "INTO", "JOIN", "NOT", "NULL", "OR",
+    "ORDER", "SELECT", "SET", "TABLE", "UPDATE", "VALUES", "WHERE",
+];
+
+fn syntax_for(language: &str) -> Option<Syntax> {
+    let syntax = match language {
+        "rust" => Syntax {
+            line_comment: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"'],
+            keywords: RUST_KEYWORDS,
+        },
+        "python" => Syntax {
+            line_comment: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: PYTHON_KEYWORDS,
+        },
+        "javascript" | "typescript" | "jsx" | "tsx" | "vue" | "svelte" => Syntax {
+            line_comment: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\'', '`'],
+            keywords: JS_KEYWORDS,
+        },
+        "c" | "cpp" | "java" | "go" | "kotlin" | "swift" | "scala" | "csharp" | "dart" | "php"
+        | "groovy" => Syntax {
+            line_comment: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\''],
+            keywords: C_KEYWORDS,
+        },
+        "css" | "scss" | "less" => Syntax {
+            line_comment: &[],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\''],
+            keywords: &[],
+        },
+        "bash" | "fish" | "makefile" | "dockerfile" | "perl" => Syntax {
+            line_comment: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: SHELL_KEYWORDS,
+        },
+        "ruby" => Syntax {
+            line_comment: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: RUBY_KEYWORDS,
+        },
+        "toml" | "yaml" | "ini" | "gitignore" => Syntax {
+            line_comment: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: &["true", "false"],
+        },
+        "sql" | "lua" => Syntax {
+            line_comment: &["--"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            keywords: SQL_KEYWORDS,
+        },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// Splits `content` into lines of `(kind, text)` tokens for `language` (a fence hint).
+/// Unknown languages come back as plain text.
+pub fn highlight<'a>(language: &str, content: &'a str) -> Vec<Vec<(TokenKind, &'a str)>> {
+    let Some(syntax) = syntax_for(language) else {
+        return content
+            .lines()
+            .map(|line| vec![(TokenKind::Plain, line)])
+            .collect();
+    };
+    let mut in_block = false;
+    content
+        .lines()
+        .map(|line| highlight_line(&syntax, line, &mut in_block))
+        .collect()
+}
+
+fn highlight_line<'a>(
+    syntax: &Syntax,
+    line: &'a str,
+    in_block: &mut bool,
+) -> Vec<(TokenKind, &'a str)> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut plain_start = 0;
+    let flush = |tokens: &mut Vec<(TokenKind, &'a str)>, from: usize, to: usize| {
+        if from < to {
+            tokens.push((TokenKind::Plain, &line[from..to]));
+        }
+    };
+
+    while pos < line.len() {
+        let rest = &line[pos..];
+        if *in_block {
+            let (_, close) = syntax.block_comment.unwrap();
+            let end = match rest.find(close) {
+                Some(i) => {
+                    *in_block = false;
+                    pos + i + close.len()
+                }
+                None => line.len(),
+            };
+            tokens.push((TokenKind::Comment, &line[pos..end]));
+            pos = end;
+            plain_start = pos;
+            continue;
+        }
+        if syntax.line_comment.iter().any(|p| rest.starts_with(p)) {
+            flush(&mut tokens, plain_start, pos);
+            tokens.push((TokenKind::Comment, rest));
+            return tokens;
+        }
+        if let Some((open, _)) = syntax
+            .block_comment
+            .filter(|(open, _)| rest.starts_with(open))
+        {
+            flush(&mut tokens, plain_start, pos);
+            tokens.push((TokenKind::Comment, &line[pos..pos + open.len()]));
+            pos += open.len();
+            plain_start = pos;
+            *in_block = true;
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        if syntax.quotes.contains(&c) {
+            flush(&mut tokens, plain_start, pos);
+            let end = string_end(rest, c).map_or(line.len(), |i| pos + i);
+            tokens.push((TokenKind::String, &line[pos..end]));
+            pos = end;
+            plain_start = pos;
+            continue;
+        }
+        let prev_is_ident = line[..pos]
+            .chars()
+            .next_back()
+            .is_some_and(|p| p.is_alphanumeric() || p == '_');
+        if (c.is_alphabetic() || c == '_') && !prev_is_ident {
+            let len = rest
+                .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            if syntax.keywords.contains(&word) {
+                flush(&mut tokens, plain_start, pos);
+                tokens.push((TokenKind::Keyword, word));
+                plain_start = pos + len;
+            }
+            pos += len;
+            continue;
+        }
+        if c.is_ascii_digit() && !prev_is_ident {
+            flush(&mut tokens, plain_start, pos);
+            let len = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '.' || ch == '_'))
+                .unwrap_or(rest.len());
+            tokens.push((TokenKind::Number, &rest[..len]));
+            pos += len;
+            plain_start = pos;
+            continue;
+        }
+        pos += c.len_utf8();
+    }
+    flush(&mut tokens, plain_start, line.len());
+    tokens
+}
+
+/// Byte offset just past the closing `quote` of the string starting at `text[0]`.
+fn string_end(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return Some(i + c.len_utf8());
+        }
+    }
+    None
+}
@@ -0,0 +1,304 @@
+//! Language of sheafy's messages.
+//!
+//! The progress and summary messages of bundle and restore, and the messages of errors
+//! with a code ([`crate::error::SheafyError`]), are looked up by key in a catalog per
+//! language, so output pasted into a report can be in the reader's language. Warnings and
+//! other messages are in English. `SHEAFY_LANG` selects
+//! the language (`en`, `zh`; locale forms like `zh_CN.UTF-8` work too), then `lang` in
+//! sheafy.toml, then English. Keys missing from a catalog fall back to English. Bundles
+//! themselves are not translated: their format is read back by restore.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Zh,
+}
+
+impl Lang {
+    /// Parses a language tag or locale name (`zh`, `zh-Hans`, `en_US.UTF-8`).
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag
+            .split(['_', '-', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match primary.as_str() {
+            "en" | "c" | "posix" => Some(Self::En),
+            "zh" => Some(Self::Zh),
+            _ => None,
+        }
+    }
+
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::En => EN,
+            Self::Zh => ZH,
+        }
+    }
+}
+
+const UNSET: u8 = 0;
+const EN_ID: u8 = 1;
+const ZH_ID: u8 = 2;
+
+static LANG: AtomicU8 = AtomicU8::new(UNSET);
+
+/// Chooses the language: `SHEAFY_LANG` if set to a known language, else `configured`
+/// (the `lang` config key), else English. Unknown values are warned about.
+pub fn select(configured: Option<&str>) {
+    let from_env = std::env::var("SHEAFY_LANG")
+        .ok()
+        .filter(|tag| !tag.is_empty());
+    let lang = from_env
+        .as_deref()
+        .map(|tag| (tag, "SHEAFY_LANG"))
+        .into_iter()
+        .chain(configured.map(|tag| (tag, "lang in sheafy.toml")))
+        .find_map(|(tag, source)| {
+            let lang = Lang::from_tag(tag);
+            if lang.is_none() {
+                eprintln!(
+                    "Warning: Unknown language '{}' in {}; expected en or zh.",
+                    tag, source
+                );
+            }
+            lang
+        })
+        .unwrap_or(Lang::En);
+    set(lang);
+}
+
+pub fn set(lang: Lang) {
+    let id = match lang {
+        Lang::En => EN_ID,
+        Lang::Zh => ZH_ID,
+    };
+    LANG.store(id, Ordering::Relaxed);
+}
+
+/// The selected language; before [`select`] runs, `SHEAFY_LANG` alone decides.
+pub fn current() -> Lang {
+    match LANG.load(Ordering::Relaxed) {
+        EN_ID => Lang::En,
+        ZH_ID => Lang::Zh,
+        _ => {
+            select(None);
+            current()
+        }
+    }
+}
+
+/// The message for `key` with `args` filled in: `{}` takes the next argument, `{N}`
+/// the N-th (for catalogs that reorder them).
+pub fn format(key: &str, args: &[&dyn Display]) -> String {
+    let template = lookup(current(), key)
+        .or_else(|| lookup(Lang::En, key))
+        .unwrap_or(key);
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('}') else {
+            rest = &rest[open..];
+            break;
+        };
+        let spec = &after[..close];
+        let index = if spec.is_empty() {
+            next += 1;
+            Some(next - 1)
+        } else {
+            spec.parse::<usize>().ok()
+        };
+        match index.and_then(|i| args.get(i)) {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str(&rest[open..open + close + 2]),
+        }
+        rest = &after[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lookup(lang: Lang, key: &str) -> Option<&'static str> {
+    lang.catalog()
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, text)| *text)
+}
+
+/// The message for a catalog key in the selected language, as a `String`:
+/// `tr!("bundle.adding", path)`.
+#[macro_export]
+macro_rules! tr {
+    ($key:expr $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::format($key, &[$(&$arg as &dyn ::std::fmt::Display),*])
+    };
+}
+
+const EN: &[(&str, &str)] = &[
+    ("main.running_from", "Running from directory: {}"),
+    ("main.working_dir", "Effective working directory: {}"),
+    ("bundle.creating", "\nCreating Markdown bundle: {}"),
+    ("bundle.creating_dir", "Creating output directory: {}"),
+    ("bundle.adding", "  Adding: {}"),
+    ("bundle.output", "Output file will be: {}"),
+    (
+        "bundle.done",
+        "\nSuccessfully created '{}' with {} file(s).",
+    ),
+    ("restore.reading", "Reading bundle file: {}"),
+    ("restore.attempting", "Attempting to restore files"),
+    ("restore.restoring", "  Restoring: {}"),
+    ("restore.creating_dir", "    Creating directory: {}"),
+    ("restore.conflict", "  Conflict, skipping: {}"),
+    (
+        "restore.done",
+        "\nRestore complete in {}: {} created, {} overwritten, {} unchanged, {} skipped.",
+    ),
+    ("restore.largest", "Largest changes:"),
+    (
+        "restore.no_blocks",
+        "Warning: No valid sheafy blocks found in '{}'. No files restored.",
+    ),
+    (
+        "restore.inspect_hint",
+        "Run `sheafy inspect {}` to see how the bundle was parsed.",
+    ),
+    ("error.config_not_found", "Config file not found: {}"),
+    (
+        "error.config_not_locatable",
+        "Cannot locate the user-wide config: neither XDG_CONFIG_HOME nor HOME is set",
+    ),
+    ("error.config_invalid", "Failed to parse config file: {}"),
+    ("error.configs_invalid", "Failed to parse config from {}"),
+    (
+        "error.working_dir_not_found",
+        "Working directory does not exist: {}",
+    ),
+    ("error.bundle_not_found", "Bundle not found: {}"),
+    ("error.parse", "line {}: {}"),
+    (
+        "error.unsupported_version",
+        "Bundle uses format v{}, but this sheafy only understands up to v{}. Please upgrade sheafy.",
+    ),
+    (
+        "error.not_whole_files",
+        "'{}' holds patches or excerpts, not whole files",
+    ),
+    ("error.non_utf8", "line {}: the bundle is not UTF-8 text"),
+    (
+        "error.bundle_too_large",
+        "Projected bundle size {} exceeds max_bundle_size ({}); nothing was written",
+    ),
+    (
+        "error.too_many_files",
+        "The scan found more than max_files ({}) files and stopped; nothing was written",
+    ),
+    (
+        "error.readonly",
+        "'{}' is declared read-only in its front matter; nothing was restored",
+    ),
+    (
+        "error.stale",
+        "'{}' is out of date; run `sheafy bundle` without --check to refresh it",
+    ),
+    (
+        "error.path_escape",
+        "'{}' is outside the working directory; nothing was restored",
+    ),
+    (
+        "error.validation_failed",
+        "Validation check `{}` failed; nothing was restored",
+    ),
+    ("error.tree_differs", "{} of {} file(s) differ from the bundle"),
+    (
+        "error.restore_incomplete",
+        "{} file(s) could not be restored; {} file(s) were written",
+    ),
+    (
+        "error.checksum_mismatch",
+        "{} section(s) do not match the bundle's checksums of {} file(s); nothing was restored",
+    ),
+];
+
+const ZH: &[(&str, &str)] = &[
+    ("main.running_from", "运行目录：{}"),
+    ("main.working_dir", "实际工作目录：{}"),
+    ("bundle.creating", "\n正在创建 Markdown 打包文件：{}"),
+    ("bundle.creating_dir", "正在创建输出目录：{}"),
+    ("bundle.adding", "  添加：{}"),
+    ("bundle.output", "输出文件：{}"),
+    ("bundle.done", "\n已成功创建 '{}'，共 {} 个文件。"),
+    ("restore.reading", "正在读取打包文件：{}"),
+    ("restore.attempting", "正在还原文件"),
+    ("restore.restoring", "  还原：{}"),
+    ("restore.creating_dir", "    创建目录：{}"),
+    ("restore.conflict", "  冲突，已跳过：{}"),
+    (
+        "restore.done",
+        "\n已在 {} 中完成还原：新建 {} 个，覆盖 {} 个，未变 {} 个，跳过 {} 个。",
+    ),
+    ("restore.largest", "变化最大的文件："),
+    (
+        "restore.no_blocks",
+        "警告：在 '{}' 中没有找到有效的 sheafy 区块，未还原任何文件。",
+    ),
+    (
+        "restore.inspect_hint",
+        "运行 `sheafy inspect {}` 查看打包文件的解析过程。",
+    ),
+    ("error.config_not_found", "找不到配置文件：{}"),
+    (
+        "error.config_not_locatable",
+        "无法定位用户级配置：XDG_CONFIG_HOME 和 HOME 均未设置",
+    ),
+    ("error.config_invalid", "无法解析配置文件：{}"),
+    ("error.configs_invalid", "无法解析配置：{}"),
+    ("error.working_dir_not_found", "工作目录不存在：{}"),
+    ("error.bundle_not_found", "找不到打包文件：{}"),
+    ("error.parse", "第 {} 行：{}"),
+    (
+        "error.unsupported_version",
+        "打包文件使用格式 v{}，但此版本的 sheafy 最高只支持 v{}。请升级 sheafy。",
+    ),
+    (
+        "error.not_whole_files",
+        "'{}' 包含补丁或摘录，而不是完整文件",
+    ),
+    ("error.non_utf8", "第 {} 行：打包文件不是 UTF-8 文本"),
+    (
+        "error.bundle_too_large",
+        "预计打包大小 {} 超过 max_bundle_size（{}）；未写入任何内容",
+    ),
+    (
+        "error.too_many_files",
+        "扫描到的文件超过 max_files（{} 个），已停止；未写入任何内容",
+    ),
+    (
+        "error.readonly",
+        "'{}' 在 front matter 中声明为只读；未还原任何文件",
+    ),
+    (
+        "error.stale",
+        "'{}' 已过期；请运行不带 --check 的 `sheafy bundle` 更新它",
+    ),
+    ("error.path_escape", "'{}' 位于工作目录之外；未还原任何文件"),
+    (
+        "error.validation_failed",
+        "校验命令 `{}` 失败；未还原任何文件",
+    ),
+    ("error.tree_differs", "{1} 个文件中有 {0} 个与打包文件不同"),
+    (
+        "error.restore_incomplete",
+        "{} 个文件无法还原；已写入 {} 个文件",
+    ),
+    (
+        "error.checksum_mismatch",
+        "{} 个区块与打包文件中 {} 个文件的校验和不符；未还原任何文件",
+    ),
+];
@@ -0,0 +1,80 @@
+//! `sheafy inspect`: print how the parser reads a bundle, step by step, to debug
+//! bundles that restore rejects or reads differently than expected.
+//!
+//! The trace comes from the same [`BundleReader`] restore uses, so it shows exactly
+//! which headings, fences and notes were found, which were accepted as file sections,
+//! and why the others were not.
+
+use crate::config::Config;
+use crate::parser::{BundleReader, TraceEvent};
+use crate::restore::{markers_version, FORMAT_VERSION};
+use anyhow::{bail, Context, Result};
+use std::{fs::File, io::BufReader};
+
+pub fn run_inspect(config: &Config, input_file: Option<&str>) -> Result<()> {
+    let (_, bundle_path) = crate::restore::resolve_input(config, input_file)?;
+    let file = File::open(&bundle_path)
+        .with_context(|| format!("Failed to read bundle: {}", bundle_path.display()))?;
+    status!("Tracing '{}':", bundle_path.display());
+
+    let mut bundle = match BundleReader::traced(BufReader::new(file)) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            status!("  error: {:#}", e);
+            bail!("Failed to parse bundle: {}", bundle_path.display());
+        }
+    };
+    let mut rejected = 0;
+    // A section is only settled once the line after its notes is read, so its
+    // verdict comes after events further down; show each batch in line order
+    let mut print = |mut events: Vec<TraceEvent>| {
+        events.sort_by_key(|event| event.line);
+        for event in events {
+            if event.rejected {
+                rejected += 1;
+                status!("{:>7}  rejected: {}", event.line, event.message);
+            } else {
+                status!("{:>7}  {}", event.line, event.message);
+            }
+        }
+    };
+    print(bundle.take_trace());
+    let version = markers_version(bundle.markers());
+
+    let mut accepted = 0;
+    let mut error = None;
+    while let Some(section) = bundle.next() {
+        print(bundle.take_trace());
+        match section {
+            Ok(_) => accepted += 1,
+            Err(e) => {
+                status!("  error: {:#}", e);
+                error = Some(e);
+            }
+        }
+    }
+    print(bundle.take_trace());
+
+    status!(
+        "\nFormat v{}; {} section(s) accepted, {} rejected.",
+        version,
+        accepted,
+        rejected
+    );
+    if version > FORMAT_VERSION {
+        status!(
+            "This sheafy only understands up to v{}, so restore refuses this bundle.",
+            FORMAT_VERSION
+        );
+    }
+    if let Some(e) = error {
+        return Err(e.context(format!("Failed to parse bundle: {}", bundle_path.display())));
+    }
+    if accepted == 0 {
+        status!(
+            "A file section is a `## path` heading followed by a code fence, or a fence \
+             whose info string carries `title=\"path\"`."
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Library behind the `sheafy` command: bundling project files into a Markdown
+//! document and restoring them. The binary is a thin CLI over these modules, and the
+//! property tests drive them in-process.
+
+#[macro_use]
+pub mod status;
+#[macro_use]
+pub mod i18n;
+
+pub mod analyze;
+pub mod archive;
+pub mod ask;
+pub mod base64;
+pub mod baseline;
+pub mod bundle;
+pub mod cargo;
+pub mod clean;
+pub mod cli;
+pub mod clipboard;
+pub mod config;
+pub mod daemon;
+pub mod deflate;
+pub mod deps;
+pub mod doctor;
+pub mod error;
+pub mod excluded;
+pub mod git;
+pub mod graph;
+pub mod handlers;
+pub mod header;
+pub mod highlight;
+pub mod inspect;
+pub mod limits;
+pub mod links;
+pub mod lint;
+pub mod long_lines;
+pub mod minified;
+pub mod notebook;
+pub mod order;
+pub mod parser;
+pub mod paths;
+pub mod presets;
+pub mod profiles;
+pub mod prompt;
+pub mod readahead;
+pub mod render;
+pub mod restore;
+pub mod scaffold;
+pub mod scopes;
+pub mod script;
+pub mod settings;
+pub mod sniff;
+pub mod state;
+pub mod stats;
+pub mod stub;
+pub mod summarize;
+pub mod table;
+pub mod tags;
+pub mod text_style;
+pub mod timestamp;
+pub mod timings;
+pub mod tokens;
+pub mod tui;
+pub mod update;
+pub mod validate;
+pub mod vars;
+pub mod verify;
+pub mod warm;
+pub mod watch;
+
+#[macro_use(defer)]
+extern crate scopeguard;
@@ -0,0 +1,113 @@
+//! `limits`: caps on how much the files matching a pattern may contribute to a bundle,
+//! as a number of files or bytes. Snapshot and fixture directories otherwise tend to
+//! crowd out the code.
+//!
+//! Files are taken in bundle order (so `priority_patterns` decide which ones stay), and
+//! a file is only kept if every pattern it matches still has room. The files left out
+//! are listed in a note at the end of the bundle.
+
+use crate::config::{ByteSize, Limit};
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The files one `limits` entry left out.
+#[derive(Debug)]
+pub struct Limited {
+    pub pattern: String,
+    pub limit: Limit,
+    pub files: Vec<PathBuf>,
+    /// Total size of the files left out
+    pub bytes: u64,
+}
+
+impl Limited {
+    /// Prose line for the bundle (outside any section, so restore skips it).
+    pub fn note(&self) -> String {
+        format!(
+            "> Left out by limits: {} more file(s) matching `{}` ({}; limit {}).",
+            self.files.len(),
+            self.pattern,
+            ByteSize(self.bytes),
+            self.limit
+        )
+    }
+}
+
+/// Drops the files past each limit from `files`, keeping order, and returns what was
+/// left out per pattern.
+pub fn apply_limits(
+    working_dir: &Path,
+    limits: &BTreeMap<String, Limit>,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<Limited>> {
+    let mut entries: Vec<(Gitignore, Limit, usize, u64)> = Vec::new();
+    let mut limited: Vec<Limited> = Vec::new();
+    for (pattern, limit) in limits {
+        let mut builder = GitignoreBuilder::new(working_dir);
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("Invalid limits pattern: '{}'", pattern))?;
+        entries.push((builder.build()?, *limit, 0, 0));
+        limited.push(Limited {
+            pattern: pattern.clone(),
+            limit: *limit,
+            files: Vec::new(),
+            bytes: 0,
+        });
+    }
+
+    files.retain(|rel_path| {
+        let matching: Vec<usize> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (matcher, ..))| {
+                matcher
+                    .matched_path_or_any_parents(rel_path, false)
+                    .is_ignore()
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if matching.is_empty() {
+            return true;
+        }
+        let size = fs::metadata(working_dir.join(rel_path)).map_or(0, |m| m.len());
+        let over = matching.iter().copied().find(|&index| {
+            let (_, limit, count, bytes) = &entries[index];
+            match limit {
+                Limit::Files(max) => count + 1 > *max,
+                Limit::Bytes(max) => bytes + size > max.0,
+            }
+        });
+        match over {
+            Some(index) => {
+                limited[index].files.push(rel_path.clone());
+                limited[index].bytes += size;
+                false
+            }
+            None => {
+                for index in matching {
+                    entries[index].2 += 1;
+                    entries[index].3 += size;
+                }
+                true
+            }
+        }
+    });
+
+    limited.retain(|l| !l.files.is_empty());
+    for l in &limited {
+        status!(
+            "Limit {} for '{}': left out {} file(s) ({}).",
+            l.limit,
+            l.pattern,
+            l.files.len(),
+            ByteSize(l.bytes)
+        );
+    }
+    Ok(limited)
+}
@@ -0,0 +1,140 @@
+//! Cross-file links for bundled Markdown documents (`--link-anchors`).
+//!
+//! Relative links such as `[setup](../docs/setup.md#install)` are dead inside a bundle,
+//! so they are rewritten to the GitHub anchor of the section holding the target file.
+//! The original targets are recorded in a `<!-- sheafy:links {...} -->` note after the
+//! section, keyed by the link's position in the file, so restore writes the document
+//! back byte for byte.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+lazy_static! {
+    /// The target of an inline link or image: `](target` up to whitespace or `)`
+    static ref LINK_TARGET_REGEX: Regex = Regex::new(r"\]\(([^)\s]+)").unwrap();
+}
+
+/// Heading anchors of the sections in a bundle, by section path.
+#[derive(Debug, Default)]
+pub struct Anchors {
+    by_path: HashMap<String, String>,
+    used: HashSet<String>,
+}
+
+impl Anchors {
+    /// Registers a heading in document order and returns its anchor. Repeated slugs
+    /// get `-1`, `-2`, ... suffixes, as on GitHub.
+    pub fn add_heading(&mut self, text: &str) -> String {
+        let slug = github_slug(text);
+        let mut anchor = slug.clone();
+        let mut n = 0;
+        while !self.used.insert(anchor.clone()) {
+            n += 1;
+            anchor = format!("{}-{}", slug, n);
+        }
+        anchor
+    }
+
+    /// Registers the ATX headings of a Markdown text outside of code fences.
+    pub fn add_headings(&mut self, markdown: &str) {
+        let mut in_fence = false;
+        for line in markdown.lines() {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+                continue;
+            }
+            let hashes = line.len() - line.trim_start_matches('#').len();
+            if in_fence || !(1..=6).contains(&hashes) {
+                continue;
+            }
+            if let Some(text) = line[hashes..].strip_prefix([' ', '\t']) {
+                self.add_heading(text.trim_end_matches('#'));
+            }
+        }
+    }
+
+    /// Registers the `## path` heading of a file section.
+    pub fn add_section(&mut self, path: &str) {
+        let anchor = self.add_heading(path);
+        self.by_path.insert(path.to_string(), anchor);
+    }
+
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.by_path.get(path).map(String::as_str)
+    }
+}
+
+/// GitHub's heading anchor: lowercase, punctuation dropped, spaces turned into `-`.
+pub fn github_slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/// Points relative links in the Markdown file at `path` to the bundle sections of the
+/// files they reference. Returns the new content and the replaced targets by link index.
+pub fn rewrite_links(
+    content: &str,
+    path: &str,
+    anchors: &Anchors,
+) -> (String, BTreeMap<usize, String>) {
+    let mut originals = BTreeMap::new();
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for (index, cap) in LINK_TARGET_REGEX.captures_iter(content).enumerate() {
+        let target = cap.get(1).unwrap();
+        let Some(anchor) = resolve(path, target.as_str()).and_then(|t| anchors.get(&t)) else {
+            continue;
+        };
+        out.push_str(&content[last..target.start()]);
+        out.push('#');
+        out.push_str(anchor);
+        last = target.end();
+        originals.insert(index, target.as_str().to_string());
+    }
+    out.push_str(&content[last..]);
+    (out, originals)
+}
+
+/// Undoes [`rewrite_links`] using the targets recorded for the section.
+pub fn restore_links(content: &str, originals: &BTreeMap<usize, String>) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for (index, cap) in LINK_TARGET_REGEX.captures_iter(content).enumerate() {
+        let Some(original) = originals.get(&index) else {
+            continue;
+        };
+        let target = cap.get(1).unwrap();
+        out.push_str(&content[last..target.start()]);
+        out.push_str(original);
+        last = target.end();
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
+/// Resolves a link target relative to the file at `from` (both `/`-separated), or
+/// `None` for URLs, absolute paths, in-page anchors and paths leaving the bundle root.
+fn resolve(from: &str, target: &str) -> Option<String> {
+    let file = target.split(['#', '?']).next().unwrap_or("");
+    // The note recording the original would be cut short by `-->`
+    if file.is_empty() || file.starts_with('/') || file.contains(':') || target.contains("-->") {
+        return None;
+    }
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in file.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
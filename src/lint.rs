@@ -0,0 +1,166 @@
+//! `sheafy config lint`: check `ignore_patterns` against the current tree.
+//!
+//! Reports patterns that fail to parse, patterns that match nothing, and negations
+//! (`!pattern`) that can never re-include a file, either because nothing they match
+//! was ignored in the first place or because a parent directory is excluded (gitignore
+//! semantics: files inside an excluded directory cannot be re-included).
+
+use crate::config::Config;
+use anyhow::{bail, Result};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    WalkBuilder,
+};
+use std::path::{Path, PathBuf};
+
+/// One pattern line with its matcher.
+struct Rule<'a> {
+    line_no: usize,
+    text: &'a str,
+    negated: bool,
+    matcher: Gitignore,
+}
+
+pub fn run_lint(config: &Config) -> Result<()> {
+    let working_dir = config.get_working_dir()?;
+    let Some(patterns) = config
+        .sheafy
+        .ignore_patterns
+        .as_deref()
+        .filter(|p| !p.trim().is_empty())
+    else {
+        status!("No ignore_patterns configured; nothing to lint.");
+        return Ok(());
+    };
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    let mut rules = Vec::new();
+    for (index, line) in patterns.lines().enumerate() {
+        let text = line.trim_end();
+        if text.trim().is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let mut builder = GitignoreBuilder::new(&working_dir);
+        let built = builder
+            .add_line(None, text)
+            .map_err(anyhow::Error::from)
+            .and_then(|b| b.build().map_err(anyhow::Error::from));
+        match built {
+            Ok(matcher) => rules.push(Rule {
+                line_no: index + 1,
+                text,
+                negated: text.starts_with('!'),
+                matcher,
+            }),
+            Err(e) => {
+                eprintln!(
+                    "Error: line {}: '{}' is not a valid pattern: {}",
+                    index + 1,
+                    text,
+                    e
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    let use_gitignore = config.sheafy.use_gitignore.unwrap_or(true);
+    let parents = use_gitignore && config.sheafy.parent_gitignore.unwrap_or(true);
+    let entries = walk(&working_dir, use_gitignore, parents);
+    for (position, rule) in rules.iter().enumerate() {
+        let matched: Vec<&(PathBuf, bool)> = entries
+            .iter()
+            .filter(|(path, is_dir)| {
+                let m = rule.matcher.matched(path, *is_dir);
+                m.is_ignore() || m.is_whitelist()
+            })
+            .collect();
+        if matched.is_empty() {
+            eprintln!(
+                "Warning: line {}: '{}' matches no files in {}",
+                rule.line_no,
+                rule.text,
+                working_dir.display()
+            );
+            warnings += 1;
+            continue;
+        }
+        if rule.negated {
+            if let Some(reason) = shadowed_negation(&rules[..position], &matched) {
+                eprintln!("Warning: line {}: '{}' {}", rule.line_no, rule.text, reason);
+                warnings += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        bail!(
+            "{} invalid pattern(s) and {} warning(s) in ignore_patterns",
+            errors,
+            warnings
+        );
+    }
+    if warnings == 0 {
+        status!(
+            "ignore_patterns look good ({} pattern(s) checked).",
+            rules.len()
+        );
+    } else {
+        status!("{} warning(s) in ignore_patterns.", warnings);
+    }
+    Ok(())
+}
+
+/// Explains why a negation can never take effect, if it can't.
+fn shadowed_negation(earlier: &[Rule], matched: &[&(PathBuf, bool)]) -> Option<String> {
+    let positive = || earlier.iter().filter(|r| !r.negated);
+    let mut blocked_by: Option<(&Path, &Rule)> = None;
+    for (path, is_dir) in matched {
+        // A file under an excluded directory cannot be re-included
+        let excluded_parent = path.ancestors().skip(1).find_map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                return None;
+            }
+            positive()
+                .find(|r| r.matcher.matched(ancestor, true).is_ignore())
+                .map(|rule| (ancestor, rule))
+        });
+        match excluded_parent {
+            Some(found) => blocked_by = blocked_by.or(Some(found)),
+            None if positive().any(|r| r.matcher.matched(path, *is_dir).is_ignore()) => {
+                // At least one match is really re-included
+                return None;
+            }
+            None => {}
+        }
+    }
+    Some(match blocked_by {
+        Some((dir, rule)) => format!(
+            "can never apply: its files are inside '{}', excluded by '{}' on line {}",
+            dir.display(),
+            rule.text,
+            rule.line_no
+        ),
+        None => "has no effect: nothing it matches is ignored by an earlier pattern".to_string(),
+    })
+}
+
+/// Every file and directory (relative to `working_dir`) before custom patterns apply.
+fn walk(working_dir: &Path, use_gitignore: bool, parents: bool) -> Vec<(PathBuf, bool)> {
+    let mut builder = WalkBuilder::new(working_dir);
+    builder.standard_filters(use_gitignore).parents(parents);
+    builder.filter_entry(|entry| {
+        !(entry.depth() == 1 && entry.file_name() == crate::state::STATE_DIR)
+    });
+    builder
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.depth() > 0)
+        .filter_map(|entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            let rel = entry.path().strip_prefix(working_dir).ok()?.to_path_buf();
+            Some((rel, is_dir))
+        })
+        .collect()
+}
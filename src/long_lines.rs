@@ -0,0 +1,159 @@
+//! Files with very long lines: minified bundles, embedded base64, generated data.
+//!
+//! A single multi-megabyte line is hard on Markdown renderers and wastes a model's
+//! context, so every line longer than `max_line_length` characters is reported. What
+//! happens to the file is set per pattern in `long_lines`: `warn` (the default) bundles
+//! it as is, `wrap` breaks the long lines into several and notes where (restore joins
+//! them again), `truncate` cuts them short and marks the section as a preview (restore
+//! skips it), and `skip` leaves the file out.
+
+use crate::config::{Config, LongLinePolicy};
+use crate::restore::WRAP_PREFIX;
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{collections::BTreeMap, path::Path};
+
+/// Lines longer than this many characters are reported unless `max_line_length` is set
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 10_000;
+
+/// The `max_line_length` and `long_lines` settings.
+pub struct LongLines {
+    pub max: usize,
+    rules: Vec<(Gitignore, LongLinePolicy)>,
+}
+
+/// The long lines of one file.
+#[derive(Debug)]
+pub struct Offenders {
+    /// How many lines are too long
+    pub count: usize,
+    /// 1-based number and length in characters of the longest line
+    pub longest: (usize, usize),
+}
+
+impl LongLines {
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (pattern, policy) in &config.sheafy.long_lines {
+            let mut builder = GitignoreBuilder::new(working_dir);
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid long_lines pattern: '{}'", pattern))?;
+            rules.push((builder.build()?, *policy));
+        }
+        Ok(Self {
+            max: config
+                .sheafy
+                .max_line_length
+                .unwrap_or(DEFAULT_MAX_LINE_LENGTH)
+                .max(1),
+            rules,
+        })
+    }
+
+    /// The policy for `rel_path`: the strictest of the patterns it matches.
+    pub fn policy(&self, rel_path: &Path) -> LongLinePolicy {
+        self.rules
+            .iter()
+            .filter(|(matcher, _)| {
+                matcher
+                    .matched_path_or_any_parents(rel_path, false)
+                    .is_ignore()
+            })
+            .map(|(_, policy)| *policy)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The lines of `content` longer than the maximum, if any.
+    pub fn find(&self, content: &str) -> Option<Offenders> {
+        let mut offenders: Option<Offenders> = None;
+        for (index, line) in content.split('\n').enumerate() {
+            let chars = line.chars().count();
+            if chars <= self.max {
+                continue;
+            }
+            let found = offenders.get_or_insert(Offenders {
+                count: 0,
+                longest: (index + 1, chars),
+            });
+            found.count += 1;
+            if chars > found.longest.1 {
+                found.longest = (index + 1, chars);
+            }
+        }
+        offenders
+    }
+
+    /// Breaks every long line of `content` into lines of at most the maximum length,
+    /// returning the new content and the note restore needs to join them again.
+    pub fn wrap(&self, content: &str) -> (String, String) {
+        let mut wrapped = Vec::new();
+        let mut pieces: Vec<String> = Vec::new();
+        for (index, line) in content.split('\n').enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.len() <= self.max {
+                pieces.push(line.to_string());
+                continue;
+            }
+            let chunks: Vec<String> = chars
+                .chunks(self.max)
+                .map(|chunk| chunk.iter().collect())
+                .collect();
+            wrapped.push(format!("{}:{}", index + 1, chunks.len()));
+            pieces.extend(chunks);
+        }
+        let note = format!(
+            "{} at={} lines={} -->",
+            WRAP_PREFIX,
+            self.max,
+            wrapped.join(",")
+        );
+        (pieces.join("\n"), note)
+    }
+
+    /// Cuts every long line of `content` short, saying how much was left out.
+    pub fn truncate(&self, content: &str) -> String {
+        content
+            .split('\n')
+            .map(|line| match line.char_indices().nth(self.max) {
+                Some((end, _)) => format!(
+                    "{} [... {} more characters]",
+                    &line[..end],
+                    line[end..].chars().count()
+                ),
+                None => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Joins the lines a `<!-- sheafy:wrap ... -->` note (given without prefix and `-->`)
+/// says were broken up. `None` if the note is malformed or doesn't fit `content`.
+pub fn unwrap(content: &str, note: &str) -> Option<String> {
+    let lines = note
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("lines="))?;
+    let mut counts = BTreeMap::new();
+    for entry in lines.split(',').filter(|e| !e.is_empty()) {
+        let (line, count) = entry.split_once(':')?;
+        counts.insert(line.parse::<usize>().ok()?, count.parse::<usize>().ok()?);
+    }
+    let mut pieces = content.split('\n');
+    let mut joined = Vec::new();
+    let mut line = 1;
+    loop {
+        let count = counts.remove(&line).unwrap_or(1);
+        let mut original = String::new();
+        for _ in 0..count {
+            original.push_str(pieces.next()?);
+        }
+        joined.push(original);
+        line += 1;
+        if counts.is_empty() {
+            joined.extend(pieces.map(str::to_string));
+            return Some(joined.join("\n"));
+        }
+    }
+}
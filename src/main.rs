@@ -9,44 +9,167 @@
 //! sheafy restore bundle.md
 //! ```
 //!
-mod bundle;
-mod cli;
-mod config;
-mod restore;
-
 use anyhow::{Context, Result};
 use clap::Parser;
+use sheafy::{
+    analyze, ask, bundle, clean, cli, config, daemon, doctor, error, graph, inspect, lint,
+    profiles, prompt, render, restore, scaffold, settings, stats, status, timings, tr, tui, update,
+    verify, watch,
+};
+use std::process::ExitCode;
 
-#[macro_use(defer)]
-extern crate scopeguard;
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            // Stable codes let scripts tell failures apart without matching messages
+            if let Some(code) = error::code(&e) {
+                eprintln!("\nerror code: {}", code);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     let cli = cli::Cli::parse();
+    // Keep stdout clean for protocol traffic, printed graphs, config values and replies
+    if matches!(
+        cli.command,
+        cli::Commands::Daemon { .. } | cli::Commands::Ask(_)
+    ) || matches!(&cli.command, cli::Commands::Graph(args) if args.output.is_none())
+        || matches!(
+            &cli.command,
+            cli::Commands::Config {
+                action: cli::ConfigCommand::Get { .. }
+            }
+        )
+    {
+        status::redirect_to_stderr(true);
+    }
+    if cli.yes {
+        prompt::assume_yes();
+    } else if cli.no_input || matches!(cli.command, cli::Commands::Daemon { .. }) {
+        // The daemon's stdin carries requests, not answers
+        prompt::disable_input();
+    }
     // Get current dir early, before potential working_dir change in config
     let initial_dir = std::env::current_dir().context("Failed to get initial working directory")?;
-    println!("Running from directory: {}", initial_dir.display());
-
+    status!("{}", tr!("main.running_from", initial_dir.display()));
+    // Phases are recorded from here on, and reported however the command ends
+    if cli.timings || cli.profile_out.is_some() {
+        timings::enable();
+    }
+    let print_timings = cli.timings;
+    let profile_out = cli.profile_out.as_ref().map(|path| initial_dir.join(path));
+    scopeguard::defer! {
+        let command_line: Vec<String> = std::env::args().skip(1).collect();
+        if let Err(e) = timings::finish(print_timings, profile_out.as_deref(), &command_line.join(" ")) {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
 
     match cli.command {
-        cli::Commands::Init => config::Config::init(),
-        cli::Commands::Bundle {
-            // REMOVED: filters
-            output,
-            use_gitignore,
-            no_gitignore,
-        } => {
-             // Load config *after* knowing the command might need it
-             let config = config::Config::load().context("Failed to load configuration")?;
-             let working_dir = config.get_working_dir()?;
-             println!("Effective working directory: {}", working_dir.display());
-             bundle::run_bundle(config, output, use_gitignore, no_gitignore)
-        },
-        cli::Commands::Restore { input_file } => {
+        cli::Commands::Init { analyze: false } => config::Config::init(),
+        cli::Commands::Init { analyze: true } => analyze::run_init_analyze(),
+        cli::Commands::Bundle(args) => {
+            // Load config *after* knowing the command might need it
+            let config = config::Config::load().context("Failed to load configuration")?;
+            let working_dir = config.get_working_dir()?;
+            status!("{}", tr!("main.working_dir", working_dir.display()));
+            if args.all_profiles {
+                return profiles::run_all(*args).map(|_| ());
+            }
+            let watch = args.watch;
+            let args = match &args.profile {
+                Some(name) => profiles::apply(&config, name, &args)?,
+                None => *args,
+            };
+            if watch {
+                return watch::run_watch(args);
+            }
+            if daemon::bundle_via_socket(&config, &args)?.is_some() {
+                return Ok(());
+            }
+            bundle::run_bundle(config, args).map(|_| ())
+        }
+        cli::Commands::Restore(args) => {
             // Load config *after* knowing the command might need it
             let config = config::Config::load().context("Failed to load configuration")?;
             let working_dir = config.get_working_dir()?;
-            println!("Effective working directory: {}", working_dir.display());
-            restore::run_restore(config, input_file)
+            status!("{}", tr!("main.working_dir", working_dir.display()));
+            restore::run_restore(&config, &args).map(|_| ())
+        }
+        cli::Commands::Update { input_file } => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            update::run_update(config, input_file)
+        }
+        cli::Commands::Verify { input_file } => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            verify::run_verify(&config, input_file.as_deref())
+        }
+        cli::Commands::Inspect { input_file } => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            inspect::run_inspect(&config, input_file.as_deref())
+        }
+        cli::Commands::Clean { dry_run } => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            clean::run_clean(&config, dry_run)
+        }
+        cli::Commands::Render {
+            input_file,
+            output,
+            pdf,
+        } => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            render::run_render(&config, input_file.as_deref(), output.as_deref(), pdf)
+        }
+        // Set and edit must work on a config that does not load
+        cli::Commands::Config { action } => match action {
+            cli::ConfigCommand::Set { key, value, global } => {
+                settings::run_set(&key, &value, global)
+            }
+            cli::ConfigCommand::Edit { global } => settings::run_edit(global),
+            cli::ConfigCommand::Lint => {
+                let config = config::Config::load().context("Failed to load configuration")?;
+                lint::run_lint(&config)
+            }
+            cli::ConfigCommand::Show { origin } => config::Config::load_layered()
+                .context("Failed to load configuration")?
+                .show(origin),
+            cli::ConfigCommand::Get { key } => config::Config::load_layered()
+                .context("Failed to load configuration")?
+                .get(&key),
         },
+        cli::Commands::Daemon {
+            socket: Some(path),
+            paranoid,
+            verbose,
+            ..
+        } => daemon::run_socket(path.as_deref(), paranoid, verbose),
+        cli::Commands::Daemon { .. } => daemon::run_stdio(),
+        // Checks the config itself, so it must not fail on loading it
+        cli::Commands::Doctor => doctor::run_doctor(),
+        cli::Commands::Graph(args) => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            graph::run_graph(&config, &args)
+        }
+        cli::Commands::Ask(args) => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            ask::run_ask(config, *args)
+        }
+        cli::Commands::New(args) => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            scaffold::run_new(&config, &args)
+        }
+        cli::Commands::Stats(args) => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            stats::run_stats(&config, &args)
+        }
+        cli::Commands::Tui(args) => {
+            let config = config::Config::load().context("Failed to load configuration")?;
+            tui::run_tui(config, &args)
+        }
     }
 }
@@ -0,0 +1,87 @@
+//! Minified and obfuscated sources: a few huge lines with hardly any whitespace.
+//!
+//! Such files cost a model enormous numbers of tokens and tell it next to nothing, so
+//! bundling recognises them by their shape rather than their name: at least
+//! [`MIN_BYTES`], lines [`MIN_AVERAGE_LINE`] bytes long on average, and less than
+//! [`MAX_WHITESPACE_PERCENT`] whitespace. The `minified` setting then decides what
+//! happens to them: `include` (the default) bundles them as they are, `truncate` keeps
+//! the first [`PREVIEW_CHARS`] characters as a preview (restore skips it) and `skip`
+//! leaves them out. Either way they are named in a warning, and the bundle ends with a
+//! note listing the ones left out.
+
+use crate::config::ByteSize;
+use std::path::PathBuf;
+
+/// Files smaller than this are never taken for minified
+pub const MIN_BYTES: usize = 1024;
+/// Average line length, in bytes, from which a file may be minified
+pub const MIN_AVERAGE_LINE: usize = 300;
+/// Share of whitespace, in percent, below which such a file is minified
+pub const MAX_WHITESPACE_PERCENT: usize = 10;
+/// Characters kept of a minified file with `minified = "truncate"`
+pub const PREVIEW_CHARS: usize = 1000;
+
+/// The shape of a file that looks minified.
+#[derive(Debug)]
+pub struct Minified {
+    /// Average line length in bytes
+    pub average_line: usize,
+    /// Share of whitespace in percent
+    pub whitespace_percent: usize,
+}
+
+impl Minified {
+    /// What makes the file look minified, for warnings.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} bytes per line, {}% whitespace",
+            self.average_line, self.whitespace_percent
+        )
+    }
+}
+
+/// Whether `content` looks minified or obfuscated.
+pub fn detect(content: &str) -> Option<Minified> {
+    if content.len() < MIN_BYTES {
+        return None;
+    }
+    let lines = content.lines().count().max(1);
+    let average_line = content.len() / lines;
+    if average_line < MIN_AVERAGE_LINE {
+        return None;
+    }
+    let whitespace = content.bytes().filter(u8::is_ascii_whitespace).count();
+    let whitespace_percent = whitespace * 100 / content.len();
+    (whitespace_percent < MAX_WHITESPACE_PERCENT).then_some(Minified {
+        average_line,
+        whitespace_percent,
+    })
+}
+
+/// The first [`PREVIEW_CHARS`] characters of `content`, ending with a newline.
+pub fn preview(content: &str) -> String {
+    let mut preview: String = content.chars().take(PREVIEW_CHARS).collect();
+    preview.push('\n');
+    preview
+}
+
+/// Prose line for the bundle listing the minified files left out (outside any section,
+/// so restore skips it).
+pub fn note(skipped: &[(PathBuf, u64)]) -> String {
+    let names: Vec<String> = skipped
+        .iter()
+        .map(|(file, size)| {
+            format!(
+                "`{}` ({})",
+                file.to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/"),
+                ByteSize(*size)
+            )
+        })
+        .collect();
+    format!(
+        "> Left out as minified: {} file(s): {}.",
+        skipped.len(),
+        names.join(", ")
+    )
+}
@@ -0,0 +1,251 @@
+//! The built-in `notebook` handler: Jupyter notebooks are bundled in the "percent"
+//! script format (`# %%` cell markers, markdown cells as `# ` comments) instead of as
+//! raw JSON, which is hard to read and costs many tokens.
+//!
+//! The kernel's language picks the code fence language and the comment prefix (`#`,
+//! `//` or `--`). Notebook and cell metadata are kept as JSON on the marker lines so
+//! restore can rebuild a valid nbformat 4 notebook. Outputs and execution counts are
+//! not bundled. Restoring over an existing notebook keeps them for the code cells whose
+//! source is unchanged, so an unedited notebook is left as it is; other cells come back
+//! with cleared outputs.
+
+use crate::handlers::{Encoded, FileHandler};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, VecDeque};
+
+/// Follows the comment prefix on the first line of a flattened notebook
+const HEADER_TAG: &str = " sheafy:notebook ";
+const CELL_TAG: &str = " %%";
+
+pub struct NotebookHandler;
+
+impl FileHandler for NotebookHandler {
+    fn encode(&self, _path: &str, content: &str) -> Result<Encoded> {
+        let mut notebook: Map<String, Value> =
+            serde_json::from_str(content).context("Not a valid notebook (JSON object)")?;
+        if notebook.get("nbformat").and_then(Value::as_u64) != Some(4) {
+            bail!("Only nbformat 4 notebooks can be flattened");
+        }
+        let cells = match notebook.remove("cells") {
+            Some(Value::Array(cells)) => cells,
+            _ => bail!("Notebook has no cells array"),
+        };
+        let language = kernel_language(&notebook);
+        let comment = comment_prefix(&language);
+        let marker = format!("{}{}", comment, CELL_TAG);
+        let mut out = format!("{}{}{}\n\n", comment, HEADER_TAG, Value::Object(notebook));
+        for cell in cells {
+            let Value::Object(mut cell) = cell else {
+                bail!("Notebook cell is not an object");
+            };
+            let cell_type = match cell.remove("cell_type") {
+                Some(Value::String(t)) => t,
+                _ => bail!("Notebook cell without a cell_type"),
+            };
+            let source = source_text(cell.remove("source"));
+            cell.remove("outputs");
+            cell.remove("execution_count");
+            if cell.get("metadata").is_some_and(|m| m == &json!({})) {
+                cell.remove("metadata");
+            }
+
+            out.push_str(&marker);
+            if cell_type != "code" {
+                out.push_str(&format!(" [{}]", cell_type));
+            }
+            if !cell.is_empty() {
+                out.push_str(&format!(" {}", Value::Object(cell)));
+            }
+            out.push('\n');
+            for line in source.lines() {
+                match (cell_type.as_str(), line) {
+                    ("code", line) => out.push_str(line),
+                    (_, "") => out.push_str(comment),
+                    (_, line) => {
+                        out.push_str(comment);
+                        out.push(' ');
+                        out.push_str(line);
+                    }
+                }
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        Ok(Encoded {
+            content: out,
+            language,
+        })
+    }
+
+    fn decode(&self, _path: &str, bundled: &str) -> Result<String> {
+        let mut lines = bundled.lines();
+        let (comment, header) = lines
+            .next()
+            .and_then(|line| line.split_once(HEADER_TAG))
+            .context("Missing notebook header line")?;
+        let mut notebook: Map<String, Value> =
+            serde_json::from_str(header).context("Invalid notebook header")?;
+        let marker = format!("{}{}", comment, CELL_TAG);
+        let commented = format!("{} ", comment);
+
+        let mut cells: Vec<(String, Map<String, Value>, Vec<&str>)> = Vec::new();
+        for line in lines {
+            if let Some(rest) = line.strip_prefix(&marker) {
+                cells.push(parse_marker(rest)?);
+                continue;
+            }
+            match cells.last_mut() {
+                Some((_, _, body)) => body.push(line),
+                None if line.is_empty() => {}
+                None => bail!("Text before the first `{}` cell marker", marker),
+            }
+        }
+
+        let cells: Vec<Value> = cells
+            .into_iter()
+            .map(|(cell_type, mut cell, mut body)| {
+                // Every cell is followed by one blank line
+                if body.last() == Some(&"") {
+                    body.pop();
+                }
+                let text: Vec<&str> = if cell_type == "code" {
+                    body
+                } else {
+                    body.iter()
+                        .map(|line| {
+                            line.strip_prefix(commented.as_str())
+                                .or(line.strip_prefix(comment))
+                                .unwrap_or(line)
+                        })
+                        .collect()
+                };
+                let text = text.join("\n");
+                cell.insert(
+                    "source".into(),
+                    Value::Array(
+                        text.split_inclusive('\n')
+                            .map(|l| Value::String(l.to_string()))
+                            .collect(),
+                    ),
+                );
+                cell.entry("metadata").or_insert_with(|| json!({}));
+                if cell_type == "code" {
+                    cell.insert("outputs".into(), json!([]));
+                    cell.insert("execution_count".into(), Value::Null);
+                }
+                cell.insert("cell_type".into(), Value::String(cell_type));
+                Value::Object(cell)
+            })
+            .collect();
+        notebook.insert("cells".into(), Value::Array(cells));
+        to_notebook_json(&Value::Object(notebook))
+    }
+
+    /// Gives each code cell the outputs and execution count of the first unclaimed code
+    /// cell of `existing` with the same source.
+    fn merge(&self, existing: &str, decoded: String) -> String {
+        let (Ok(existing), Ok(mut notebook)) = (
+            serde_json::from_str::<Value>(existing),
+            serde_json::from_str::<Value>(&decoded),
+        ) else {
+            return decoded;
+        };
+        let mut outputs: HashMap<String, VecDeque<(Value, Value)>> = HashMap::new();
+        for cell in code_cells(&existing) {
+            outputs
+                .entry(source_text(cell.get("source").cloned()))
+                .or_default()
+                .push_back((
+                    cell.get("outputs").cloned().unwrap_or_else(|| json!([])),
+                    cell.get("execution_count").cloned().unwrap_or(Value::Null),
+                ));
+        }
+        let Some(Value::Array(cells)) = notebook.get_mut("cells") else {
+            return decoded;
+        };
+        for cell in cells.iter_mut().filter(|cell| cell["cell_type"] == "code") {
+            let source = source_text(cell.get("source").cloned());
+            if let Some((cell_outputs, count)) =
+                outputs.get_mut(&source).and_then(VecDeque::pop_front)
+            {
+                cell["outputs"] = cell_outputs;
+                cell["execution_count"] = count;
+            }
+        }
+        to_notebook_json(&notebook).unwrap_or(decoded)
+    }
+}
+
+/// The code cells of a notebook.
+fn code_cells(notebook: &Value) -> impl Iterator<Item = &Value> {
+    notebook["cells"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|cell| cell["cell_type"] == "code")
+}
+
+/// The kernel's language as a code fence hint, defaulting to Python.
+fn kernel_language(notebook: &Map<String, Value>) -> String {
+    let metadata = notebook.get("metadata");
+    let language = metadata
+        .and_then(|m| m.pointer("/kernelspec/language"))
+        .or_else(|| metadata.and_then(|m| m.pointer("/language_info/name")))
+        .and_then(Value::as_str)
+        .unwrap_or("python")
+        .to_lowercase();
+    match language.as_str() {
+        "c++" | "c++17" | "c++20" => "cpp".to_string(),
+        "c#" => "csharp".to_string(),
+        "f#" => "fsharp".to_string(),
+        _ => language,
+    }
+}
+
+/// Line comment syntax of a kernel language.
+fn comment_prefix(language: &str) -> &'static str {
+    match language {
+        "javascript" | "typescript" | "cpp" | "c" | "csharp" | "fsharp" | "java" | "kotlin"
+        | "scala" | "go" | "rust" | "swift" | "dart" => "//",
+        "sql" | "haskell" | "lua" => "--",
+        _ => "#",
+    }
+}
+
+/// Parses what follows `# %%`: an optional `[type]` and optional cell JSON.
+fn parse_marker(marker: &str) -> Result<(String, Map<String, Value>, Vec<&str>)> {
+    let mut rest = marker.trim();
+    let mut cell_type = "code".to_string();
+    if let Some(tagged) = rest.strip_prefix('[') {
+        let end = tagged.find(']').context("Unclosed `[` in cell marker")?;
+        cell_type = tagged[..end].to_string();
+        rest = tagged[end + 1..].trim();
+    }
+    let cell = if rest.is_empty() {
+        Map::new()
+    } else {
+        serde_json::from_str(rest).with_context(|| format!("Invalid cell metadata: {}", rest))?
+    };
+    Ok((cell_type, cell, Vec::new()))
+}
+
+/// Notebook sources are either a string or a list of lines.
+fn source_text(source: Option<Value>) -> String {
+    match source {
+        Some(Value::String(text)) => text,
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Serializes like Jupyter does: sorted keys, one-space indent, trailing newline.
+fn to_notebook_json(notebook: &Value) -> Result<String> {
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b" ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    notebook.serialize(&mut serializer)?;
+    out.push(b'\n');
+    Ok(String::from_utf8(out)?)
+}
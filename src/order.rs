@@ -0,0 +1,142 @@
+//! Bundle orders other than alphabetical.
+//!
+//! `auto_order = "smart"` reads well without configuration. Manifests come first, then
+//! READMEs, then entry points, each group shallowest path first. Then come the modules
+//! those files reference, in the order they are referenced (depth first, using the same
+//! lexical resolution as `--focus`), and then everything else alphabetically.
+//!
+//! `auto_order = "recent-first"` puts the files changed last at the top, where a model
+//! pays them the most attention. A file's time is that of its last commit, or its
+//! modification time if it has uncommitted changes or the tree is not a git work tree.
+//!
+//! `priority_patterns` still apply on top of either.
+
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "setup.py",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "build.gradle.kts",
+    "composer.json",
+    "Gemfile",
+    "CMakeLists.txt",
+];
+
+const ENTRY_POINTS: &[&str] = &[
+    "main.rs",
+    "lib.rs",
+    "main.go",
+    "main.py",
+    "__main__.py",
+    "app.py",
+    "index.ts",
+    "index.tsx",
+    "index.js",
+    "main.ts",
+    "main.js",
+];
+
+/// Group of a file that is placed by name; `None` for the others.
+fn group(rel_path: &Path) -> Option<usize> {
+    let name = rel_path.file_name()?.to_str()?;
+    if MANIFESTS.contains(&name) {
+        Some(0)
+    } else if name
+        .split('.')
+        .next()
+        .is_some_and(|stem| stem.eq_ignore_ascii_case("readme"))
+    {
+        Some(1)
+    } else if ENTRY_POINTS.contains(&name) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Reorders `files` (relative to `working_dir`) as described in the module docs.
+pub fn smart_order(working_dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut leading: Vec<(usize, usize, &PathBuf)> = files
+        .iter()
+        .filter_map(|rel_path| Some((group(rel_path)?, rel_path.components().count(), rel_path)))
+        .collect();
+    leading.sort();
+    let graph = crate::deps::reference_graph(working_dir, files);
+
+    let mut ordered: Vec<PathBuf> = Vec::with_capacity(files.len());
+    let mut placed: HashSet<PathBuf> = HashSet::new();
+    for (_, _, rel_path) in &leading {
+        if placed.insert((*rel_path).clone()) {
+            ordered.push((*rel_path).clone());
+        }
+    }
+    // Depth first from each leading file, in the order references appear
+    let mut stack: Vec<PathBuf> = ordered.iter().rev().cloned().collect();
+    let mut expanded: HashSet<PathBuf> = HashSet::new();
+    while let Some(file) = stack.pop() {
+        if !expanded.insert(file.clone()) {
+            continue;
+        }
+        if placed.insert(file.clone()) {
+            ordered.push(file.clone());
+        }
+        if let Some(references) = graph.get(&file) {
+            stack.extend(
+                references
+                    .iter()
+                    .rev()
+                    .filter(|r| !expanded.contains(*r))
+                    .cloned(),
+            );
+        }
+    }
+    let mut rest: Vec<PathBuf> = files
+        .iter()
+        .filter(|rel_path| !placed.contains(*rel_path))
+        .cloned()
+        .collect();
+    rest.sort();
+    ordered.extend(rest);
+    *files = ordered;
+}
+
+/// Reorders `files` (relative to `working_dir`) most recently changed first, keeping
+/// their order among files changed at the same second.
+pub fn recent_first(working_dir: &Path, files: &mut [PathBuf]) {
+    let git = crate::git::is_work_tree(working_dir);
+    let committed = git
+        .then(|| crate::git::commit_times(working_dir).ok())
+        .flatten()
+        .unwrap_or_default();
+    let changed: HashSet<PathBuf> = git
+        .then(|| crate::git::changed_files(working_dir, "HEAD").ok())
+        .flatten()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let modified = |rel_path: &Path| -> i64 {
+        std::fs::metadata(working_dir.join(rel_path))
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |age| age.as_secs() as i64)
+    };
+    files.sort_by_cached_key(|rel_path| {
+        let key = rel_path.to_string_lossy().replace('\\', "/");
+        let time = match committed.get(&key) {
+            Some(&time) if !changed.contains(rel_path) => time,
+            _ => modified(rel_path),
+        };
+        Reverse(time)
+    });
+}
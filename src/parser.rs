@@ -0,0 +1,856 @@
+//! Line-oriented bundle parser.
+//!
+//! A bundle is read one line at a time through a small state machine: front matter,
+//! leading `<!-- sheafy:... -->` markers, then prose, file sections and the notes that
+//! follow them. Only the section being read is held in memory: finished sections are
+//! spilled to an anonymous temporary file, where `same-as` references are read back
+//! from, so bundles of any size can be restored. A malformed bundle is reported with the
+//! line where it goes wrong.
+//!
+//! A section starts with a `## path` heading followed (after optional blank lines) by a
+//! code fence, a `same-as` reference or a `stub` note (a `stats` note may come first),
+//! or with a fence whose info string carries the path (`title="..."` /
+//! `filename="..."`). Headings that cannot be a path (`## Getting started`, see
+//! [`crate::header::parse_path`]) are prose, even when a code fence follows them. A fence opened with N backticks is closed by a line of at least N
+//! backticks, as in CommonMark; bundling picks N so that no line of the file closes it
+//! (see [`fence_for`]). A file split into parts has one fence per part under its
+//! heading, each preceded by `<!-- sheafy:part k/N -->`. Fences in prose are skipped
+//! whole, so headings quoted inside them are not mistaken for sections, and so is the
+//! task prompt between `<!-- sheafy:prompt -->` and `<!-- sheafy:prompt-end -->`.
+//! The `## sheafy:checksums` section is not a file: its fence lists the SHA-256 of each
+//! section, and is kept for [`BundleReader::checksums`].
+
+use crate::error::SheafyError;
+use crate::restore::{
+    ensure_eof_newline, Section, CHECKSUMS_HEADING, HANDLER_PREFIX, LINKS_PREFIX, NO_EOL_MARKER,
+    PART_PREFIX, PREVIEW_PREFIX, PROMPT_END_MARKER, PROMPT_MARKER, SAME_AS_PREFIX, STATS_PREFIX,
+    UNMAPPED_MARKER, WRAP_PREFIX,
+};
+use crate::stub::Stub;
+use crate::vars::Template;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufRead, Read, Seek, SeekFrom, Write},
+};
+
+lazy_static! {
+    // Path in the info string of a headingless section, as rendered by mkdocs-material
+    // (```rust title="src/main.rs") and pandoc (```{.rust filename="src/main.rs"})
+    static ref INFO_PATH_REGEX: Regex = Regex::new(r#"\b(?:title|filename)="([^"\n]*)""#).unwrap();
+}
+
+/// Reads the sections of a bundle in order.
+pub struct BundleReader<R> {
+    reader: R,
+    /// Number of the last line read (1-based)
+    line_no: usize,
+    /// A line read ahead and given back, with its number
+    peeked: Option<(String, usize)>,
+    markers: Vec<String>,
+    pending: Option<Pending>,
+    /// Where the content of every section so far was spilled, for `same-as` references
+    by_path: HashMap<String, Spilled>,
+    /// Contents of the finished sections; created with the first one
+    spill: Option<File>,
+    /// Header path -> SHA-256, from the `## sheafy:checksums` section
+    checksums: Option<BTreeMap<String, String>>,
+    /// Top-level `key: value` lines of the front matter
+    front_matter: BTreeMap<String, String>,
+    /// Variables filled into the paths and contents of sections
+    template: Option<Template>,
+    done: bool,
+    /// Yield a section the bundle ends inside of instead of failing
+    keep_truncated: bool,
+    /// What the parser saw and decided, when tracing (`sheafy inspect`)
+    trace: Option<Vec<TraceEvent>>,
+}
+
+/// One step of a parse trace.
+#[derive(Debug)]
+pub struct TraceEvent {
+    /// Line the event refers to (1-based)
+    pub line: usize,
+    /// Whether something that looked like part of a section was not accepted
+    pub rejected: bool,
+    pub message: String,
+}
+
+/// A finished section's content in the spill file.
+struct Spilled {
+    offset: u64,
+    len: usize,
+    handler: Option<String>,
+}
+
+/// What a section's body holds before `same-as` references are resolved.
+enum Body {
+    /// Fenced content, and whether the bundle ended before the closing fence
+    Code(String, bool),
+    SameAs(String),
+    Stub(Stub),
+}
+
+/// A top-level line the state machine cares about.
+enum Item {
+    /// A section and the line it starts on
+    Section(usize, String, Body),
+    Note(String),
+}
+
+/// The last section read and the notes following it so far.
+struct Pending {
+    line: usize,
+    /// Last line of the section's body or notes
+    end: usize,
+    path: String,
+    body: Body,
+    notes: Vec<String>,
+}
+
+impl<R: BufRead> BundleReader<R> {
+    /// Reads the front matter and the leading markers.
+    pub fn new(reader: R) -> Result<Self> {
+        Self::open(reader, false)
+    }
+
+    /// Like [`BundleReader::new`], but records every step in a trace; see
+    /// [`BundleReader::take_trace`].
+    pub fn traced(reader: R) -> Result<Self> {
+        Self::open(reader, true)
+    }
+
+    fn open(reader: R, trace: bool) -> Result<Self> {
+        let mut bundle = Self {
+            reader,
+            line_no: 0,
+            peeked: None,
+            markers: Vec::new(),
+            pending: None,
+            by_path: HashMap::new(),
+            spill: None,
+            checksums: None,
+            front_matter: BTreeMap::new(),
+            template: None,
+            done: false,
+            keep_truncated: false,
+            trace: trace.then(Vec::new),
+        };
+        bundle.read_header()?;
+        Ok(bundle)
+    }
+
+    /// Yields a file section whose code fence is still open when the bundle ends (as
+    /// LLM output cut off mid-file is) with [`Section::truncated`] set, instead of
+    /// failing. An unclosed fence outside any section is still an error.
+    pub fn keep_truncated(mut self) -> Self {
+        self.keep_truncated = true;
+        self
+    }
+
+    /// The trace recorded since the last call (empty unless opened with
+    /// [`BundleReader::traced`]).
+    pub fn take_trace(&mut self) -> Vec<TraceEvent> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn note(&mut self, line: usize, message: String) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent {
+                line,
+                rejected: false,
+                message,
+            });
+        }
+    }
+
+    fn reject(&mut self, line: usize, message: String) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent {
+                line,
+                rejected: true,
+                message,
+            });
+        }
+    }
+
+    /// The `<!-- sheafy:... -->` marker lines at the start of the bundle, trimmed.
+    pub fn markers(&self) -> &[String] {
+        &self.markers
+    }
+
+    /// The value of a top-level `key: value` line of the front matter, trimmed.
+    pub fn front_matter(&self, key: &str) -> Option<&str> {
+        self.front_matter.get(key).map(String::as_str)
+    }
+
+    /// Whether the front matter declares the bundle for reading only (`readonly: true` or
+    /// `restore: false`), as for documentation snapshots and context bundles.
+    pub fn declared_readonly(&self) -> bool {
+        self.front_matter("readonly") == Some("true")
+            || self.front_matter("restore") == Some("false")
+    }
+
+    /// Fills the variables the bundle declares in its front matter (see [`crate::vars`])
+    /// into the paths and contents of its sections, from `values`. Fails if one has no
+    /// value; bundles declaring none are read unchanged.
+    pub fn with_vars(mut self, values: &BTreeMap<String, String>) -> Result<Self> {
+        if let Some(declared) = self.front_matter(crate::vars::FRONT_MATTER_KEY) {
+            self.template = Some(Template::new(declared, values)?);
+        }
+        Ok(self)
+    }
+
+    /// The checksums section read so far, by header path. It comes after the files, so
+    /// it is only complete once every section has been read.
+    pub fn checksums(&self) -> Option<&BTreeMap<String, String>> {
+        self.checksums.as_ref()
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some((line, line_no)) = self.peeked.take() {
+            self.line_no = line_no;
+            return Ok(Some(line));
+        }
+        let mut line = String::new();
+        let read = match self.reader.read_line(&mut line) {
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                return Err(SheafyError::NonUtf8File {
+                    line: self.line_no + 1,
+                }
+                .into())
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("line {}: failed to read bundle", self.line_no + 1))
+            }
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        self.line_no += 1;
+        Ok(Some(line))
+    }
+
+    fn unread(&mut self, line: String) {
+        self.peeked = Some((line, self.line_no));
+        self.line_no -= 1;
+    }
+
+    fn read_header(&mut self) -> Result<()> {
+        let Some(first) = self.next_line()? else {
+            return Ok(());
+        };
+        if first.trim_start_matches('\u{feff}').trim_end() == "---" {
+            loop {
+                match self.next_line()? {
+                    Some(line) if line.trim_end() == "---" => break,
+                    Some(line) => {
+                        // Nested values are indented; only top-level scalars are kept
+                        if let Some((key, value)) = line
+                            .split_once(':')
+                            .filter(|(key, _)| !key.starts_with(char::is_whitespace))
+                        {
+                            self.front_matter
+                                .insert(key.trim().to_string(), value.trim().to_string());
+                        }
+                    }
+                    None => {
+                        return Err(parse_error(
+                            1,
+                            "front matter is never closed by a `---` line",
+                        ))
+                    }
+                }
+            }
+            self.note(1, format!("front matter (lines 1-{})", self.line_no));
+        } else {
+            self.unread(first);
+        }
+        while let Some(line) = self.next_line()? {
+            let marker = line.trim().trim_start_matches('\u{feff}');
+            if marker.starts_with("<!-- sheafy:") && marker != PROMPT_MARKER {
+                self.note(self.line_no, format!("marker {}", marker));
+                self.markers.push(marker.to_string());
+            } else if !marker.is_empty() {
+                self.unread(line);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a fenced body up to its closing fence, returning it and whether the bundle
+    /// ended first. That is an error unless `truncated_ok`; `opened_at` and `what`
+    /// describe the fence for it.
+    fn read_fenced(
+        &mut self,
+        ticks: usize,
+        opened_at: usize,
+        what: &str,
+        truncated_ok: bool,
+    ) -> Result<(String, bool)> {
+        let mut body = String::new();
+        let mut lines = 0;
+        while let Some(line) = self.next_line()? {
+            if closes_fence(&line, ticks) {
+                self.note(
+                    self.line_no,
+                    format!("fence closes {} after {} line(s)", what, lines),
+                );
+                return Ok((body, false));
+            }
+            body.push_str(&line);
+            lines += 1;
+        }
+        if truncated_ok {
+            self.reject(
+                opened_at,
+                format!(
+                    "the bundle ends inside the fence of {} after {} line(s); truncated",
+                    what, lines
+                ),
+            );
+            return Ok((body, true));
+        }
+        Err(parse_error(
+            opened_at,
+            format!(
+                "code fence of {} is never closed (expected a line of {} backticks)",
+                what, ticks
+            ),
+        ))
+    }
+
+    /// The next line that is not blank.
+    fn next_nonblank_line(&mut self) -> Result<Option<String>> {
+        loop {
+            match self.next_line()? {
+                Some(line) if line.trim().is_empty() => {}
+                line => return Ok(line),
+            }
+        }
+    }
+
+    /// Reads the `total` parts of a split file, the first part's marker having been
+    /// read, and joins them. A bundle ending before the last part closes is truncated
+    /// (an error unless keeping truncated sections).
+    fn read_parts(&mut self, path: &str, total: usize) -> Result<(String, bool)> {
+        let mut body = String::new();
+        for number in 1..=total {
+            let what = format!("part {}/{} of '{}'", number, total, path);
+            if number > 1 {
+                match self.next_nonblank_line()? {
+                    Some(line) if part_number(line.trim()) == Some((number, total)) => {}
+                    None if self.keep_truncated => {
+                        self.reject(
+                            self.line_no,
+                            format!("the bundle ends before {}; truncated", what),
+                        );
+                        return Ok((body, true));
+                    }
+                    _ => return Err(parse_error(self.line_no, format!("expected {}", what))),
+                }
+            }
+            let ticks = match self.next_nonblank_line()? {
+                Some(line) => fence_ticks(line.trim_end()),
+                None if self.keep_truncated => {
+                    self.reject(
+                        self.line_no,
+                        format!("the bundle ends before {}; truncated", what),
+                    );
+                    return Ok((body, true));
+                }
+                None => None,
+            };
+            let Some(ticks) = ticks else {
+                return Err(parse_error(
+                    self.line_no,
+                    format!("{} is not followed by a code fence", what),
+                ));
+            };
+            self.note(
+                self.line_no,
+                format!("fence of {} backticks opens {}", ticks, what),
+            );
+            let (part, truncated) =
+                self.read_fenced(ticks, self.line_no, &what, self.keep_truncated)?;
+            body.push_str(&part);
+            if truncated {
+                return Ok((body, true));
+            }
+        }
+        Ok((body, false))
+    }
+
+    /// Advances to the next section or note, skipping prose and fences without a path.
+    fn next_item(&mut self) -> Result<Option<Item>> {
+        while let Some(line) = self.next_line()? {
+            let line_no = self.line_no;
+            let trimmed = line.trim_end();
+            if let Some(text) = heading_text(trimmed) {
+                let Some(path) = crate::header::parse_path(text) else {
+                    self.reject(
+                        line_no,
+                        format!(
+                            "heading '{}' does not name a path; treated as prose",
+                            trimmed
+                        ),
+                    );
+                    continue;
+                };
+                if path == CHECKSUMS_HEADING {
+                    self.read_checksums(line_no)?;
+                    continue;
+                }
+                self.note(line_no, format!("heading '{}'", trimmed));
+                // Blank lines and a stats note may separate the heading from its body
+                let mut next = self.next_nonblank_line()?;
+                while next
+                    .as_deref()
+                    .is_some_and(|line| note_value(line.trim(), STATS_PREFIX).is_some())
+                {
+                    self.note(self.line_no, format!("stats of '{}'", path));
+                    next = self.next_nonblank_line()?;
+                }
+                let Some(next) = next else {
+                    self.reject(
+                        line_no,
+                        format!("heading '{}' ends the bundle without a body", path),
+                    );
+                    return Ok(None);
+                };
+                if let Some(target) = same_as_target(next.trim()) {
+                    self.note(
+                        self.line_no,
+                        format!("'{}' is the same as '{}'", path, target),
+                    );
+                    return Ok(Some(Item::Section(line_no, path, Body::SameAs(target))));
+                }
+                if let Some(stub) = Stub::parse(next.trim()) {
+                    self.note(
+                        self.line_no,
+                        format!("'{}' is a stub of {} bytes", path, stub.size),
+                    );
+                    return Ok(Some(Item::Section(line_no, path, Body::Stub(stub))));
+                }
+                if let Some((1, total)) = part_number(next.trim()) {
+                    self.note(
+                        self.line_no,
+                        format!("'{}' is split into {} parts", path, total),
+                    );
+                    let (body, truncated) = self.read_parts(&path, total)?;
+                    return Ok(Some(Item::Section(
+                        line_no,
+                        path,
+                        Body::Code(body, truncated),
+                    )));
+                }
+                if let Some(ticks) = fence_ticks(next.trim_end()) {
+                    self.note(
+                        self.line_no,
+                        format!("fence of {} backticks opens '{}'", ticks, path),
+                    );
+                    let what = format!("'{}'", path);
+                    let (body, truncated) =
+                        self.read_fenced(ticks, self.line_no, &what, self.keep_truncated)?;
+                    return Ok(Some(Item::Section(
+                        line_no,
+                        path,
+                        Body::Code(body, truncated),
+                    )));
+                }
+                self.reject(
+                    line_no,
+                    format!(
+                        "heading '{}' is followed by prose (line {}), not by a code fence, \
+                         same-as reference or stub; treated as prose",
+                        path, self.line_no
+                    ),
+                );
+                // A heading of the surrounding prose; look at the next line afresh
+                self.unread(next);
+            } else if let Some(ticks) = fence_ticks(trimmed) {
+                match fence_path(trimmed) {
+                    Some(path) => {
+                        self.note(
+                            line_no,
+                            format!("fence of {} backticks with a path opens '{}'", ticks, path),
+                        );
+                        let what = format!("'{}'", path);
+                        let (body, truncated) =
+                            self.read_fenced(ticks, line_no, &what, self.keep_truncated)?;
+                        return Ok(Some(Item::Section(
+                            line_no,
+                            path,
+                            Body::Code(body, truncated),
+                        )));
+                    }
+                    None => {
+                        self.reject(
+                            line_no,
+                            format!(
+                                "fence of {} backticks without a heading or path in its info \
+                                 string; skipped as prose",
+                                ticks
+                            ),
+                        );
+                        self.read_fenced(
+                            ticks,
+                            line_no,
+                            "a code block outside any section",
+                            false,
+                        )?;
+                    }
+                }
+            } else if trimmed == PROMPT_MARKER {
+                loop {
+                    match self.next_line()? {
+                        Some(line) if line.trim() == PROMPT_END_MARKER => break,
+                        Some(_) => {}
+                        None => {
+                            return Err(parse_error(
+                                line_no,
+                                format!("task prompt is never closed by `{}`", PROMPT_END_MARKER),
+                            ))
+                        }
+                    }
+                }
+                self.note(
+                    line_no,
+                    format!("task prompt (lines {}-{}) skipped", line_no, self.line_no),
+                );
+            } else if trimmed.starts_with("<!-- sheafy:") {
+                return Ok(Some(Item::Note(trimmed.to_string())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the fence of the checksums section, whose heading is on `line_no`: lines of
+    /// a hex SHA-256, two spaces and a header path, as `sha256sum` prints them.
+    fn read_checksums(&mut self, line_no: usize) -> Result<()> {
+        let ticks = match self.next_nonblank_line()? {
+            Some(line) => fence_ticks(line.trim_end()),
+            None => None,
+        };
+        let Some(ticks) = ticks else {
+            return Err(parse_error(
+                line_no,
+                "the checksums section is not followed by a code fence",
+            ));
+        };
+        let opened_at = self.line_no;
+        let (body, _) = self.read_fenced(ticks, opened_at, "the checksums", false)?;
+        let mut checksums = BTreeMap::new();
+        for (index, line) in body.lines().enumerate() {
+            match line.split_once("  ") {
+                Some((hash, path)) if !path.is_empty() => {
+                    checksums.insert(crate::header::normalize(path), hash.trim().to_string());
+                }
+                _ if line.trim().is_empty() => {}
+                _ => {
+                    return Err(parse_error(
+                        opened_at + 1 + index,
+                        "checksum lines must be a SHA-256, two spaces and a path",
+                    ))
+                }
+            }
+        }
+        self.note(
+            line_no,
+            format!(
+                "checksums of {} file(s) (lines {}-{})",
+                checksums.len(),
+                line_no,
+                self.line_no
+            ),
+        );
+        self.checksums = Some(checksums);
+        Ok(())
+    }
+
+    /// Turns a parsed section into a [`Section`], resolving `same-as` references.
+    fn resolve(&mut self, end: usize, path: String, body: Body) -> Result<Option<Section>> {
+        let (content, handler, truncated) = match body {
+            Body::Code(code, truncated) => {
+                (ensure_eof_newline(&code).into_owned(), None, truncated)
+            }
+            Body::Stub(stub) => {
+                return Ok(Some(Section {
+                    path,
+                    content: String::new(),
+                    handler: None,
+                    preview: false,
+                    truncated: false,
+                    stub: Some(stub),
+                    unmapped: false,
+                }))
+            }
+            Body::SameAs(target) => match self.by_path.get(&target) {
+                Some(spilled) => {
+                    let handler = spilled.handler.clone();
+                    (self.read_spilled(&target)?, handler, false)
+                }
+                None if self.trace.is_some() => {
+                    self.reject(
+                        end,
+                        format!(
+                            "'{}' refers to '{}', which is not an earlier section",
+                            path, target
+                        ),
+                    );
+                    return Ok(None);
+                }
+                None => {
+                    eprintln!(
+                        "Warning: '{}' refers to '{}', which is not an earlier section. Skipping.",
+                        path, target
+                    );
+                    return Ok(None);
+                }
+            },
+        };
+        Ok(Some(Section {
+            path,
+            content,
+            handler,
+            preview: false,
+            truncated,
+            stub: None,
+            unmapped: false,
+        }))
+    }
+
+    /// Appends a finished section's content to the spill file.
+    fn spill(&mut self, section: &Section) -> Result<()> {
+        let file = match &mut self.spill {
+            Some(file) => file,
+            None => self.spill.insert(
+                tempfile::tempfile().context("Failed to create a temporary file for the bundle")?,
+            ),
+        };
+        let offset = file.seek(SeekFrom::End(0))?;
+        file.write_all(section.content.as_bytes())
+            .context("Failed to write to a temporary file")?;
+        self.by_path.insert(
+            section.path.clone(),
+            Spilled {
+                offset,
+                len: section.content.len(),
+                handler: section.handler.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// The content of the spilled section of `path`.
+    fn read_spilled(&mut self, path: &str) -> Result<String> {
+        let spilled = &self.by_path[path];
+        let file = self
+            .spill
+            .as_mut()
+            .expect("a spilled section has a spill file");
+        let mut content = vec![0; spilled.len];
+        file.seek(SeekFrom::Start(spilled.offset))?;
+        file.read_exact(&mut content)
+            .context("Failed to read from a temporary file")?;
+        Ok(String::from_utf8(content).expect("spilled content is a section's text"))
+    }
+
+    /// Applies a note to the section it follows.
+    fn apply_note(section: &mut Section, note: &str) {
+        if let Some(name) = note_value(note, HANDLER_PREFIX) {
+            if !name.is_empty() && !name.contains(char::is_whitespace) {
+                section.handler = Some(name.to_string());
+            }
+        } else if note == NO_EOL_MARKER {
+            if section.content.ends_with('\n') {
+                section.content.pop();
+            }
+        } else if note == UNMAPPED_MARKER {
+            section.unmapped = true;
+        } else if note_value(note, PREVIEW_PREFIX).is_some() {
+            section.preview = true;
+        } else if let Some(wrap) = note_value(note, WRAP_PREFIX) {
+            match crate::long_lines::unwrap(&section.content, wrap) {
+                Some(content) => section.content = content,
+                None => eprintln!(
+                    "Warning: Ignoring malformed wrap note after '{}'",
+                    section.path
+                ),
+            }
+        } else if let Some(json) = note_value(note, LINKS_PREFIX) {
+            match serde_json::from_str::<BTreeMap<usize, String>>(json) {
+                Ok(originals) => {
+                    section.content = crate::links::restore_links(&section.content, &originals)
+                }
+                Err(e) => eprintln!(
+                    "Warning: Ignoring malformed links note after '{}': {}",
+                    section.path, e
+                ),
+            }
+        }
+    }
+
+    /// Resolves a section once all of its notes are read, and spills it for later
+    /// `same-as` references.
+    fn finish(&mut self, pending: Pending) -> Result<Option<Section>> {
+        let Some(mut section) = self.resolve(pending.end, pending.path, pending.body)? else {
+            return Ok(None);
+        };
+        for note in &pending.notes {
+            Self::apply_note(&mut section, note);
+        }
+        self.note(
+            pending.end,
+            format!(
+                "section '{}' from line {} accepted ({} line(s))",
+                section.path,
+                pending.line,
+                section.content.lines().count()
+            ),
+        );
+        self.spill(&section)?;
+        if let Some(template) = &self.template {
+            section.path = template.apply(&section.path);
+            section.content = template.apply(&section.content);
+        }
+        Ok(Some(section))
+    }
+}
+
+impl<R: BufRead> Iterator for BundleReader<R> {
+    type Item = Result<Section>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let finished = match self.next_item() {
+                Ok(Some(Item::Note(note))) => {
+                    let line = self.line_no;
+                    match self.pending.as_mut() {
+                        Some(pending) => {
+                            let message = format!("note {} applies to '{}'", note, pending.path);
+                            pending.notes.push(note);
+                            pending.end = line;
+                            self.note(line, message);
+                        }
+                        None => self.reject(line, format!("note {} follows no section", note)),
+                    }
+                    continue;
+                }
+                Ok(Some(Item::Section(line, path, body))) => self.pending.replace(Pending {
+                    line,
+                    end: self.line_no,
+                    path,
+                    body,
+                    notes: Vec::new(),
+                }),
+                Ok(None) => {
+                    self.done = true;
+                    self.pending.take()
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            match finished.map(|pending| self.finish(pending)) {
+                Some(Ok(Some(section))) => return Some(Ok(section)),
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                Some(Ok(None)) | None => {}
+            }
+        }
+        None
+    }
+}
+
+/// The text of a `## ...` heading line (not `###`).
+fn heading_text(line: &str) -> Option<&str> {
+    line.strip_prefix("##")
+        .filter(|rest| !rest.starts_with('#'))
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> anyhow::Error {
+    SheafyError::ParseError {
+        line,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// The number of backticks opening a fence (at least three).
+pub fn fence_ticks(line: &str) -> Option<usize> {
+    let ticks = line.len() - line.trim_start_matches('`').len();
+    (ticks >= 3).then_some(ticks)
+}
+
+/// The path in the info string of a fence opening `line`, as written by `--header-style
+/// title` or `pandoc`.
+pub fn fence_path(line: &str) -> Option<String> {
+    let ticks = fence_ticks(line)?;
+    INFO_PATH_REGEX
+        .captures(&line[ticks..])
+        .map(|cap| crate::header::normalize(cap[1].trim()))
+}
+
+pub fn closes_fence(line: &str, ticks: usize) -> bool {
+    let line = line.trim_end();
+    line.len() >= ticks && line.bytes().all(|b| b == b'`')
+}
+
+/// Follows code fences line by line: whether `line` opens, closes or lies inside a
+/// fence. `fence` holds the backticks of the open fence, if any.
+pub fn fence_line(fence: &mut Option<usize>, line: &str) -> bool {
+    match *fence {
+        Some(ticks) => {
+            if closes_fence(line, ticks) {
+                *fence = None;
+            }
+            true
+        }
+        None => {
+            *fence = fence_ticks(line.trim_end());
+            fence.is_some()
+        }
+    }
+}
+
+/// The backticks of a fence that `content` cannot close: one more than its longest
+/// line of only backticks (indented ones too, as Markdown renderers close on those),
+/// and at least three. Bundled files that are themselves bundles or hold fenced
+/// examples stay inside their section this way, and restore reads them back unchanged.
+pub fn fence_for(content: &str) -> String {
+    let longest = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.bytes().all(|b| b == b'`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat((longest + 1).max(3))
+}
+
+/// Part number and count of a `<!-- sheafy:part k/N -->` line.
+fn part_number(line: &str) -> Option<(usize, usize)> {
+    let (number, total) = note_value(line, PART_PREFIX)?.split_once('/')?;
+    let (number, total) = (number.trim().parse().ok()?, total.trim().parse().ok()?);
+    (number >= 1 && number <= total).then_some((number, total))
+}
+
+fn same_as_target(line: &str) -> Option<String> {
+    note_value(line, SAME_AS_PREFIX).map(crate::header::normalize)
+}
+
+/// The text between a note's prefix and its closing `-->`, trimmed.
+fn note_value<'a>(note: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = note.strip_prefix(prefix)?.strip_suffix("-->")?;
+    rest.starts_with([' ', '\t']).then(|| rest.trim())
+}
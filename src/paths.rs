@@ -0,0 +1,122 @@
+//! Path handling shared by bundle and restore: absolute paths for comparing outputs with
+//! walked files, and the mapping between bundle paths (`/` separated) and the disk.
+//!
+//! `Path::canonicalize` fails for paths that don't exist yet, such as a bundle about to
+//! be written, and on Windows returns verbatim paths (`\\?\C:\...`) that never equal the
+//! plain ones of a walk. [`absolute`] canonicalizes the part of a path that exists and
+//! resolves the rest lexically, without the verbatim prefix, so one path always has one
+//! spelling; [`same`] compares two such paths the way the file system would.
+
+use std::path::{Component, Path, PathBuf};
+
+/// `path` made absolute against `base`, with `.` and `..` resolved and symlinks of the
+/// part that exists followed. Never fails: what doesn't exist is resolved lexically.
+pub fn absolute(path: &Path, base: &Path) -> PathBuf {
+    let joined = normalize(&base.join(path));
+    // Canonicalize the longest existing ancestor and put the rest back on
+    let mut existing = joined.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            let mut resolved = strip_verbatim(&canonical);
+            resolved.extend(rest.iter().rev());
+            return resolved;
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return joined,
+        }
+    }
+}
+
+/// `path` with `.` components dropped and `..` components resolved lexically (which
+/// differs from the file system only across symlinks). Leading `..` of a relative path
+/// are kept; `..` above a root is dropped.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// `path` without the Windows verbatim prefix: `\\?\C:\x` becomes `C:\x` and
+/// `\\?\UNC\server\share` becomes `\\server\share`. Other paths are returned as they are.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if let Some(rest) = text.strip_prefix(r"\\?\UNC\") {
+        return PathBuf::from(format!(r"\\{}", rest));
+    }
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Whether two absolute paths (from [`absolute`]) name the same file. Comparison
+/// ignores case where the file system usually does (Windows, macOS).
+pub fn same(a: &Path, b: &Path) -> bool {
+    if is_case_insensitive() {
+        to_slash(a).to_lowercase() == to_slash(b).to_lowercase()
+    } else {
+        a == b
+    }
+}
+
+/// Whether paths differing only in case name the same file, per the default file
+/// systems of the platform (NTFS and APFS ignore case).
+pub fn is_case_insensitive() -> bool {
+    cfg!(any(windows, target_os = "macos"))
+}
+
+/// `path` with `/` separators, as bundles write it.
+pub fn to_slash(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// The file a bundle path (`/` separated) names below `base`. Bundle paths are in NFC
+/// (see [`crate::header`]), so a name that doesn't exist as written is looked up among
+/// the entries of its directory by its NFC form: a file named in NFD (as files created
+/// on macOS are) is found rather than restored a second time under the NFC name.
+pub fn from_slash(base: &Path, path: &str) -> PathBuf {
+    if path.is_ascii() {
+        return base.join(path.replace('/', std::path::MAIN_SEPARATOR_STR));
+    }
+    let mut target = base.to_path_buf();
+    for name in path.split('/') {
+        let exact = target.join(name);
+        target = match exact.symlink_metadata() {
+            Ok(_) => exact,
+            Err(_) => same_name_in(&target, name).unwrap_or(exact),
+        };
+    }
+    target
+}
+
+/// The entry of `dir` whose name is `name` once both are in NFC.
+fn same_name_in(dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = crate::header::normalize(name);
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|entry_name| crate::header::normalize(entry_name) == name)
+        })
+        .map(|entry| entry.path())
+}
@@ -0,0 +1,117 @@
+//! Built-in ignore patterns for common project types, detected from the manifests at
+//! the top of the working directory (`package.json`, `pyproject.toml`, `Cargo.toml`).
+//!
+//! They keep dependency folders, virtual environments and build output out of bundles
+//! even when the project has no `.gitignore` yet or `use_gitignore` is off. The patterns
+//! come before `ignore_patterns`, so a `!pattern` there brings a file back.
+
+use crate::config::Config;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+pub struct Preset {
+    pub name: &'static str,
+    /// Files whose presence at the top of the working directory selects the preset
+    markers: &'static [&'static str],
+    /// Ignore patterns (gitignore syntax)
+    patterns: &'static [&'static str],
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "node",
+        markers: &["package.json"],
+        patterns: &[
+            "node_modules/",
+            "dist/",
+            "coverage/",
+            ".next/",
+            ".nuxt/",
+            ".svelte-kit/",
+            ".turbo/",
+            ".parcel-cache/",
+            "*.tsbuildinfo",
+        ],
+    },
+    Preset {
+        name: "python",
+        markers: &[
+            "pyproject.toml",
+            "setup.py",
+            "setup.cfg",
+            "requirements.txt",
+            "Pipfile",
+        ],
+        patterns: &[
+            ".venv/",
+            "venv/",
+            "__pycache__/",
+            "*.pyc",
+            ".pytest_cache/",
+            ".mypy_cache/",
+            ".ruff_cache/",
+            ".tox/",
+            "*.egg-info/",
+            "htmlcov/",
+            ".coverage",
+            "dist/",
+            "build/",
+        ],
+    },
+    Preset {
+        name: "rust",
+        markers: &["Cargo.toml"],
+        patterns: &["target/"],
+    },
+];
+
+/// The presets that apply to `working_dir`, unless `presets = false`.
+pub fn detect(config: &Config, working_dir: &Path) -> Vec<&'static Preset> {
+    if !config.sheafy.presets.unwrap_or(true) {
+        return Vec::new();
+    }
+    PRESETS
+        .iter()
+        .filter(|preset| {
+            preset
+                .markers
+                .iter()
+                .any(|marker| working_dir.join(marker).is_file())
+        })
+        .collect()
+}
+
+/// The ignore patterns of `presets`, one per line.
+pub fn patterns(presets: &[&Preset]) -> String {
+    let mut out = String::new();
+    for preset in presets {
+        for pattern in preset.patterns {
+            out.push_str(pattern);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Preset patterns followed by the user's `ignore_patterns`, or None if both are empty.
+pub fn combined_ignore_patterns(config: &Config, presets: &[&Preset]) -> Option<String> {
+    let user = config.sheafy.ignore_patterns.as_deref().unwrap_or("");
+    let combined = format!("{}{}", patterns(presets), user);
+    (!combined.trim().is_empty()).then_some(combined)
+}
+
+/// `patterns` (gitignore syntax) as a matcher anchored at `working_dir`, so `/build`
+/// is the top-level `build` only. Invalid patterns are skipped with a warning.
+pub fn ignore_matcher(working_dir: &Path, patterns: &str) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(working_dir);
+    for line in patterns.lines() {
+        if let Err(e) = builder.add_line(None, line) {
+            eprintln!(
+                "Warning: Skipping invalid ignore pattern '{}': {}",
+                line.trim(),
+                e
+            );
+        }
+    }
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
@@ -0,0 +1,144 @@
+//! Bundle profiles: `[sheafy.profiles.<name>]` tables holding `sheafy bundle` options,
+//! for the different audiences one project is bundled for.
+//!
+//! `bundle --profile <name>` takes its options from a profile, with the ones given on
+//! the command line on top. `bundle --all-profiles` writes every profile to its own
+//! output in one run, walking the tree once for all profiles that walk it alike (same
+//! gitignore and depth settings) and filtering the shared file list per profile.
+
+use crate::bundle::{BundleSummary, SharedWalks};
+use crate::cli::BundleArgs;
+use crate::config::{Config, DEFAULT_BUNDLE_NAME};
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// The options of profile `name` with those given on the command line (`cli`, where
+/// they differ from the defaults) on top.
+pub fn apply(config: &Config, name: &str, cli: &BundleArgs) -> Result<BundleArgs> {
+    let Some(profile) = config.sheafy.profiles.get(name) else {
+        let known: Vec<&str> = config.sheafy.profiles.keys().map(String::as_str).collect();
+        bail!(
+            "No profile '{}' in the config (profiles: {})",
+            name,
+            if known.is_empty() {
+                "none".to_string()
+            } else {
+                known.join(", ")
+            }
+        );
+    };
+    let defaults = serde_json::to_value(BundleArgs::default())?;
+    let mut merged = serde_json::to_value(profile)?;
+    if let (Some(merged), Some(given)) = (
+        merged.as_object_mut(),
+        serde_json::to_value(cli)?.as_object(),
+    ) {
+        for (key, value) in given {
+            if defaults.get(key) != Some(value) {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    let mut args: BundleArgs = serde_json::from_value(merged)
+        .with_context(|| format!("Invalid options in profile '{}'", name))?;
+    args.output = Some(output(config, name, &args));
+    Ok(args)
+}
+
+/// Where profile `name` is written: its `output`, or the bundle name with `-<name>`
+/// before the extension.
+pub fn output(config: &Config, name: &str, args: &BundleArgs) -> String {
+    if let Some(output) = &args.output {
+        return output.clone();
+    }
+    let bundle_name = config
+        .sheafy
+        .bundle_name
+        .as_deref()
+        .unwrap_or(DEFAULT_BUNDLE_NAME);
+    let file_start = bundle_name.rfind(['/', '\\']).map_or(0, |i| i + 1);
+    match bundle_name[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => format!(
+            "{}-{}{}",
+            &bundle_name[..file_start + dot],
+            name,
+            &bundle_name[file_start + dot..]
+        ),
+        _ => format!("{}-{}", bundle_name, name),
+    }
+}
+
+/// `bundle --all-profiles`: bundles every profile, sharing walks between them.
+pub fn run_all(cli: BundleArgs) -> Result<Vec<BundleSummary>> {
+    let config = Config::load().context("Failed to load configuration")?;
+    if config.sheafy.profiles.is_empty() {
+        bail!(
+            "--all-profiles found no profiles; add [sheafy.profiles.<name>] tables to sheafy.toml"
+        );
+    }
+    let names: Vec<String> = config.sheafy.profiles.keys().cloned().collect();
+    let mut walks = SharedWalks::default();
+    let mut summaries = Vec::new();
+    for name in &names {
+        status!("\n=== Profile {} ===", name);
+        let args = apply(&config, name, &cli)?;
+        // Bundling consumes the config
+        let config = Config::load().context("Failed to load configuration")?;
+        let summary = crate::bundle::run_bundle_sharing(config, args, &mut walks)
+            .with_context(|| format!("Failed to bundle profile '{}'", name))?;
+        summaries.push(summary);
+    }
+    status!(
+        "\nBundled {} profile(s) with {} walk(s) of the tree:",
+        names.len(),
+        walks.len()
+    );
+    for (name, summary) in names.iter().zip(&summaries) {
+        status!(
+            "  {:<12} {} ({} file(s))",
+            name,
+            summary.output.display(),
+            summary.files.len()
+        );
+    }
+    Ok(summaries)
+}
+
+/// The `include`/`exclude` patterns of a bundle run, applied to the walked files.
+pub struct Patterns {
+    include: Option<Gitignore>,
+    exclude: Option<Gitignore>,
+}
+
+impl Patterns {
+    pub fn from_args(args: &BundleArgs, working_dir: &Path) -> Result<Option<Self>> {
+        if args.include.is_empty() && args.exclude.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            include: matcher(&args.include, working_dir, "include")?,
+            exclude: matcher(&args.exclude, working_dir, "exclude")?,
+        }))
+    }
+
+    /// Whether a path relative to the working directory is bundled.
+    pub fn permits(&self, path: &Path) -> bool {
+        let matches =
+            |matcher: &Gitignore| matcher.matched_path_or_any_parents(path, false).is_ignore();
+        self.include.as_ref().is_none_or(matches) && !self.exclude.as_ref().is_some_and(matches)
+    }
+}
+
+fn matcher(patterns: &[String], working_dir: &Path, what: &str) -> Result<Option<Gitignore>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(working_dir);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("Invalid {} pattern: '{}'", what, pattern))?;
+    }
+    Ok(Some(builder.build()?))
+}
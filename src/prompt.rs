@@ -0,0 +1,86 @@
+//! Yes/no questions, and how they are answered when nobody is there to answer.
+//!
+//! `--yes` answers every question with yes. `--no-input`, or a stdin that is not a
+//! terminal (CI, pipes, `sheafy daemon`), makes each question take its default answer
+//! without waiting, so scripts never hang on a prompt.
+
+use anyhow::{bail, Context, Result};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const ASK: u8 = 0;
+const ASSUME_YES: u8 = 1;
+const NO_INPUT: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(ASK);
+
+/// Answers all subsequent questions with yes (`--yes`).
+pub fn assume_yes() {
+    MODE.store(ASSUME_YES, Ordering::Relaxed);
+}
+
+/// Never reads an answer from stdin (`--no-input`).
+pub fn disable_input() {
+    MODE.store(NO_INPUT, Ordering::Relaxed);
+}
+
+/// Whether questions can be asked: no `--yes`/`--no-input` and stdin is a terminal.
+pub fn is_interactive() -> bool {
+    MODE.load(Ordering::Relaxed) == ASK && io::stdin().is_terminal()
+}
+
+/// Asks `question` on stderr and reads a y/n answer; an empty answer means `default`.
+/// Without a terminal the default is taken, and noted, instead of asking.
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    if MODE.load(Ordering::Relaxed) == ASSUME_YES {
+        eprintln!("{} yes (--yes)", question);
+        return Ok(true);
+    }
+    if !is_interactive() {
+        eprintln!(
+            "{} {} (not asked: no interactive input; pass --yes to agree)",
+            question,
+            if default { "yes" } else { "no" }
+        );
+        return Ok(default);
+    }
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    let stdin = io::stdin();
+    loop {
+        eprint!("{} {} ", question, hint);
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut answer)
+            .context("Failed to read answer")?
+            == 0
+        {
+            bail!("No answer to '{}' (end of input)", question);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => eprintln!("Please answer y or n."),
+        }
+    }
+}
+
+/// Asks `question` on stderr and reads a line of text; an empty answer is `None`.
+/// Without a terminal, or with `--yes`, nothing is asked and the answer is `None`.
+pub fn ask(question: &str) -> Result<Option<String>> {
+    if !is_interactive() {
+        eprintln!("{} (not asked: no interactive input)", question);
+        return Ok(None);
+    }
+    eprint!("{} ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("Failed to read answer")?;
+    let answer = answer.trim();
+    Ok((!answer.is_empty()).then(|| answer.to_string()))
+}
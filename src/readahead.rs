@@ -0,0 +1,91 @@
+//! Reading the files of a bundle, in bundle order.
+//!
+//! `--io sync` (the default) reads each file when the writer gets to it. `--io async`
+//! (built with the `async-io` feature) reads up to [`READ_AHEAD`] files concurrently on
+//! a tokio runtime while the bundle is written, so on NFS or SMB mounts the round trips
+//! of many files overlap instead of adding up. Directory walking is already parallel
+//! (`--jobs`) in both modes.
+
+use crate::cli::IoMode;
+use anyhow::Result;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Files read ahead of the writer in async mode
+pub const READ_AHEAD: usize = 32;
+
+pub enum FileReader {
+    Sync(PathBuf),
+    #[cfg(feature = "async-io")]
+    Async(AsyncReader),
+}
+
+impl FileReader {
+    /// A reader for `files` (relative to `working_dir`), which must then be read in
+    /// that order.
+    pub fn new(mode: IoMode, working_dir: &Path, files: &[PathBuf]) -> Result<Self> {
+        match mode {
+            IoMode::Sync => Ok(Self::Sync(working_dir.to_path_buf())),
+            #[cfg(feature = "async-io")]
+            IoMode::Async => Ok(Self::Async(AsyncReader::start(working_dir, files)?)),
+            #[cfg(not(feature = "async-io"))]
+            IoMode::Async => {
+                let _ = files;
+                anyhow::bail!(
+                    "--io async needs sheafy built with the `async-io` feature \
+                     (cargo install sheafy --features async-io)"
+                )
+            }
+        }
+    }
+
+    /// Contents of `rel_path`, the next file in the order given to [`Self::new`].
+    pub fn read(&mut self, rel_path: &Path) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Sync(working_dir) => fs::read(working_dir.join(rel_path)),
+            #[cfg(feature = "async-io")]
+            Self::Async(reader) => reader.read(rel_path),
+        }
+    }
+}
+
+#[cfg(feature = "async-io")]
+pub struct AsyncReader {
+    runtime: tokio::runtime::Runtime,
+    /// Reads in flight or done, in bundle order; the channel's capacity bounds how far
+    /// ahead of the writer they get
+    pending: tokio::sync::mpsc::Receiver<(PathBuf, tokio::task::JoinHandle<io::Result<Vec<u8>>>)>,
+}
+
+#[cfg(feature = "async-io")]
+impl AsyncReader {
+    fn start(working_dir: &Path, files: &[PathBuf]) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("sheafy-io")
+            .build()?;
+        let (sender, pending) = tokio::sync::mpsc::channel(READ_AHEAD);
+        let paths: Vec<(PathBuf, PathBuf)> = files
+            .iter()
+            .map(|file| (file.clone(), working_dir.join(file)))
+            .collect();
+        runtime.spawn(async move {
+            for (rel_path, path) in paths {
+                let read = tokio::spawn(tokio::fs::read(path));
+                if sender.send((rel_path, read)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self { runtime, pending })
+    }
+
+    fn read(&mut self, rel_path: &Path) -> io::Result<Vec<u8>> {
+        let Some((expected, read)) = self.pending.blocking_recv() else {
+            return Err(io::Error::other("no read was started for this file"));
+        };
+        debug_assert_eq!(expected, rel_path, "files must be read in bundle order");
+        self.runtime.block_on(read).map_err(io::Error::other)?
+    }
+}
@@ -0,0 +1,397 @@
+//! `sheafy render`: turn a bundle into a read-only HTML or PDF snapshot with a table
+//! of contents, line numbers and syntax highlighting.
+//!
+//! Both formats are written by hand: the HTML is a single self-contained page, and the
+//! PDF uses the standard Courier fonts every PDF reader ships, so no fonts or external
+//! renderers are needed.
+
+use crate::config::Config;
+use crate::highlight::{highlight, TokenKind};
+use crate::restore::{parse_sections, Section};
+use anyhow::{bail, Context, Result};
+use std::{fmt::Write as _, fs, path::Path};
+
+pub fn run_render(
+    config: &Config,
+    input_file: Option<&str>,
+    output: Option<&str>,
+    pdf: bool,
+) -> Result<()> {
+    let (working_dir, bundle_path) = crate::restore::resolve_input(config, input_file)?;
+    let content = fs::read_to_string(&bundle_path)
+        .with_context(|| format!("Failed to read bundle: {}", bundle_path.display()))?;
+    let sections = parse_sections(&content)?;
+    if sections.is_empty() {
+        bail!("No file sections found in '{}'", bundle_path.display());
+    }
+
+    let pdf = pdf || output.is_some_and(|o| o.to_ascii_lowercase().ends_with(".pdf"));
+    let output_path = match output {
+        Some(output) => working_dir.join(output),
+        None => bundle_path.with_extension(if pdf { "pdf" } else { "html" }),
+    };
+    let title = bundle_path
+        .file_name()
+        .map_or_else(|| "bundle".into(), |n| n.to_string_lossy().into_owned());
+
+    let rendered = if pdf {
+        render_pdf(&title, &sections)
+    } else {
+        render_html(&title, &sections).into_bytes()
+    };
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(&output_path, rendered)
+        .with_context(|| format!("Failed to write {}", output_path.display()))?;
+    status!(
+        "Rendered {} file(s) from '{}' to '{}'.",
+        sections.len(),
+        bundle_path.display(),
+        output_path.display()
+    );
+    Ok(())
+}
+
+fn language(section: &Section) -> &'static str {
+    crate::sniff::language_hint(Path::new(&section.path), &section.content)
+}
+
+// --- HTML ---
+
+const HTML_STYLE: &str = "\
+body{margin:0;display:flex;font-family:system-ui,sans-serif;color:#1f2328}\
+nav{position:sticky;top:0;height:100vh;overflow:auto;min-width:16rem;max-width:22rem;\
+padding:1rem;box-sizing:border-box;background:#f6f8fa;border-right:1px solid #d0d7de}\
+nav h1{font-size:1rem}nav ol{padding-left:1.2rem;font-size:.85rem}nav a{color:#0969da;text-decoration:none}\
+main{flex:1;min-width:0;padding:1rem 2rem}h2{font-size:1rem;font-family:ui-monospace,monospace}\
+h2 a{color:inherit;text-decoration:none}\
+pre{background:#f6f8fa;border:1px solid #d0d7de;border-radius:6px;padding:.75rem 0;overflow:auto;\
+font-size:.8rem;line-height:1.45}\
+.ln{display:inline-block;width:3.5em;padding-right:1em;text-align:right;color:#8c959f;user-select:none}\
+.k{color:#cf222e}.s{color:#0a3069}.c{color:#6e7781;font-style:italic}.n{color:#0550ae}";
+
+fn render_html(title: &str, sections: &[Section]) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<nav>\n<h1>{}</h1>\n<ol>\n",
+        escape_html(title),
+        HTML_STYLE,
+        escape_html(title)
+    );
+    for (index, section) in sections.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "<li><a href=\"#file-{}\">{}</a></li>",
+            index + 1,
+            escape_html(&section.path)
+        );
+    }
+    out.push_str("</ol>\n</nav>\n<main>\n");
+    for (index, section) in sections.iter().enumerate() {
+        let _ = write!(
+            out,
+            "<section id=\"file-{0}\">\n<h2><a href=\"#file-{0}\">{1}</a></h2>\n<pre><code>",
+            index + 1,
+            escape_html(&section.path)
+        );
+        for (line_no, tokens) in highlight(language(section), &section.content)
+            .iter()
+            .enumerate()
+        {
+            let _ = write!(out, "<span class=\"ln\">{}</span>", line_no + 1);
+            for (kind, text) in tokens {
+                let class = match kind {
+                    TokenKind::Plain => None,
+                    TokenKind::Keyword => Some("k"),
+                    TokenKind::String => Some("s"),
+                    TokenKind::Comment => Some("c"),
+                    TokenKind::Number => Some("n"),
+                };
+                match class {
+                    Some(class) => {
+                        let _ = write!(
+                            out,
+                            "<span class=\"{}\">{}</span>",
+                            class,
+                            escape_html(text)
+                        );
+                    }
+                    None => out.push_str(&escape_html(text)),
+                }
+            }
+            out.push('\n');
+        }
+        out.push_str("</code></pre>\n</section>\n");
+    }
+    out.push_str("</main>\n</body>\n</html>\n");
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// --- PDF ---
+
+const PAGE_WIDTH: f32 = 595.0; // A4, in points
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const FONT_SIZE: f32 = 8.0;
+const LEADING: f32 = 10.0;
+/// Courier glyphs are 0.6 em wide
+const MAX_COLUMNS: usize = ((PAGE_WIDTH - 2.0 * MARGIN) / (FONT_SIZE * 0.6)) as usize;
+const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - 2.0 * MARGIN) / LEADING) as usize;
+/// Width of the line number gutter, including the separating spaces
+const GUTTER: usize = 7;
+
+/// One line of text on a page: (bold, color, text) runs.
+type PdfLine = Vec<(bool, TokenKind, String)>;
+
+fn render_pdf(title: &str, sections: &[Section]) -> Vec<u8> {
+    // Lay out the files first so the table of contents can show page numbers
+    let toc_pages = (sections.len() + 2).div_ceil(LINES_PER_PAGE);
+    let mut pages: Vec<Vec<PdfLine>> = Vec::new();
+    let mut first_pages = Vec::new();
+    for section in sections {
+        first_pages.push(toc_pages + pages.len());
+        let mut lines: Vec<PdfLine> = vec![
+            vec![(true, TokenKind::Plain, section.path.clone())],
+            Vec::new(),
+        ];
+        for (line_no, tokens) in highlight(language(section), &section.content)
+            .iter()
+            .enumerate()
+        {
+            lines.extend(wrap_line(line_no + 1, tokens));
+        }
+        pages.extend(lines.chunks(LINES_PER_PAGE).map(<[PdfLine]>::to_vec));
+    }
+
+    let mut toc: Vec<PdfLine> = vec![
+        vec![(true, TokenKind::Plain, title.to_string())],
+        Vec::new(),
+    ];
+    for (section, first_page) in sections.iter().zip(&first_pages) {
+        let page = (first_page + 1).to_string();
+        let dots = MAX_COLUMNS.saturating_sub(section.path.chars().count() + page.len() + 2);
+        toc.push(vec![
+            (false, TokenKind::Plain, format!("{} ", section.path)),
+            (false, TokenKind::Comment, ".".repeat(dots)),
+            (false, TokenKind::Plain, format!(" {}", page)),
+        ]);
+    }
+    let mut all_pages: Vec<Vec<PdfLine>> = toc
+        .chunks(LINES_PER_PAGE)
+        .map(<[PdfLine]>::to_vec)
+        .collect();
+    all_pages.resize(toc_pages, Vec::new());
+    all_pages.extend(pages);
+
+    let outline: Vec<(&str, usize)> = sections
+        .iter()
+        .map(|s| s.path.as_str())
+        .zip(first_pages)
+        .collect();
+    write_pdf(&all_pages, &outline)
+}
+
+/// Splits a highlighted line into page-width lines with a line number gutter.
+fn wrap_line(line_no: usize, tokens: &[(TokenKind, &str)]) -> Vec<PdfLine> {
+    let width = MAX_COLUMNS - GUTTER;
+    let mut lines = Vec::new();
+    let mut current: PdfLine = vec![(false, TokenKind::Comment, format!("{:>5}  ", line_no))];
+    let mut column = 0;
+    for (kind, text) in tokens {
+        let mut run = String::new();
+        for c in text.replace('\t', "    ").chars() {
+            if column == width {
+                current.push((false, *kind, std::mem::take(&mut run)));
+                lines.push(std::mem::replace(
+                    &mut current,
+                    vec![(false, TokenKind::Plain, " ".repeat(GUTTER))],
+                ));
+                column = 0;
+            }
+            run.push(c);
+            column += 1;
+        }
+        current.push((false, *kind, run));
+    }
+    lines.push(current);
+    lines
+}
+
+fn color(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Plain => "0.12 0.14 0.16",
+        TokenKind::Keyword => "0.81 0.13 0.18",
+        TokenKind::String => "0.04 0.19 0.41",
+        TokenKind::Comment => "0.43 0.47 0.51",
+        TokenKind::Number => "0.02 0.31 0.68",
+    }
+}
+
+/// Encodes text as a PDF literal string in WinAnsiEncoding.
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push(b'\\');
+                out.push(c as u8);
+            }
+            ' '..='~' => out.push(c as u8),
+            // Latin-1 supplement maps directly; everything else has no glyph
+            '\u{a0}'..='\u{ff}' => out.push(c as u32 as u8),
+            _ => out.push(b'?'),
+        }
+    }
+    out.push(b')');
+    out
+}
+
+fn write_pdf(pages: &[Vec<PdfLine>], outline: &[(&str, usize)]) -> Vec<u8> {
+    // Object numbers: 1 catalog, 2 page tree, 3 outlines, 4-5 fonts,
+    // then a page and a content stream per page, then one object per outline entry
+    let page_obj = |i: usize| 6 + 2 * i;
+    let outline_obj = |i: usize| 6 + 2 * pages.len() + i;
+    let object_count = 5 + 2 * pages.len() + outline.len();
+
+    let mut out: Vec<u8> = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = vec![0usize; object_count + 1];
+    let mut object = |out: &mut Vec<u8>, number: usize, body: &[u8]| {
+        offsets[number] = out.len();
+        out.extend_from_slice(format!("{} 0 obj\n", number).as_bytes());
+        out.extend_from_slice(body);
+        out.extend_from_slice(b"\nendobj\n");
+    };
+
+    object(
+        &mut out,
+        1,
+        b"<< /Type /Catalog /Pages 2 0 R /Outlines 3 0 R /PageMode /UseOutlines >>",
+    );
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", page_obj(i)))
+        .collect();
+    object(
+        &mut out,
+        2,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .as_bytes(),
+    );
+    let outlines = if outline.is_empty() {
+        "<< /Type /Outlines /Count 0 >>".to_string()
+    } else {
+        format!(
+            "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+            outline_obj(0),
+            outline_obj(outline.len() - 1),
+            outline.len()
+        )
+    };
+    object(&mut out, 3, outlines.as_bytes());
+    object(
+        &mut out,
+        4,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>",
+    );
+    object(
+        &mut out,
+        5,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold /Encoding /WinAnsiEncoding >>",
+    );
+
+    for (index, lines) in pages.iter().enumerate() {
+        let mut stream: Vec<u8> = format!(
+            "BT\n{} TL\n{} {} Td\n",
+            LEADING,
+            MARGIN,
+            PAGE_HEIGHT - MARGIN - FONT_SIZE
+        )
+        .into_bytes();
+        for line in lines {
+            for (bold, kind, text) in line {
+                stream.extend_from_slice(
+                    format!(
+                        "/F{} {} Tf {} rg ",
+                        if *bold { 2 } else { 1 },
+                        FONT_SIZE,
+                        color(*kind)
+                    )
+                    .as_bytes(),
+                );
+                stream.extend_from_slice(&pdf_string(text));
+                stream.extend_from_slice(b" Tj\n");
+            }
+            stream.extend_from_slice(b"T*\n");
+        }
+        stream.extend_from_slice(b"ET");
+
+        object(
+            &mut out,
+            page_obj(index),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 4 0 R /F2 5 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                page_obj(index) + 1
+            )
+            .as_bytes(),
+        );
+        let mut content = format!("<< /Length {} >>\nstream\n", stream.len()).into_bytes();
+        content.extend_from_slice(&stream);
+        content.extend_from_slice(b"\nendstream");
+        object(&mut out, page_obj(index) + 1, &content);
+    }
+
+    for (index, (title, page)) in outline.iter().enumerate() {
+        let mut body = b"<< /Title ".to_vec();
+        body.extend_from_slice(&pdf_string(title));
+        body.extend_from_slice(
+            format!(
+                " /Parent 3 0 R /Dest [{} 0 R /XYZ 0 {} 0]",
+                page_obj(*page),
+                PAGE_HEIGHT
+            )
+            .as_bytes(),
+        );
+        if index > 0 {
+            body.extend_from_slice(format!(" /Prev {} 0 R", outline_obj(index - 1)).as_bytes());
+        }
+        if index + 1 < outline.len() {
+            body.extend_from_slice(format!(" /Next {} 0 R", outline_obj(index + 1)).as_bytes());
+        }
+        body.extend_from_slice(b" >>");
+        object(&mut out, outline_obj(index), &body);
+    }
+
+    let xref_offset = out.len();
+    out.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", object_count + 1).as_bytes(),
+    );
+    for offset in &offsets[1..] {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            object_count + 1,
+            xref_offset
+        )
+        .as_bytes(),
+    );
+    out
+}
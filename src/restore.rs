@@ -1,20 +1,127 @@
-use crate::config::{Config, DEFAULT_BUNDLE_NAME}; // Keep Config import
-use anyhow::{Context, Result};
-use lazy_static::lazy_static;
-use regex::Regex;
+use crate::archive::ArchiveKind;
+use crate::cli::{DiffBase, OnConflict, OnStub, OnTruncated, RestoreArgs};
+use crate::config::{ByteSize, Config, OnDisallowed}; // Keep Config import
+use crate::error::SheafyError;
+use crate::handlers::Handlers;
+use crate::parser::BundleReader;
+use crate::stub::Stub;
+use crate::text_style::TextStyle;
+use crate::validate::Validation;
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Serialize;
 use std::{
     borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashSet},
     fs::{self, File},
-    io::{BufWriter, Write},
-    path::PathBuf, // Add PathBuf import
+    io::{BufRead, BufReader, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf}, // Add PathBuf import
 };
 
-lazy_static! {
-    static ref RESTORE_REGEX: Regex =
-        Regex::new(r"(?ms)^##\s*(.*?)\s*\n```[^\n]*\n(.*?)\n```\s*$").unwrap();
+/// Current bundle format version, written as `<!-- sheafy:v2 -->` on the first line.
+/// Bundles without a version marker are v1: plain `## path` headings and fences.
+pub const FORMAT_VERSION: u32 = 2;
+pub const VERSION_MARKER: &str = "<!-- sheafy:v2 -->";
+/// Marks bundles holding excerpts (e.g. `--context-lines`) that must not be restored.
+pub const READONLY_MARKER: &str = "<!-- sheafy:readonly -->";
+/// Marks bundles whose sections are unified diffs to be applied rather than written.
+pub const PATCH_MARKER: &str = "<!-- sheafy:format patch -->";
+/// First line of the report written when `--on-conflict skip` leaves files untouched.
+pub const CONFLICTS_MARKER: &str = "<!-- sheafy:conflicts -->";
+/// Where the conflict report is written, relative to the working directory.
+pub const CONFLICTS_FILENAME: &str = "sheafy-conflicts.md";
+/// What restore did with each section, written with `--report`
+pub const REPORT_FILENAME: &str = "sheafy-restore-report.json";
+/// Written by `restore --flat`, mapping flattened file names to bundle paths.
+pub const FLAT_MAP_FILENAME: &str = "sheafy-map.json";
+/// Starts a section body that reuses the content of an earlier section (`--dedup`).
+pub const SAME_AS_PREFIX: &str = "<!-- sheafy:same-as";
+/// Follows a Markdown section whose relative links were rewritten to bundle anchors
+/// (`--link-anchors`); holds the original link targets by link index.
+pub const LINKS_PREFIX: &str = "<!-- sheafy:links";
+/// Follows a table section holding only the first rows of the file (`--csv-preview-rows`).
+pub const PREVIEW_PREFIX: &str = "<!-- sheafy:preview";
+/// Follows a section whose long lines were broken up (`long_lines = "wrap"`), as
+/// `<!-- sheafy:wrap at=5000 lines=12:3,40:2 -->`: original line 12 became 3 lines.
+pub const WRAP_PREFIX: &str = "<!-- sheafy:wrap";
+/// Heading path of the section listing each file's SHA-256 (`--checksums`); not a file.
+pub const CHECKSUMS_HEADING: &str = "sheafy:checksums";
+/// Follows a section whose content was transformed by a file handler, naming it.
+pub const HANDLER_PREFIX: &str = "<!-- sheafy:handler";
+/// Follows a section whose file does not end with a newline; restore drops the one the
+/// code fence needs.
+pub const NO_EOL_MARKER: &str = "<!-- sheafy:no-eol -->";
+/// Precedes each code fence of a file split across several (`--split-size`), as
+/// `<!-- sheafy:part 2/3 -->`; restore joins the parts.
+pub const PART_PREFIX: &str = "<!-- sheafy:part";
+/// Sits between a section's heading and its code fence (`--file-stats`), as
+/// `<!-- sheafy:stats lines=120 bytes=4312 tokens=1078 -->`; restore ignores it.
+pub const STATS_PREFIX: &str = "<!-- sheafy:stats";
+/// Stands in for the code fence of a file bundled as a stub (`stub_patterns`), as
+/// `<!-- sheafy:stub {"size":..,"sha256":".."} -->`; restore has no content to write.
+pub const STUB_PREFIX: &str = "<!-- sheafy:stub";
+/// Open and close the task instructions of `bundle --prompt`; restore skips what lies
+/// between them, so the text may quote headings and fences freely.
+pub const PROMPT_MARKER: &str = "<!-- sheafy:prompt -->";
+pub const PROMPT_END_MARKER: &str = "<!-- sheafy:prompt-end -->";
+
+/// Follows a section whose header is its working tree path as is: the file lies outside
+/// `header_strip_prefix`, yet its path looks like a header under `header_add_prefix`, so
+/// restore must not map it back.
+pub const UNMAPPED_MARKER: &str = "<!-- sheafy:unmapped -->";
+
+/// Returns the content after a leading `---` YAML front matter block, if there is one.
+pub fn skip_front_matter(content: &str) -> &str {
+    let content = content.trim_start_matches('\u{feff}');
+    let Some(rest) = content
+        .split_once('\n')
+        .filter(|(first, _)| first.trim_end() == "---")
+        .map(|(_, rest)| rest)
+    else {
+        return content;
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        if line.trim_end() == "---" {
+            return &rest[offset..];
+        }
+    }
+    content
+}
+
+/// Returns the `<!-- sheafy:... -->` marker lines at the start of a bundle, after any front matter.
+pub fn leading_markers(content: &str) -> Vec<&str> {
+    skip_front_matter(content)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take_while(|line| line.starts_with("<!-- sheafy:"))
+        .collect()
+}
+
+/// Returns the format version declared by a bundle's leading `<!-- sheafy:vN -->` marker (1 if none).
+pub fn format_version(content: &str) -> u32 {
+    markers_version(&leading_markers(content))
 }
 
-pub fn get_language_hint(extension: &str) -> &str {
+/// The format version declared among a bundle's leading markers (1 if none).
+pub fn markers_version<S: AsRef<str>>(markers: &[S]) -> u32 {
+    markers
+        .iter()
+        .find_map(|marker| {
+            marker
+                .as_ref()
+                .strip_prefix("<!-- sheafy:v")?
+                .strip_suffix("-->")?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .unwrap_or(1)
+}
+
+pub fn get_language_hint(extension: &str) -> &'static str {
     match extension {
         "py" => "python",
         "js" => "javascript",
@@ -27,7 +134,9 @@ pub fn get_language_hint(extension: &str) -> &str {
         "cpp" => "cpp",
         "sh" => "bash",
         "md" => "markdown",
-        "json" => "json",
+        "json" | "ipynb" => "json",
+        "csv" => "csv",
+        "tsv" => "tsv",
         "yaml" | "yml" => "yaml",
         "toml" => "toml",
         "sql" => "sql",
@@ -42,7 +151,7 @@ pub fn get_language_hint(extension: &str) -> &str {
     }
 }
 
-fn ensure_eof_newline(slice: &str) -> Cow<str> {
+pub fn ensure_eof_newline(slice: &str) -> Cow<'_, str> {
     if slice.ends_with('\n') {
         Cow::Borrowed(slice)
     } else {
@@ -53,117 +162,1724 @@ fn ensure_eof_newline(slice: &str) -> Cow<str> {
     }
 }
 
-// Update function signature
-pub fn run_restore(config: Config, input_filename: Option<String>) -> Result<()> {
-    println!("Attempting to restore files");
+/// A file section parsed from a bundle.
+#[derive(Debug)]
+pub struct Section {
+    /// Path from the section header, using `/` separators.
+    pub path: String,
+    pub content: String,
+    /// File handler that encoded the content, to be reversed on restore
+    pub handler: Option<String>,
+    /// Only the first rows of a table; restoring it would truncate the file
+    pub preview: bool,
+    /// The bundle ended inside the section's code fence, so the content is incomplete
+    /// (only with [`BundleReader::keep_truncated`])
+    pub truncated: bool,
+    /// Size and hash of a file bundled as a stub; `content` is empty
+    pub stub: Option<Stub>,
+    /// The header is the working tree path; header prefixes don't apply to it
+    pub unmapped: bool,
+}
+
+impl Section {
+    /// The content to write to disk, decoded by the section's handler if it has one.
+    /// With `normalize_eof_newline`, a missing final newline is added.
+    pub fn into_file_content(self, options: &RestoreOptions) -> Result<String> {
+        let content = match &self.handler {
+            Some(name) => options.handlers.decode(name, &self.path, &self.content)?,
+            None => self.content,
+        };
+        if options.normalize_eof && !content.ends_with('\n') {
+            return Ok(content + "\n");
+        }
+        Ok(content)
+    }
+}
+
+/// Config-derived settings for turning sections back into files.
+pub struct RestoreOptions {
+    pub handlers: Handlers,
+    pub prefixes: HeaderPrefixes,
+    pub normalize_eof: bool,
+    /// Set by `--tracked-only`
+    pub tracked_only: Option<TrackedOnly>,
+    /// Set by `--on-truncated`
+    pub on_truncated: OnTruncated,
+    /// Paths bundled from this project before, when known and `--allow-new-paths` is
+    /// not given; restore refuses bundles with other paths
+    pub known_paths: Option<BTreeSet<String>>,
+    /// `allowed_paths` of `[sheafy.restore]`, when configured
+    pub allowed_paths: Option<AllowedPaths>,
+    /// Set by `--validate` or `validate` in `[sheafy.restore]`
+    pub validation: Option<Validation>,
+    /// Set by `--stubs`
+    pub stubs: OnStub,
+    /// Set by `--stop-on-error`: fail at the first file that cannot be written instead
+    /// of restoring the others and listing the failures at the end
+    pub stop_on_error: bool,
+    /// Set by `--verify-checksums`
+    pub verify_checksums: bool,
+    /// Values of template variables, from `[sheafy.restore.vars]` and `--var`
+    pub vars: BTreeMap<String, String>,
+    /// Keep what was done with each section for `sheafy-restore-report.json`
+    pub report: bool,
+    /// Set by `--force-readonly`: restore bundles declared read-only
+    pub force_readonly: bool,
+    /// Write over existing files in their own line endings and byte order mark; off
+    /// with `--as-bundled` or `match_existing = false`
+    pub match_existing: bool,
+    /// Set by `--strip-components`: leading path components dropped from every path
+    pub strip_components: usize,
+    /// Set by `--prefix`: directory (ending in `/`) put in front of every path, after
+    /// stripping; empty for none
+    pub path_prefix: String,
+}
+
+impl RestoreOptions {
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Self> {
+        Ok(Self {
+            handlers: Handlers::from_config(config, working_dir)?,
+            prefixes: HeaderPrefixes::from_config(config),
+            normalize_eof: config.sheafy.normalize_eof_newline.unwrap_or(false),
+            tracked_only: None,
+            on_truncated: OnTruncated::Fail,
+            stubs: OnStub::Skip,
+            stop_on_error: false,
+            verify_checksums: false,
+            vars: config.sheafy.restore.vars.clone(),
+            report: config.sheafy.restore.report.unwrap_or(false),
+            force_readonly: false,
+            match_existing: config.sheafy.restore.match_existing.unwrap_or(true),
+            strip_components: 0,
+            path_prefix: String::new(),
+            known_paths: None,
+            allowed_paths: AllowedPaths::from_config(config, working_dir)?,
+            validation: config
+                .sheafy
+                .restore
+                .validate
+                .unwrap_or(false)
+                .then(|| Validation::from_config(config)),
+        })
+    }
+
+    /// Notes that `path` could not be written, or with `--stop-on-error` returns the
+    /// error to end the restore.
+    fn record_failure(
+        &self,
+        summary: &mut RestoreSummary,
+        path: &str,
+        error: anyhow::Error,
+    ) -> Result<()> {
+        if self.stop_on_error {
+            return Err(error.context(format!("Failed to restore '{}' (--stop-on-error)", path)));
+        }
+        eprintln!("Warning: Failed to restore '{}': {:#}", path, error);
+        summary.failures.push(Failure {
+            path: path.to_string(),
+            error: format!("{:#}", error),
+        });
+        Ok(())
+    }
+
+    /// Opens a bundle to restore from; unless `--on-truncated fail`, a final section the
+    /// bundle ends inside of is read as truncated rather than failing the parse.
+    pub fn open_bundle(&self, path: &Path) -> Result<BundleReader<BufReader<File>>> {
+        status!("{}", tr!("restore.reading", path.display()));
+        self.reread_bundle(path)
+    }
+
+    /// [`Self::open_bundle`] without announcing it, for the checks that read a bundle
+    /// before it is restored.
+    pub fn reread_bundle(&self, path: &Path) -> Result<BundleReader<BufReader<File>>> {
+        let bundle = read_bundle(path)?.with_vars(&self.vars)?;
+        Ok(match self.on_truncated {
+            OnTruncated::Fail => bundle,
+            OnTruncated::Skip | OnTruncated::Partial => bundle.keep_truncated(),
+        })
+    }
+
+    /// Where to write a truncated section: None to skip it, or its `.partial` path.
+    fn truncated_path(&self, path: &str) -> Option<String> {
+        match self.on_truncated {
+            OnTruncated::Fail | OnTruncated::Skip => None,
+            OnTruncated::Partial => Some(format!("{}.partial", path)),
+        }
+    }
+
+    /// [`Self::truncated_path`], telling the user what becomes of the file.
+    fn place_truncated(&self, path: &str) -> Option<String> {
+        let target = self.truncated_path(path);
+        match &target {
+            Some(partial) => eprintln!(
+                "Warning: '{}' is truncated (the bundle ends inside its code fence); writing what there is to '{}'.",
+                path, partial
+            ),
+            None => eprintln!(
+                "Warning: '{}' is truncated (the bundle ends inside its code fence); skipping it.",
+                path
+            ),
+        }
+        target
+    }
+
+    /// A parsed section with its header path mapped back to the working tree path.
+    pub fn map_path(&self, mut section: Section) -> Section {
+        section.path = self.section_path(&section);
+        section
+    }
+
+    /// The working tree path of a parsed section; see [`Self::to_path`].
+    pub fn section_path(&self, section: &Section) -> String {
+        if section.unmapped {
+            self.relocate(&section.path)
+        } else {
+            self.to_path(&section.path)
+        }
+    }
+
+    /// `content`, restored from a section bundled with `handler`, fitted to `existing`,
+    /// the file it is about to replace: what the handler left out of the bundle is taken
+    /// from the file, and the file's text style is kept (see [`Self::in_style_of`]).
+    pub fn fit_to(&self, existing: &[u8], handler: Option<&str>, content: String) -> String {
+        let content = match (handler, std::str::from_utf8(existing)) {
+            (Some(name), Ok(existing)) => self.handlers.merge(name, existing, content),
+            _ => content,
+        };
+        self.in_style_of(existing, &content).into_owned()
+    }
+
+    /// The working tree path of a section header: the header prefixes mapped back, then
+    /// [`Self::relocate`]d.
+    pub fn to_path(&self, header: &str) -> String {
+        self.relocate(&self.prefixes.to_path(header))
+    }
+
+    /// `content` in the line endings and byte order mark of `existing`, the file it is
+    /// about to replace, unless `--as-bundled`.
+    pub fn in_style_of<'a>(&self, existing: &[u8], content: &'a str) -> Cow<'a, str> {
+        match TextStyle::detect(existing).filter(|_| self.match_existing) {
+            Some(style) => style.apply(content),
+            None => Cow::Borrowed(content),
+        }
+    }
+
+    /// `path` without its first `--strip-components` components and under `--prefix`,
+    /// as `tar` does. Empty if no component is left.
+    pub fn relocate(&self, path: &str) -> String {
+        if self.strip_components == 0 && self.path_prefix.is_empty() {
+            return path.to_string();
+        }
+        let rest: Vec<&str> = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .skip(self.strip_components)
+            .collect();
+        if rest.is_empty() {
+            return String::new();
+        }
+        format!("{}{}", self.path_prefix, rest.join("/"))
+    }
+}
+
+/// `restore --tracked-only`: the paths restore may write are those git tracks, plus
+/// the `restore_allow` patterns.
+pub struct TrackedOnly {
+    tracked: HashSet<String>,
+    allow: Gitignore,
+}
+
+impl TrackedOnly {
+    pub fn load(config: &Config, working_dir: &Path) -> Result<Self> {
+        let tracked = crate::git::tracked_files(working_dir)
+            .context("--tracked-only needs the working directory to be in a git repository")?;
+        let mut builder = GitignoreBuilder::new(working_dir);
+        for line in config.sheafy.restore_allow.as_deref().unwrap_or("").lines() {
+            builder
+                .add_line(None, line)
+                .with_context(|| format!("Invalid restore_allow pattern: '{}'", line))?;
+        }
+        Ok(Self {
+            tracked: tracked.into_iter().collect(),
+            allow: builder.build()?,
+        })
+    }
+
+    /// Whether a section path (relative, `/` separators) may be written.
+    pub fn permits(&self, path: &str) -> bool {
+        self.tracked.contains(path)
+            || (!path.split('/').any(|part| part == ".." || part.is_empty())
+                && self
+                    .allow
+                    .matched_path_or_any_parents(path, false)
+                    .is_ignore())
+    }
+
+    /// Reads the whole bundle first and fails, listing the offending paths, if it
+    /// holds any path that may not be written.
+    fn check(&self, path: &Path, options: &RestoreOptions) -> Result<()> {
+        let mut paths = Vec::new();
+        for section in options.reread_bundle(path)? {
+            let mut section = options.map_path(section.with_context(|| parse_failed(path))?);
+            if section.truncated {
+                match options.truncated_path(&section.path) {
+                    Some(partial) => section.path = partial,
+                    None => continue,
+                }
+            }
+            if !section.preview && !section.path.is_empty() {
+                paths.push(section.path);
+            }
+        }
+        self.check_paths(path, paths)
+    }
+
+    /// Fails, listing them, if any of `paths` (from the bundle at `path`) may not be
+    /// written.
+    fn check_paths(&self, path: &Path, paths: Vec<String>) -> Result<()> {
+        let refused: Vec<String> = paths.into_iter().filter(|p| !self.permits(p)).collect();
+        if refused.is_empty() {
+            return Ok(());
+        }
+        for path in &refused {
+            eprintln!("  Not tracked by git: {}", path);
+        }
+        Err(SheafyError::PathsRefused {
+            reason: format!(
+                "--tracked-only: {} file(s) in '{}' are not tracked by git or allowed by restore_allow; nothing was restored",
+                refused.len(),
+                path.display()
+            ),
+            paths: refused,
+        }
+        .into())
+    }
+}
+
+/// `allowed_paths` in `[sheafy.restore]`: the only paths restore may write.
+pub struct AllowedPaths {
+    matcher: Gitignore,
+    on_disallowed: OnDisallowed,
+}
+
+impl AllowedPaths {
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Option<Self>> {
+        let restore = &config.sheafy.restore;
+        let Some(patterns) = &restore.allowed_paths else {
+            return Ok(None);
+        };
+        let mut builder = GitignoreBuilder::new(working_dir);
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid allowed_paths pattern: '{}'", pattern))?;
+        }
+        Ok(Some(Self {
+            matcher: builder.build()?,
+            on_disallowed: restore.on_disallowed,
+        }))
+    }
+
+    /// Whether a section path (relative, `/` separators) may be written.
+    pub fn permits(&self, path: &str) -> bool {
+        !path.split('/').any(|part| part == ".." || part.is_empty())
+            && self
+                .matcher
+                .matched_path_or_any_parents(path, false)
+                .is_ignore()
+    }
+
+    /// With `on_disallowed = "fail"`, reads the whole bundle first and fails, listing
+    /// the offending paths, if it holds any path outside the allowed ones.
+    fn check(&self, path: &Path, options: &RestoreOptions) -> Result<()> {
+        if self.on_disallowed == OnDisallowed::Skip {
+            return Ok(());
+        }
+        let mut paths = Vec::new();
+        for section in options.reread_bundle(path)? {
+            let section = options.map_path(section.with_context(|| parse_failed(path))?);
+            if !section.preview && !section.path.is_empty() {
+                paths.push(section.path);
+            }
+        }
+        self.check_paths(path, paths)
+    }
+
+    /// With `on_disallowed = "fail"`, fails, listing them, if any of `paths` (from the
+    /// bundle at `path`) is outside the allowed ones.
+    fn check_paths(&self, path: &Path, paths: Vec<String>) -> Result<()> {
+        if self.on_disallowed == OnDisallowed::Skip {
+            return Ok(());
+        }
+        let refused: Vec<String> = paths.into_iter().filter(|p| !self.permits(p)).collect();
+        if refused.is_empty() {
+            return Ok(());
+        }
+        for path in &refused {
+            eprintln!("  Outside allowed_paths: {}", path);
+        }
+        Err(SheafyError::PathsRefused {
+            reason: format!(
+                "{} file(s) in '{}' are outside allowed_paths of [sheafy.restore]; nothing was restored. \
+                 Set on_disallowed = \"skip\" to restore the other files",
+                refused.len(),
+                path.display()
+            ),
+            paths: refused,
+        }
+        .into())
+    }
+}
+
+/// Whether a section path is absolute or leads out of the directory it is restored
+/// into with `..`.
+pub fn escapes(path: &str) -> bool {
+    path.starts_with(['/', '\\'])
+        || Path::new(path).is_absolute()
+        || path.split(['/', '\\']).any(|part| part == "..")
+}
+
+/// Reads the whole bundle first and fails if any path escapes the working directory,
+/// before anything is written.
+fn check_paths_inside(path: &Path, options: &RestoreOptions) -> Result<()> {
+    for section in options.reread_bundle(path)? {
+        let section = options.map_path(section.with_context(|| parse_failed(path))?);
+        if !section.preview && escapes(&section.path) {
+            return Err(SheafyError::PathEscape { path: section.path }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Reads the whole bundle first and fails, listing them, if it holds paths no bundle
+/// of this project has held: a bundle meant for another project, or invented files.
+fn check_known_paths(
+    path: &Path,
+    known_paths: &BTreeSet<String>,
+    options: &RestoreOptions,
+) -> Result<()> {
+    let mut paths = Vec::new();
+    for section in options.reread_bundle(path)? {
+        let section = options.map_path(section.with_context(|| parse_failed(path))?);
+        if !section.preview && !section.path.is_empty() {
+            paths.push(section.path);
+        }
+    }
+    refuse_unknown_paths(path, known_paths, paths)
+}
+
+/// Fails, listing them, if any of `paths` (from the bundle at `path`) is not in
+/// `known_paths`.
+fn refuse_unknown_paths(
+    path: &Path,
+    known_paths: &BTreeSet<String>,
+    paths: Vec<String>,
+) -> Result<()> {
+    let unknown: Vec<String> = paths
+        .into_iter()
+        .filter(|p| !known_paths.contains(p))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    for path in &unknown {
+        eprintln!("  Never bundled from this project: {}", path);
+    }
+    Err(SheafyError::PathsRefused {
+        reason: format!(
+            "{} file(s) in '{}' were never bundled from this project (per {}/{}); nothing was restored. \
+             If the bundle is right, pass --allow-new-paths",
+            unknown.len(),
+            path.display(),
+            crate::state::STATE_DIR,
+            crate::state::STATE_FILE
+        ),
+        paths: unknown,
+    }
+    .into())
+}
+
+/// Maps working tree paths to section header paths (`header_strip_prefix`, then
+/// `header_add_prefix`) and back.
+#[derive(Debug, Default, Clone)]
+pub struct HeaderPrefixes {
+    strip: String,
+    add: String,
+}
+
+impl HeaderPrefixes {
+    pub fn from_config(config: &Config) -> Self {
+        // Prefixes are directories: `src` and `src/` both mean `src/`
+        let dir = |prefix: &Option<String>| {
+            let prefix = prefix.as_deref().unwrap_or("").trim_matches('/');
+            if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{}/", prefix)
+            }
+        };
+        Self {
+            strip: dir(&config.sheafy.header_strip_prefix),
+            add: dir(&config.sheafy.header_add_prefix),
+        }
+    }
+
+    /// Whether restore can map the header of `path` back to it. Paths outside the
+    /// stripped prefix keep their header, which may look like a mapped one.
+    pub fn is_reversible(&self, path: &str) -> bool {
+        self.to_path(&self.to_header(path)) == path
+    }
+
+    pub fn to_header(&self, path: &str) -> String {
+        match path.strip_prefix(&self.strip) {
+            Some(rest) => format!("{}{}", self.add, rest),
+            None => path.to_string(),
+        }
+    }
+
+    pub fn to_path(&self, header: &str) -> String {
+        match header.strip_prefix(&self.add) {
+            Some(rest) if !header.is_empty() => format!("{}{}", self.strip, rest),
+            _ => header.to_string(),
+        }
+    }
+}
+
+/// Result of a restore run, reported back to callers such as the daemon.
+#[derive(Debug, Default)]
+pub struct RestoreSummary {
+    pub input: PathBuf,
+    /// Files written (or patched), relative to the working directory.
+    pub restored: Vec<PathBuf>,
+    /// Files left untouched because they differ from the bundle (`--on-conflict skip`).
+    pub conflicts: Vec<Conflict>,
+    /// Files that could not be written, in bundle order.
+    pub failures: Vec<Failure>,
+    /// Every section and what restore did with it, kept with `--report`
+    pub report: Vec<ReportEntry>,
+}
+
+/// One section in `sheafy-restore-report.json`.
+#[derive(Debug, Serialize)]
+pub struct ReportEntry {
+    /// Path in the section header
+    pub section: String,
+    /// The file the section was restored to
+    pub target: PathBuf,
+    /// `created`, `overwritten`, `unchanged`, `deleted` (by a patch), `skipped` or `error`
+    pub action: &'static str,
+    /// Why the section was skipped, or the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// SHA-256 of the file before the restore; null if there was none
+    pub sha256_before: Option<String>,
+    /// SHA-256 of the file after the restore; null if there is none
+    pub sha256_after: Option<String>,
+}
+
+/// A file restore could not write, such as a read-only file or one in a directory
+/// without write permission.
+#[derive(Debug)]
+pub struct Failure {
+    /// Path from the section header, using `/` separators.
+    pub path: String,
+    /// The error, with its causes
+    pub error: String,
+}
+
+/// A file whose working tree content differs from the bundled version.
+#[derive(Debug)]
+pub struct Conflict {
+    /// Path from the section header, using `/` separators.
+    pub path: String,
+    pub bundled: String,
+    pub current: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    /// The file does not exist in the working tree yet
+    New,
+    Modified,
+    Unchanged,
+}
+
+impl DiffStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiffStatus::New => "new",
+            DiffStatus::Modified => "modified",
+            DiffStatus::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// Extracts every file section from a bundle's content with the parser matching its
+/// format version. Bundles from a newer sheafy are rejected rather than half-restored.
+pub fn parse_sections(content: &str) -> Result<Vec<Section>> {
+    open_sections(content.as_bytes())?.collect()
+}
+
+/// Starts reading a bundle's sections, after checking that its format version is one
+/// this sheafy understands. v1 bundles only use headings and fences, which v2 still
+/// reads the same way.
+pub fn open_sections<R: BufRead>(reader: R) -> Result<BundleReader<R>> {
+    let bundle = BundleReader::new(reader)?;
+    let version = markers_version(bundle.markers());
+    if version > FORMAT_VERSION {
+        return Err(SheafyError::UnsupportedVersion {
+            version,
+            supported: FORMAT_VERSION,
+        }
+        .into());
+    }
+    Ok(bundle)
+}
+
+/// Resolves the working directory and the absolute path of the bundle to read.
+pub fn resolve_input(config: &Config, input_filename: Option<&str>) -> Result<(PathBuf, PathBuf)> {
     // Use working_dir already determined in main.rs
     let working_dir = config
         .get_working_dir()
         .context("Failed to get working directory for restore")?;
 
-    // Determine input file path relative to the *initial* directory sheafy was run from,
-    // or use the bundle_name from config (which is usually relative to working_dir)
-    let input_path_str = input_filename
-        .as_deref()
-        .or(config.sheafy.bundle_name.as_deref())
-        .unwrap_or(DEFAULT_BUNDLE_NAME);
-
-    // Resolve input path: if absolute, use it; otherwise, assume relative to initial run dir OR working_dir?
-    // Let's assume relative to working_dir for consistency with bundle output default.
-    let input_path = PathBuf::from(input_path_str);
-    let absolute_input_path = if input_path.is_absolute() {
-        input_path
-    } else {
-        working_dir.join(input_path)
-    };
+    // Placeholders in bundle_name expand as when bundling, so the same file is found
+    let absolute_input_path = crate::bundle::bundle_path(config, input_filename, &working_dir)?;
+    Ok((working_dir, absolute_input_path))
+}
 
-    println!("Reading bundle file: {}", absolute_input_path.display());
-    let content = fs::read_to_string(&absolute_input_path).with_context(|| {
-        format!(
-            "Failed to read input file: {}",
-            absolute_input_path.display()
-        )
+/// Opens a bundle and reads its header; sections are parsed as they are iterated.
+fn open_bundle(path: &Path) -> Result<BundleReader<BufReader<File>>> {
+    status!("{}", tr!("restore.reading", path.display()));
+    read_bundle(path)
+}
+
+fn read_bundle(path: &Path) -> Result<BundleReader<BufReader<File>>> {
+    let file = File::open(path).map_err(|e| {
+        let error = anyhow::Error::from(e);
+        if path.exists() {
+            error.context(format!("Failed to read input file: {}", path.display()))
+        } else {
+            error.context(SheafyError::BundleNotFound {
+                path: path.to_path_buf(),
+            })
+        }
     })?;
+    let mut reader = BufReader::new(file);
+    if crate::archive::sniff(reader.fill_buf()?).is_some() {
+        bail!(
+            "'{}' is an archive, not a bundle; `sheafy restore` extracts it",
+            path.display()
+        );
+    }
+    // Scripts from `--format shell`/`powershell` are read as the bundle they stand for
+    if crate::script::is_script(reader.fill_buf()?) {
+        let mut script = String::new();
+        reader
+            .read_to_string(&mut script)
+            .with_context(|| format!("Failed to read input file: {}", path.display()))?;
+        let bundle = crate::script::to_bundle(&script).with_context(|| parse_failed(path))?;
+        let mut file = tempfile::tempfile().context("Failed to create a temporary file")?;
+        file.write_all(bundle.as_bytes())?;
+        file.rewind()?;
+        reader = BufReader::new(file);
+    }
+    open_sections(reader).with_context(|| parse_failed(path))
+}
+
+pub fn parse_failed(path: &Path) -> String {
+    format!("Failed to parse bundle: {}", path.display())
+}
+
+fn has_marker(bundle: &BundleReader<impl BufRead>, marker: &str) -> bool {
+    bundle.markers().iter().any(|m| m == marker)
+}
+
+/// Compares every section of a bundle against the working tree, or the last commit,
+/// without writing anything.
+pub fn diff_bundle(
+    config: &Config,
+    input_filename: Option<&str>,
+    vars: &[String],
+    base: DiffBase,
+) -> Result<Vec<(String, DiffStatus)>> {
+    Ok(compare_bundle(config, input_filename, vars, base)?
+        .into_iter()
+        .map(|compared| (compared.path, compared.status))
+        .collect())
+}
+
+/// A bundle section compared with the working tree.
+pub struct Compared {
+    pub path: String,
+    pub status: DiffStatus,
+    /// What restore would write, in the style of the existing file; `None` for stubs
+    pub content: Option<String>,
+}
 
-    let mut restored_count = 0;
+/// [`diff_bundle`], also keeping the content of each section.
+pub fn compare_bundle(
+    config: &Config,
+    input_filename: Option<&str>,
+    vars: &[String],
+    base: DiffBase,
+) -> Result<Vec<Compared>> {
+    let (working_dir, absolute_input_path) = resolve_input(config, input_filename)?;
+    let mut head = match base {
+        DiffBase::Worktree => None,
+        DiffBase::Head if !crate::git::is_work_tree(&working_dir) => {
+            bail!("--diff-base head needs a git work tree; compare with the files on disk instead")
+        }
+        // Before the first commit, every file is new
+        DiffBase::Head => Some(crate::git::HeadFiles::open(&working_dir)?),
+    };
+    // The file the section would replace, as of `base`; `None` if there is none
+    let mut existing = |path: &str| -> Result<Option<Vec<u8>>> {
+        match &mut head {
+            None => Ok(fs::read(crate::paths::from_slash(&working_dir, path)).ok()),
+            Some(Some(files)) => files.read(path),
+            Some(None) => Ok(None),
+        }
+    };
+    let mut options = RestoreOptions::from_config(config, &working_dir)?;
+    options.vars = crate::vars::merge(&options.vars, vars)?;
+    let bundle = open_bundle(&absolute_input_path)?.with_vars(&options.vars)?;
+    if has_marker(&bundle, PATCH_MARKER) || has_marker(&bundle, READONLY_MARKER) {
+        return Err(SheafyError::NotWholeFiles {
+            path: absolute_input_path,
+        })
+        .context("A bundle of patches or excerpts cannot be compared with the working tree");
+    }
+    bundle
+        .filter(|section| {
+            section
+                .as_ref()
+                .map_or(true, |s| !s.path.is_empty() && !s.preview)
+        })
+        .map(|section| {
+            let section = section.with_context(|| parse_failed(&absolute_input_path))?;
+            let section = options.map_path(section);
+            let path = section.path.clone();
+            if let Some(stub) = &section.stub {
+                let status = match existing(&path)? {
+                    Some(existing) if crate::stub::sha256_hex(&existing) == stub.sha256 => {
+                        DiffStatus::Unchanged
+                    }
+                    Some(_) => DiffStatus::Modified,
+                    None => DiffStatus::New,
+                };
+                return Ok(Compared {
+                    path,
+                    status,
+                    content: None,
+                });
+            }
+            let handler = section.handler.clone();
+            let file_content = section.into_file_content(&options)?;
+            let (status, content) = match existing(&path)? {
+                Some(existing) => {
+                    let styled = options.fit_to(&existing, handler.as_deref(), file_content);
+                    let status = if existing == styled.as_bytes() {
+                        DiffStatus::Unchanged
+                    } else {
+                        DiffStatus::Modified
+                    };
+                    (status, styled)
+                }
+                None => (DiffStatus::New, file_content),
+            };
+            Ok(Compared {
+                path,
+                status,
+                content: Some(content),
+            })
+        })
+        .collect()
+}
+
+// Update function signature
+pub fn run_restore(config: &Config, args: &RestoreArgs) -> Result<RestoreSummary> {
+    if args.check {
+        return check_bundle(
+            config,
+            args.input_file.as_deref(),
+            &args.vars,
+            args.diff_base,
+        );
+    }
+    status!("{}", tr!("restore.attempting"));
+    let working_dir = config
+        .get_working_dir()
+        .context("Failed to get working directory for restore")?;
+    let mut options = RestoreOptions::from_config(config, &working_dir)?;
+    if args.tracked_only {
+        options.tracked_only = Some(TrackedOnly::load(config, &working_dir)?);
+    }
+    options.on_truncated = args.on_truncated;
+    options.stubs = args.stubs;
+    options.stop_on_error = args.stop_on_error;
+    options.verify_checksums = args.verify_checksums;
+    options.vars = crate::vars::merge(&options.vars, &args.vars)?;
+    options.report |= args.report;
+    options.force_readonly = args.force_readonly;
+    options.match_existing &= !args.as_bundled;
+    options.strip_components = args.strip_components;
+    options.path_prefix = match args.prefix.as_deref().map(|p| p.trim_matches('/')) {
+        Some(prefix) if !prefix.is_empty() => format!("{}/", prefix),
+        _ => String::new(),
+    };
+    if args.validate {
+        options.validation = Some(Validation::from_config(config));
+    } else if args.no_validate {
+        options.validation = None;
+    }
+    if !args.allow_new_paths {
+        options.known_paths =
+            crate::state::State::load(&working_dir)?.map(|state| state.bundled_paths);
+    }
+    if let Some(dir) = &args.flat {
+        let (_, absolute_input_path) = resolve_input(config, args.input_file.as_deref())?;
+        return restore_flat(&working_dir, &absolute_input_path, dir, &options);
+    }
+    let summary = match &args.all {
+        Some(dir) => restore_all(&working_dir, dir, args.on_conflict, &options)?,
+        None => {
+            let (_, absolute_input_path) = resolve_input(config, args.input_file.as_deref())?;
+            restore_bundle(
+                &working_dir,
+                &absolute_input_path,
+                args.on_conflict,
+                &options,
+            )?
+        }
+    };
+    if options.report {
+        write_report(&working_dir, &summary)?;
+    }
+    if !summary.conflicts.is_empty() {
+        let report_path = working_dir.join(CONFLICTS_FILENAME);
+        write_conflict_report(&report_path, &summary)?;
+        eprintln!(
+            "Warning: {} file(s) differ from the bundle and were left untouched; see '{}'.",
+            summary.conflicts.len(),
+            report_path.display()
+        );
+    }
+    if !summary.failures.is_empty() {
+        eprintln!(
+            "\n{} file(s) could not be restored:",
+            summary.failures.len()
+        );
+        for failure in &summary.failures {
+            eprintln!("  {}: {}", failure.path, failure.error);
+        }
+        return Err(SheafyError::RestoreIncomplete {
+            failed: summary.failures.len(),
+            restored: summary.restored.len(),
+        }
+        .into());
+    }
+    Ok(summary)
+}
+
+/// Writes `sheafy-restore-report.json`: the bundle and, per section, the file it went
+/// to, what was done and the file's hash before and after.
+fn write_report(working_dir: &Path, summary: &RestoreSummary) -> Result<()> {
+    let path = working_dir.join(REPORT_FILENAME);
+    let report = serde_json::json!({
+        "bundle": summary.input,
+        "working_dir": working_dir,
+        "time": crate::timestamp::rfc3339(crate::timestamp::now_unix()),
+        "entries": summary.report,
+    });
+    fs::write(&path, serde_json::to_string_pretty(&report)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    status!(
+        "Wrote a report of {} section(s) to {}",
+        summary.report.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// `restore --check`: succeeds only if restoring the bundle would not change any file.
+fn check_bundle(
+    config: &Config,
+    input_filename: Option<&str>,
+    vars: &[String],
+    base: DiffBase,
+) -> Result<RestoreSummary> {
+    let (_, absolute_input_path) = resolve_input(config, input_filename)?;
+    let entries = diff_bundle(config, input_filename, vars, base)?;
+    let differing: Vec<_> = entries
+        .iter()
+        .filter(|(_, status)| *status != DiffStatus::Unchanged)
+        .collect();
+    if differing.is_empty() {
+        status!(
+            "{} matches '{}' ({} file(s) checked).",
+            match base {
+                DiffBase::Worktree => "Working tree",
+                DiffBase::Head => "HEAD",
+            },
+            absolute_input_path.display(),
+            entries.len()
+        );
+        return Ok(RestoreSummary {
+            input: absolute_input_path,
+            ..Default::default()
+        });
+    }
+    for (path, status) in &differing {
+        status!("  {:<9} {}", status.as_str(), path);
+    }
+    Err(SheafyError::TreeDiffers {
+        differing: differing.len(),
+        checked: entries.len(),
+    })
+    .with_context(|| format!("'{}' is not applied", absolute_input_path.display()))
+}
+
+/// Writes the skipped files as a bundle holding the bundled versions, each followed by
+/// the working tree version for comparison. After editing, restoring the report with
+/// `--on-conflict overwrite` writes the resolved files.
+fn write_conflict_report(path: &Path, summary: &RestoreSummary) -> Result<()> {
+    let mut report = String::new();
+    report.push_str(VERSION_MARKER);
+    report.push('\n');
+    report.push_str(CONFLICTS_MARKER);
+    report.push_str("\n# Restore conflicts\n\n");
+    report.push_str(&format!(
+        "{} file(s) from `{}` differ from the working tree and were not restored.\n\
+         Each section below holds the bundled version, followed by the file as it is on disk.\n\
+         Edit the sections you want to keep (delete the others), then run\n\
+         `sheafy restore {} --on-conflict overwrite`.\n",
+        summary.conflicts.len(),
+        summary.input.display(),
+        CONFLICTS_FILENAME
+    ));
+    for conflict in &summary.conflicts {
+        let lang_hint = crate::sniff::language_hint(Path::new(&conflict.path), &conflict.bundled);
+        let no_eol = if conflict.bundled.ends_with('\n') {
+            String::new()
+        } else {
+            format!("{}\n", NO_EOL_MARKER)
+        };
+        let bundled_fence = crate::parser::fence_for(&conflict.bundled);
+        let current_fence = crate::parser::fence_for(&conflict.current);
+        report.push_str(&format!(
+            "\n## {}\n{}{}\n{}{}\n{}\nWorking tree version of `{}`:\n\n{}{}\n{}{}\n",
+            crate::header::escape(&conflict.path),
+            bundled_fence,
+            lang_hint,
+            ensure_eof_newline(&conflict.bundled),
+            bundled_fence,
+            no_eol,
+            conflict.path,
+            current_fence,
+            lang_hint,
+            ensure_eof_newline(&conflict.current),
+            current_fence
+        ));
+    }
+    fs::write(path, report)
+        .with_context(|| format!("Failed to write conflict report: {}", path.display()))
+}
+
+/// Restores every `*.md` bundle in `dir` in filename order, so later bundles
+/// override files written by earlier ones.
+fn restore_all(
+    working_dir: &Path,
+    dir: &str,
+    on_conflict: OnConflict,
+    options: &RestoreOptions,
+) -> Result<RestoreSummary> {
+    let bundle_dir = working_dir.join(dir);
+    let mut bundles: Vec<PathBuf> = fs::read_dir(&bundle_dir)
+        .with_context(|| format!("Failed to read bundle directory: {}", bundle_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "md"))
+        .collect();
+    bundles.sort();
+    if bundles.is_empty() {
+        bail!("No *.md bundles found in {}", bundle_dir.display());
+    }
+
+    let mut summary = RestoreSummary {
+        input: bundle_dir.clone(),
+        ..Default::default()
+    };
+    // Which bundle last wrote each path, to report overrides
+    let mut writers: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for bundle in &bundles {
+        status!("\n=== {} ===", bundle.display());
+        let bundle_summary = restore_bundle(working_dir, bundle, on_conflict, options)?;
+        summary.conflicts.extend(bundle_summary.conflicts);
+        summary.failures.extend(bundle_summary.failures);
+        summary.report.extend(bundle_summary.report);
+        let bundle_name = bundle
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for path in bundle_summary.restored {
+            let entry = writers.entry(path.clone()).or_default();
+            if entry.is_empty() {
+                summary.restored.push(path);
+            }
+            entry.push(bundle_name.clone());
+        }
+    }
+
+    status!(
+        "\nRestored {} bundle(s) from {}: {} distinct file(s) written.",
+        bundles.len(),
+        bundle_dir.display(),
+        summary.restored.len()
+    );
+    let overridden: Vec<_> = writers.iter().filter(|(_, w)| w.len() > 1).collect();
+    if !overridden.is_empty() {
+        status!("Files overridden by later bundles:");
+        for (path, bundle_names) in overridden {
+            status!("  {} ({})", path.display(), bundle_names.join(" -> "));
+        }
+    }
+    Ok(summary)
+}
+
+/// Restores one bundle (or archive) into `working_dir`.
+pub fn restore_bundle(
+    working_dir: &Path,
+    absolute_input_path: &Path,
+    on_conflict: OnConflict,
+    options: &RestoreOptions,
+) -> Result<RestoreSummary> {
+    if let Some(kind) = archive_kind(absolute_input_path) {
+        return restore_archive(working_dir, absolute_input_path, kind, on_conflict, options);
+    }
+    let bundle = options.open_bundle(absolute_input_path)?;
+    if has_marker(&bundle, PATCH_MARKER) {
+        if options.strip_components > 0 || !options.path_prefix.is_empty() {
+            bail!("--strip-components and --prefix don't apply to patch bundles, which git applies as they are");
+        }
+        return restore_patches(working_dir, bundle, absolute_input_path, options);
+    }
+    if has_marker(&bundle, READONLY_MARKER) {
+        return Err(SheafyError::NotWholeFiles {
+            path: absolute_input_path.to_path_buf(),
+        })
+        .context(
+            "This read-only bundle cannot be restored. \
+             Re-create it with `--format patch` to get a bundle that restore can apply.",
+        );
+    }
+    if bundle.declared_readonly() && !options.force_readonly {
+        return Err(SheafyError::ReadonlyBundle {
+            path: absolute_input_path.to_path_buf(),
+        })
+        .context(
+            "This bundle is meant for reading, not for restoring over the working tree. \
+             Pass --force-readonly to restore it anyway.",
+        );
+    }
+    let check = crate::timings::span("check");
+    check_paths_inside(absolute_input_path, options)?;
+    if options.verify_checksums {
+        verify_checksums(working_dir, absolute_input_path, options)?;
+    }
+    if let Some(tracked_only) = &options.tracked_only {
+        tracked_only.check(absolute_input_path, options)?;
+    }
+    if let Some(known_paths) = &options.known_paths {
+        check_known_paths(absolute_input_path, known_paths, options)?;
+    }
+    if let Some(allowed_paths) = &options.allowed_paths {
+        allowed_paths.check(absolute_input_path, options)?;
+    }
+    if let Some(validation) = &options.validation {
+        validation.run(working_dir, absolute_input_path, options)?;
+    }
+    drop(check);
+
+    let mut summary = RestoreSummary {
+        input: absolute_input_path.to_path_buf(),
+        ..Default::default()
+    };
     let mut found_blocks = 0;
+    let mut changes = ChangeReport::new(working_dir, options);
 
-    for cap in RESTORE_REGEX.captures_iter(&content) {
+    for section in crate::timings::timed("parse", bundle) {
+        let mut section =
+            options.map_path(section.with_context(|| parse_failed(absolute_input_path))?);
         found_blocks += 1;
-        let rel_path_str = cap.get(1).map_or("", |m| m.as_str()).trim();
-        let code_content = ensure_eof_newline(cap.get(2).map_or("", |m| m.as_str()));
+        if section.preview {
+            status!("  Skipping preview: {} (not the whole file)", section.path);
+            changes.skip(&section.path, "preview, not the whole file");
+            continue;
+        }
+        if section.truncated {
+            match options.place_truncated(&section.path) {
+                Some(partial) => section.path = partial,
+                None => {
+                    changes.skip(&section.path, "truncated: the bundle ends inside it");
+                    continue;
+                }
+            }
+        }
+        if let Some(stub) = &section.stub {
+            let placed = if options.stubs == OnStub::Placeholder
+                && !section.path.is_empty()
+                && options
+                    .allowed_paths
+                    .as_ref()
+                    .is_none_or(|allowed| allowed.permits(&section.path))
+            {
+                match place_stub(working_dir, &section.path) {
+                    Ok(placed) => placed,
+                    Err(e) => {
+                        changes.fail(&section.path, &e);
+                        options.record_failure(&mut summary, &section.path, e)?;
+                        continue;
+                    }
+                }
+            } else {
+                false
+            };
+            if placed {
+                changes.record(&section.path, None, &[]);
+                summary.restored.push(PathBuf::from(&section.path));
+            } else {
+                status!(
+                    "  Skipping stub: {} ({}, content not in the bundle)",
+                    section.path,
+                    ByteSize(stub.size)
+                );
+                changes.skip(&section.path, "stub, content not in the bundle");
+            }
+            continue;
+        }
+        let rel_path = section.path.clone();
+        let rel_path_str = rel_path.as_str();
+        let handler = section.handler.clone();
+        let code_content = section.into_file_content(options)?;
 
         if rel_path_str.is_empty() {
-            eprintln!("Warning: Found block with empty filepath. Skipping.");
+            if options.strip_components > 0 {
+                status!("  Skipping a section with no path left after --strip-components");
+            } else {
+                eprintln!("Warning: Found block with empty filepath. Skipping.");
+            }
+            changes.skip(rel_path_str, "empty path");
+            continue;
+        }
+        if options
+            .allowed_paths
+            .as_ref()
+            .is_some_and(|allowed| !allowed.permits(rel_path_str))
+        {
+            eprintln!(
+                "Warning: '{}' is outside allowed_paths of [sheafy.restore]; skipping it.",
+                rel_path_str
+            );
+            changes.skip(rel_path_str, "outside allowed_paths");
             continue;
         }
 
         // Construct target path relative to the determined working_dir
-        let target_path =
-            working_dir.join(rel_path_str.replace('/', std::path::MAIN_SEPARATOR_STR));
-
-        println!("  Restoring: {}", target_path.display());
-
-        // Ensure parent directory exists
-        if let Some(parent_dir) = target_path.parent() {
-            if !parent_dir.exists() && !parent_dir.as_os_str().is_empty() {
-                println!("    Creating directory: {}", parent_dir.display());
-                fs::create_dir_all(parent_dir).with_context(|| {
-                    format!("Failed to create directory: {}", parent_dir.display())
-                })?;
-            }
-        }
-
-        // Write the file content
-        match File::create(&target_path) {
-            Ok(output_file) => {
-                let mut writer = BufWriter::new(output_file);
-                match writer.write_all(code_content.as_bytes()) {
-                    Ok(_) => {
-                        // Explicitly flush before dropping to catch potential errors
-                        if let Err(e) = writer.flush() {
-                            eprintln!(
-                                "Error flushing buffer for file '{}': {}. File might be incomplete.",
-                                target_path.display(), e
-                            );
-                            // Optionally continue, or return Err(e.into()) ? Continuing seems reasonable.
-                        }
-                        // Buffer flushed implicitly on drop if flush() wasn't called or succeeded
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "Error writing content to file '{}': {}. Skipping file.",
-                            target_path.display(),
-                            e
-                        );
-                        continue; // Skip this file
-                    }
+        let target_path = crate::paths::from_slash(working_dir, rel_path_str);
+
+        let existing = fs::read(&target_path).ok();
+        let code_content = match &existing {
+            Some(existing) => options.fit_to(existing, handler.as_deref(), code_content),
+            None => code_content,
+        };
+        if on_conflict == OnConflict::Skip {
+            if let Some(existing) = &existing {
+                if existing != code_content.as_bytes() {
+                    status!("{}", tr!("restore.conflict", target_path.display()));
+                    summary.conflicts.push(Conflict {
+                        path: rel_path.clone(),
+                        current: String::from_utf8_lossy(existing).into_owned(),
+                        bundled: code_content,
+                    });
+                    changes.skip(&rel_path, "conflict: differs from the bundle");
+                    continue;
                 }
             }
-            Err(e) => {
-                eprintln!(
-                    "Error creating/opening file '{}' for writing: {}. Skipping file.",
-                    target_path.display(),
-                    e
-                );
-                continue; // Skip this file
-            }
         }
-        restored_count += 1;
+
+        status!("{}", tr!("restore.restoring", target_path.display()));
+
+        let write = crate::timings::span("write");
+        let written = write_file(&target_path, code_content.as_bytes());
+        drop(write);
+        if let Err(e) = written {
+            changes.fail(rel_path_str, &e);
+            options.record_failure(&mut summary, rel_path_str, e)?;
+            continue;
+        }
+        changes.record(rel_path_str, existing.as_deref(), code_content.as_bytes());
+        summary.restored.push(PathBuf::from(rel_path_str));
     }
 
     if found_blocks == 0 {
-        println!(
-            "Warning: No valid sheafy blocks found in '{}'. No files restored.",
-            absolute_input_path.display()
+        status!(
+            "{}",
+            tr!("restore.no_blocks", absolute_input_path.display())
+        );
+        status!(
+            "{}",
+            tr!("restore.inspect_hint", absolute_input_path.display())
         );
     } else {
-        println!(
-            "\nRestore complete. {} file(s) restored/overwritten in {}.",
-            restored_count,
-            working_dir.display()
+        changes.print(working_dir);
+    }
+
+    summary.report = changes.entries;
+    Ok(summary)
+}
+
+/// `restore --verify-checksums`: every section must hold what the checksums section
+/// lists for it. Sections changed or added after bundling, and listed files the bundle
+/// no longer holds, fail the restore before anything is written. Files edited on disk
+/// since bundling are only reported, as restoring overwrites them like any other file.
+fn verify_checksums(working_dir: &Path, path: &Path, options: &RestoreOptions) -> Result<()> {
+    let mut bundle = options.reread_bundle(path)?;
+    // Header path -> hash of the decoded section (None if truncated), and its file
+    let mut sections = BTreeMap::new();
+    for section in bundle.by_ref() {
+        let section = section.with_context(|| parse_failed(path))?;
+        if section.stub.is_some() || section.preview || section.path.is_empty() {
+            continue;
+        }
+        let hash =
+            (!section.truncated).then(|| crate::stub::sha256_hex(section.content.as_bytes()));
+        // Handler sections hold the encoded file, so only plain ones compare with the disk
+        let target = section
+            .handler
+            .is_none()
+            .then(|| working_dir.join(options.section_path(&section)));
+        sections.insert(section.path, (hash, target));
+    }
+    let Some(manifest) = bundle.checksums() else {
+        bail!(
+            "'{}' has no `## {}` section to verify against; bundle with --checksums",
+            path.display(),
+            CHECKSUMS_HEADING
+        );
+    };
+
+    let mut mismatched = Vec::new();
+    let mut edited_on_disk = Vec::new();
+    for (section_path, (hash, target)) in &sections {
+        let Some(expected) = manifest.get(section_path) else {
+            mismatched.push(("added", section_path));
+            continue;
+        };
+        if hash.as_ref() != Some(expected) {
+            mismatched.push(("changed", section_path));
+            continue;
+        }
+        let on_disk = target
+            .as_ref()
+            .and_then(|target| fs::read(target).ok())
+            .map(|bytes| crate::stub::sha256_hex(&bytes));
+        if on_disk.is_some_and(|on_disk| on_disk != *expected) {
+            edited_on_disk.push(section_path);
+        }
+    }
+    for listed in manifest.keys().filter(|p| !sections.contains_key(*p)) {
+        mismatched.push(("missing", listed));
+    }
+
+    if !edited_on_disk.is_empty() {
+        eprintln!(
+            "Warning: {} file(s) were edited on disk since bundling; restoring overwrites them:",
+            edited_on_disk.len()
+        );
+        for path in edited_on_disk {
+            eprintln!("  {}", path);
+        }
+    }
+    if mismatched.is_empty() {
+        status!("Checksums: all {} section(s) match.", sections.len());
+        return Ok(());
+    }
+    status!("Sections that do not match the checksums (corrupted or edited in the bundle):");
+    for (kind, path) in &mismatched {
+        status!("  {:<9} {}", kind, path);
+    }
+    Err(SheafyError::ChecksumMismatch {
+        mismatched: mismatched.len(),
+        listed: manifest.len(),
+    }
+    .into())
+}
+
+/// The kind of archive at `path`, if it is one rather than a bundle.
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let mut head = Vec::with_capacity(crate::archive::SNIFF_LEN);
+    File::open(path)
+        .ok()?
+        .take(crate::archive::SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .ok()?;
+    crate::archive::sniff(&head)
+}
+
+/// Extracts a tar, tar.gz or zip archive, with the checks a bundle gets: paths must stay
+/// inside the working directory and pass `--tracked-only`, the known paths and
+/// `allowed_paths`.
+fn restore_archive(
+    working_dir: &Path,
+    absolute_input_path: &Path,
+    kind: ArchiveKind,
+    on_conflict: OnConflict,
+    options: &RestoreOptions,
+) -> Result<RestoreSummary> {
+    status!("{}", tr!("restore.reading", absolute_input_path.display()));
+    let bytes = fs::read(absolute_input_path).with_context(|| {
+        format!(
+            "Failed to read input file: {}",
+            absolute_input_path.display()
+        )
+    })?;
+    let mut entries = crate::archive::read_archive(kind, &bytes)
+        .with_context(|| format!("Failed to read archive: {}", absolute_input_path.display()))?;
+    // Entries left without a path by --strip-components are dropped, as tar does
+    entries.retain_mut(|entry| {
+        entry.path = options.relocate(&entry.path);
+        !entry.path.is_empty()
+    });
+    if let Some(entry) = entries.iter().find(|entry| escapes(&entry.path)) {
+        return Err(SheafyError::PathEscape {
+            path: entry.path.clone(),
+        }
+        .into());
+    }
+    let paths: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+    if let Some(tracked_only) = &options.tracked_only {
+        tracked_only.check_paths(absolute_input_path, paths.clone())?;
+    }
+    if let Some(known_paths) = &options.known_paths {
+        refuse_unknown_paths(absolute_input_path, known_paths, paths.clone())?;
+    }
+    if let Some(allowed_paths) = &options.allowed_paths {
+        allowed_paths.check_paths(absolute_input_path, paths)?;
+    }
+    if options.validation.is_some() {
+        eprintln!(
+            "Warning: Validation needs a Markdown bundle; extracting the archive without it."
         );
     }
 
+    let mut summary = RestoreSummary {
+        input: absolute_input_path.to_path_buf(),
+        ..Default::default()
+    };
+    let mut changes = ChangeReport::new(working_dir, options);
+    for entry in entries {
+        if options
+            .allowed_paths
+            .as_ref()
+            .is_some_and(|allowed| !allowed.permits(&entry.path))
+        {
+            eprintln!(
+                "Warning: '{}' is outside allowed_paths of [sheafy.restore]; skipping it.",
+                entry.path
+            );
+            changes.skip(&entry.path, "outside allowed_paths");
+            continue;
+        }
+        let target_path = crate::paths::from_slash(working_dir, &entry.path);
+        let existing = fs::read(&target_path).ok();
+        if let Some(existing) = existing
+            .as_ref()
+            .filter(|_| on_conflict == OnConflict::Skip)
+        {
+            if *existing != entry.content {
+                status!("{}", tr!("restore.conflict", target_path.display()));
+                summary.conflicts.push(Conflict {
+                    path: entry.path.clone(),
+                    current: String::from_utf8_lossy(existing).into_owned(),
+                    bundled: String::from_utf8_lossy(&entry.content).into_owned(),
+                });
+                changes.skip(&entry.path, "conflict: differs from the archive");
+                continue;
+            }
+        }
+        status!("{}", tr!("restore.restoring", target_path.display()));
+        if let Err(e) = write_file(&target_path, &entry.content)
+            .and_then(|_| set_mode(&target_path, entry.mode))
+        {
+            changes.fail(&entry.path, &e);
+            options.record_failure(&mut summary, &entry.path, e)?;
+            continue;
+        }
+        changes.record(&entry.path, existing.as_deref(), &entry.content);
+        summary.restored.push(PathBuf::from(&entry.path));
+    }
+    changes.print(working_dir);
+    summary.report = changes.entries;
+    Ok(summary)
+}
+
+/// Gives an extracted file the executable bits the archive records for it.
+#[cfg(unix)]
+fn set_mode(target_path: &Path, mode: Option<u32>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(mode) = mode.filter(|mode| mode & 0o111 != 0) {
+        fs::set_permissions(target_path, fs::Permissions::from_mode(mode & 0o777))
+            .with_context(|| format!("Failed to set permissions of {}", target_path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_target_path: &Path, _mode: Option<u32>) -> Result<()> {
     Ok(())
 }
+
+/// Writes `content` to `target_path`, creating its parent directories as needed.
+fn write_file(target_path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent_dir) = target_path.parent() {
+        if !parent_dir.exists() && !parent_dir.as_os_str().is_empty() {
+            status!("{}", tr!("restore.creating_dir", parent_dir.display()));
+            fs::create_dir_all(parent_dir)
+                .with_context(|| format!("Failed to create directory: {}", parent_dir.display()))?;
+        }
+    }
+    let mut writer = BufWriter::new(
+        File::create(target_path)
+            .with_context(|| format!("Failed to create {}", target_path.display()))?,
+    );
+    writer
+        .write_all(content)
+        .and_then(|_| writer.flush())
+        .with_context(|| format!("Failed to write {}", target_path.display()))
+}
+
+/// Creates an empty file standing in for a stub section, unless a file is already at
+/// `path`. Returns whether it created one.
+fn place_stub(working_dir: &Path, path: &str) -> Result<bool> {
+    let target_path = crate::paths::from_slash(working_dir, path);
+    if target_path.exists() {
+        return Ok(false);
+    }
+    if let Some(parent_dir) = target_path.parent() {
+        fs::create_dir_all(parent_dir)
+            .with_context(|| format!("Failed to create directory: {}", parent_dir.display()))?;
+    }
+    status!("  Creating placeholder: {}", target_path.display());
+    File::create(&target_path)
+        .with_context(|| format!("Failed to create {}", target_path.display()))?;
+    Ok(true)
+}
+
+/// What a restore did to each file, summarized at the end like `git status`.
+#[derive(Debug, Default)]
+struct ChangeReport {
+    /// Path and size of each new file
+    created: Vec<(String, u64)>,
+    /// Path and size change of each file whose content changed
+    overwritten: Vec<(String, i64)>,
+    unchanged: usize,
+    skipped: usize,
+    /// The working directory and header prefixes, when entries are kept for the report
+    report_dir: Option<(PathBuf, HeaderPrefixes)>,
+    /// Every section and what was done with it, for the report file
+    entries: Vec<ReportEntry>,
+}
+
+impl ChangeReport {
+    /// Number of largest changes listed
+    const LARGEST: usize = 5;
+
+    fn new(working_dir: &Path, options: &RestoreOptions) -> Self {
+        Self {
+            report_dir: options
+                .report
+                .then(|| (working_dir.to_path_buf(), options.prefixes.clone())),
+            ..Default::default()
+        }
+    }
+
+    fn record(&mut self, path: &str, existing: Option<&[u8]>, written: &[u8]) {
+        let action = match existing {
+            None => {
+                self.created.push((path.to_string(), written.len() as u64));
+                "created"
+            }
+            Some(existing) if existing == written => {
+                self.unchanged += 1;
+                "unchanged"
+            }
+            Some(existing) => {
+                self.overwritten.push((
+                    path.to_string(),
+                    written.len() as i64 - existing.len() as i64,
+                ));
+                "overwritten"
+            }
+        };
+        if let Some((dir, prefixes)) = &self.report_dir {
+            self.entries.push(ReportEntry {
+                section: prefixes.to_header(path),
+                target: crate::paths::from_slash(dir, path),
+                action,
+                reason: None,
+                sha256_before: existing.map(crate::stub::sha256_hex),
+                sha256_after: Some(crate::stub::sha256_hex(written)),
+            });
+        }
+    }
+
+    /// Notes a file a patch removed.
+    fn delete(&mut self, path: &str, existing: Option<&[u8]>) {
+        if let Some((dir, prefixes)) = &self.report_dir {
+            self.entries.push(ReportEntry {
+                section: prefixes.to_header(path),
+                target: crate::paths::from_slash(dir, path),
+                action: "deleted",
+                reason: None,
+                sha256_before: existing.map(crate::stub::sha256_hex),
+                sha256_after: None,
+            });
+        }
+    }
+
+    /// Notes a section that was not restored, and why.
+    fn skip(&mut self, path: &str, reason: &str) {
+        self.skipped += 1;
+        self.note_untouched(path, "skipped", reason.to_string());
+    }
+
+    /// Notes a file that could not be written.
+    fn fail(&mut self, path: &str, error: &anyhow::Error) {
+        self.note_untouched(path, "error", format!("{:#}", error));
+    }
+
+    fn note_untouched(&mut self, path: &str, action: &'static str, reason: String) {
+        let Some((dir, prefixes)) = &self.report_dir else {
+            return;
+        };
+        let target = crate::paths::from_slash(dir, path);
+        // The file is as it was, if there is one
+        let current = fs::read(&target)
+            .ok()
+            .filter(|_| !path.is_empty())
+            .map(|content| crate::stub::sha256_hex(&content));
+        self.entries.push(ReportEntry {
+            section: prefixes.to_header(path),
+            target,
+            action,
+            reason: Some(reason),
+            sha256_before: current.clone(),
+            sha256_after: current,
+        });
+    }
+
+    fn print(&self, working_dir: &Path) {
+        status!(
+            "{}",
+            tr!(
+                "restore.done",
+                working_dir.display(),
+                self.created.len(),
+                self.overwritten.len(),
+                self.unchanged,
+                self.skipped
+            )
+        );
+        for (path, delta) in &self.overwritten {
+            status!("  overwritten  {} ({})", path, signed_size(*delta));
+        }
+        let mut changes: Vec<(&str, i64, &str)> = self
+            .created
+            .iter()
+            .map(|(path, len)| (path.as_str(), *len as i64, " (created)"))
+            .chain(
+                self.overwritten
+                    .iter()
+                    .map(|(path, delta)| (path.as_str(), *delta, "")),
+            )
+            .collect();
+        // Overwritten files are listed above; new ones only show up here
+        if !self.created.is_empty() && changes.len() > 1 {
+            changes
+                .sort_by_key(|(path, delta, _)| (std::cmp::Reverse(delta.unsigned_abs()), *path));
+            status!("{}", tr!("restore.largest"));
+            for (path, delta, note) in changes.iter().take(Self::LARGEST) {
+                status!("  {:>10}  {}{}", signed_size(*delta), path, note);
+            }
+        }
+    }
+}
+
+/// A size change such as `+1.2 KiB` or `-40 B`.
+fn signed_size(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{}{}", sign, ByteSize(delta.unsigned_abs()))
+}
+
+/// `restore --flat`: writes every section into one directory under a sanitized name
+/// and records where each file came from.
+fn restore_flat(
+    working_dir: &Path,
+    absolute_input_path: &Path,
+    dir: &str,
+    options: &RestoreOptions,
+) -> Result<RestoreSummary> {
+    let bundle = options.open_bundle(absolute_input_path)?;
+    if has_marker(&bundle, PATCH_MARKER) || has_marker(&bundle, READONLY_MARKER) {
+        return Err(SheafyError::NotWholeFiles {
+            path: absolute_input_path.to_path_buf(),
+        })
+        .context("A bundle of patches or excerpts cannot be restored flat");
+    }
+    let flat_dir = working_dir.join(dir);
+    fs::create_dir_all(&flat_dir)
+        .with_context(|| format!("Failed to create directory: {}", flat_dir.display()))?;
+
+    let mut summary = RestoreSummary {
+        input: absolute_input_path.to_path_buf(),
+        ..Default::default()
+    };
+    // Flat name -> path in the bundle
+    let mut mapping: BTreeMap<String, String> = BTreeMap::new();
+    for section in bundle {
+        let mut section =
+            options.map_path(section.with_context(|| parse_failed(absolute_input_path))?);
+        if section.path.is_empty() {
+            eprintln!("Warning: Found block with empty filepath. Skipping.");
+            continue;
+        }
+        if section.preview {
+            status!("  Skipping preview: {} (not the whole file)", section.path);
+            continue;
+        }
+        if section.stub.is_some() {
+            status!(
+                "  Skipping stub: {} (content not in the bundle)",
+                section.path
+            );
+            continue;
+        }
+        if section.truncated {
+            match options.place_truncated(&section.path) {
+                Some(partial) => section.path = partial,
+                None => continue,
+            }
+        }
+        let path = section.path.clone();
+        let name = unique_flat_name(&path, &mapping);
+        let target_path = flat_dir.join(&name);
+        status!("  Restoring: {} -> {}", path, target_path.display());
+        if let Err(e) = fs::write(&target_path, section.into_file_content(options)?)
+            .with_context(|| format!("Failed to write {}", target_path.display()))
+        {
+            options.record_failure(&mut summary, &path, e)?;
+            continue;
+        }
+        summary.restored.push(PathBuf::from(dir).join(&name));
+        mapping.insert(name, path);
+    }
+
+    let map_path = flat_dir.join(FLAT_MAP_FILENAME);
+    fs::write(&map_path, serde_json::to_string_pretty(&mapping)? + "\n")
+        .with_context(|| format!("Failed to write {}", map_path.display()))?;
+    status!(
+        "\nRestore complete. {} file(s) written to {} (name mapping in {}).",
+        summary.restored.len(),
+        flat_dir.display(),
+        FLAT_MAP_FILENAME
+    );
+    Ok(summary)
+}
+
+/// `src/main.rs` -> `src__main.rs`, with characters that are invalid in file names on
+/// common platforms replaced, and a `-2`, `-3`, ... suffix if the name is taken.
+fn unique_flat_name(path: &str, taken: &BTreeMap<String, String>) -> String {
+    let flat: String = path
+        .trim_start_matches("./")
+        .replace('/', "__")
+        .chars()
+        .map(|c| match c {
+            '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let reserved = |name: &str| taken.contains_key(name) || name == FLAT_MAP_FILENAME;
+    if !reserved(&flat) {
+        return flat;
+    }
+    let (stem, ext) = match flat.rfind('.') {
+        Some(dot) if dot > 0 => flat.split_at(dot),
+        _ => (flat.as_str(), ""),
+    };
+    (2..)
+        .map(|n| format!("{}-{}{}", stem, n, ext))
+        .find(|name| !reserved(name))
+        .unwrap()
+}
+
+/// Applies every diff section of a `--format patch` bundle with `git apply`.
+fn restore_patches(
+    working_dir: &Path,
+    bundle: BundleReader<impl BufRead>,
+    input_path: &Path,
+    options: &RestoreOptions,
+) -> Result<RestoreSummary> {
+    let mut patch = String::new();
+    let mut summary = RestoreSummary {
+        input: input_path.to_path_buf(),
+        ..Default::default()
+    };
+    for section in bundle {
+        let section = section.with_context(|| parse_failed(input_path))?;
+        status!("  Patching: {}", section.path);
+        patch.push_str(&section.content);
+        summary.restored.push(PathBuf::from(section.path));
+    }
+
+    if summary.restored.is_empty() {
+        status!(
+            "Warning: No patch sections found in '{}'. Nothing applied.",
+            input_path.display()
+        );
+        return Ok(summary);
+    }
+
+    let patch_file =
+        tempfile::NamedTempFile::new().context("Failed to create temporary patch file")?;
+    fs::write(patch_file.path(), patch).context("Failed to write temporary patch file")?;
+    // Read before applying, for the report's hashes
+    let before: Vec<Option<Vec<u8>>> = summary
+        .restored
+        .iter()
+        .map(|path| {
+            options
+                .report
+                .then(|| fs::read(working_dir.join(path)).ok())
+                .flatten()
+        })
+        .collect();
+    crate::git::apply_patch(working_dir, patch_file.path())
+        .with_context(|| format!("Failed to apply patches from {}", input_path.display()))?;
+    let mut changes = ChangeReport::new(working_dir, options);
+    for (path, before) in summary.restored.iter().zip(&before) {
+        let path = crate::paths::to_slash(path);
+        match fs::read(working_dir.join(&path)) {
+            Ok(after) => changes.record(&path, before.as_deref(), &after),
+            Err(_) => changes.delete(&path, before.as_deref()),
+        }
+    }
+    summary.report = changes.entries;
+
+    status!(
+        "\nRestore complete. {} patch(es) applied in {}.",
+        summary.restored.len(),
+        working_dir.display()
+    );
+    Ok(summary)
+}
@@ -0,0 +1,114 @@
+//! `sheafy new <template> <dir>`: creates a project from a template bundle.
+//!
+//! Templates are bundles named `<template>.md` in the registry set by
+//! `template_registry`, a local directory or a git repository (cloned shallowly for
+//! each run). A path to a bundle works as a template too. The bundle is restored into
+//! the new directory with its template variables filled in (see [`crate::vars`]); `name`
+//! defaults to the name of the directory.
+
+use crate::cli::{NewArgs, OnConflict};
+use crate::config::Config;
+use crate::restore::{restore_bundle, RestoreOptions};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Variable set to the name of the new directory unless given
+const NAME_VAR: &str = "name";
+
+pub fn run_new(config: &Config, args: &NewArgs) -> Result<()> {
+    let target = PathBuf::from(&args.dir);
+    if target.exists()
+        && fs::read_dir(&target)
+            .with_context(|| format!("Failed to read '{}'", target.display()))?
+            .next()
+            .is_some()
+    {
+        bail!(
+            "'{}' already exists and is not empty; `sheafy new` only creates projects",
+            target.display()
+        );
+    }
+    // Kept alive until the bundle is restored
+    let clone;
+    let bundle = if Path::new(&args.template).is_file() {
+        PathBuf::from(&args.template)
+    } else {
+        let Some(registry) = &config.sheafy.template_registry else {
+            bail!(
+                "No template '{}': it is not a file and no template_registry is configured",
+                args.template
+            );
+        };
+        let dir = if is_git_url(registry) {
+            status!("Fetching templates from {}", registry);
+            clone = tempfile::tempdir().context("Failed to create a temporary directory")?;
+            crate::git::clone_shallow(registry, clone.path())?;
+            clone.path().to_path_buf()
+        } else {
+            PathBuf::from(registry)
+        };
+        find_template(&dir, &args.template, registry)?
+    };
+
+    let mut vars = config.sheafy.restore.vars.clone();
+    if let Some(name) = target.file_name() {
+        vars.insert(NAME_VAR.to_string(), name.to_string_lossy().into_owned());
+    }
+    fs::create_dir_all(&target)
+        .with_context(|| format!("Failed to create '{}'", target.display()))?;
+    let target = crate::paths::absolute(&target, Path::new("."));
+    let mut options = RestoreOptions::from_config(config, &target)?;
+    options.vars = crate::vars::merge(&vars, &args.vars)?;
+    let bundle = bundle
+        .canonicalize()
+        .with_context(|| format!("Failed to read '{}'", bundle.display()))?;
+    let summary = restore_bundle(&target, &bundle, OnConflict::Overwrite, &options)?;
+    if !summary.failures.is_empty() {
+        bail!(
+            "{} file(s) of the template could not be written",
+            summary.failures.len()
+        );
+    }
+    status!(
+        "Created {} from template '{}' ({} file(s))",
+        target.display(),
+        args.template,
+        summary.restored.len()
+    );
+    Ok(())
+}
+
+/// Whether a registry names a git repository rather than a local directory.
+fn is_git_url(registry: &str) -> bool {
+    registry.contains("://") || registry.starts_with("git@") || registry.ends_with(".git")
+}
+
+/// `<name>.md` in the registry directory, or an error listing the templates there.
+fn find_template(dir: &Path, name: &str, registry: &str) -> Result<PathBuf> {
+    let path = dir.join(format!("{}.md", name));
+    if path.is_file() {
+        return Ok(path);
+    }
+    let mut known: Vec<String> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read template registry '{}'", registry))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "md" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    known.sort();
+    bail!(
+        "No template '{}' in '{}' (templates: {})",
+        name,
+        registry,
+        if known.is_empty() {
+            "none".to_string()
+        } else {
+            known.join(", ")
+        }
+    )
+}
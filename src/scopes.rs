@@ -0,0 +1,246 @@
+//! Directory-scoped settings: `[sheafy.dir."path"]` tables and nested `sheafy.toml` files.
+//!
+//! The subtrees of a monorepo often need different rules. Settings given for a
+//! directory apply to the files below it, on top of those of enclosing directories:
+//! its `ignore_patterns` (relative to the directory) leave out more files, and a
+//! `!pattern` there brings back a file an enclosing directory's patterns left out; its
+//! `priority_patterns` rank after the project-wide ones; its `language_hints` win over
+//! those of enclosing directories. A `sheafy.toml` below the working directory holds
+//! the same keys in its `[sheafy]` table, adds to a `[sheafy.dir]` table for the same
+//! directory, and is itself never bundled.
+
+use crate::config::{Config, DirConfig, CONFIG_FILENAME};
+use crate::error::SheafyError;
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A nested `sheafy.toml`; keys other than those of [`DirConfig`] are ignored.
+#[derive(Deserialize)]
+struct NestedConfig {
+    #[serde(default)]
+    sheafy: DirConfig,
+}
+
+#[derive(Debug)]
+struct Scope {
+    /// Relative to the working directory; empty for the project-wide settings
+    dir: PathBuf,
+    settings: DirConfig,
+    ignore: Option<Gitignore>,
+    /// One matcher per priority pattern line
+    priority: Vec<Gitignore>,
+}
+
+/// The settings of every configured directory, shallowest first.
+#[derive(Debug)]
+pub struct Scopes {
+    scopes: Vec<Scope>,
+    /// Nested config files read, relative to the working directory
+    config_files: Vec<PathBuf>,
+}
+
+impl Scopes {
+    /// The project-wide settings and the `[sheafy.dir]` tables of `config`.
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Self> {
+        let mut scopes = Self {
+            scopes: Vec::new(),
+            config_files: Vec::new(),
+        };
+        scopes.scopes.push(Scope::new(
+            working_dir,
+            PathBuf::new(),
+            DirConfig {
+                ignore_patterns: None,
+                priority_patterns: config.sheafy.priority_patterns.clone(),
+                language_hints: config.sheafy.language_hints.clone(),
+            },
+        )?);
+        for (dir, settings) in &config.sheafy.dir {
+            let dir = normalize_dir(dir);
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+            scopes.add(working_dir, dir, settings.clone())?;
+        }
+        Ok(scopes)
+    }
+
+    /// Reads the `sheafy.toml` files of the directories holding walked `files` (found
+    /// even where ignore rules leave them out), shallowest first, and drops them and
+    /// every file their settings or those of enclosing directories leave out from `files`.
+    pub fn apply(&mut self, working_dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let dirs: BTreeSet<&Path> = files
+            .iter()
+            .flat_map(|rel_path| rel_path.ancestors().skip(1))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+        let mut nested: Vec<PathBuf> = dirs
+            .into_iter()
+            .map(|dir| dir.join(CONFIG_FILENAME))
+            .filter(|rel_path| working_dir.join(rel_path).is_file())
+            .collect();
+        nested.sort_by_key(|rel_path| rel_path.components().count());
+        for rel_path in nested {
+            if self.is_ignored(&rel_path) {
+                continue;
+            }
+            let path = working_dir.join(&rel_path);
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let NestedConfig { sheafy: settings } =
+                toml::from_str(&text).context(SheafyError::ConfigInvalid {
+                    paths: vec![path.clone()],
+                })?;
+            let dir = rel_path.parent().expect("nested config").to_path_buf();
+            self.add(working_dir, dir, settings)?;
+            self.config_files.push(rel_path);
+        }
+        if self.scopes.len() > 1 {
+            status!(
+                "Applying the settings of {} director{}.",
+                self.scopes.len() - 1,
+                if self.scopes.len() == 2 { "y" } else { "ies" }
+            );
+        }
+        files.retain(|rel_path| !self.is_config_file(rel_path) && !self.is_ignored(rel_path));
+        Ok(())
+    }
+
+    /// Adds settings for `dir`, merging them into those already given for it.
+    fn add(&mut self, working_dir: &Path, dir: PathBuf, mut settings: DirConfig) -> Result<()> {
+        if let Some(i) = self.scopes.iter().position(|scope| scope.dir == dir) {
+            let mut merged = self.scopes.remove(i).settings;
+            merge(&mut merged, settings);
+            settings = merged;
+        }
+        let scope = Scope::new(working_dir, dir, settings)?;
+        let key = |scope: &Scope| (scope.dir.components().count(), scope.dir.clone());
+        let at = self
+            .scopes
+            .iter()
+            .position(|other| key(other) > key(&scope))
+            .unwrap_or(self.scopes.len());
+        self.scopes.insert(at, scope);
+        Ok(())
+    }
+
+    pub fn is_config_file(&self, rel_path: &Path) -> bool {
+        self.config_files.iter().any(|p| p == rel_path)
+    }
+
+    /// Whether the `ignore_patterns` of the directories enclosing `rel_path` leave it
+    /// out. The deepest directory with a pattern matching the file decides.
+    pub fn is_ignored(&self, rel_path: &Path) -> bool {
+        for scope in self.scopes.iter().rev() {
+            let (Some(ignore), Ok(below)) = (&scope.ignore, rel_path.strip_prefix(&scope.dir))
+            else {
+                continue;
+            };
+            let matched = ignore.matched_path_or_any_parents(below, false);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Rank of `rel_path` by `priority_patterns`: project-wide patterns first, then those
+    /// of each directory, shallowest first; `None` if no pattern matches.
+    pub fn priority(&self, rel_path: &Path) -> Option<usize> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.priority.iter().map(move |m| (&scope.dir, m)))
+            .position(|(dir, matcher)| {
+                rel_path.strip_prefix(dir).is_ok_and(|below| {
+                    matcher
+                        .matched_path_or_any_parents(below, false)
+                        .is_ignore()
+                })
+            })
+    }
+
+    pub fn has_priorities(&self) -> bool {
+        self.scopes.iter().any(|scope| !scope.priority.is_empty())
+    }
+
+    /// The configured code fence language for `rel_path`'s extension, from the deepest
+    /// directory that has one.
+    pub fn language_hint(&self, rel_path: &Path) -> Option<&str> {
+        let ext = rel_path.extension()?.to_str()?;
+        self.scopes
+            .iter()
+            .rev()
+            .filter(|scope| rel_path.starts_with(&scope.dir))
+            .find_map(|scope| scope.settings.language_hints.get(ext))
+            .map(String::as_str)
+    }
+}
+
+impl Scope {
+    fn new(working_dir: &Path, dir: PathBuf, settings: DirConfig) -> Result<Self> {
+        let root = working_dir.join(&dir);
+        let ignore = match settings.ignore_patterns.as_deref() {
+            Some(patterns) => {
+                let mut builder = GitignoreBuilder::new(&root);
+                for line in patterns.lines() {
+                    builder.add_line(None, line).with_context(|| {
+                        format!("Invalid ignore pattern for {}: '{}'", dir.display(), line)
+                    })?;
+                }
+                Some(builder.build()?)
+            }
+            None => None,
+        };
+        let mut priority = Vec::new();
+        for line in settings.priority_patterns.as_deref().unwrap_or("").lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut builder = GitignoreBuilder::new(&root);
+            builder
+                .add_line(None, line)
+                .with_context(|| format!("Invalid priority pattern: '{}'", line))?;
+            priority.push(builder.build()?);
+        }
+        Ok(Self {
+            dir,
+            settings,
+            ignore,
+            priority,
+        })
+    }
+}
+
+/// Adds `more` to `settings`: pattern lines are appended, language hints override.
+fn merge(settings: &mut DirConfig, more: DirConfig) {
+    for (field, extra) in [
+        (&mut settings.ignore_patterns, more.ignore_patterns),
+        (&mut settings.priority_patterns, more.priority_patterns),
+    ] {
+        if let Some(extra) = extra {
+            *field = Some(match field.take() {
+                Some(lines) => format!("{}\n{}", lines.trim_end(), extra),
+                None => extra,
+            });
+        }
+    }
+    settings.language_hints.extend(more.language_hints);
+}
+
+/// `./src/tests/` -> `src/tests`
+fn normalize_dir(dir: &str) -> PathBuf {
+    dir.trim()
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
+}
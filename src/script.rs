@@ -0,0 +1,379 @@
+//! `--format shell` and `--format powershell`: bundles that unpack themselves.
+//!
+//! Someone without sheafy can recreate the bundled tree by running the script in an
+//! empty directory. The POSIX script writes each file with a quoted heredoc (a file
+//! without a final newline goes through `printf` instead), the PowerShell script with a
+//! literal here-string (or base64, for content a here-string cannot hold). Scripts only
+//! carry whole text files: stubs, handlers, previews and the other Markdown-only
+//! settings don't apply, and binary files are skipped. `sheafy restore` reads both kinds
+//! back by converting them to a Markdown bundle first.
+
+use crate::header;
+use crate::parser::fence_for;
+use crate::restore::{NO_EOL_MARKER, VERSION_MARKER};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Second line of a POSIX script, first of a PowerShell one; tells restore what it reads
+pub const SCRIPT_MARKER: &str = "# sheafy:script v1";
+
+/// Heredoc delimiter, numbered when a file has a line equal to it
+const DELIMITER: &str = "SHEAFY_EOF";
+
+/// Writes a file without a final newline
+const PRINTF: &str = "printf '%s' ";
+
+/// Characters PowerShell takes for a single quote
+const PS_QUOTES: &[char] = &['\'', '\u{2018}', '\u{2019}', '\u{201A}', '\u{201B}'];
+
+const PS_PREAMBLE: &str = r#"$ErrorActionPreference = 'Stop'
+function Write-SheafyFile([string]$Path, [bool]$FinalNewline, [string]$Text) {
+    if ($FinalNewline) { $Text += "`n" }
+    Write-SheafyBytes $Path ([Text.Encoding]::UTF8.GetBytes($Text))
+}
+function Write-SheafyBytes([string]$Path, [byte[]]$Bytes) {
+    $Full = Join-Path (Get-Location) $Path
+    [void][IO.Directory]::CreateDirectory((Split-Path -Parent $Full))
+    [IO.File]::WriteAllBytes($Full, $Bytes)
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Posix,
+    PowerShell,
+}
+
+/// Writes `files` (relative to `working_dir`) as a script to `output`; returns the files
+/// it holds.
+pub fn write_script(
+    shell: Shell,
+    working_dir: &Path,
+    files: &[PathBuf],
+    output: &Path,
+) -> Result<Vec<PathBuf>> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
+    }
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create output file: {}", output.display()))?;
+    let mut writer = BufWriter::new(file);
+    let run = match shell {
+        Shell::Posix => {
+            writeln!(writer, "#!/bin/sh\n{}", SCRIPT_MARKER)?;
+            "sh"
+        }
+        Shell::PowerShell => {
+            // Windows PowerShell reads scripts without a byte order mark as ANSI
+            writeln!(writer, "\u{FEFF}{}", SCRIPT_MARKER)?;
+            "powershell -ExecutionPolicy Bypass -File"
+        }
+    };
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    writeln!(
+        writer,
+        "# Recreates the bundled files below the current directory: {} {}",
+        run, name
+    )?;
+    match shell {
+        Shell::Posix => writeln!(writer, "set -e")?,
+        Shell::PowerShell => writer.write_all(PS_PREAMBLE.as_bytes())?,
+    }
+
+    let mut bundled = Vec::new();
+    let mut dirs = BTreeSet::new();
+    for rel_path in files {
+        let path = rel_path
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        let full_path = working_dir.join(rel_path);
+        let bytes = match fs::read(&full_path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Could not open file '{}': {}. Skipping.",
+                    full_path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        if crate::sniff::is_binary(&bytes) {
+            eprintln!(
+                "Warning: Skipping binary file '{}'; scripts only carry text files.",
+                full_path.display()
+            );
+            continue;
+        }
+        let Ok(content) = String::from_utf8(bytes) else {
+            eprintln!(
+                "Warning: Could not read file '{}': not valid UTF-8. Skipping.",
+                full_path.display()
+            );
+            continue;
+        };
+        status!("{}", tr!("bundle.adding", path));
+        writeln!(writer)?;
+        match shell {
+            Shell::Posix => {
+                if let Some((dir, _)) = path.rsplit_once('/') {
+                    if dirs.insert(dir.to_string()) {
+                        writeln!(writer, "mkdir -p {}", sh_quote(dir))?;
+                    }
+                }
+                write_posix(&mut writer, &path, &content)?;
+            }
+            Shell::PowerShell => write_powershell(&mut writer, &path, &content)?,
+        }
+        bundled.push(rel_path.clone());
+    }
+    writer.flush()?;
+    drop(writer);
+
+    #[cfg(unix)]
+    if shell == Shell::Posix {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", output.display()))?;
+    }
+    Ok(bundled)
+}
+
+fn write_posix(writer: &mut impl Write, path: &str, content: &str) -> Result<()> {
+    if !content.is_empty() && !content.ends_with('\n') {
+        // A heredoc always ends with a newline
+        writeln!(
+            writer,
+            "{}{} > {}",
+            PRINTF,
+            sh_quote(content),
+            sh_quote(path)
+        )?;
+        return Ok(());
+    }
+    let mut delimiter = DELIMITER.to_string();
+    let mut n = 1;
+    while content.lines().any(|line| line == delimiter) {
+        n += 1;
+        delimiter = format!("{}_{}", DELIMITER, n);
+    }
+    writeln!(writer, "cat > {} <<'{}'", sh_quote(path), delimiter)?;
+    writer.write_all(content.as_bytes())?;
+    writeln!(writer, "{}", delimiter)?;
+    Ok(())
+}
+
+fn write_powershell(writer: &mut impl Write, path: &str, content: &str) -> Result<()> {
+    // A here-string ends at the first line starting with a quote and `@`, and
+    // PowerShell may not keep its carriage returns
+    let fits = !content.contains('\r')
+        && !content.lines().any(|line| {
+            line.strip_prefix(PS_QUOTES)
+                .is_some_and(|l| l.starts_with('@'))
+        });
+    if !fits {
+        writeln!(
+            writer,
+            "Write-SheafyBytes {} ([Convert]::FromBase64String('{}'))",
+            ps_quote(path),
+            crate::base64::encode(content.as_bytes())
+        )?;
+        return Ok(());
+    }
+    let (text, final_newline) = match content.strip_suffix('\n') {
+        Some(text) => (text, true),
+        None => (content, false),
+    };
+    writeln!(
+        writer,
+        "Write-SheafyFile {} ${} @'\n{}\n'@",
+        ps_quote(path),
+        final_newline,
+        text
+    )?;
+    Ok(())
+}
+
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+fn ps_quote(text: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in text.chars() {
+        if PS_QUOTES.contains(&c) {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Whether a file starting with `head` is a script written by [`write_script`].
+pub fn is_script(head: &[u8]) -> bool {
+    let head = head.strip_prefix("\u{FEFF}".as_bytes()).unwrap_or(head);
+    let head = match head.strip_prefix(b"#!") {
+        Some(rest) => match rest.iter().position(|b| *b == b'\n') {
+            Some(end) => &rest[end + 1..],
+            None => return false,
+        },
+        None => head,
+    };
+    head.starts_with(SCRIPT_MARKER.as_bytes())
+}
+
+/// The files a script writes, in order, as `(path, content)`.
+pub fn parse_script(script: &str) -> Result<Vec<(String, String)>> {
+    let script = script.strip_prefix('\u{FEFF}').unwrap_or(script);
+    let mut files = Vec::new();
+    let mut rest = script;
+    let mut line_no = 0;
+    while !rest.is_empty() {
+        let line_start = rest;
+        let (line, after) = rest.split_once('\n').unwrap_or((rest, ""));
+        line_no += 1;
+        rest = after;
+        let unexpected = || format!("Unexpected line {} of the script: {}", line_no, line);
+        if let Some(command) = line.strip_prefix("cat > ") {
+            let (path, redirect) = sh_word(command).with_context(unexpected)?;
+            let delimiter = redirect
+                .strip_prefix("<<'")
+                .and_then(|d| d.strip_suffix('\''))
+                .with_context(unexpected)?;
+            let mut content = String::new();
+            loop {
+                let Some((body_line, after)) = rest.split_once('\n') else {
+                    bail!("The script ends inside the heredoc of '{}'", path);
+                };
+                line_no += 1;
+                rest = after;
+                if body_line == delimiter {
+                    break;
+                }
+                content.push_str(body_line);
+                content.push('\n');
+            }
+            files.push((path, content));
+        } else if line.starts_with(PRINTF) {
+            // The quoted content may span lines
+            let (content, after) = sh_word(&line_start[PRINTF.len()..]).with_context(unexpected)?;
+            let (path, after) = after
+                .strip_prefix("> ")
+                .and_then(sh_word)
+                .filter(|(_, after)| after.is_empty() || after.starts_with('\n'))
+                .with_context(unexpected)?;
+            line_no += content.matches('\n').count();
+            rest = after.strip_prefix('\n').unwrap_or(after);
+            files.push((path, content));
+        } else if let Some(command) = line.strip_prefix("Write-SheafyFile ") {
+            let (path, args) = ps_word(command).with_context(unexpected)?;
+            let final_newline = match args {
+                "$true @'" => true,
+                "$false @'" => false,
+                _ => bail!("{}", unexpected()),
+            };
+            let mut lines = Vec::new();
+            loop {
+                let Some((body_line, after)) = rest.split_once('\n') else {
+                    bail!("The script ends inside the here-string of '{}'", path);
+                };
+                line_no += 1;
+                rest = after;
+                if body_line == "'@" {
+                    break;
+                }
+                lines.push(body_line);
+            }
+            let mut content = lines.join("\n");
+            if final_newline {
+                content.push('\n');
+            }
+            files.push((path, content));
+        } else if let Some(command) = line.strip_prefix("Write-SheafyBytes ") {
+            let (path, args) = ps_word(command).with_context(unexpected)?;
+            let content = args
+                .strip_prefix("([Convert]::FromBase64String('")
+                .and_then(|a| a.strip_suffix("'))"))
+                .and_then(crate::base64::decode)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .with_context(unexpected)?;
+            files.push((path, content));
+        }
+    }
+    Ok(files)
+}
+
+/// A Markdown bundle holding the files a script writes, for restore to read.
+pub fn to_bundle(script: &str) -> Result<String> {
+    let mut bundle = format!("{}\n", VERSION_MARKER);
+    for (path, content) in parse_script(script)? {
+        let fence = fence_for(&content);
+        bundle.push_str(&format!("\n## {}\n{}\n", header::escape(&path), fence));
+        bundle.push_str(&content);
+        let missing_eol = !content.ends_with('\n');
+        if missing_eol {
+            bundle.push('\n');
+        }
+        bundle.push_str(&format!("{}\n", fence));
+        if missing_eol {
+            bundle.push_str(&format!("{}\n", NO_EOL_MARKER));
+        }
+    }
+    Ok(bundle)
+}
+
+/// Reads one single-quoted POSIX word (`'...'` runs and `\'`) from the start of `text`;
+/// returns it and the text after the space that ends it.
+fn sh_word(text: &str) -> Option<(String, &str)> {
+    let mut word = String::new();
+    let mut chars = text.char_indices();
+    loop {
+        match chars.next() {
+            Some((_, '\'')) => loop {
+                match chars.next()? {
+                    (_, '\'') => break,
+                    (_, c) => word.push(c),
+                }
+            },
+            Some((_, '\\')) => word.push(chars.next()?.1),
+            Some((i, ' ')) => return Some((word, &text[i + 1..])),
+            Some((i, '\n')) => return Some((word, &text[i..])),
+            None => return Some((word, "")),
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Reads one single-quoted PowerShell word from the start of `text`, where a doubled
+/// quote stands for one; returns it and the text after the space that ends it.
+fn ps_word(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.char_indices().peekable();
+    if !PS_QUOTES.contains(&chars.next()?.1) {
+        return None;
+    }
+    let mut word = String::new();
+    while let Some((i, c)) = chars.next() {
+        if !PS_QUOTES.contains(&c) {
+            word.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some((_, next)) if PS_QUOTES.contains(next) => {
+                word.push(c);
+                chars.next();
+            }
+            _ => {
+                let after = &text[i + c.len_utf8()..];
+                return Some((word, after.strip_prefix(' ').unwrap_or(after)));
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,198 @@
+//! `sheafy config set` and `sheafy config edit`: change a config file from the command
+//! line or in an editor. Also saves the selections made in `sheafy tui` as profiles.
+//!
+//! `set` edits the TOML document in place, so comments and layout survive, and writes
+//! nothing unless the result still loads: unknown keys and values of the wrong type are
+//! refused. Both work on a config that currently fails to load, to repair it.
+
+use crate::config::{
+    full_key, global_config_path, Config, CONFIG_FILENAME, DEFAULT_CONFIG_CONTENT,
+};
+use crate::error::SheafyError;
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+/// The file `set` and `edit` work on: `sheafy.toml`, or the user-wide config.
+fn target(global: bool) -> Result<PathBuf> {
+    if global {
+        global_config_path().ok_or_else(|| SheafyError::ConfigNotFound { path: None }.into())
+    } else {
+        Ok(PathBuf::from(CONFIG_FILENAME))
+    }
+}
+
+/// The config file's text, or the `sheafy init` template for a file not created yet.
+fn read_or_template(path: &Path) -> Result<String> {
+    if path.exists() {
+        fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))
+    } else {
+        Ok(DEFAULT_CONFIG_CONTENT.to_string())
+    }
+}
+
+fn write(path: &Path, text: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(path, text)
+        .with_context(|| format!("Failed to write config file: {}", path.display()))
+}
+
+/// Fails unless `text` loads as a config with no unknown keys.
+fn validate(text: &str) -> Result<()> {
+    let mut unknown = Vec::new();
+    serde_ignored::deserialize::<_, _, Config>(toml::Deserializer::new(text), |key| {
+        unknown.push(key.to_string())
+    })
+    .map_err(|e| anyhow::anyhow!("{}", e.to_string().trim()))?;
+    if !unknown.is_empty() {
+        bail!("unknown config key(s): {}", unknown.join(", "));
+    }
+    Ok(())
+}
+
+/// Parses a command-line value as TOML, falling back to a plain string.
+fn parse_value(text: &str) -> Value {
+    text.parse::<Value>().unwrap_or_else(|_| Value::from(text))
+}
+
+/// The table at `segments` below `root`, created (without a header of its own until it
+/// holds values) where missing.
+fn table_mut<'a>(root: &'a mut Table, segments: &[&str]) -> Result<&'a mut Table> {
+    let mut table = root;
+    for segment in segments {
+        let entry = table.entry(segment).or_insert_with(|| {
+            let mut new = Table::new();
+            new.set_implicit(true);
+            Item::Table(new)
+        });
+        table = match entry.as_table_mut() {
+            Some(table) => table,
+            None => bail!("'{}' is not a table", segment),
+        };
+    }
+    Ok(table)
+}
+
+pub fn run_set(key: &str, value: &str, global: bool) -> Result<()> {
+    let path = target(global)?;
+    let key = full_key(key);
+    let mut doc: DocumentMut = read_or_template(&path)?
+        .parse()
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+    let segments: Vec<&str> = key.split('.').collect();
+    let (last, tables) = segments.split_last().expect("split yields a segment");
+    let table =
+        table_mut(doc.as_table_mut(), tables).with_context(|| format!("Cannot set {}", key))?;
+    let value = parse_value(value);
+    // Keep an existing value's comments and spacing
+    match table.get_mut(last).and_then(Item::as_value_mut) {
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(last, toml_edit::value(value));
+        }
+    }
+    let text = doc.to_string();
+    validate(&text).with_context(|| format!("Not setting {}", key))?;
+    write(&path, &text)?;
+    status!("Set {} in {}.", key, path.display());
+    Ok(())
+}
+
+pub fn run_edit(global: bool) -> Result<()> {
+    let path = target(global)?;
+    if !path.exists() {
+        write(&path, DEFAULT_CONFIG_CONTENT)?;
+        status!("Created {} from the default template.", path.display());
+    }
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .or_else(|| {
+            std::env::var("EDITOR")
+                .ok()
+                .filter(|e| !e.trim().is_empty())
+        })
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    // Editors are often configured with arguments (`code --wait`)
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    status!("Opening {} in {}.", path.display(), editor);
+    let exit = Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to run editor '{}' (set $VISUAL or $EDITOR)", editor))?;
+    if !exit.success() {
+        bail!("Editor '{}' exited with {}", editor, exit);
+    }
+    validate(&read_or_template(&path)?).with_context(|| {
+        format!(
+            "{} does not load after editing; run `sheafy config edit{}` again to fix it",
+            path.display(),
+            if global { " --global" } else { "" }
+        )
+    })?;
+    status!("{} is valid.", path.display());
+    Ok(())
+}
+
+/// Saves `include` and `exclude` patterns as profile `name` in `sheafy.toml`. A profile
+/// of that name keeps its other options; its patterns are replaced.
+pub fn save_profile(name: &str, include: &[String], exclude: &[String]) -> Result<PathBuf> {
+    let path = target(false)?;
+    save_profile_at(&path, name, include, exclude)?;
+    Ok(path)
+}
+
+/// [`save_profile`] into the config file at `path`.
+pub fn save_profile_at(
+    path: &Path,
+    name: &str,
+    include: &[String],
+    exclude: &[String],
+) -> Result<()> {
+    let mut doc: DocumentMut = read_or_template(path)?
+        .parse()
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+    let is_new = doc
+        .get("sheafy")
+        .and_then(|sheafy| sheafy.get("profiles"))
+        .and_then(|profiles| profiles.get(name))
+        .is_none();
+    // Comments after the last table (the template's commented-out `[sheafy]` keys) stay
+    // above a new table instead of ending up in it
+    let trailing = doc.trailing().as_str().unwrap_or_default().to_string();
+    if is_new {
+        doc.set_trailing("");
+    }
+    let profile = table_mut(doc.as_table_mut(), &["sheafy", "profiles", name])
+        .with_context(|| format!("Cannot save profile '{}'", name))?;
+    if is_new && !trailing.trim().is_empty() {
+        profile
+            .decor_mut()
+            .set_prefix(format!("{}\n\n", trailing.trim_end()));
+    }
+    for (key, patterns) in [("include", include), ("exclude", exclude)] {
+        if patterns.is_empty() {
+            profile.remove(key);
+        } else {
+            let array: toml_edit::Array = patterns.iter().map(String::as_str).collect();
+            profile.insert(key, toml_edit::value(array));
+        }
+    }
+    let text = doc.to_string();
+    validate(&text).with_context(|| format!("Not saving profile '{}'", name))?;
+    write(path, &text)
+}
@@ -0,0 +1,78 @@
+//! Content sniffing for files whose extension says little or nothing:
+//! well-known filenames, shebang lines and a text/binary heuristic.
+
+use std::path::Path;
+
+/// How many leading bytes are inspected by the binary heuristic.
+const SNIFF_LEN: usize = 8192;
+
+/// Returns true if the data looks binary (contains a NUL byte near the start),
+/// the same heuristic git and grep use.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(SNIFF_LEN)].contains(&0)
+}
+
+/// Picks the code fence language for a file, looking at its extension first,
+/// then its name, then a shebang line.
+pub fn language_hint(path: &Path, content: &str) -> &'static str {
+    let by_extension = path
+        .extension()
+        .and_then(|os| os.to_str())
+        .map(crate::restore::get_language_hint)
+        .unwrap_or("");
+    if !by_extension.is_empty() {
+        return by_extension;
+    }
+    let by_name = path
+        .file_name()
+        .and_then(|os| os.to_str())
+        .map(filename_hint)
+        .unwrap_or("");
+    if !by_name.is_empty() {
+        return by_name;
+    }
+    shebang_hint(content)
+}
+
+fn filename_hint(name: &str) -> &'static str {
+    match name {
+        "Dockerfile" | "Containerfile" => "dockerfile",
+        "Makefile" | "GNUmakefile" | "makefile" => "makefile",
+        "CMakeLists.txt" => "cmake",
+        "Jenkinsfile" => "groovy",
+        "Gemfile" | "Rakefile" | "Vagrantfile" | "Podfile" => "ruby",
+        "Justfile" | "justfile" => "just",
+        ".bashrc" | ".bash_profile" | ".profile" | ".zshrc" | ".envrc" => "bash",
+        ".gitignore" | ".dockerignore" | ".gitattributes" => "gitignore",
+        ".editorconfig" => "ini",
+        _ if name.starts_with("Dockerfile.") => "dockerfile",
+        _ => "",
+    }
+}
+
+/// Maps a `#!` interpreter line to a language hint.
+fn shebang_hint(content: &str) -> &'static str {
+    let Some(line) = content.lines().next().and_then(|l| l.strip_prefix("#!")) else {
+        return "";
+    };
+    let mut parts = line.split_whitespace();
+    let mut interpreter = parts.next().unwrap_or("").rsplit('/').next().unwrap_or("");
+    if interpreter == "env" {
+        // `#!/usr/bin/env -S deno run` style: skip flags
+        interpreter = parts.find(|p| !p.starts_with('-')).unwrap_or("");
+    }
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    match interpreter {
+        "sh" | "bash" | "dash" | "ksh" | "zsh" | "ash" => "bash",
+        "python" | "pypy" => "python",
+        "node" | "nodejs" => "javascript",
+        "deno" | "ts-node" | "bun" => "typescript",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "php" => "php",
+        "lua" => "lua",
+        "fish" => "fish",
+        "pwsh" => "powershell",
+        _ => "",
+    }
+}
@@ -0,0 +1,91 @@
+//! The `.sheafy/` directory in the working directory holds sheafy's own files: the
+//! bundled paths (`state.json`), the metrics history, the daemon's socket, the last
+//! `ask` reply, and user-authored extras. It is never bundled, and features create it
+//! only when they have something to store.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const STATE_DIR: &str = ".sheafy";
+
+/// What sheafy remembers about the project between runs, in `.sheafy/state.json`.
+pub const STATE_FILE: &str = "state.json";
+
+/// Entries of `.sheafy/` that sheafy regenerates on demand and `clean` removes, besides
+/// the socket of a daemon that is no longer running. Everything else in the directory
+/// is left alone.
+pub const GENERATED_ENTRIES: &[&str] = &[crate::ask::REPLY_FILENAME];
+
+pub fn state_dir(working_dir: &Path) -> PathBuf {
+    working_dir.join(STATE_DIR)
+}
+
+/// Generated entries currently present in `.sheafy/`.
+pub fn generated_entries(working_dir: &Path) -> Vec<PathBuf> {
+    let dir = state_dir(working_dir);
+    let socket = dir.join(crate::daemon::SOCKET_NAME);
+    let mut entries: Vec<PathBuf> = GENERATED_ENTRIES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.exists())
+        .collect();
+    if crate::daemon::is_stale_socket(&socket) {
+        entries.push(socket);
+    }
+    entries
+}
+
+/// Contents of `.sheafy/state.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// Every path (relative, `/` separators, NFC) a bundle of this project has held, so
+    /// restore can tell files it knows from paths it never bundled
+    pub bundled_paths: BTreeSet<String>,
+}
+
+impl State {
+    /// The recorded state, or None if nothing was recorded yet.
+    pub fn load(working_dir: &Path) -> Result<Option<Self>> {
+        let path = state_dir(working_dir).join(STATE_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, working_dir: &Path) -> Result<()> {
+        let dir = state_dir(working_dir);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        let path = dir.join(STATE_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Adds `files` (relative to `working_dir`) to the bundled paths.
+    pub fn record_bundled(working_dir: &Path, files: &[PathBuf]) -> Result<()> {
+        let mut state = Self::load(working_dir)?.unwrap_or_default();
+        let before = state.bundled_paths.len();
+        state.bundled_paths.extend(files.iter().map(|file| {
+            crate::header::normalize(
+                &file
+                    .to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/"),
+            )
+        }));
+        if state.bundled_paths.len() == before {
+            return Ok(());
+        }
+        state.save(working_dir)
+    }
+}
@@ -0,0 +1,269 @@
+//! Bundle size metrics: files, bytes and tokens per top-level directory.
+//!
+//! Every Markdown bundle of the whole project appends its metrics to
+//! `.sheafy/metrics.jsonl`, one JSON object per line, so the growth of a prompt can be
+//! followed over time. `sheafy stats` measures a bundle, `--compare` shows what grew
+//! between two bundles, directory by directory, and `--history` lists the recorded runs.
+
+use crate::cli::StatsArgs;
+use crate::config::{ByteSize, Config};
+use crate::restore::parse_sections;
+use crate::tokens::Counter;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::Path,
+};
+
+/// The metrics history in `.sheafy/`
+pub const METRICS_FILE: &str = "metrics.jsonl";
+
+/// Key of the files at the top of the bundle, outside any directory
+const ROOT_KEY: &str = "./";
+
+/// Files, bytes and tokens of part of a bundle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Totals {
+    pub files: usize,
+    pub bytes: u64,
+    pub tokens: u64,
+}
+
+impl Totals {
+    fn add(&mut self, bytes: u64, tokens: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+        self.tokens += tokens;
+    }
+}
+
+/// One line of `.sheafy/metrics.jsonl`: the size of one bundle.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    /// When the bundle was written (RFC 3339)
+    pub time: String,
+    /// The bundle, relative to the working directory where possible
+    pub output: String,
+    /// What the tokens were counted with: `estimate` or a rank file
+    pub tokenizer: String,
+    pub total: Totals,
+    /// By top-level directory (`src/`), with `./` for files outside any
+    pub dirs: BTreeMap<String, Totals>,
+}
+
+impl Metrics {
+    /// Adds one file, by its path in the bundle (`/` separators).
+    pub fn add(&mut self, path: &str, content: &str, counter: &Counter) {
+        let (bytes, tokens) = (content.len() as u64, counter.count(content) as u64);
+        let dir = match path.split_once('/') {
+            Some((dir, _)) => format!("{}/", dir),
+            None => ROOT_KEY.to_string(),
+        };
+        self.dirs.entry(dir).or_default().add(bytes, tokens);
+        self.total.add(bytes, tokens);
+    }
+
+    /// Measures the file sections of the bundle at `path`.
+    pub fn of_bundle(path: &Path, counter: &Counter) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bundle: {}", path.display()))?;
+        let mut metrics = Self {
+            output: path.display().to_string(),
+            tokenizer: counter.name(),
+            ..Self::default()
+        };
+        for section in parse_sections(&content)? {
+            if section.stub.is_none() {
+                metrics.add(&section.path, &section.content, counter);
+            }
+        }
+        Ok(metrics)
+    }
+}
+
+/// Appends `metrics` of a bundle just written to `.sheafy/metrics.jsonl`.
+pub fn record(working_dir: &Path, mut metrics: Metrics, output: &Path) -> Result<()> {
+    metrics.time = crate::timestamp::rfc3339(crate::timestamp::now_unix());
+    metrics.output = crate::paths::to_slash(output.strip_prefix(working_dir).unwrap_or(output));
+    let dir = crate::state::state_dir(working_dir);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let path = dir.join(METRICS_FILE);
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&metrics)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The runs recorded in `.sheafy/metrics.jsonl`, oldest first.
+pub fn history(working_dir: &Path) -> Result<Vec<Metrics>> {
+    let path = crate::state::state_dir(working_dir).join(METRICS_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: not a metrics record", path.display(), i + 1))
+        })
+        .collect()
+}
+
+pub fn run_stats(config: &Config, args: &StatsArgs) -> Result<()> {
+    let working_dir = config.get_working_dir()?;
+    if args.history {
+        return print_history(&working_dir);
+    }
+    let counter = Counter::from_config(config, &working_dir)?;
+    if args.compare {
+        let [old, new] = args.bundles.as_slice() else {
+            bail!("--compare takes two bundles: the older one, then the newer one");
+        };
+        let old = Metrics::of_bundle(
+            &crate::paths::absolute(Path::new(old), &working_dir),
+            &counter,
+        )?;
+        let new = Metrics::of_bundle(
+            &crate::paths::absolute(Path::new(new), &working_dir),
+            &counter,
+        )?;
+        print_comparison(&old, &new);
+        return Ok(());
+    }
+    let input = match args.bundles.as_slice() {
+        [] => None,
+        [input] => Some(input.as_str()),
+        _ => bail!("Give one bundle to measure, or two with --compare"),
+    };
+    let (_, path) = crate::restore::resolve_input(config, input)?;
+    let metrics = Metrics::of_bundle(&path, &counter)?;
+    status!("Bundle: {} (tokens: {})", metrics.output, metrics.tokenizer);
+    status!(
+        "{:<24} {:>7} {:>12} {:>10}",
+        "Directory",
+        "Files",
+        "Size",
+        "Tokens"
+    );
+    let mut dirs: Vec<_> = metrics.dirs.iter().collect();
+    dirs.sort_by(|a, b| b.1.tokens.cmp(&a.1.tokens).then_with(|| a.0.cmp(b.0)));
+    for (dir, totals) in dirs
+        .into_iter()
+        .chain([(&"total".to_string(), &metrics.total)])
+    {
+        status!(
+            "{:<24} {:>7} {:>12} {:>10}",
+            dir,
+            totals.files,
+            ByteSize(totals.bytes).to_string(),
+            totals.tokens
+        );
+    }
+    Ok(())
+}
+
+/// Prints the growth of each directory from `old` to `new`, largest change first.
+fn print_comparison(old: &Metrics, new: &Metrics) {
+    status!("Comparing {} -> {}", old.output, new.output);
+    status!(
+        "{:<24} {:>15} {:>25} {:>25}",
+        "Directory",
+        "Files",
+        "Size",
+        "Tokens"
+    );
+    let dirs: BTreeSet<&String> = old.dirs.keys().chain(new.dirs.keys()).collect();
+    let mut rows: Vec<(&str, Totals, Totals)> = dirs
+        .into_iter()
+        .map(|dir| {
+            let totals = |metrics: &Metrics| metrics.dirs.get(dir).copied().unwrap_or_default();
+            (dir.as_str(), totals(old), totals(new))
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        let change = |(_, old, new): &(&str, Totals, Totals)| new.tokens.abs_diff(old.tokens);
+        change(b).cmp(&change(a)).then_with(|| a.0.cmp(b.0))
+    });
+    for (dir, old, new) in rows.into_iter().chain([("total", old.total, new.total)]) {
+        if old == new && dir != "total" {
+            continue;
+        }
+        status!(
+            "{:<24} {:>15} {:>25} {:>25}",
+            dir,
+            format!("{} -> {}", old.files, new.files),
+            format!(
+                "{} ({})",
+                ByteSize(new.bytes),
+                growth(old.bytes, new.bytes, |n| ByteSize(n).to_string())
+            ),
+            format!(
+                "{} ({})",
+                new.tokens,
+                growth(old.tokens, new.tokens, |n| n.to_string())
+            )
+        );
+    }
+}
+
+/// `+1.2 KiB, +12%`, or `new` for what grew from nothing.
+fn growth(old: u64, new: u64, show: impl Fn(u64) -> String) -> String {
+    let sign = if new >= old { '+' } else { '-' };
+    let delta = format!("{}{}", sign, show(new.abs_diff(old)));
+    if old == 0 {
+        return if new == 0 { delta } else { "new".to_string() };
+    }
+    let percent = (new as f64 - old as f64) / old as f64 * 100.0;
+    format!("{}, {:+.0}%", delta, percent)
+}
+
+fn print_history(working_dir: &Path) -> Result<()> {
+    let runs = history(working_dir)?;
+    if runs.is_empty() {
+        status!("No bundle metrics recorded yet; each `sheafy bundle` records one run.");
+        return Ok(());
+    }
+    status!(
+        "{:<22} {:<28} {:>7} {:>12} {:>10} {:>8}",
+        "Time",
+        "Bundle",
+        "Files",
+        "Size",
+        "Tokens",
+        "Change"
+    );
+    // Changes are against the previous run of the same bundle
+    let mut previous: BTreeMap<&str, u64> = BTreeMap::new();
+    for run in &runs {
+        let change = match previous.insert(&run.output, run.total.tokens) {
+            Some(old) if old > 0 => {
+                format!(
+                    "{:+.0}%",
+                    (run.total.tokens as f64 - old as f64) / old as f64 * 100.0
+                )
+            }
+            _ => String::new(),
+        };
+        status!(
+            "{:<22} {:<28} {:>7} {:>12} {:>10} {:>8}",
+            run.time,
+            run.output,
+            run.total.files,
+            ByteSize(run.total.bytes).to_string(),
+            run.total.tokens,
+            change
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! Routing for human-readable progress messages.
+//!
+//! Progress output normally goes to stdout. Modes that reserve stdout for
+//! machine-readable data (such as `sheafy daemon --stdio`) redirect it to stderr.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Sends all subsequent `status!` output to stderr instead of stdout.
+pub fn redirect_to_stderr(enabled: bool) {
+    TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_redirected() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Prints a progress message, honoring the current output routing.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::status::is_redirected() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
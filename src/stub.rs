@@ -0,0 +1,108 @@
+//! Stub sections: files the bundle documents without holding their content.
+//!
+//! Files matching `stub_patterns` (or `bundle --stub`), typically images and other
+//! large assets, get a section whose body is a single `<!-- sheafy:stub {...} -->`
+//! note with their size, SHA-256 and, if `stub_url` is set, where to fetch them.
+//! Restore skips them, or with `--stubs placeholder` creates empty files in their place.
+
+use crate::config::Config;
+use crate::restore::STUB_PREFIX;
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// What a stub section records about its file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Stub {
+    /// Size in bytes
+    pub size: u64,
+    /// SHA-256 of the content, lowercase hex
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl Stub {
+    /// The note standing in for the section's code fence.
+    pub fn note(&self) -> String {
+        format!(
+            "{} {} -->",
+            STUB_PREFIX,
+            serde_json::to_string(self).expect("stubs serialize")
+        )
+    }
+
+    /// The stub a `<!-- sheafy:stub {...} -->` line describes.
+    pub fn parse(line: &str) -> Option<Self> {
+        let json = line.strip_prefix(STUB_PREFIX)?.strip_suffix("-->")?;
+        serde_json::from_str(json.trim()).ok()
+    }
+}
+
+/// Which files are bundled as stubs, and how their URLs are formed.
+pub struct Stubs {
+    matcher: Gitignore,
+    /// `stub_url`, with `{path}` standing for the header path
+    url: Option<String>,
+}
+
+impl Stubs {
+    /// The stub settings of the config plus `--stub` patterns, or `None` if no pattern
+    /// is given.
+    pub fn from_config(
+        config: &Config,
+        extra_patterns: &[String],
+        working_dir: &Path,
+    ) -> Result<Option<Self>> {
+        let mut builder = GitignoreBuilder::new(working_dir);
+        let mut any = false;
+        let configured = config.sheafy.stub_patterns.as_deref().unwrap_or("");
+        for line in configured
+            .lines()
+            .chain(extra_patterns.iter().map(String::as_str))
+            .map(str::trim)
+        {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            builder
+                .add_line(None, line)
+                .with_context(|| format!("Invalid stub pattern: '{}'", line))?;
+            any = true;
+        }
+        if !any {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            matcher: builder.build()?,
+            url: config.sheafy.stub_url.clone(),
+        }))
+    }
+
+    pub fn matches(&self, rel_path: &Path) -> bool {
+        self.matcher
+            .matched_path_or_any_parents(rel_path, false)
+            .is_ignore()
+    }
+
+    /// The stub of a file with content `bytes`, bundled under `header_path`.
+    pub fn stub(&self, header_path: &str, bytes: &[u8]) -> Stub {
+        Stub {
+            size: bytes.len() as u64,
+            sha256: sha256_hex(bytes),
+            url: self
+                .url
+                .as_ref()
+                .map(|template| template.replace("{path}", header_path)),
+        }
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
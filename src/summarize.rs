@@ -0,0 +1,73 @@
+//! External summarizers: files bundled as a command's condensed version of them.
+//!
+//! Each `[[sheafy.summarize]]` entry names the files it applies to (`pattern`, gitignore
+//! syntax) and a shell `command` that reads a file on stdin and prints its summary. The
+//! summary is bundled in place of the file, followed by a `<!-- sheafy:preview ... -->`
+//! note saying so; restore skips such sections, so the file on disk is never replaced by
+//! its summary. Entries are tried in order and the first match wins.
+
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// One `[[sheafy.summarize]]` entry.
+pub struct Summarizer {
+    matcher: Gitignore,
+    /// Shell command printing the summary of the file on its stdin
+    pub command: String,
+    /// Code fence language of the summary
+    pub language: String,
+    working_dir: PathBuf,
+}
+
+impl Summarizer {
+    /// The summary of `content`, the file at `path`.
+    pub fn run(&self, path: &str, content: &str) -> Result<String> {
+        let output = crate::handlers::pipe(&self.command, &self.working_dir, path, content)
+            .with_context(|| format!("Failed to run summarizer: {}", self.command))?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        let summary = String::from_utf8(output.stdout).context("The summary is not valid UTF-8")?;
+        if summary.trim().is_empty() {
+            bail!("the summary is empty");
+        }
+        Ok(summary)
+    }
+}
+
+/// The configured summarizers, in config order.
+#[derive(Default)]
+pub struct Summarizers(Vec<Summarizer>);
+
+impl Summarizers {
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Self> {
+        let mut summarizers = Vec::new();
+        for entry in &config.sheafy.summarize {
+            let mut builder = GitignoreBuilder::new(working_dir);
+            builder
+                .add_line(None, &entry.pattern)
+                .with_context(|| format!("Invalid summarize pattern '{}'", entry.pattern))?;
+            summarizers.push(Summarizer {
+                matcher: builder
+                    .build()
+                    .with_context(|| format!("Invalid summarize pattern '{}'", entry.pattern))?,
+                command: entry.command.clone(),
+                language: entry.language.clone().unwrap_or_default(),
+                working_dir: working_dir.to_path_buf(),
+            });
+        }
+        Ok(Self(summarizers))
+    }
+
+    /// The first summarizer whose pattern matches `rel_path`.
+    pub fn for_path(&self, rel_path: &Path) -> Option<&Summarizer> {
+        self.0.iter().find(|summarizer| {
+            summarizer
+                .matcher
+                .matched_path_or_any_parents(rel_path, false)
+                .is_ignore()
+        })
+    }
+}
@@ -0,0 +1,48 @@
+//! Previews of tabular data files (`--csv-preview-rows`): the header row and the first
+//! rows are bundled instead of the whole file, so the schema stays visible without
+//! spending the context budget on data.
+
+use std::path::Path;
+
+/// Returns true for CSV and TSV files.
+pub fn is_table(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("tsv"))
+}
+
+/// The header plus the first `rows` data rows of `content`, with the total number of
+/// data rows. `None` if the table is short enough to bundle whole.
+pub fn preview(content: &str, rows: usize) -> Option<(String, usize)> {
+    let ends = record_ends(content);
+    let total = ends.len().saturating_sub(1);
+    if total <= rows {
+        return None;
+    }
+    Some((content[..ends[rows]].to_string(), total))
+}
+
+/// Byte offsets just past each record. Newlines inside quoted fields don't end a record.
+fn record_ends(content: &str) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut in_quotes = false;
+    for (i, b) in content.bytes().enumerate() {
+        match b {
+            b'"' => in_quotes = !in_quotes,
+            b'\n' if !in_quotes => ends.push(i + 1),
+            _ => {}
+        }
+    }
+    if ends.last().copied().unwrap_or(0) < content.len() {
+        ends.push(content.len());
+    }
+    // Blank trailing lines are not rows
+    while ends.len() > 1
+        && content[ends[ends.len() - 2]..ends[ends.len() - 1]]
+            .trim()
+            .is_empty()
+    {
+        ends.pop();
+    }
+    ends
+}
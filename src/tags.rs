@@ -0,0 +1,112 @@
+//! `[sheafy.tags]`: sections grouped by what the files are, not where they are.
+//!
+//! Each tag names the files it covers with patterns (gitignore syntax), as in
+//! `core = ["src/**"]`. The bundle holds the files of each tag together under a `# Core`
+//! heading, tags in `tag_order` first and the others by name, with the untagged files
+//! last under `# Other`. A file matching several tags goes with the first of them.
+//! Within a group, files keep the bundle order (`auto_order`, then `priority_patterns`).
+//! Restore reads the `# ...` headings as prose and skips them.
+
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The configured tags, in bundle order.
+pub struct Tags(Vec<(String, Gitignore)>);
+
+impl Tags {
+    /// The tags of `config`, or `None` if it declares none.
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Option<Self>> {
+        let tags = &config.sheafy.tags;
+        if tags.is_empty() {
+            return Ok(None);
+        }
+        let order = config.sheafy.tag_order.as_deref().unwrap_or_default();
+        if let Some(name) = order.iter().find(|name| !tags.contains_key(*name)) {
+            bail!("tag_order names '{}', which is not in [sheafy.tags]", name);
+        }
+        let names = order
+            .iter()
+            .chain(tags.keys().filter(|name| !order.contains(name)));
+        let mut matchers = Vec::new();
+        for name in names {
+            let mut builder = GitignoreBuilder::new(working_dir);
+            for pattern in &tags[name] {
+                builder
+                    .add_line(None, pattern)
+                    .with_context(|| format!("Invalid pattern '{}' of tag '{}'", pattern, name))?;
+            }
+            let matcher = builder
+                .build()
+                .with_context(|| format!("Invalid patterns of tag '{}'", name))?;
+            matchers.push((name.clone(), matcher));
+        }
+        Ok(Some(Self(matchers)))
+    }
+
+    /// Index of the first tag covering `rel_path`; `None` if it is untagged.
+    fn tag(&self, rel_path: &Path) -> Option<usize> {
+        self.0.iter().position(|(_, matcher)| {
+            matcher
+                .matched_path_or_any_parents(rel_path, false)
+                .is_ignore()
+        })
+    }
+
+    /// Moves the files of each tag together, in tag order, keeping their order within it.
+    pub fn group(&self, files: &mut [PathBuf]) {
+        files.sort_by_cached_key(|rel_path| self.tag(rel_path).unwrap_or(usize::MAX));
+    }
+
+    /// The heading over the files of `tag`: the tag's name with a capital first letter.
+    fn heading(&self, tag: Option<usize>) -> String {
+        let name = tag.map_or("other", |tag| self.0[tag].0.as_str());
+        let mut chars = name.chars();
+        let title: String = chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default();
+        format!("# {}", title)
+    }
+}
+
+/// Writes a group's heading before the first section of the group.
+pub struct GroupHeadings<'a> {
+    tags: Option<&'a Tags>,
+    /// Tag of the last section written; `None` before the first
+    last: Option<Option<usize>>,
+}
+
+impl<'a> GroupHeadings<'a> {
+    pub fn new(tags: Option<&'a Tags>) -> Self {
+        Self { tags, last: None }
+    }
+
+    /// The heading to write before the section of `rel_path`, if it starts a group.
+    pub fn heading_for(&mut self, rel_path: &Path) -> Option<String> {
+        let tags = self.tags?;
+        let tag = tags.tag(rel_path);
+        (self.last != Some(tag)).then(|| {
+            self.last = Some(tag);
+            tags.heading(tag)
+        })
+    }
+
+    /// Writes the heading of `rel_path`'s group if its section starts the group; call
+    /// before writing the section.
+    pub fn start(
+        &mut self,
+        writer: &mut impl Write,
+        rel_path: &Path,
+        separator: &str,
+    ) -> io::Result<()> {
+        match self.heading_for(rel_path) {
+            Some(heading) => writeln!(writer, "{}{}", separator, heading),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Line endings and byte order mark of the files restore writes over.
+//!
+//! A bundle made on Windows carries CRLF line endings, one made elsewhere (or pasted back
+//! from a chat) carries LF, and editors add or drop a UTF-8 byte order mark. Restoring
+//! such a bundle over a working tree with the other convention would rewrite every line
+//! of every file. So when a file already exists, restore writes the new content in the
+//! file's own style; `restore --as-bundled` (or `match_existing = false` in
+//! `[sheafy.restore]`) writes the content exactly as bundled instead.
+
+use std::borrow::Cow;
+
+const BOM: &str = "\u{feff}";
+
+/// The conventions of an existing text file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    /// Lines end in CRLF (most of them, in a file with mixed endings)
+    pub crlf: bool,
+    /// The file starts with a UTF-8 byte order mark
+    pub bom: bool,
+}
+
+impl TextStyle {
+    /// The style of `existing`, or `None` if it isn't UTF-8 text or has no line endings
+    /// to go by.
+    pub fn detect(existing: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(existing).ok()?;
+        if text.contains('\0') {
+            return None;
+        }
+        let lines = text.matches('\n').count();
+        let crlf = text.matches("\r\n").count();
+        (lines > 0).then_some(Self {
+            crlf: crlf * 2 > lines,
+            bom: text.starts_with(BOM),
+        })
+    }
+
+    /// `content` with this style's line endings and byte order mark.
+    pub fn apply<'a>(&self, content: &'a str) -> Cow<'a, str> {
+        let has_bom = content.starts_with(BOM);
+        let body = content.strip_prefix(BOM).unwrap_or(content);
+        let crlf = body.matches("\r\n").count();
+        let endings_match = if self.crlf {
+            crlf == body.matches('\n').count()
+        } else {
+            crlf == 0
+        };
+        if endings_match && has_bom == self.bom {
+            return Cow::Borrowed(content);
+        }
+        let lf = body.replace("\r\n", "\n");
+        let body = if self.crlf {
+            lf.replace('\n', "\r\n")
+        } else {
+            lf
+        };
+        Cow::Owned(if self.bom {
+            format!("{}{}", BOM, body)
+        } else {
+            body
+        })
+    }
+}
@@ -0,0 +1,191 @@
+//! `--timings` and `--profile-out`: where a command spends its time.
+//!
+//! Commands mark their phases with [`span`] (or [`timed`] for an iterator's items); the
+//! guard records the phase when it is dropped. Nothing is recorded until [`enable`] is
+//! called. At the end, [`finish`] prints the total of each phase and writes every span
+//! as a speedscope "evented" profile (<https://www.speedscope.app>).
+//!
+//! Bundling records `walk`, `read`, `transform` and `write`; restoring records `check`
+//! (the checks made before anything is written), `parse` and `write`.
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+struct Recorder {
+    started: Instant,
+    /// Phase, start and end, relative to `started`, in the order the spans ended
+    spans: Vec<(&'static str, Duration, Duration)>,
+}
+
+/// Starts recording spans.
+pub fn enable() {
+    *RECORDER.lock().unwrap() = Some(Recorder {
+        started: Instant::now(),
+        spans: Vec::new(),
+    });
+}
+
+fn is_enabled() -> bool {
+    RECORDER.lock().unwrap().is_some()
+}
+
+/// A phase in progress, recorded when dropped.
+pub struct Span {
+    name: &'static str,
+    started: Option<Instant>,
+}
+
+/// Marks the start of phase `name`; it ends when the returned guard is dropped.
+pub fn span(name: &'static str) -> Span {
+    Span {
+        name,
+        started: is_enabled().then(Instant::now),
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(started) = self.started else {
+            return;
+        };
+        let ended = Instant::now();
+        if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+            let start = started.saturating_duration_since(recorder.started);
+            let end = ended.saturating_duration_since(recorder.started);
+            recorder.spans.push((self.name, start, end));
+        }
+    }
+}
+
+/// `iter` with the time taken by each item recorded as phase `name`.
+pub fn timed<I: Iterator>(name: &'static str, iter: I) -> Timed<I> {
+    Timed { name, iter }
+}
+
+pub struct Timed<I> {
+    name: &'static str,
+    iter: I,
+}
+
+impl<I: Iterator> Iterator for Timed<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let _span = span(self.name);
+        self.iter.next()
+    }
+}
+
+/// Stops recording; prints the totals per phase on stderr if `print`, and writes the
+/// spans to `profile_out` if given.
+pub fn finish(print: bool, profile_out: Option<&Path>, command: &str) -> Result<()> {
+    let Some(recorder) = RECORDER.lock().unwrap().take() else {
+        return Ok(());
+    };
+    let elapsed = recorder.started.elapsed();
+    if print {
+        eprintln!("{}", summary(&recorder.spans, elapsed));
+    }
+    if let Some(path) = profile_out {
+        let profile = speedscope(&recorder.spans, elapsed, command);
+        std::fs::write(path, serde_json::to_string(&profile)? + "\n")
+            .with_context(|| format!("Failed to write the profile to {}", path.display()))?;
+        eprintln!("Wrote the profile to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Total time and count of each phase, in the order the phases first started.
+fn summary(spans: &[(&'static str, Duration, Duration)], elapsed: Duration) -> String {
+    let mut ordered: Vec<&(&'static str, Duration, Duration)> = spans.iter().collect();
+    ordered.sort_by_key(|(_, start, _)| *start);
+    let mut phases: Vec<(&'static str, Duration, usize)> = Vec::new();
+    for (name, start, end) in ordered {
+        let time = end.saturating_sub(*start);
+        match phases.iter_mut().find(|(phase, _, _)| phase == name) {
+            Some((_, total, count)) => {
+                *total += time;
+                *count += 1;
+            }
+            None => phases.push((name, time, 1)),
+        }
+    }
+    let mut lines = vec![format!("Timings (total {}):", millis(elapsed))];
+    for (name, total, count) in phases {
+        lines.push(format!(
+            "  {:<10} {:>10}  {:>3}%  ({} span{})",
+            name,
+            millis(total),
+            total.as_micros() * 100 / elapsed.as_micros().max(1),
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+    }
+    lines.join("\n")
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// The spans as a speedscope file with one evented profile, in microseconds.
+fn speedscope(
+    spans: &[(&'static str, Duration, Duration)],
+    elapsed: Duration,
+    command: &str,
+) -> serde_json::Value {
+    let mut frames: Vec<&'static str> = Vec::new();
+    let mut sorted: Vec<(usize, u128, u128)> = spans
+        .iter()
+        .map(|(name, start, end)| {
+            let frame = match frames.iter().position(|frame| frame == name) {
+                Some(frame) => frame,
+                None => {
+                    frames.push(name);
+                    frames.len() - 1
+                }
+            };
+            (frame, start.as_micros(), end.as_micros())
+        })
+        .collect();
+    // Enclosing spans open first; speedscope wants the events properly nested
+    sorted.sort_by_key(|&(_, start, end)| (start, std::cmp::Reverse(end)));
+    let mut events = Vec::new();
+    let mut open: Vec<(usize, u128)> = Vec::new();
+    for (frame, start, end) in sorted {
+        while let Some(&(top, top_end)) = open.last().filter(|(_, top_end)| *top_end <= start) {
+            events.push(json!({"type": "C", "frame": top, "at": top_end}));
+            open.pop();
+        }
+        let end = open.last().map_or(end, |&(_, top_end)| end.min(top_end));
+        events.push(json!({"type": "O", "frame": frame, "at": start}));
+        open.push((frame, end));
+    }
+    while let Some((frame, end)) = open.pop() {
+        events.push(json!({"type": "C", "frame": frame, "at": end}));
+    }
+    let name = format!("sheafy {}", command);
+    json!({
+        "$schema": "https://www.speedscope.app/file-format-schema.json",
+        "shared": {
+            "frames": frames.iter().map(|name| json!({"name": name})).collect::<Vec<_>>(),
+        },
+        "profiles": [{
+            "type": "evented",
+            "name": name,
+            "unit": "microseconds",
+            "startValue": 0,
+            "endValue": elapsed.as_micros(),
+            "events": events,
+        }],
+        "name": name,
+        "exporter": format!("sheafy {}", env!("CARGO_PKG_VERSION")),
+    })
+}
@@ -0,0 +1,210 @@
+//! Token counts, for budgeting a bundle against a model's context window.
+//!
+//! By default tokens are estimated: about four characters per token, but one per CJK
+//! character, which the usual rule of thumb undercounts several times over. For exact
+//! counts `tokenizer` names a model family's byte-level BPE (`cl100k`, `o200k` or
+//! `llama3`), whose merge ranks are read from a `.tiktoken` file: `tokenizer_file`, or
+//! `<name>.tiktoken` next to the user-wide config. The vocabularies are megabytes of
+//! data, so they are not built in; without one, the estimate is used with a warning.
+
+use crate::config::{Config, Tokenizer};
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Rough token count of `text`: about four characters per token, the usual rule of
+/// thumb for English prose and source code, plus one per CJK character.
+pub fn estimate(text: &str) -> usize {
+    let (cjk, other) = text.chars().fold((0, 0), |(cjk, other), c| {
+        if is_cjk(c) {
+            (cjk + 1, other)
+        } else {
+            (cjk, other + 1)
+        }
+    });
+    cjk + usize::div_ceil(other, 4)
+}
+
+/// Han, kana, Hangul and full-width forms, which tokenizers split about per character.
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF
+        | 0xF900..=0xFAFF
+        | 0xFF00..=0xFFEF
+        | 0x20000..=0x2FFFF)
+}
+
+/// Pre-tokenization of cl100k and Llama 3, without the `\s+(?!\S)` alternative the regex
+/// crate cannot express; [`Bpe::pieces`] makes up for it.
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+/// Pre-tokenization of o200k, likewise.
+const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+",
+);
+
+/// Counts tokens the way `tokenizer` says.
+pub struct Counter {
+    bpe: Option<Bpe>,
+}
+
+impl Counter {
+    pub fn from_config(config: &Config, working_dir: &Path) -> Result<Self> {
+        let tokenizer = config.sheafy.tokenizer.unwrap_or_default();
+        let (pattern, encoding) = match tokenizer {
+            Tokenizer::Estimate => return Ok(Self { bpe: None }),
+            Tokenizer::Cl100k => (CL100K_PATTERN, "cl100k_base"),
+            Tokenizer::O200k => (O200K_PATTERN, "o200k_base"),
+            Tokenizer::Llama3 => (CL100K_PATTERN, "llama3"),
+        };
+        let path = match &config.sheafy.tokenizer_file {
+            Some(file) => Some(working_dir.join(file)),
+            None => rank_file_dir().map(|dir| dir.join(format!("{}.tiktoken", encoding))),
+        };
+        match path.filter(|path| path.is_file()) {
+            Some(path) => Ok(Self {
+                bpe: Some(Bpe::load(&path, pattern)?),
+            }),
+            None => {
+                eprintln!(
+                    "Warning: tokenizer '{}' needs its rank file ({}.tiktoken); set \
+                     tokenizer_file or put it in {}. Estimating tokens instead.",
+                    tokenizer.as_str(),
+                    encoding,
+                    rank_file_dir()
+                        .map(|dir| dir.display().to_string())
+                        .unwrap_or_else(|| "the user-wide config directory".to_string())
+                );
+                Ok(Self { bpe: None })
+            }
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match &self.bpe {
+            Some(bpe) => bpe.count(text),
+            None => estimate(text),
+        }
+    }
+
+    /// What the counts are, for telling cached ones apart: `estimate` or the rank file.
+    pub fn name(&self) -> String {
+        match &self.bpe {
+            Some(bpe) => bpe.source.display().to_string(),
+            None => "estimate".to_string(),
+        }
+    }
+
+    /// Whether counts are exact rather than estimated.
+    pub fn is_exact(&self) -> bool {
+        self.bpe.is_some()
+    }
+}
+
+/// Where `<name>.tiktoken` rank files are looked for: `tokenizers/` next to the
+/// user-wide config.
+fn rank_file_dir() -> Option<PathBuf> {
+    Some(
+        crate::config::global_config_path()?
+            .parent()?
+            .join("tokenizers"),
+    )
+}
+
+/// A byte-level BPE tokenizer, as used by tiktoken.
+struct Bpe {
+    source: PathBuf,
+    pattern: Regex,
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl Bpe {
+    /// Reads a `.tiktoken` file: one base64 token and its rank per line.
+    fn load(path: &Path, pattern: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tokenizer file: {}", path.display()))?;
+        let mut ranks = HashMap::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(token, rank)| {
+                Some((
+                    crate::base64::decode(token)?,
+                    rank.trim().parse::<u32>().ok()?,
+                ))
+            });
+            let Some((token, rank)) = parsed else {
+                bail!(
+                    "Invalid tokenizer file {} (line {}): expected a base64 token and a rank",
+                    path.display(),
+                    index + 1
+                );
+            };
+            ranks.insert(token, rank);
+        }
+        Ok(Self {
+            source: path.to_path_buf(),
+            pattern: Regex::new(pattern).expect("valid tokenizer pattern"),
+            ranks,
+        })
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.pieces(text)
+            .map(|piece| self.count_piece(piece.as_bytes()))
+            .sum()
+    }
+
+    /// Splits `text` like the model's pre-tokenizer. Where the original pattern's
+    /// `\s+(?!\S)` applies (a run of spaces before a word), the run stops one character
+    /// short, leaving that character to the next piece.
+    fn pieces<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let mut at = 0;
+        std::iter::from_fn(move || {
+            let found = self.pattern.find_at(text, at)?;
+            let mut end = found.end();
+            let piece = found.as_str();
+            let spaces_only = piece.chars().all(char::is_whitespace)
+                && !piece.ends_with(['\r', '\n'])
+                && end < text.len();
+            if spaces_only && piece.chars().nth(1).is_some() {
+                end -= piece.chars().next_back().expect("not empty").len_utf8();
+            }
+            at = end;
+            Some(&text[found.start()..end])
+        })
+    }
+
+    /// Tokens of one piece: its bytes, merged pairwise lowest rank first.
+    fn count_piece(&self, piece: &[u8]) -> usize {
+        if piece.len() < 2 || self.ranks.contains_key(piece) {
+            return piece.len().min(1);
+        }
+        // Start offsets of the parts, plus the end
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..bounds.len() - 2)
+                .filter_map(|i| {
+                    let rank = self.ranks.get(&piece[bounds[i]..bounds[i + 2]])?;
+                    Some((*rank, i))
+                })
+                .min();
+            match best {
+                Some((_, i)) => {
+                    bounds.remove(i + 1);
+                }
+                None => return bounds.len() - 1,
+            }
+        }
+    }
+}
@@ -0,0 +1,708 @@
+//! `sheafy tui`: a terminal UI for choosing what to bundle and previewing restores.
+//!
+//! The file view shows the files `sheafy bundle` would hold as a tree with checkboxes,
+//! and the number, size and tokens of the checked ones, updated as files are checked
+//! and unchecked. `w` bundles the checked files: the selection is passed to the bundle
+//! as `--exclude` patterns for the unchecked files, or `--include` patterns for the
+//! checked ones when those are fewer, a directory whose files are all on one side
+//! taking a single pattern. sheafy then offers to save the patterns as a profile in
+//! `sheafy.toml`, so the selection can be bundled again from the command line.
+//!
+//! The restore view (`--restore`) lists the sections of a bundle with how each compares
+//! with the working tree (or, with `--diff-base head`, the last commit), and the diff of
+//! the selected one; `r` restores the bundle.
+//!
+//! Without the `tui` feature, the command only says so.
+
+#[cfg(feature = "tui")]
+pub use imp::{run_tui, selection_patterns};
+
+#[cfg(not(feature = "tui"))]
+pub fn run_tui(_config: crate::config::Config, _args: &crate::cli::TuiArgs) -> anyhow::Result<()> {
+    anyhow::bail!("This sheafy was built without the `tui` feature")
+}
+
+#[cfg(feature = "tui")]
+mod imp {
+    use crate::bundle::BundleSummary;
+    use crate::cli::{BundleArgs, DiffBase, RestoreArgs, TuiArgs};
+    use crate::config::{ByteSize, Config};
+    use crate::git::HeadFiles;
+    use crate::restore::{Compared, DiffStatus};
+    use crate::tokens::Counter;
+    use anyhow::{bail, Context, Result};
+    use ratatui::{
+        crossterm::event::{self, Event, KeyCode, KeyEventKind},
+        layout::{Constraint, Layout},
+        style::{Color, Modifier, Style},
+        text::{Line, Text},
+        widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+        DefaultTerminal, Frame,
+    };
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        io::{IsTerminal, Write},
+        ops::Range,
+        path::{Path, PathBuf},
+    };
+
+    pub fn run_tui(config: Config, args: &TuiArgs) -> Result<()> {
+        if !std::io::stdout().is_terminal() || !std::io::stdin().is_terminal() {
+            bail!("`sheafy tui` needs a terminal");
+        }
+        match &args.restore {
+            Some(bundle) => run_restore_view(config, bundle.as_deref(), args.diff_base),
+            None => run_file_view(config),
+        }
+    }
+
+    /// Runs `view` on the terminal, restoring the terminal however it ends.
+    fn with_terminal<T>(view: impl FnOnce(&mut DefaultTerminal) -> Result<T>) -> Result<T> {
+        let mut terminal = ratatui::init();
+        let result = view(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    /// A key press, ignoring releases and repeats reported by some terminals.
+    fn next_key() -> Result<Option<KeyCode>> {
+        Ok(
+            match event::read().context("Failed to read a terminal event")? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => Some(key.code),
+                _ => None,
+            },
+        )
+    }
+
+    fn highlight() -> Style {
+        Style::default().add_modifier(Modifier::REVERSED)
+    }
+
+    // ---- File view ----
+
+    /// A file the bundle would hold.
+    struct Candidate {
+        path: PathBuf,
+        size: u64,
+        tokens: usize,
+    }
+
+    /// A line of the tree: a directory with the range of `Tree::order` below it, or a file.
+    struct Row {
+        path: PathBuf,
+        depth: usize,
+        kind: RowKind,
+    }
+
+    enum RowKind {
+        Dir(Range<usize>),
+        File(usize),
+    }
+
+    /// The candidates as a tree, with what is checked and collapsed.
+    struct Tree {
+        candidates: Vec<Candidate>,
+        /// Candidate indices in path order, so that each directory's files are adjacent
+        order: Vec<usize>,
+        rows: Vec<Row>,
+        checked: Vec<bool>,
+        collapsed: HashSet<PathBuf>,
+    }
+
+    impl Tree {
+        fn new(candidates: Vec<Candidate>) -> Self {
+            let mut order: Vec<usize> = (0..candidates.len()).collect();
+            order.sort_by(|a, b| candidates[*a].path.cmp(&candidates[*b].path));
+            let mut rows: Vec<Row> = Vec::new();
+            // Directories entered and not yet left: (row, path)
+            let mut open: Vec<(usize, PathBuf)> = Vec::new();
+            for (position, &index) in order.iter().enumerate() {
+                let path = &candidates[index].path;
+                while let Some((row, dir)) = open.last() {
+                    if path.starts_with(dir) {
+                        break;
+                    }
+                    close(&mut rows[*row], position);
+                    open.pop();
+                }
+                let mut missing: Vec<&Path> = path
+                    .ancestors()
+                    .skip(1)
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .take_while(|dir| !open.iter().any(|(_, open)| open == dir))
+                    .collect();
+                missing.reverse();
+                for dir in missing {
+                    open.push((rows.len(), dir.to_path_buf()));
+                    rows.push(Row {
+                        path: dir.to_path_buf(),
+                        depth: open.len() - 1,
+                        kind: RowKind::Dir(position..position),
+                    });
+                }
+                rows.push(Row {
+                    path: path.clone(),
+                    depth: open.len(),
+                    kind: RowKind::File(index),
+                });
+            }
+            for (row, _) in open {
+                close(&mut rows[row], order.len());
+            }
+            let checked = vec![true; candidates.len()];
+            Self {
+                candidates,
+                order,
+                rows,
+                checked,
+                collapsed: HashSet::new(),
+            }
+        }
+
+        /// Indices of the rows not inside a collapsed directory.
+        fn visible(&self) -> Vec<usize> {
+            let mut visible = Vec::new();
+            let mut hidden_below: Option<&Path> = None;
+            for (index, row) in self.rows.iter().enumerate() {
+                if hidden_below.is_some_and(|dir| row.path.starts_with(dir)) {
+                    continue;
+                }
+                hidden_below = None;
+                visible.push(index);
+                if self.collapsed.contains(&row.path) {
+                    hidden_below = Some(&row.path);
+                }
+            }
+            visible
+        }
+
+        /// The candidates a row stands for.
+        fn files(&self, row: &Row) -> Vec<usize> {
+            match &row.kind {
+                RowKind::Dir(range) => self.order[range.clone()].to_vec(),
+                RowKind::File(index) => vec![*index],
+            }
+        }
+
+        /// Checks the row's files, or unchecks them if they all are checked.
+        fn toggle(&mut self, row: usize) {
+            let files = self.files(&self.rows[row]);
+            let check = !files.iter().all(|&index| self.checked[index]);
+            for index in files {
+                self.checked[index] = check;
+            }
+        }
+
+        fn toggle_all(&mut self) {
+            let check = !self.checked.iter().all(|&checked| checked);
+            self.checked.fill(check);
+        }
+
+        fn toggle_collapsed(&mut self, row: usize) {
+            let row = &self.rows[row];
+            if matches!(row.kind, RowKind::Dir(_)) && !self.collapsed.remove(&row.path) {
+                self.collapsed.insert(row.path.clone());
+            }
+        }
+
+        /// Number, bytes and tokens of the checked files.
+        fn totals(&self) -> (usize, u64, usize) {
+            self.candidates
+                .iter()
+                .zip(&self.checked)
+                .filter(|(_, &checked)| checked)
+                .fold((0, 0, 0), |(files, bytes, tokens), (candidate, _)| {
+                    (files + 1, bytes + candidate.size, tokens + candidate.tokens)
+                })
+        }
+
+        /// `--include` and `--exclude` patterns that select the checked files, whichever
+        /// takes fewer; both empty when every file is checked.
+        fn patterns(&self) -> (Vec<String>, Vec<String>) {
+            let exclude = self.covering(false);
+            if exclude.is_empty() {
+                return (Vec::new(), Vec::new());
+            }
+            let include = self.covering(true);
+            if include.len() < exclude.len() {
+                (include, Vec::new())
+            } else {
+                (Vec::new(), exclude)
+            }
+        }
+
+        /// Anchored patterns for the files checked (or not) as `checked`, with one
+        /// pattern for a directory whose files all are.
+        fn covering(&self, checked: bool) -> Vec<String> {
+            let mut patterns = Vec::new();
+            let mut covered: Option<&Path> = None;
+            for row in &self.rows {
+                if covered.is_some_and(|dir| row.path.starts_with(dir)) {
+                    continue;
+                }
+                if self
+                    .files(row)
+                    .iter()
+                    .all(|&index| self.checked[index] == checked)
+                {
+                    let path = escape(&row.path);
+                    patterns.push(match row.kind {
+                        RowKind::Dir(_) => format!("/{}/", path),
+                        RowKind::File(_) => format!("/{}", path),
+                    });
+                    covered = Some(&row.path);
+                }
+            }
+            patterns
+        }
+
+        fn item(&self, row: &Row) -> ListItem<'static> {
+            let files = self.files(row);
+            let checked = files.iter().filter(|&&index| self.checked[index]).count();
+            let mark = match checked {
+                0 => "[ ]",
+                n if n == files.len() => "[x]",
+                _ => "[-]",
+            };
+            let name = row
+                .path
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+            let indent = "  ".repeat(row.depth);
+            let text = match &row.kind {
+                RowKind::Dir(_) => {
+                    let arrow = if self.collapsed.contains(&row.path) {
+                        "▸"
+                    } else {
+                        "▾"
+                    };
+                    let size: u64 = files.iter().map(|&i| self.candidates[i].size).sum();
+                    format!(
+                        "{}{} {} {}/  ({} files, {})",
+                        indent,
+                        mark,
+                        arrow,
+                        name,
+                        files.len(),
+                        ByteSize(size)
+                    )
+                }
+                RowKind::File(index) => {
+                    let candidate = &self.candidates[*index];
+                    format!(
+                        "{}{}   {}  ({}, {} tokens)",
+                        indent,
+                        mark,
+                        name,
+                        ByteSize(candidate.size),
+                        candidate.tokens
+                    )
+                }
+            };
+            let style = if checked == 0 {
+                Style::default().fg(Color::DarkGray)
+            } else {
+                Style::default()
+            };
+            ListItem::new(text).style(style)
+        }
+    }
+
+    /// The `--include` or `--exclude` patterns (include, exclude) the file view bundles
+    /// `files` with when those marked in `checked` are checked; see `Tree::patterns`.
+    pub fn selection_patterns(files: &[PathBuf], checked: &[bool]) -> (Vec<String>, Vec<String>) {
+        let candidates = files
+            .iter()
+            .map(|path| Candidate {
+                path: path.clone(),
+                size: 0,
+                tokens: 0,
+            })
+            .collect();
+        let mut tree = Tree::new(candidates);
+        tree.checked = checked.to_vec();
+        tree.patterns()
+    }
+
+    fn close(row: &mut Row, end: usize) {
+        if let RowKind::Dir(range) = &mut row.kind {
+            range.end = end;
+        }
+    }
+
+    /// `path` with `/` separators, and the glob characters and trailing spaces (which
+    /// gitignore syntax drops) escaped.
+    fn escape(path: &Path) -> String {
+        let path = path.to_string_lossy();
+        let trailing = path.len() - path.trim_end_matches(' ').len();
+        let mut escaped = String::new();
+        for (i, c) in path.char_indices() {
+            match c {
+                std::path::MAIN_SEPARATOR => escaped.push('/'),
+                '*' | '?' | '[' | ']' | '\\' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                ' ' if i >= path.len() - trailing => escaped.push_str("\\ "),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn run_file_view(config: Config) -> Result<()> {
+        let selection = crate::bundle::select_files(&config, &BundleArgs::default())?;
+        if selection.files.is_empty() {
+            bail!("There are no files to bundle");
+        }
+        status!("Counting tokens of {} file(s)...", selection.files.len());
+        let counter = Counter::from_config(&config, &selection.working_dir)?;
+        let candidates = selection
+            .files
+            .iter()
+            .map(|rel_path| {
+                let full_path = selection.working_dir.join(rel_path);
+                Candidate {
+                    path: rel_path.clone(),
+                    size: fs::metadata(&full_path).map_or(0, |m| m.len()),
+                    tokens: fs::read_to_string(&full_path)
+                        .map_or(0, |content| counter.count(&content)),
+                }
+            })
+            .collect();
+        let mut tree = Tree::new(candidates);
+        let output = selection.output_path.display().to_string();
+        let write = with_terminal(|terminal| file_view(terminal, &mut tree, &output))?;
+        if !write {
+            return Ok(());
+        }
+        let (include, exclude) = tree.patterns();
+        bundle_selection(config, &include, &exclude)?;
+        if !include.is_empty() || !exclude.is_empty() {
+            offer_profile(&include, &exclude)?;
+        }
+        Ok(())
+    }
+
+    /// Asks for a profile name to save the selection under, so that it can be bundled
+    /// again with `bundle --profile`.
+    fn offer_profile(include: &[String], exclude: &[String]) -> Result<()> {
+        let Some(name) = crate::prompt::ask(
+            "Save this selection as a profile in sheafy.toml? Name it (empty to skip):",
+        )?
+        else {
+            return Ok(());
+        };
+        let path = crate::settings::save_profile(&name, include, exclude)?;
+        status!(
+            "Saved profile '{}' to {}; `sheafy bundle --profile {}` bundles the same files.",
+            name,
+            path.display(),
+            name
+        );
+        Ok(())
+    }
+
+    /// Runs the file view until the user writes the bundle (true) or quits (false).
+    fn file_view(terminal: &mut DefaultTerminal, tree: &mut Tree, output: &str) -> Result<bool> {
+        let mut state = ListState::default().with_selected(Some(0));
+        let mut message = String::new();
+        loop {
+            let visible = tree.visible();
+            terminal.draw(|frame| draw_file_view(frame, tree, &visible, &mut state, &message))?;
+            let Some(key) = next_key()? else {
+                continue;
+            };
+            let selected = state.selected().unwrap_or(0).min(visible.len() - 1);
+            message.clear();
+            match key {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Up | KeyCode::Char('k') => state.select(Some(selected.saturating_sub(1))),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    state.select(Some((selected + 1).min(visible.len() - 1)))
+                }
+                KeyCode::PageUp => state.select(Some(selected.saturating_sub(20))),
+                KeyCode::PageDown => state.select(Some((selected + 20).min(visible.len() - 1))),
+                KeyCode::Home => state.select(Some(0)),
+                KeyCode::End => state.select(Some(visible.len() - 1)),
+                KeyCode::Char(' ') => tree.toggle(visible[selected]),
+                KeyCode::Char('a') => tree.toggle_all(),
+                KeyCode::Enter
+                | KeyCode::Left
+                | KeyCode::Right
+                | KeyCode::Char('h')
+                | KeyCode::Char('l') => tree.toggle_collapsed(visible[selected]),
+                KeyCode::Char('w') if tree.totals().0 == 0 => {
+                    message = "Nothing is checked.".to_string()
+                }
+                KeyCode::Char('w') => return Ok(true),
+                _ => {
+                    message = format!(
+                        "Keys: space check, a all, enter fold, w write {}, q quit",
+                        output
+                    )
+                }
+            }
+        }
+    }
+
+    fn draw_file_view(
+        frame: &mut Frame,
+        tree: &Tree,
+        visible: &[usize],
+        state: &mut ListState,
+        message: &str,
+    ) {
+        let [list_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(2)]).areas(frame.area());
+        let items: Vec<ListItem> = visible
+            .iter()
+            .map(|&row| tree.item(&tree.rows[row]))
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Files to bundle "),
+            )
+            .highlight_style(highlight());
+        frame.render_stateful_widget(list, list_area, state);
+        let (files, bytes, tokens) = tree.totals();
+        let totals = format!(
+            "{} of {} files checked · {} · {} tokens",
+            files,
+            tree.candidates.len(),
+            ByteSize(bytes),
+            tokens
+        );
+        let help = if message.is_empty() {
+            "space check · a all · enter fold · w write the bundle · q quit"
+        } else {
+            message
+        };
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(totals),
+                Line::from(help).style(Style::default().fg(Color::DarkGray)),
+            ]),
+            status_area,
+        );
+    }
+
+    /// Bundles the files the patterns select, saying how to do it again.
+    fn bundle_selection(
+        config: Config,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<BundleSummary> {
+        if !include.is_empty() || !exclude.is_empty() {
+            let options: Vec<String> = include
+                .iter()
+                .map(|p| format!("--include '{}'", p))
+                .chain(exclude.iter().map(|p| format!("--exclude '{}'", p)))
+                .collect();
+            status!("Bundling with {}", options.join(" "));
+        }
+        crate::bundle::run_bundle(
+            config,
+            BundleArgs {
+                include: include.to_vec(),
+                exclude: exclude.to_vec(),
+                ..BundleArgs::default()
+            },
+        )
+    }
+
+    // ---- Restore view ----
+
+    fn run_restore_view(config: Config, bundle: Option<&str>, base: DiffBase) -> Result<()> {
+        let sections = crate::restore::compare_bundle(&config, bundle, &[], base)?;
+        if sections.is_empty() {
+            bail!("The bundle holds no files to restore");
+        }
+        let working_dir = config.get_working_dir()?;
+        // Before the first commit every section is new, so no diff needs HEAD
+        let mut head = match base {
+            DiffBase::Worktree => None,
+            DiffBase::Head => HeadFiles::open(&working_dir)?,
+        };
+        let restore = with_terminal(|terminal| {
+            restore_view(terminal, &sections, &working_dir, head.as_mut())
+        })?;
+        if !restore {
+            return Ok(());
+        }
+        let args = RestoreArgs {
+            input_file: bundle.map(str::to_string),
+            ..RestoreArgs::default()
+        };
+        crate::restore::run_restore(&config, &args).map(|_| ())
+    }
+
+    /// Runs the restore view until the user restores the bundle (true) or quits (false).
+    fn restore_view(
+        terminal: &mut DefaultTerminal,
+        sections: &[Compared],
+        working_dir: &Path,
+        mut head: Option<&mut HeadFiles>,
+    ) -> Result<bool> {
+        let mut state = ListState::default().with_selected(Some(0));
+        let mut scroll: u16 = 0;
+        let mut previews: HashMap<usize, Text<'static>> = HashMap::new();
+        loop {
+            let selected = state.selected().unwrap_or(0);
+            let preview = previews
+                .entry(selected)
+                .or_insert_with(|| preview(working_dir, &sections[selected], head.as_deref_mut()))
+                .clone();
+            terminal
+                .draw(|frame| draw_restore_view(frame, sections, &mut state, preview, scroll))?;
+            let Some(key) = next_key()? else {
+                continue;
+            };
+            let last = sections.len() - 1;
+            let moved = match key {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('r') => return Ok(true),
+                KeyCode::Up | KeyCode::Char('k') => Some(selected.saturating_sub(1)),
+                KeyCode::Down | KeyCode::Char('j') => Some((selected + 1).min(last)),
+                KeyCode::Home => Some(0),
+                KeyCode::End => Some(last),
+                KeyCode::PageDown | KeyCode::Char(' ') => {
+                    scroll = scroll.saturating_add(20);
+                    None
+                }
+                KeyCode::PageUp => {
+                    scroll = scroll.saturating_sub(20);
+                    None
+                }
+                _ => None,
+            };
+            if let Some(index) = moved {
+                state.select(Some(index));
+                scroll = 0;
+            }
+        }
+    }
+
+    fn draw_restore_view(
+        frame: &mut Frame,
+        sections: &[Compared],
+        state: &mut ListState,
+        preview: Text<'static>,
+        scroll: u16,
+    ) {
+        let [main_area, help_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main_area);
+        let items: Vec<ListItem> = sections
+            .iter()
+            .map(|section| {
+                let color = match section.status {
+                    DiffStatus::New => Color::Green,
+                    DiffStatus::Modified => Color::Yellow,
+                    DiffStatus::Unchanged => Color::DarkGray,
+                };
+                ListItem::new(format!("{:<9} {}", section.status.as_str(), section.path))
+                    .style(Style::default().fg(color))
+            })
+            .collect();
+        let changed = sections
+            .iter()
+            .filter(|s| s.status != DiffStatus::Unchanged)
+            .count();
+        let title = format!(" {} of {} files change ", changed, sections.len());
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(highlight());
+        frame.render_stateful_widget(list, list_area, state);
+        frame.render_widget(
+            Paragraph::new(preview)
+                .block(Block::default().borders(Borders::ALL).title(" Diff "))
+                .scroll((scroll, 0)),
+            preview_area,
+        );
+        frame.render_widget(
+            Paragraph::new("↑↓ section · PgUp/PgDn scroll · r restore the bundle · q quit")
+                .style(Style::default().fg(Color::DarkGray)),
+            help_area,
+        );
+    }
+
+    /// What restoring `section` changes, as colored diff lines: against the file in the
+    /// working tree, or in the last commit if `head` is given.
+    fn preview(
+        working_dir: &Path,
+        section: &Compared,
+        head: Option<&mut HeadFiles>,
+    ) -> Text<'static> {
+        let diff = match (&section.content, section.status) {
+            (None, _) => {
+                return Text::from("A stub: restore leaves the file alone.");
+            }
+            (Some(_), DiffStatus::Unchanged) => {
+                return Text::from("The file is the same as in the bundle.");
+            }
+            (Some(content), DiffStatus::New) => content
+                .lines()
+                .map(|line| format!("+{}", line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            (Some(content), DiffStatus::Modified) => {
+                match diff_against(working_dir, &section.path, head, content) {
+                    // The header names the temporary file; the hunks say it all
+                    Ok(diff) => diff
+                        .lines()
+                        .skip_while(|line| !line.starts_with("@@"))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    Err(e) => return Text::from(format!("No diff: {:#}", e)),
+                }
+            }
+        };
+        let lines: Vec<Line> = diff
+            .lines()
+            .map(|line| {
+                let color = if line.starts_with("@@") {
+                    Color::Cyan
+                } else if line.starts_with('+') && !line.starts_with("+++") {
+                    Color::Green
+                } else if line.starts_with('-') && !line.starts_with("---") {
+                    Color::Red
+                } else {
+                    Color::Reset
+                };
+                Line::styled(line.to_string(), Style::default().fg(color))
+            })
+            .collect();
+        Text::from(lines)
+    }
+
+    /// Unified diff from the file at `path`, in the working tree or in `head`, to `content`.
+    fn diff_against(
+        working_dir: &Path,
+        path: &str,
+        head: Option<&mut HeadFiles>,
+        content: &str,
+    ) -> Result<String> {
+        let mut bundled = tempfile::NamedTempFile::new()?;
+        bundled.write_all(content.as_bytes())?;
+        bundled.flush()?;
+        match head {
+            None => {
+                let target = crate::paths::from_slash(working_dir, path);
+                crate::git::diff_files(&target, bundled.path(), 3)
+            }
+            Some(head) => {
+                let committed = head.read(path)?.unwrap_or_default();
+                let mut old = tempfile::NamedTempFile::new()?;
+                old.write_all(&committed)?;
+                old.flush()?;
+                crate::git::diff_files(old.path(), bundled.path(), 3)
+            }
+        }
+    }
+}
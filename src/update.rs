@@ -0,0 +1,205 @@
+//! `sheafy update`: regenerate an existing bundle in place while preserving
+//! reviewer annotations.
+//!
+//! Annotations are HTML comments of the form `<!-- sheafy:note ... -->` (which may
+//! span several lines) placed between sections. A note belongs to the section that
+//! follows it; notes after the last section stay at the end of the bundle. Restore
+//! never sees them because they live outside of any file section.
+//!
+//! The bundle is made again with the options it was made with, recorded in its leading
+//! `<!-- sheafy:args {...} -->` marker (options left at their defaults are not listed).
+
+use crate::error::SheafyError;
+use crate::parser::{fence_line, fence_path};
+use crate::{bundle, cli::BundleArgs, config::Config};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs};
+
+pub const NOTE_PREFIX: &str = "<!-- sheafy:note";
+/// Leading marker with the options a bundle was made with, as JSON
+pub const ARGS_PREFIX: &str = "<!-- sheafy:args";
+
+/// Options that leave the bundle's content as it is, and the output `update` sets itself
+const UNRECORDED_ARGS: &[&str] = &[
+    "output",
+    "session",
+    "max_files",
+    "jobs",
+    "io",
+    "copy_chunks",
+    "show_excluded",
+];
+
+/// Notes found in a bundle, keyed by the path of the section they precede.
+#[derive(Debug, Default)]
+pub struct Notes {
+    by_section: BTreeMap<String, Vec<String>>,
+    /// Notes after the last section
+    trailing: Vec<String>,
+}
+
+impl Notes {
+    pub fn is_empty(&self) -> bool {
+        self.by_section.is_empty() && self.trailing.is_empty()
+    }
+}
+
+pub fn run_update(config: Config, input_file: Option<String>) -> Result<()> {
+    let (_, bundle_path) = crate::restore::resolve_input(&config, input_file.as_deref())?;
+    if !bundle_path.exists() {
+        return Err(SheafyError::BundleNotFound { path: bundle_path })
+            .context("Run `sheafy bundle` first");
+    }
+    let old_content = fs::read_to_string(&bundle_path)
+        .with_context(|| format!("Failed to read bundle: {}", bundle_path.display()))?;
+    let notes = extract_notes(&old_content);
+
+    let recorded = recorded_args(&old_content)
+        .with_context(|| format!("Failed to read the options of {}", bundle_path.display()))?;
+    if recorded.files_from.as_deref() == Some("-") || recorded.prompt_file.as_deref() == Some("-") {
+        bail!(
+            "'{}' was made from standard input, which `sheafy update` cannot read again; run `sheafy bundle` instead",
+            bundle_path.display()
+        );
+    }
+    let args = BundleArgs {
+        output: Some(bundle_path.to_string_lossy().into_owned()),
+        ..recorded
+    };
+    bundle::run_bundle(config, args)?;
+
+    if notes.is_empty() {
+        return Ok(());
+    }
+    let new_content = fs::read_to_string(&bundle_path)
+        .with_context(|| format!("Failed to read bundle: {}", bundle_path.display()))?;
+    let (merged, orphaned) = insert_notes(&new_content, notes);
+    if orphaned > 0 {
+        eprintln!(
+            "Warning: {} note(s) belonged to files no longer in the bundle; moved them to the end.",
+            orphaned
+        );
+    }
+    fs::write(&bundle_path, merged)
+        .with_context(|| format!("Failed to write bundle: {}", bundle_path.display()))?;
+    status!("Preserved annotations in '{}'.", bundle_path.display());
+    Ok(())
+}
+
+/// The `<!-- sheafy:args {...} -->` marker for a bundle made with `args`, listing the
+/// options that differ from their defaults; None if none do.
+pub fn args_marker(args: &BundleArgs) -> Result<Option<String>> {
+    let Value::Object(mut given) = serde_json::to_value(args)? else {
+        bail!("Bundle options are not a JSON object");
+    };
+    let defaults = serde_json::to_value(BundleArgs::default())?;
+    given.retain(|key, value| {
+        !UNRECORDED_ARGS.contains(&key.as_str()) && defaults.get(key) != Some(value)
+    });
+    if given.is_empty() {
+        return Ok(None);
+    }
+    // `-->` in a prompt would end the comment early
+    let json = Value::Object(given).to_string().replace("-->", "--\\u003e");
+    Ok(Some(format!("{} {} -->", ARGS_PREFIX, json)))
+}
+
+/// The options recorded in a bundle's leading markers, or the defaults.
+fn recorded_args(content: &str) -> Result<BundleArgs> {
+    let recorded = crate::restore::leading_markers(content)
+        .into_iter()
+        .find_map(|marker| marker.strip_prefix(ARGS_PREFIX)?.strip_suffix("-->"));
+    match recorded {
+        Some(json) => Ok(serde_json::from_str(json.trim())?),
+        None => Ok(BundleArgs::default()),
+    }
+}
+
+/// Path of the section a line outside of code fences starts: a `## path` heading, or a
+/// fence with the path in its info string (`--header-style title` or `pandoc`).
+fn section_header(fence: Option<usize>, line: &str) -> Option<String> {
+    if fence.is_some() {
+        return None;
+    }
+    if let Some(path) = fence_path(line.trim_end()) {
+        return Some(path);
+    }
+    line.strip_prefix("##")
+        .filter(|rest| !rest.starts_with('#'))
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .and_then(crate::header::parse_path)
+}
+
+/// Collects every note block in `content`, attached to the section that follows it.
+pub fn extract_notes(content: &str) -> Notes {
+    let mut notes = Notes::default();
+    let mut pending: Vec<String> = Vec::new();
+    let mut current_note: Option<String> = None;
+    let mut fence = None;
+
+    for line in content.lines() {
+        if let Some(note) = current_note.as_mut() {
+            note.push('\n');
+            note.push_str(line);
+            if line.contains("-->") {
+                pending.push(current_note.take().unwrap());
+            }
+            continue;
+        }
+        if let Some(path) = section_header(fence, line) {
+            if !pending.is_empty() {
+                notes
+                    .by_section
+                    .entry(path)
+                    .or_default()
+                    .append(&mut pending);
+            }
+        }
+        if fence_line(&mut fence, line) {
+            continue;
+        }
+        if line.trim_start().starts_with(NOTE_PREFIX) {
+            if line.contains("-->") {
+                pending.push(line.to_string());
+            } else {
+                current_note = Some(line.to_string());
+            }
+        }
+    }
+    // An unterminated note is still kept rather than silently dropped
+    pending.extend(current_note);
+    notes.trailing = pending;
+    notes
+}
+
+/// Re-inserts notes above their sections. Returns the merged content and the
+/// number of notes whose section no longer exists (appended at the end).
+pub fn insert_notes(content: &str, mut notes: Notes) -> (String, usize) {
+    let mut merged = String::with_capacity(content.len());
+    let mut fence = None;
+    for line in content.lines() {
+        if let Some(section_notes) =
+            section_header(fence, line).and_then(|p| notes.by_section.remove(&p))
+        {
+            for note in section_notes {
+                merged.push_str(&note);
+                merged.push_str("\n\n");
+            }
+        }
+        fence_line(&mut fence, line);
+        merged.push_str(line);
+        merged.push('\n');
+    }
+
+    let mut orphaned: Vec<String> = notes.by_section.into_values().flatten().collect();
+    let orphaned_count = orphaned.len();
+    orphaned.append(&mut notes.trailing);
+    for note in orphaned {
+        merged.push('\n');
+        merged.push_str(&note);
+        merged.push('\n');
+    }
+    (merged, orphaned_count)
+}
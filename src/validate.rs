@@ -0,0 +1,202 @@
+//! `restore --validate`: check what a bundle would produce before writing it.
+//!
+//! The working tree (minus ignored files) is copied to a temporary directory, the
+//! bundle is restored there, and a check command runs for each language the bundle
+//! holds files of, e.g. `cargo check` for Rust. Only if every check passes is the
+//! bundle restored into the real tree. The commands come from `[sheafy.restore.checks]`,
+//! over built-in presets for Rust, TypeScript and Python.
+
+use crate::config::Config;
+use crate::error::SheafyError;
+use crate::restore::{parse_failed, RestoreOptions};
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Check commands used unless `[sheafy.restore.checks]` says otherwise, by fence language.
+const PRESETS: &[(&str, &str)] = &[
+    ("rust", "cargo check --quiet"),
+    ("typescript", "tsc --noEmit"),
+    ("python", "python3 -m compileall -q ."),
+];
+
+/// Directories linked into the temporary tree instead of copied: ignored, but needed
+/// by the checks (`tsc` resolves types from `node_modules`).
+const LINKED_DIRS: &[&str] = &["node_modules"];
+
+/// Lines of a failing check's output shown
+const OUTPUT_LINES: usize = 40;
+
+pub struct Validation {
+    /// Command by language; an empty command turns a preset off
+    checks: BTreeMap<String, String>,
+}
+
+impl Validation {
+    pub fn from_config(config: &Config) -> Self {
+        let mut checks: BTreeMap<String, String> = PRESETS
+            .iter()
+            .map(|(language, command)| (language.to_string(), command.to_string()))
+            .collect();
+        checks.extend(config.sheafy.restore.checks.clone());
+        checks.retain(|_, command| !command.trim().is_empty());
+        Self { checks }
+    }
+
+    /// Restores `bundle` into a copy of `working_dir` and runs the checks for the
+    /// languages it holds; fails, naming the check, if any of them fails.
+    pub fn run(&self, working_dir: &Path, bundle: &Path, options: &RestoreOptions) -> Result<()> {
+        let staging = tempfile::Builder::new()
+            .prefix("sheafy-validate-")
+            .tempdir()
+            .context("Failed to create a temporary directory to validate in")?;
+        copy_tree(working_dir, staging.path())?;
+        let languages = stage(bundle, staging.path(), options)?;
+        let checks: Vec<(&String, &String)> = self
+            .checks
+            .iter()
+            .filter(|(language, _)| languages.contains_key(language.as_str()))
+            .collect();
+        if checks.is_empty() {
+            status!("No validation checks apply to the files in the bundle.");
+            return Ok(());
+        }
+        for (language, command) in checks {
+            status!(
+                "Validating {} {} file(s) with `{}`...",
+                languages[language.as_str()],
+                language,
+                command
+            );
+            let output = shell(command)
+                .current_dir(staging.path())
+                // Reuse the project's build cache rather than building from scratch
+                .env(
+                    "CARGO_TARGET_DIR",
+                    std::env::var_os("CARGO_TARGET_DIR")
+                        .unwrap_or_else(|| working_dir.join("target").into_os_string()),
+                )
+                .stdin(Stdio::null())
+                .output()
+                .with_context(|| format!("Failed to run validation check `{}`", command))?;
+            if !output.status.success() {
+                let text = String::from_utf8_lossy(&output.stdout).into_owned()
+                    + &String::from_utf8_lossy(&output.stderr);
+                let lines: Vec<&str> = text.lines().collect();
+                for line in &lines[lines.len().saturating_sub(OUTPUT_LINES)..] {
+                    eprintln!("  | {}", line);
+                }
+                return Err(SheafyError::ValidationFailed {
+                    command: command.clone(),
+                })
+                .with_context(|| {
+                    format!(
+                        "'{}' did not pass validation ({})",
+                        bundle.display(),
+                        output.status
+                    )
+                });
+            }
+        }
+        status!("Validation passed.");
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", command]);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
+
+/// Copies the files of `from` that git would not ignore into `to`. Build output
+/// (`target`, which the checks share through `CARGO_TARGET_DIR`) is never copied, even
+/// outside a git repository.
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    for entry in WalkBuilder::new(from)
+        .hidden(false)
+        .filter_entry(|entry| {
+            let name = entry.file_name();
+            name != ".git" && name != "target" && !LINKED_DIRS.iter().any(|dir| name == *dir)
+        })
+        .build()
+    {
+        let entry = entry?;
+        let Ok(rel) = entry.path().strip_prefix(from) else {
+            continue;
+        };
+        let target = to.join(rel);
+        if entry.file_type().is_some_and(|t| t.is_dir()) {
+            fs::create_dir_all(&target)?;
+        } else if entry.file_type().is_some_and(|t| t.is_file()) {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("Failed to copy {} to validate", rel.display()))?;
+        }
+    }
+    for dir in LINKED_DIRS {
+        let source = from.join(dir);
+        if source.is_dir() && !to.join(dir).exists() {
+            link_dir(&source, &to.join(dir))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn link_dir(source: &Path, link: &Path) -> Result<()> {
+    Ok(std::os::unix::fs::symlink(source, link)?)
+}
+
+#[cfg(windows)]
+fn link_dir(source: &Path, link: &Path) -> Result<()> {
+    Ok(std::os::windows::fs::symlink_dir(source, link)?)
+}
+
+/// Writes the files `bundle` would restore under `root`, returning how many there are
+/// of each language.
+fn stage(bundle: &Path, root: &Path, options: &RestoreOptions) -> Result<BTreeMap<String, usize>> {
+    let mut languages = BTreeMap::new();
+    for section in options.reread_bundle(bundle)? {
+        let section = options.map_path(section.with_context(|| parse_failed(bundle))?);
+        // Paths restore would refuse or that would leave the temporary tree
+        if section.preview
+            || section.truncated
+            || section.stub.is_some()
+            || crate::restore::escapes(&section.path)
+            || section.path.split('/').any(str::is_empty)
+        {
+            continue;
+        }
+        if options
+            .allowed_paths
+            .as_ref()
+            .is_some_and(|allowed| !allowed.permits(&section.path))
+        {
+            continue;
+        }
+        let path = section.path.clone();
+        let content = section.into_file_content(options)?;
+        let language = crate::sniff::language_hint(Path::new(&path), &content);
+        *languages.entry(language.to_string()).or_default() += 1;
+        let target = crate::paths::from_slash(root, &path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, content)
+            .with_context(|| format!("Failed to stage {} to validate", path))?;
+    }
+    Ok(languages)
+}
@@ -0,0 +1,99 @@
+//! Template variables: `{{name}}` placeholders that restore fills in, turning a bundle
+//! into a small project template.
+//!
+//! A bundle opts in by declaring its variables in the front matter, as
+//! `vars: [name, author]` (`bundle --template-vars name,author` writes that line). Only
+//! declared names are replaced, in file contents and paths alike, so other `{{...}}`
+//! text (Handlebars, Jinja) is left alone. Values come from `[sheafy.restore.vars]` and
+//! `restore --var name=value`, the latter winning; a declared variable without a value
+//! fails the restore before anything is written.
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+
+lazy_static! {
+    static ref PLACEHOLDER_REGEX: Regex =
+        Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap();
+}
+
+/// Front matter key declaring a bundle's variables
+pub const FRONT_MATTER_KEY: &str = "vars";
+
+/// The values of a bundle's declared variables.
+#[derive(Debug, Clone)]
+pub struct Template {
+    values: BTreeMap<String, String>,
+}
+
+impl Template {
+    /// The template of a bundle declaring `declared` (the front matter value), with
+    /// values from `given`. Fails naming every declared variable without a value.
+    pub fn new(declared: &str, given: &BTreeMap<String, String>) -> Result<Self> {
+        let names = parse_list(declared);
+        let missing: Vec<&str> = names
+            .iter()
+            .filter(|name| !given.contains_key(*name))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "The bundle is a template; give {} with --var {}=... or in [sheafy.restore.vars]",
+                missing
+                    .iter()
+                    .map(|name| format!("{{{{{}}}}}", name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                missing[0]
+            );
+        }
+        let values = names
+            .into_iter()
+            .map(|name| {
+                let value = given[&name].clone();
+                (name, value)
+            })
+            .collect();
+        Ok(Self { values })
+    }
+
+    /// `text` with the declared placeholders replaced.
+    pub fn apply(&self, text: &str) -> String {
+        PLACEHOLDER_REGEX
+            .replace_all(text, |caps: &Captures| match self.values.get(&caps[1]) {
+                Some(value) => value.clone(),
+                None => caps[0].to_string(),
+            })
+            .into_owned()
+    }
+}
+
+/// Parses `--var` arguments (`name=value`) over the values from the config.
+pub fn merge(
+    config_vars: &BTreeMap<String, String>,
+    args: &[String],
+) -> Result<BTreeMap<String, String>> {
+    let mut values = config_vars.clone();
+    for arg in args {
+        let Some((name, value)) = arg.split_once('=') else {
+            bail!("--var expects name=value, got '{}'", arg);
+        };
+        values.insert(name.trim().to_string(), value.to_string());
+    }
+    Ok(values)
+}
+
+/// The names of a YAML flow list (`[a, b]`) or of a comma-separated line.
+fn parse_list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(value);
+    value
+        .split(',')
+        .map(|name| name.trim().trim_matches(['"', '\'']).to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
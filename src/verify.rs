@@ -0,0 +1,183 @@
+//! `sheafy verify`: check that a bundle can be read by this version of sheafy and
+//! report its format version and the format features it uses.
+
+use crate::config::Config;
+use crate::parser::fence_line;
+use crate::restore::{
+    format_version, leading_markers, parse_sections, skip_front_matter, CHECKSUMS_HEADING,
+    CONFLICTS_MARKER, HANDLER_PREFIX, LINKS_PREFIX, NO_EOL_MARKER, PART_PREFIX, PATCH_MARKER,
+    PREVIEW_PREFIX, PROMPT_END_MARKER, PROMPT_MARKER, READONLY_MARKER, SAME_AS_PREFIX,
+    STATS_PREFIX, STUB_PREFIX, WRAP_PREFIX,
+};
+use crate::update::NOTE_PREFIX;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+};
+
+pub fn run_verify(config: &Config, input_file: Option<&str>) -> Result<()> {
+    let (_, bundle_path) = crate::restore::resolve_input(config, input_file)?;
+    let content = fs::read_to_string(&bundle_path)
+        .with_context(|| format!("Failed to read bundle: {}", bundle_path.display()))?;
+
+    let version = format_version(&content);
+    status!("Bundle: {}", bundle_path.display());
+    if leading_markers(&content)
+        .iter()
+        .any(|m| m.starts_with("<!-- sheafy:v"))
+    {
+        status!("Format: v{}", version);
+    } else {
+        status!("Format: v{} (no version marker)", version);
+    }
+    // Rejects bundles newer than FORMAT_VERSION
+    let sections = parse_sections(&content)?;
+    let scan = scan(&content);
+    status!("Sections: {}", sections.len());
+    if scan.features.is_empty() {
+        status!("Features: none beyond headings and fences");
+    } else {
+        status!("Features:");
+        for (feature, count) in &scan.features {
+            status!("  {} ({})", feature, count);
+        }
+    }
+    for warning in &scan.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    for problem in &scan.problems {
+        eprintln!("Error: {}", problem);
+    }
+    if sections.is_empty() {
+        bail!("No file sections found in '{}'", bundle_path.display());
+    }
+    if !scan.problems.is_empty() {
+        bail!(
+            "{} problem(s) found in '{}'",
+            scan.problems.len(),
+            bundle_path.display()
+        );
+    }
+    status!("OK: '{}' can be restored.", bundle_path.display());
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Scan {
+    /// Feature name -> number of occurrences
+    features: BTreeMap<&'static str, usize>,
+    warnings: Vec<String>,
+    problems: Vec<String>,
+}
+
+/// Walks the bundle outside of code fences, recording which format features appear.
+fn scan(content: &str) -> Scan {
+    let mut scan = Scan::default();
+    let body = skip_front_matter(content);
+    if body.len() != content.trim_start_matches('\u{feff}').len() {
+        scan.features.insert("front matter", 1);
+    }
+    let mut seen_paths: HashSet<String> = HashSet::new();
+    let mut fence = None;
+    let mut in_prompt = false;
+    for line in body.lines() {
+        if in_prompt {
+            in_prompt = line.trim() != PROMPT_END_MARKER;
+            continue;
+        }
+        if fence.is_none() && line.trim() == PROMPT_MARKER {
+            *scan.features.entry("task prompt").or_default() += 1;
+            in_prompt = true;
+            continue;
+        }
+        let opening = fence.is_none();
+        if fence_line(&mut fence, line) {
+            let info = line.trim_start_matches('`');
+            if opening && (info.contains("title=\"") || info.contains("filename=\"")) {
+                *scan
+                    .features
+                    .entry("paths in fence info strings")
+                    .or_default() += 1;
+                if let Some(path) = info_path(info) {
+                    seen_paths.insert(crate::header::normalize(path));
+                }
+            }
+            continue;
+        }
+        let trimmed = line.trim();
+        if let Some(path) = trimmed
+            .strip_prefix("##")
+            .filter(|r| !r.starts_with('#'))
+            .and_then(crate::header::parse_path)
+        {
+            if path == CHECKSUMS_HEADING {
+                *scan.features.entry("checksums").or_default() += 1;
+            } else if path.is_empty() {
+                scan.problems
+                    .push("Section heading with an empty path".to_string());
+            } else if !seen_paths.insert(path.clone()) {
+                scan.warnings.push(format!(
+                    "'{}' appears more than once; the last copy wins",
+                    path
+                ));
+            }
+        } else if let Some(target) = trimmed.strip_prefix(SAME_AS_PREFIX) {
+            let target = crate::header::normalize(target.trim_end_matches("-->").trim());
+            *scan.features.entry("same-as references").or_default() += 1;
+            if !seen_paths.contains(&target) {
+                scan.problems.push(format!(
+                    "same-as reference to '{}', which is not an earlier section",
+                    target
+                ));
+            }
+        } else if trimmed.starts_with(PREVIEW_PREFIX) {
+            *scan.features.entry("previews").or_default() += 1;
+        } else if trimmed.starts_with(WRAP_PREFIX) {
+            *scan.features.entry("files with wrapped lines").or_default() += 1;
+        } else if trimmed.starts_with(HANDLER_PREFIX) {
+            *scan.features.entry("handler-encoded sections").or_default() += 1;
+        } else if trimmed.starts_with(STATS_PREFIX) {
+            *scan.features.entry("file statistics").or_default() += 1;
+        } else if trimmed.starts_with(STUB_PREFIX) {
+            *scan.features.entry("stub sections").or_default() += 1;
+        } else if trimmed.starts_with(LINKS_PREFIX) {
+            *scan.features.entry("rewritten links").or_default() += 1;
+        } else if trimmed == NO_EOL_MARKER {
+            *scan
+                .features
+                .entry("files without a final newline")
+                .or_default() += 1;
+        } else if let Some(part) = trimmed.strip_prefix(PART_PREFIX) {
+            if part.trim_start().starts_with("1/") {
+                *scan.features.entry("files split into parts").or_default() += 1;
+            }
+        } else if trimmed.starts_with(NOTE_PREFIX) {
+            *scan.features.entry("notes").or_default() += 1;
+        } else if trimmed == READONLY_MARKER {
+            *scan.features.entry("read-only excerpts").or_default() += 1;
+        } else if trimmed == PATCH_MARKER {
+            *scan.features.entry("patch sections").or_default() += 1;
+        } else if trimmed == CONFLICTS_MARKER {
+            *scan.features.entry("conflict report").or_default() += 1;
+        } else if trimmed.starts_with("<!-- sheafy:v") {
+            // Version marker, reported separately
+        } else if trimmed.starts_with("<!-- sheafy:") {
+            scan.warnings.push(format!(
+                "Unknown directive '{}' (written by a newer sheafy?)",
+                trimmed
+            ));
+        }
+    }
+    scan
+}
+
+/// Path from a `title="..."` or `filename="..."` fence attribute.
+fn info_path(info: &str) -> Option<&str> {
+    let start = info
+        .find("title=\"")
+        .map(|i| i + "title=\"".len())
+        .or_else(|| info.find("filename=\"").map(|i| i + "filename=\"".len()))?;
+    let rest = &info[start..];
+    rest.find('"').map(|end| rest[..end].trim())
+}
@@ -0,0 +1,258 @@
+//! In-memory caches kept warm by `sheafy daemon --socket` between requests.
+//!
+//! Walking a large tree (and matching every path against the ignore rules) is what makes
+//! a cold bundle slow. The daemon remembers each walk with the modification times of the
+//! directories it entered and of their `.gitignore`/`.ignore` files. Adding, removing or
+//! renaming an entry changes its directory's time, so if none of them changed, the next
+//! request with the same config reuses the file list instead of walking again. Ignore
+//! rules read from elsewhere (parent directories, `.git/info/exclude`, git's global
+//! excludes file) are part of the walk's key with their contents. Per-file
+//! statistics (size, lines, estimated tokens, SHA-256) are cached by size and
+//! modification time. A file modified within [`RACY_WINDOW`] of being cached could have
+//! changed again without its time changing on filesystems with coarse timestamps, so its
+//! SHA-256 is checked before the statistics are reused; `--paranoid` checks every file
+//! that way. Outside the daemon the caches are disabled and cost nothing.
+
+use crate::tokens::Counter;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Ignore files whose changes invalidate a walk, besides the directories themselves
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// Files modified less than this before their statistics were cached are checked by
+/// hash: FAT and some network filesystems keep modification times in 2 s steps
+pub const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Cache {
+    /// By walk key (the config and walk-relevant arguments)
+    walks: HashMap<String, Walk>,
+    files: HashMap<PathBuf, FileStats>,
+    /// Check every file's SHA-256 before reusing its statistics
+    paranoid: bool,
+    counts: Counts,
+}
+
+/// How often the caches were used, since the daemon started.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Counts {
+    pub walk_hits: usize,
+    pub walk_misses: usize,
+    pub file_hits: usize,
+    pub file_misses: usize,
+    /// File hits whose SHA-256 was checked first (paranoid or racy)
+    pub verified: usize,
+}
+
+impl Counts {
+    /// One line for the daemon's verbose output.
+    pub fn describe(&self) -> String {
+        format!(
+            "Cache: walks {} hit(s), {} miss(es); files {} hit(s) ({} checked by hash), {} miss(es)",
+            self.walk_hits, self.walk_misses, self.file_hits, self.verified, self.file_misses
+        )
+    }
+}
+
+struct Walk {
+    stamps: Vec<(PathBuf, Stamp)>,
+    files: Vec<PathBuf>,
+}
+
+/// Modification times of a directory and of its ignore files
+type Stamp = Vec<Option<SystemTime>>;
+
+fn stamp(dir: &Path) -> Stamp {
+    std::iter::once(dir.to_path_buf())
+        .chain(IGNORE_FILES.iter().map(|name| dir.join(name)))
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// The ignore files a walk of `working_dir` reads outside of the directories it enters,
+/// with the SHA-256 of their contents (None for those that don't exist): `.gitignore`
+/// and `.ignore` of the parent directories, the repository's `.git/info/exclude` and
+/// git's global excludes file. Empty when the caches are off.
+pub fn outside_ignores(working_dir: &Path) -> Vec<(PathBuf, Option<String>)> {
+    if !is_enabled() {
+        return Vec::new();
+    }
+    let mut paths: Vec<PathBuf> = working_dir
+        .ancestors()
+        .skip(1)
+        .flat_map(|dir| IGNORE_FILES.iter().map(move |name| dir.join(name)))
+        .collect();
+    if let Some(git_dir) = working_dir.ancestors().find_map(git_dir) {
+        paths.push(git_dir.join("info").join("exclude"));
+    }
+    paths.extend(ignore::gitignore::gitconfig_excludes_path());
+    paths
+        .into_iter()
+        .map(|path| {
+            let hash = fs::read(&path)
+                .ok()
+                .map(|content| crate::stub::sha256_hex(&content));
+            (path, hash)
+        })
+        .collect()
+}
+
+/// The git directory of a repository rooted at `dir`: `.git`, or where a `.git` file
+/// (of a worktree or submodule) points.
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let text = fs::read_to_string(&dot_git).ok()?;
+    let target = text.trim().strip_prefix("gitdir:")?.trim();
+    Some(dir.join(target))
+}
+
+/// Size and content statistics of one file.
+#[derive(Clone, Debug)]
+pub struct FileStats {
+    pub bytes: u64,
+    pub lines: usize,
+    pub tokens: usize,
+    pub sha256: String,
+    modified: Option<SystemTime>,
+    /// When the statistics were computed or last checked
+    checked: SystemTime,
+    /// What counted the tokens ([`Counter::name`])
+    counted_by: String,
+}
+
+/// Turns the caches on for the rest of the process; `paranoid` checks the SHA-256 of
+/// every file before reusing its statistics.
+pub fn enable(paranoid: bool) {
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(Cache::default);
+    cache.paranoid = paranoid;
+}
+
+pub fn is_enabled() -> bool {
+    CACHE.lock().unwrap().is_some()
+}
+
+/// How often the caches were used so far, if they are on.
+pub fn counts() -> Option<Counts> {
+    CACHE.lock().unwrap().as_ref().map(|cache| cache.counts)
+}
+
+/// The files of the walk stored under `key`, if no directory it entered has changed.
+pub fn cached_walk(key: &str) -> Option<Vec<PathBuf>> {
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.as_mut()?;
+    let files = cache.walks.get(key).and_then(|walk| {
+        walk.stamps
+            .iter()
+            .all(|(dir, stamp)| self::stamp(dir) == *stamp)
+            .then(|| walk.files.clone())
+    });
+    match files {
+        Some(_) => cache.counts.walk_hits += 1,
+        None => cache.counts.walk_misses += 1,
+    }
+    files
+}
+
+/// Remembers a walk that entered `dirs` and found `files`.
+pub fn store_walk(key: String, dirs: Vec<PathBuf>, files: &[PathBuf]) {
+    let mut cache = CACHE.lock().unwrap();
+    let Some(cache) = cache.as_mut() else {
+        return;
+    };
+    let stamps = dirs
+        .into_iter()
+        .map(|dir| {
+            let stamp = stamp(&dir);
+            (dir, stamp)
+        })
+        .collect();
+    cache.walks.insert(
+        key,
+        Walk {
+            stamps,
+            files: files.to_vec(),
+        },
+    );
+}
+
+/// Statistics of the file at `path`, from the cache while its size and modification
+/// time are unchanged and the tokens were counted the same way. The cached SHA-256 is
+/// checked against the file first when paranoid or when the modification time is too
+/// close to when the statistics were taken to be trusted.
+pub fn file_stats(path: &Path, counter: &Counter) -> std::io::Result<FileStats> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified().ok();
+    let mut candidate = None;
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        if let Some(stats) = cache.files.get(path) {
+            if stats.bytes == metadata.len()
+                && stats.modified == modified
+                && modified.is_some()
+                && stats.counted_by == counter.name()
+            {
+                if !cache.paranoid && !stats.is_racy() {
+                    cache.counts.file_hits += 1;
+                    return Ok(stats.clone());
+                }
+                candidate = Some(stats.clone());
+            }
+        }
+    }
+    let content = fs::read(path)?;
+    let sha256 = crate::stub::sha256_hex(&content);
+    let verified = candidate.filter(|stats| stats.sha256 == sha256);
+    let hit = verified.is_some();
+    let stats = match verified {
+        Some(stats) => FileStats {
+            checked: SystemTime::now(),
+            ..stats
+        },
+        None => {
+            let text = String::from_utf8_lossy(&content);
+            FileStats {
+                bytes: content.len() as u64,
+                lines: text.lines().count(),
+                tokens: counter.count(&text),
+                sha256,
+                modified,
+                checked: SystemTime::now(),
+                counted_by: counter.name(),
+            }
+        }
+    };
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        if hit {
+            cache.counts.file_hits += 1;
+            cache.counts.verified += 1;
+        } else {
+            cache.counts.file_misses += 1;
+        }
+        cache.files.insert(path.to_path_buf(), stats.clone());
+    }
+    Ok(stats)
+}
+
+impl FileStats {
+    /// Whether the file was modified so shortly before the statistics were taken that
+    /// a later change might not have moved its modification time.
+    fn is_racy(&self) -> bool {
+        self.modified.is_none_or(|modified| {
+            self.checked
+                .duration_since(modified)
+                .map_or(true, |age| age < RACY_WINDOW)
+        })
+    }
+}
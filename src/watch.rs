@@ -0,0 +1,225 @@
+//! `sheafy bundle --watch`: bundles again whenever a file changes.
+//!
+//! The tree is polled: each round walks it (with the git ignore rules) and compares the
+//! size and modification time of every file with the previous round, so nothing but the
+//! walk costs anything while the project is idle. With `--session FILE`, every bundle
+//! after the first also appends the changes to the bundled files since the one before,
+//! as diffs under a timestamped heading. Pasting what was appended since the last
+//! message keeps a long chat up to date without sending the whole bundle again.
+
+use crate::bundle::{absolute_output, bundle_path, BundleSummary};
+use crate::cli::BundleArgs;
+use crate::config::Config;
+use crate::parser::fence_for;
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tempfile::TempDir;
+
+/// How often the tree is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size and modification time per file, relative to the working directory
+type Fingerprint = BTreeMap<PathBuf, (u64, Option<SystemTime>)>;
+
+pub fn run_watch(args: BundleArgs) -> Result<()> {
+    let config = Config::load().context("Failed to load configuration")?;
+    let working_dir = config.get_working_dir()?;
+    // Writing these must not count as a change
+    let mut skip = vec![bundle_path(&config, args.output.as_deref(), &working_dir)?];
+    let mut session = match &args.session {
+        Some(path) => {
+            let path = absolute_output(path, &working_dir)?;
+            skip.push(path.clone());
+            Some(Session::new(path)?)
+        }
+        None => None,
+    };
+    let mut last = None;
+    loop {
+        let fingerprint = fingerprint(&working_dir, &skip);
+        if last.as_ref() != Some(&fingerprint) {
+            // Reloaded each round, so edits to sheafy.toml apply
+            let config = Config::load().context("Failed to load configuration")?;
+            match crate::bundle::run_bundle(config, args.clone()) {
+                Ok(summary) => {
+                    if let Some(session) = &mut session {
+                        session.record(&working_dir, &summary)?;
+                    }
+                }
+                // A broken edit shouldn't end the session; the next change may fix it
+                Err(e) => eprintln!("Warning: Bundling failed: {:#}", e),
+            }
+            last = Some(fingerprint);
+            status!(
+                "\nWatching {} for changes (Ctrl-C to stop)...",
+                working_dir.display()
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The files of the tree below `working_dir`, but for `skip` and sheafy's state.
+fn fingerprint(working_dir: &Path, skip: &[PathBuf]) -> Fingerprint {
+    let mut files = Fingerprint::new();
+    let walker = WalkBuilder::new(working_dir)
+        .filter_entry(|entry| !(entry.depth() == 1 && entry.file_name() == crate::state::STATE_DIR))
+        .build();
+    for entry in walker.flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) || skip.iter().any(|p| p == entry.path())
+        {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if let Ok(rel_path) = entry.path().strip_prefix(working_dir) {
+            files.insert(
+                rel_path.to_path_buf(),
+                (metadata.len(), metadata.modified().ok()),
+            );
+        }
+    }
+    files
+}
+
+/// The session file with copies of the files as last bundled, to diff against.
+struct Session {
+    path: PathBuf,
+    snapshot: TempDir,
+    /// Files of the last bundle; `None` before the first
+    files: Option<Vec<PathBuf>>,
+}
+
+impl Session {
+    fn new(path: PathBuf) -> Result<Self> {
+        Ok(Self {
+            path,
+            snapshot: tempfile::tempdir().context("Failed to create a temporary directory")?,
+            files: None,
+        })
+    }
+
+    /// Appends the changes of the bundled files since the last bundle, and takes a new
+    /// snapshot of them.
+    fn record(&mut self, working_dir: &Path, summary: &BundleSummary) -> Result<()> {
+        let now = crate::timestamp::rfc3339(crate::timestamp::now_unix());
+        let mut record = String::new();
+        let changes = match &self.files {
+            None => {
+                record.push_str(&format!(
+                    "## {}: bundled {} file(s) to {}\n",
+                    now,
+                    summary.files.len(),
+                    summary.output.display()
+                ));
+                0
+            }
+            Some(previous) => {
+                let mut changes = 0;
+                for rel_path in &summary.files {
+                    let old = self.snapshot.path().join(rel_path);
+                    let new = working_dir.join(rel_path);
+                    let (label, diff) = if old.is_file() {
+                        ("", crate::git::diff_files(&old, &new, 3)?)
+                    } else {
+                        (
+                            " (new file)",
+                            crate::git::diff_files(Path::new("/dev/null"), &new, 3)?,
+                        )
+                    };
+                    if diff.is_empty() {
+                        continue;
+                    }
+                    changes += 1;
+                    record.push_str(&section(rel_path, label, &hunks(&diff)));
+                }
+                for rel_path in previous {
+                    if !summary.files.contains(rel_path) {
+                        changes += 1;
+                        let label = if working_dir.join(rel_path).exists() {
+                            " (no longer bundled)"
+                        } else {
+                            " (deleted)"
+                        };
+                        record.push_str(&format!(
+                            "\n### {}{}\n",
+                            crate::paths::to_slash(rel_path),
+                            label
+                        ));
+                    }
+                }
+                if changes > 0 {
+                    record.insert_str(0, &format!("## {}: {} file(s) changed\n", now, changes));
+                }
+                changes
+            }
+        };
+        if !record.is_empty() {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| {
+                    format!("Failed to open session file '{}'", self.path.display())
+                })?;
+            if file.metadata()?.len() > 0 {
+                writeln!(file)?;
+            }
+            file.write_all(record.as_bytes())?;
+            if changes > 0 {
+                status!("Appended {} change(s) to {}", changes, self.path.display());
+            }
+        }
+        self.take_snapshot(working_dir, &summary.files)
+    }
+
+    fn take_snapshot(&mut self, working_dir: &Path, files: &[PathBuf]) -> Result<()> {
+        let snapshot = tempfile::tempdir().context("Failed to create a temporary directory")?;
+        for rel_path in files {
+            let copy = snapshot.path().join(rel_path);
+            if let Some(parent) = copy.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // A file removed since bundling is simply missing next time
+            let _ = fs::copy(working_dir.join(rel_path), &copy);
+        }
+        self.snapshot = snapshot;
+        self.files = Some(files.to_vec());
+        Ok(())
+    }
+}
+
+/// The hunks of a `git diff` without its header, which names the snapshot's paths.
+fn hunks(diff: &str) -> String {
+    match diff.find("\n@@") {
+        Some(start) => diff[start + 1..].to_string(),
+        // Binary files have no hunks
+        None => "Binary file changed.\n".to_string(),
+    }
+}
+
+fn section(rel_path: &Path, label: &str, hunks: &str) -> String {
+    let fence = fence_for(hunks);
+    let mut section = format!(
+        "\n### {}{}\n{}diff\n{}",
+        crate::paths::to_slash(rel_path),
+        label,
+        fence,
+        hunks
+    );
+    if !hunks.ends_with('\n') {
+        section.push('\n');
+    }
+    section.push_str(&fence);
+    section.push('\n');
+    section
+}
@@ -0,0 +1,128 @@
+//! Tests of the inflater on streams written by zlib (Python's `zlib` and `gzip` modules),
+//! one for each block type the archives of other tools may contain.
+
+use sheafy::deflate::{crc32, decompress};
+
+/// `zlib.compress(STORED_TEXT, 0)`: a zlib header and one stored block.
+const STORED_ZLIB: &[u8] = &[
+    0x78, 0x01, 0x01, 0x25, 0x00, 0xda, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x20, 0x62, 0x6c,
+    0x6f, 0x63, 0x6b, 0x73, 0x20, 0x61, 0x72, 0x65, 0x20, 0x63, 0x6f, 0x70, 0x69, 0x65, 0x64, 0x20,
+    0x61, 0x73, 0x20, 0x74, 0x68, 0x65, 0x79, 0x20, 0x61, 0x72, 0x65, 0x0a, 0x07, 0xad, 0x0d, 0x4c,
+];
+const STORED_TEXT: &[u8] = b"stored blocks are copied as they are\n";
+
+/// Raw deflate of `FIXED_TEXT` at level 9: one block with the fixed Huffman codes.
+const FIXED: &[u8] = &[
+    0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x85, 0xb4, 0xcc, 0x8a, 0xd4, 0x14, 0x85, 0xe4, 0xfc, 0x94,
+    0xd4, 0x62, 0x2e, 0x00,
+];
+const FIXED_TEXT: &[u8] = b"abcabcabcabc fixed codes\n";
+
+/// Raw deflate of `items()` at level 9: one block with dynamic Huffman codes.
+const DYNAMIC: &[u8] = &[
+    0x5d, 0x93, 0xa1, 0x0e, 0x42, 0x31, 0x0c, 0x45, 0x3d, 0x5f, 0x31, 0x09, 0x82, 0x64, 0x6d, 0xb7,
+    0x6e, 0x33, 0xfc, 0x0a, 0x8a, 0x97, 0x20, 0x40, 0x81, 0x22, 0xfc, 0x3b, 0x09, 0x41, 0xdc, 0x83,
+    0x3e, 0xe9, 0xda, 0x9e, 0xde, 0x6d, 0xf7, 0x72, 0x7d, 0x5c, 0x6e, 0xe7, 0xba, 0x3f, 0x94, 0xe3,
+    0xa9, 0x3c, 0xc3, 0xcb, 0xab, 0xd4, 0xf2, 0xde, 0x6d, 0x3f, 0x60, 0x0a, 0x4c, 0x80, 0x2b, 0x68,
+    0x02, 0x42, 0xc1, 0x12, 0xd0, 0xf0, 0x54, 0x0a, 0xe9, 0x4a, 0xbc, 0x0b, 0x49, 0x25, 0xa1, 0x35,
+    0x03, 0xfd, 0xb5, 0xcf, 0x54, 0x92, 0x3a, 0xda, 0x52, 0x32, 0x75, 0x1b, 0x83, 0x00, 0xab, 0x50,
+    0x40, 0x07, 0x8e, 0x3a, 0x68, 0xb0, 0xa6, 0xdd, 0x2c, 0xb8, 0xb0, 0xce, 0x68, 0x94, 0xb1, 0x74,
+    0x33, 0xa3, 0x0e, 0xf8, 0xb0, 0xa4, 0x2a, 0xd4, 0x41, 0x89, 0x4f, 0xf4, 0x83, 0x94, 0x70, 0xcc,
+    0xb9, 0x28, 0x19, 0x57, 0x86, 0x97, 0x06, 0x2f, 0x0e, 0x2f, 0xad, 0xa1, 0x8e, 0xf1, 0x98, 0xda,
+    0xcf, 0xe1, 0xa5, 0xbb, 0xce, 0xe9, 0xf0, 0xd2, 0x87, 0xee, 0xe7, 0xf0, 0x92, 0xf0, 0xe2, 0xf0,
+    0x92, 0xac, 0x83, 0x97, 0xc1, 0x7e, 0xf0, 0x32, 0x38, 0x27, 0xe3, 0x82, 0xfd, 0x02, 0x5e, 0x16,
+    0xbc, 0x04, 0xbc, 0x2c, 0xf8, 0x0c, 0xe6, 0xa5, 0xe2, 0x10, 0xc1, 0xc0, 0x54, 0x5c, 0x30, 0x98,
+    0x18, 0xc3, 0xe9, 0xa3, 0x33, 0xa2, 0x70, 0x13, 0x49, 0x88, 0xb0, 0x05, 0xe4, 0x58, 0x20, 0xa5,
+    0x31, 0xff, 0xd2, 0x8d, 0x69, 0xa1, 0xc7, 0xfa, 0xf7, 0x5f, 0x7c, 0x00,
+];
+
+/// Raw deflate of `CROSS_TEXT` twice, with a `Z_SYNC_FLUSH` between the copies: the
+/// second block is a back-reference into the first, past the empty stored block between.
+const CROSS: &[u8] = &[
+    0x0c, 0xc9, 0xc1, 0x0d, 0xc0, 0x20, 0x0c, 0x03, 0xc0, 0x55, 0x3c, 0x00, 0x13, 0xb0, 0x4d, 0x01,
+    0x23, 0x50, 0x51, 0x82, 0x12, 0xef, 0xaf, 0xf6, 0xbe, 0xa7, 0x45, 0xcc, 0x1d, 0x29, 0xb4, 0xe3,
+    0xfd, 0x05, 0x6d, 0x24, 0x16, 0x83, 0x05, 0xfa, 0x2b, 0xd9, 0xdd, 0x06, 0xdc, 0x88, 0xe0, 0xe5,
+    0xa3, 0xc4, 0x56, 0xc5, 0x07, 0x00, 0x00, 0xff, 0xff, 0x2b, 0x21, 0x4b, 0x17, 0x00,
+];
+const CROSS_TEXT: &[u8] = b"the first block ends here, the second one repeats it: ";
+
+/// `gzip.compress(items(), mtime=0)`.
+const GZIP: &[u8] = &[
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x5d, 0x93, 0xa1, 0x0e, 0x42, 0x31,
+    0x0c, 0x45, 0x3d, 0x5f, 0x31, 0x09, 0x82, 0x64, 0x6d, 0xb7, 0x6e, 0x33, 0xfc, 0x0a, 0x8a, 0x97,
+    0x20, 0x40, 0x81, 0x22, 0xfc, 0x3b, 0x09, 0x41, 0xdc, 0x83, 0x3e, 0xe9, 0xda, 0x9e, 0xde, 0x6d,
+    0xf7, 0x72, 0x7d, 0x5c, 0x6e, 0xe7, 0xba, 0x3f, 0x94, 0xe3, 0xa9, 0x3c, 0xc3, 0xcb, 0xab, 0xd4,
+    0xf2, 0xde, 0x6d, 0x3f, 0x60, 0x0a, 0x4c, 0x80, 0x2b, 0x68, 0x02, 0x42, 0xc1, 0x12, 0xd0, 0xf0,
+    0x54, 0x0a, 0xe9, 0x4a, 0xbc, 0x0b, 0x49, 0x25, 0xa1, 0x35, 0x03, 0xfd, 0xb5, 0xcf, 0x54, 0x92,
+    0x3a, 0xda, 0x52, 0x32, 0x75, 0x1b, 0x83, 0x00, 0xab, 0x50, 0x40, 0x07, 0x8e, 0x3a, 0x68, 0xb0,
+    0xa6, 0xdd, 0x2c, 0xb8, 0xb0, 0xce, 0x68, 0x94, 0xb1, 0x74, 0x33, 0xa3, 0x0e, 0xf8, 0xb0, 0xa4,
+    0x2a, 0xd4, 0x41, 0x89, 0x4f, 0xf4, 0x83, 0x94, 0x70, 0xcc, 0xb9, 0x28, 0x19, 0x57, 0x86, 0x97,
+    0x06, 0x2f, 0x0e, 0x2f, 0xad, 0xa1, 0x8e, 0xf1, 0x98, 0xda, 0xcf, 0xe1, 0xa5, 0xbb, 0xce, 0xe9,
+    0xf0, 0xd2, 0x87, 0xee, 0xe7, 0xf0, 0x92, 0xf0, 0xe2, 0xf0, 0x92, 0xac, 0x83, 0x97, 0xc1, 0x7e,
+    0xf0, 0x32, 0x38, 0x27, 0xe3, 0x82, 0xfd, 0x02, 0x5e, 0x16, 0xbc, 0x04, 0xbc, 0x2c, 0xf8, 0x0c,
+    0xe6, 0xa5, 0xe2, 0x10, 0xc1, 0xc0, 0x54, 0x5c, 0x30, 0x98, 0x18, 0xc3, 0xe9, 0xa3, 0x33, 0xa2,
+    0x70, 0x13, 0x49, 0x88, 0xb0, 0x05, 0xe4, 0x58, 0x20, 0xa5, 0x31, 0xff, 0xd2, 0x8d, 0x69, 0xa1,
+    0xc7, 0xfa, 0xf7, 0x5f, 0x7c, 0x00, 0xae, 0x0a, 0x24, 0xa6, 0x50, 0x04, 0x00, 0x00,
+];
+
+fn items() -> Vec<u8> {
+    (0..40)
+        .flat_map(|i| format!("fn item_{}() -> u32 {{ {} }}\n", i, i * i).into_bytes())
+        .collect()
+}
+
+#[test]
+fn inflates_stored_block_from_zlib() {
+    // Two header bytes before the deflate data, the Adler-32 checksum after it
+    let (out, used) = decompress(&STORED_ZLIB[2..]).unwrap();
+    assert_eq!(out, STORED_TEXT);
+    assert_eq!(used, STORED_ZLIB.len() - 2 - 4);
+}
+
+#[test]
+fn inflates_fixed_huffman_block() {
+    let (out, used) = decompress(FIXED).unwrap();
+    assert_eq!(out, FIXED_TEXT);
+    assert_eq!(used, FIXED.len());
+}
+
+#[test]
+fn inflates_dynamic_huffman_block() {
+    let (out, used) = decompress(DYNAMIC).unwrap();
+    assert_eq!(out, items());
+    assert_eq!(used, DYNAMIC.len());
+}
+
+#[test]
+fn back_references_reach_into_earlier_blocks() {
+    let (out, used) = decompress(CROSS).unwrap();
+    assert_eq!(out, [CROSS_TEXT, CROSS_TEXT].concat());
+    assert_eq!(used, CROSS.len());
+}
+
+#[test]
+fn inflates_gzip_member_and_matches_its_crc() {
+    // Ten header bytes without optional fields, then the data, its CRC-32 and size
+    assert_eq!(&GZIP[..4], &[0x1f, 0x8b, 0x08, 0x00]);
+    let (out, used) = decompress(&GZIP[10..]).unwrap();
+    let trailer = &GZIP[10 + used..];
+    assert_eq!(trailer.len(), 8);
+    assert_eq!(out, items());
+    assert_eq!(
+        u32::from_le_bytes(trailer[..4].try_into().unwrap()),
+        crc32(&out)
+    );
+    assert_eq!(
+        u32::from_le_bytes(trailer[4..].try_into().unwrap()) as usize,
+        out.len()
+    );
+}
+
+#[test]
+fn rejects_truncated_and_corrupt_streams() {
+    assert!(decompress(&DYNAMIC[..DYNAMIC.len() / 2]).is_err());
+    // A stored block whose length and its complement disagree
+    let mut stored = STORED_ZLIB[2..].to_vec();
+    stored[1] ^= 0xff;
+    assert!(decompress(&stored).is_err());
+}
@@ -104,6 +104,272 @@ fn test_init_fails_if_config_exists() {
     );
 }
 
+#[test]
+fn test_init_overwrite_with_yes() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("sheafy.toml");
+    fs::write(&config_path, "[sheafy]").unwrap();
+
+    // Stdin is not a terminal, so the question takes its default answer (no)
+    let output = get_sheafy_cmd()
+        .arg("init")
+        .current_dir(dir.path())
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute sheafy init");
+    assert!(!output.status.success(), "init should not overwrite");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Overwrite it? no (not asked"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&config_path).unwrap(), "[sheafy]");
+
+    let output = get_sheafy_cmd()
+        .args(["init", "--yes"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy init");
+    assert!(output.status.success(), "init --yes should overwrite");
+    assert_ne!(fs::read_to_string(&config_path).unwrap(), "[sheafy]");
+}
+
+#[test]
+fn test_doctor_reports_config_problems() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nworking_dirr = \"src\"\n",
+    )
+    .unwrap();
+
+    // An unknown key is only a warning
+    let output = get_sheafy_cmd()
+        .arg("doctor")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy doctor");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("unknown key(s), ignored: sheafy.working_dirr"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("ok    workdir"), "{}", stdout);
+
+    // A config that does not parse fails, with a fix
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nuse_gitignore = \"yes\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .arg("doctor")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy doctor");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.contains("fail  config"), "{}", stdout);
+    assert!(stdout.contains("fix: correct the value"), "{}", stdout);
+}
+
+#[test]
+fn test_messages_in_configured_language() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "Content A").unwrap();
+    fs::write(dir.path().join("sheafy.toml"), "[sheafy]\nlang = \"zh\"\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .env_remove("SHEAFY_LANG")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("已成功创建"), "{}", stdout);
+    // The bundle format stays the same
+    check_bundle_content(&dir.path().join("out.md"), &["a.txt"], &[]);
+
+    // The environment variable wins over the config
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .env("SHEAFY_LANG", "en_US.UTF-8")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Successfully created"), "{}", stdout);
+
+    // Errors with a code are translated too; the code line is not
+    let output = get_sheafy_cmd()
+        .args(["restore", "missing.md", "--yes"])
+        .env_remove("SHEAFY_LANG")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("找不到打包文件"), "{}", stderr);
+    assert!(stderr.contains("error code: E201"), "{}", stderr);
+}
+
+#[test]
+fn test_graph_dot_and_json() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/util")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "mod util;\nfn main() {}\n").unwrap();
+    fs::write(dir.path().join("src/util/mod.rs"), "pub fn x() {}\n").unwrap();
+    fs::write(dir.path().join("README.md"), "# Demo\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["graph", "--imports"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy graph");
+    assert!(output.status.success(), "sheafy graph failed");
+    let dot = String::from_utf8_lossy(&output.stdout);
+    assert!(dot.starts_with("digraph sheafy {"), "{}", dot);
+    assert!(dot.contains("subgraph \"cluster_src/util\""), "{}", dot);
+    assert!(
+        dot.contains("\"src/main.rs\" -> \"src/util/mod.rs\";"),
+        "{}",
+        dot
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["graph", "--format", "json", "-o", "graph.json"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy graph");
+    assert!(output.status.success(), "sheafy graph failed");
+    let graph: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join("graph.json")).unwrap()).unwrap();
+    let edges = graph["edges"].as_array().unwrap();
+    assert!(edges
+        .iter()
+        .any(|e| e["from"] == "src" && e["to"] == "src/main.rs"));
+    // Without --imports there are only containment edges
+    assert!(edges.iter().all(|e| e["kind"] == "contains"));
+    let nodes = graph["nodes"].as_array().unwrap();
+    assert!(nodes
+        .iter()
+        .any(|n| n["id"] == "src/main.rs" && n["language"] == "rust"));
+}
+
+#[test]
+fn test_global_config_defaults() {
+    let dir = tempdir().unwrap();
+    let xdg = tempdir().unwrap();
+    fs::create_dir(xdg.path().join("sheafy")).unwrap();
+    fs::write(
+        xdg.path().join("sheafy/config.toml"),
+        "[sheafy]\nignore_patterns = \"*.swp\"\nbundle_name = \"global.md\"\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"*.log\"\nbundle_name = \"project.md\"\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("main.rs.swp"), "swap").unwrap();
+    fs::write(dir.path().join("debug.log"), "log").unwrap();
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .env("XDG_CONFIG_HOME", xdg.path())
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    // The project's patterns extend the global ones; its bundle_name wins
+    check_bundle_content(
+        &dir.path().join("project.md"),
+        &["main.rs"],
+        &["main.rs.swp", "debug.log"],
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["config", "show", "--origin"])
+        .env("XDG_CONFIG_HOME", xdg.path())
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy config show");
+    assert!(output.status.success(), "sheafy config show failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("sheafy.bundle_name = \"project.md\"  # sheafy.toml"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("sheafy.ignore_patterns = \"*.swp\\n*.log\"  # "),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("config.toml + sheafy.toml"), "{}", stdout);
+}
+
+#[test]
+fn test_config_set_and_get() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\n# Where bundles go\nbundle_name = \"a.md\"\n",
+    )
+    .unwrap();
+    let sheafy = |args: &[&str]| {
+        get_sheafy_cmd()
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy config")
+    };
+
+    assert!(sheafy(&["config", "set", "bundle_name", "out.md"])
+        .status
+        .success());
+    assert!(
+        sheafy(&["config", "set", "restore.allowed_paths", "[\"src/**\"]"])
+            .status
+            .success()
+    );
+    let text = fs::read_to_string(dir.path().join("sheafy.toml")).unwrap();
+    assert!(
+        text.contains("# Where bundles go\nbundle_name = \"out.md\""),
+        "{}",
+        text
+    );
+
+    let output = sheafy(&["config", "get", "bundle_name"]);
+    assert!(output.status.success());
+    // Only the value goes to stdout, for scripts
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "out.md\n");
+    let output = sheafy(&["config", "get", "sheafy.restore.allowed_paths"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[\"src/**\"]\n");
+
+    // Typos and wrong types are refused without touching the file
+    let output = sheafy(&["config", "set", "bundel_name", "x.md"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("unknown config key(s): sheafy.bundel_name"));
+    assert!(!sheafy(&["config", "set", "use_gitignore", "maybe"])
+        .status
+        .success());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("sheafy.toml")).unwrap(),
+        text
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["config", "edit"])
+        .env("VISUAL", "true")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy config edit");
+    assert!(output.status.success(), "sheafy config edit failed");
+}
+
 #[test]
 fn test_bundle_basic() {
     let dir = tempdir().unwrap();
@@ -289,7 +555,7 @@ epilogue = "### END ###"
     let content = fs::read_to_string(bundle_path).unwrap();
 
     assert!(
-        content.starts_with("### START ###\n"),
+        content.contains("\n---\n<!-- sheafy:v2 -->\n### START ###\n"),
         "Prologue missing or incorrect"
     );
     // The check for the file section adds a newline before ##, so account for that
@@ -491,3 +757,5597 @@ fn test_bundle_non_utf8_file_handling() {
     // Ensure the valid file was still bundled, and the invalid one wasn't
     check_bundle_content(&bundle_path, &["valid.txt"], &["invalid_utf8.bin"]);
 }
+
+#[test]
+fn test_bundle_focus_follows_rust_references() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"").unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(
+        dir.path().join("src/main.rs"),
+        "mod a;\nmod b;\nfn main() { a::run(); }",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("src/a.rs"),
+        "use crate::b::helper;\npub fn run() { helper(); }",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("src/b.rs"),
+        "pub fn helper() { crate::c::deep(); }",
+    )
+    .unwrap();
+    fs::write(dir.path().join("src/c.rs"), "pub fn deep() {}").unwrap();
+    fs::write(dir.path().join("src/unrelated.rs"), "pub fn unused() {}").unwrap();
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.args(["bundle", "--focus", "src/a.rs", "--depth", "1"])
+        .current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle --focus failed");
+
+    let bundle_path = dir.path().join("project_bundle.md");
+    check_bundle_content(
+        &bundle_path,
+        &["src/a.rs", "src/b.rs"],
+        &["src/c.rs", "src/main.rs", "src/unrelated.rs", "Cargo.toml"],
+    );
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.args(["bundle", "--focus", "src/main.rs", "--depth", "2"])
+        .current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle --focus failed");
+    check_bundle_content(
+        &bundle_path,
+        &["src/main.rs", "src/a.rs", "src/b.rs", "src/c.rs"],
+        &["src/unrelated.rs"],
+    );
+}
+
+#[test]
+fn test_bundle_focus_follows_python_and_js_imports() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("pkg")).unwrap();
+    fs::write(
+        dir.path().join("app.py"),
+        "from pkg import util\nimport helpers\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("pkg/__init__.py"), "").unwrap();
+    fs::write(dir.path().join("pkg/util.py"), "def f(): pass\n").unwrap();
+    fs::write(dir.path().join("helpers.py"), "X = 1\n").unwrap();
+    fs::write(
+        dir.path().join("index.ts"),
+        "import { x } from './lib/x';\n",
+    )
+    .unwrap();
+    fs::create_dir(dir.path().join("lib")).unwrap();
+    fs::write(dir.path().join("lib/x.ts"), "export const x = 1;\n").unwrap();
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.args(["bundle", "--focus", "app.py"])
+        .current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle --focus failed");
+    let bundle_path = dir.path().join("project_bundle.md");
+    check_bundle_content(
+        &bundle_path,
+        &["app.py", "`pkg/__init__.py`", "pkg/util.py", "helpers.py"],
+        &["index.ts", "lib/x.ts"],
+    );
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.args(["bundle", "--focus", "index.ts"])
+        .current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle --focus failed");
+    check_bundle_content(&bundle_path, &["index.ts", "lib/x.ts"], &["app.py"]);
+}
+
+// Helper to run git inside a test repository
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "user.name=sheafy",
+            "-c",
+            "user.email=sheafy@example.com",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute git")
+        .status;
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+fn init_git_repo(dir: &Path) {
+    git(dir, &["init", "-q"]);
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-q", "-m", "initial"]);
+}
+
+#[test]
+fn test_bundle_context_lines_is_read_only() {
+    let dir = tempdir().unwrap();
+    let original: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+    fs::write(dir.path().join("changed.txt"), &original).unwrap();
+    fs::write(dir.path().join("same.txt"), "untouched\n").unwrap();
+    init_git_repo(dir.path());
+    fs::write(
+        dir.path().join("changed.txt"),
+        original.replace("line 15\n", "LINE FIFTEEN\n"),
+    )
+    .unwrap();
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.args(["bundle", "--since", "HEAD", "--context-lines", "2"])
+        .current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle --context-lines failed"
+    );
+
+    let bundle_path = dir.path().join("project_bundle.md");
+    let content = fs::read_to_string(&bundle_path).unwrap();
+    assert!(content.contains("\n---\n<!-- sheafy:v2 -->\n<!-- sheafy:readonly -->\n"));
+    assert!(content.contains("\n## changed.txt\n"));
+    assert!(content
+        .contains("Lines 13-17:\n```\nline 13\nline 14\nLINE FIFTEEN\nline 16\nline 17\n```"));
+    assert!(
+        !content.contains("line 1\n"),
+        "Unchanged lines should not be bundled"
+    );
+    assert!(!content.contains("## same.txt"));
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.arg("restore").current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy restore");
+    assert!(
+        !output.status.success(),
+        "Restoring a read-only bundle should fail"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("read-only bundle"));
+}
+
+#[test]
+fn test_bundle_patch_format_round_trip() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "one\ntwo\nthree\n").unwrap();
+    fs::write(dir.path().join(".gitignore"), "project_bundle.md\n").unwrap();
+    init_git_repo(dir.path());
+    fs::write(dir.path().join("a.txt"), "one\n2\nthree\n").unwrap();
+    fs::write(dir.path().join("new.txt"), "brand new\n").unwrap();
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.args(["bundle", "--since", "HEAD", "--format", "patch"])
+        .current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle --format patch failed"
+    );
+    let content = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+    assert!(content.contains("\n---\n<!-- sheafy:v2 -->\n<!-- sheafy:format patch -->\n"));
+    assert!(content.contains("```diff\n"));
+
+    // Reset the tree to the base commit and re-apply the bundle
+    git(dir.path(), &["checkout", "-q", "--", "a.txt"]);
+    fs::remove_file(dir.path().join("new.txt")).unwrap();
+
+    let mut cmd = get_sheafy_cmd();
+    cmd.arg("restore").current_dir(dir.path());
+    let output = cmd.output().expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore of patch bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+        "one\n2\nthree\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+        "brand new\n"
+    );
+}
+
+#[test]
+fn test_daemon_stdio_json_rpc() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "Content A").unwrap();
+    fs::write(dir.path().join("b.rs"), "fn b() {}").unwrap();
+
+    let mut child = get_sheafy_cmd()
+        .args(["daemon", "--stdio"])
+        .current_dir(dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn sheafy daemon");
+
+    let requests = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"list"}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"bundle","params":{"output":"out.md"}}"#,
+        r#"{"jsonrpc":"2.0","method":"list"}"#, // notification, no response
+        r#"{"jsonrpc":"2.0","id":3,"method":"diff","params":{"input_file":"out.md"}}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"frobnicate"}"#,
+        r#"{"jsonrpc":"2.0","id":5,"method":"diff","params":{"input_file":"missing.md"}}"#,
+        r#"{"jsonrpc":"2.0","id":6,"method":"shutdown"}"#,
+    ];
+    {
+        let stdin = child.stdin.as_mut().unwrap();
+        for request in requests {
+            writeln!(stdin, "{}", request).unwrap();
+        }
+    }
+    let output = child.wait_with_output().expect("Failed to wait for daemon");
+    assert!(output.status.success(), "sheafy daemon exited with failure");
+
+    let responses: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("stdout must only contain JSON-RPC"))
+        .collect();
+    assert_eq!(responses.len(), 6, "responses: {:?}", responses);
+
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(
+        responses[0]["result"]["files"],
+        serde_json::json!(["a.txt", "b.rs"])
+    );
+
+    assert_eq!(responses[1]["id"], 2);
+    assert_eq!(
+        responses[1]["result"]["files"],
+        serde_json::json!(["a.txt", "b.rs"])
+    );
+    assert!(dir.path().join("out.md").exists());
+
+    assert_eq!(responses[2]["id"], 3);
+    assert_eq!(
+        responses[2]["result"]["entries"],
+        serde_json::json!([
+            {"path": "a.txt", "status": "unchanged"},
+            {"path": "b.rs", "status": "unchanged"}
+        ])
+    );
+
+    assert_eq!(responses[3]["id"], 4);
+    assert_eq!(responses[3]["error"]["code"], -32601);
+
+    assert_eq!(responses[4]["id"], 5);
+    assert_eq!(responses[4]["error"]["code"], -32000);
+    assert_eq!(responses[4]["error"]["data"]["code"], "E201");
+
+    assert_eq!(responses[5]["id"], 6);
+    assert!(responses[5]["result"].is_null());
+}
+
+#[test]
+fn test_update_preserves_notes_and_restore_ignores_them() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "Alpha\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "Beta\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    // A reviewer annotates the bundle
+    let bundle_path = dir.path().join("project_bundle.md");
+    let content = fs::read_to_string(&bundle_path).unwrap();
+    let annotated = content.replace(
+        "\n## b.txt\n",
+        "\n<!-- sheafy:note\nBeta needs a rename -->\n\n## b.txt\n",
+    ) + "<!-- sheafy:note overall looks good -->\n";
+    fs::write(&bundle_path, annotated).unwrap();
+
+    // Sources change, then the bundle is updated
+    fs::write(dir.path().join("b.txt"), "Beta v2\n").unwrap();
+    fs::write(dir.path().join("c.txt"), "Gamma\n").unwrap();
+    let output = get_sheafy_cmd()
+        .arg("update")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy update");
+    assert!(output.status.success(), "sheafy update failed");
+
+    let updated = fs::read_to_string(&bundle_path).unwrap();
+    assert!(updated.contains("Beta v2"));
+    assert!(updated.contains("## c.txt"));
+    assert!(
+        updated.contains("<!-- sheafy:note\nBeta needs a rename -->\n\n## b.txt\n"),
+        "Note was not kept above its section:\n{}",
+        updated
+    );
+    assert!(updated
+        .trim_end()
+        .ends_with("<!-- sheafy:note overall looks good -->"));
+
+    // Restore ignores the notes entirely
+    fs::remove_file(dir.path().join("b.txt")).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+        "Beta v2\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+        "Alpha\n"
+    );
+}
+
+#[test]
+fn test_update_keeps_the_options_the_bundle_was_made_with() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "Alpha\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "Beta\n").unwrap();
+    fs::write(dir.path().join("c.txt"), "Alpha\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args([
+            "bundle",
+            "--header-style",
+            "title",
+            "--dedup",
+            "-o",
+            "out.md",
+        ])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle_path = dir.path().join("out.md");
+    let content = fs::read_to_string(&bundle_path).unwrap();
+    let fence = "```text title=\"b.txt\"\n";
+    assert!(content.contains(fence), "bundle: {}", content);
+    fs::write(
+        &bundle_path,
+        content.replace(fence, &format!("<!-- sheafy:note check b -->\n\n{}", fence)),
+    )
+    .unwrap();
+
+    fs::write(dir.path().join("b.txt"), "Beta v2\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["update", "out.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy update");
+    assert!(output.status.success(), "sheafy update failed");
+    assert!(
+        !String::from_utf8_lossy(&output.stderr).contains("no longer in the bundle"),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let updated = fs::read_to_string(&bundle_path).unwrap();
+    assert!(
+        updated.contains("<!-- sheafy:note check b -->\n\n```text title=\"b.txt\"\nBeta v2\n"),
+        "updated: {}",
+        updated
+    );
+    assert!(
+        updated.contains("## c.txt\n<!-- sheafy:same-as a.txt -->"),
+        "updated: {}",
+        updated
+    );
+
+    // A bundle of a list read from stdin can't be made again
+    let mut child = get_sheafy_cmd()
+        .args(["bundle", "--files-from", "-", "-o", "listed.md"])
+        .current_dir(dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to execute sheafy bundle");
+    child.stdin.take().unwrap().write_all(b"a.txt\n").unwrap();
+    assert!(child.wait().unwrap().success());
+    let output = get_sheafy_cmd()
+        .args(["update", "listed.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy update");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("standard input"));
+}
+
+#[test]
+fn test_restore_all_applies_bundles_in_filename_order() {
+    let dir = tempdir().unwrap();
+    let bundles = dir.path().join("bundles");
+    fs::create_dir(&bundles).unwrap();
+    fs::write(
+        bundles.join("02-override.md"),
+        "## shared.txt\n```\nfrom second\n```\n\n## second.txt\n```\ntwo\n```\n",
+    )
+    .unwrap();
+    fs::write(
+        bundles.join("01-base.md"),
+        "## shared.txt\n```\nfrom first\n```\n\n## first.txt\n```\none\n```\n",
+    )
+    .unwrap();
+    fs::write(
+        bundles.join("notes.txt"),
+        "## ignored.txt\n```\nnot a bundle\n```\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "--all", "bundles"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore --all");
+    assert!(output.status.success(), "sheafy restore --all failed");
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("shared.txt")).unwrap(),
+        "from second\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("first.txt")).unwrap(),
+        "one\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("second.txt")).unwrap(),
+        "two\n"
+    );
+    assert!(!dir.path().join("ignored.txt").exists());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("3 distinct file(s) written"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("shared.txt (01-base.md -> 02-override.md)"));
+}
+
+#[test]
+fn test_bundle_jobs_output_is_identical() {
+    let dir = tempdir().unwrap();
+    for d in ["a", "b", "c"] {
+        fs::create_dir(dir.path().join(d)).unwrap();
+        for i in 0..5 {
+            fs::write(
+                dir.path().join(d).join(format!("f{}.txt", i)),
+                format!("{}{}", d, i),
+            )
+            .unwrap();
+        }
+    }
+    // Keep each run's output out of the other run's bundle
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"bundle_*.md\"\n",
+    )
+    .unwrap();
+
+    let mut outputs = Vec::new();
+    for jobs in ["1", "4"] {
+        let out_name = format!("bundle_{}.md", jobs);
+        let output = get_sheafy_cmd()
+            .args(["bundle", "--reproducible", "--jobs", jobs, "-o", &out_name])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(
+            output.status.success(),
+            "sheafy bundle --jobs {} failed",
+            jobs
+        );
+        outputs.push(fs::read_to_string(dir.path().join(&out_name)).unwrap());
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    assert!(outputs[0].contains("## c/f4.txt"));
+}
+
+#[test]
+fn test_bundle_io_throttle() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("big.txt"), "x".repeat(256 * 1024)).unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nio_throttle_mb_s = 1.0\n",
+    )
+    .unwrap();
+
+    let started = std::time::Instant::now();
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    // 256 KiB at 1 MiB/s must take at least ~250ms
+    assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+    check_bundle_content(&dir.path().join("project_bundle.md"), &["big.txt"], &[]);
+}
+
+#[test]
+fn test_bundle_sniffs_extensionless_and_binary_files() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("Dockerfile"), "FROM rust:1\n").unwrap();
+    fs::write(
+        dir.path().join("run"),
+        "#!/usr/bin/env python3\nprint('hi')\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("build"), "#!/bin/sh\necho build\n").unwrap();
+    fs::write(dir.path().join("LICENSE"), "MIT License\n").unwrap();
+    fs::write(
+        dir.path().join("blob"),
+        [0x7f, b'E', b'L', b'F', 0x00, 0x01],
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Skipping binary file"),
+        "stderr: {}",
+        stderr
+    );
+
+    let bundle_path = dir.path().join("project_bundle.md");
+    check_bundle_content(
+        &bundle_path,
+        &["Dockerfile", "run", "build", "LICENSE"],
+        &["blob"],
+    );
+    let content = fs::read_to_string(bundle_path).unwrap();
+    assert!(content.contains("## Dockerfile\n```dockerfile\n"));
+    assert!(content.contains("## run\n```python\n"));
+    assert!(content.contains("## build\n```bash\n"));
+    assert!(content.contains("## LICENSE\n```\n"));
+}
+
+#[test]
+fn test_bundle_max_size_aborts_with_suggestions() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("assets")).unwrap();
+    fs::write(
+        dir.path().join("assets").join("data.json"),
+        "x".repeat(64 * 1024),
+    )
+    .unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nmax_bundle_size = \"16KB\"\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        !output.status.success(),
+        "bundle over max_bundle_size should fail"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("exceeds max_bundle_size"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(stderr.contains("assets/data.json"), "stderr: {}", stderr);
+    assert!(stderr.contains("assets/"), "stderr: {}", stderr);
+    assert!(stderr.contains("*.json"), "stderr: {}", stderr);
+    assert!(!dir.path().join("project_bundle.md").exists());
+
+    // Within the limit the bundle is written as usual
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nmax_bundle_size = 1048576\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(&dir.path().join("project_bundle.md"), &["main.rs"], &[]);
+}
+
+#[test]
+fn test_bundle_dedup_round_trip() {
+    let dir = tempdir().unwrap();
+    let fixtures = dir.path().join("fixtures");
+    fs::create_dir(&fixtures).unwrap();
+    let fixture = "{\n  \"id\": 1,\n  \"name\": \"sample\"\n}\n";
+    fs::write(fixtures.join("a.json"), fixture).unwrap();
+    fs::write(fixtures.join("b.json"), fixture).unwrap();
+    fs::write(fixtures.join("c.json"), fixture).unwrap();
+    fs::write(dir.path().join("other.txt"), "unique\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--dedup"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle --dedup");
+    assert!(output.status.success(), "sheafy bundle --dedup failed");
+
+    let bundle_path = dir.path().join("project_bundle.md");
+    let content = fs::read_to_string(&bundle_path).unwrap();
+    assert_eq!(
+        content.matches("\"sample\"").count(),
+        1,
+        "bundle: {}",
+        content
+    );
+    assert!(content.contains("## fixtures/b.json\n<!-- sheafy:same-as fixtures/a.json -->\n"));
+    assert!(content.contains("## fixtures/c.json\n<!-- sheafy:same-as fixtures/a.json -->\n"));
+
+    fs::remove_dir_all(&fixtures).unwrap();
+    fs::remove_file(dir.path().join("other.txt")).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    for name in ["a.json", "b.json", "c.json"] {
+        assert_eq!(fs::read_to_string(fixtures.join(name)).unwrap(), fixture);
+    }
+    assert_eq!(
+        fs::read_to_string(dir.path().join("other.txt")).unwrap(),
+        "unique\n"
+    );
+}
+
+#[test]
+fn test_init_analyze_writes_tailored_config() {
+    let dir = tempdir().unwrap();
+    // .gitignore handling needs a repository
+    git(dir.path(), &["init", "-q"]);
+    fs::create_dir_all(dir.path().join("node_modules").join("left-pad")).unwrap();
+    fs::write(
+        dir.path()
+            .join("node_modules")
+            .join("left-pad")
+            .join("index.js"),
+        "x\n",
+    )
+    .unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src").join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(
+        dir.path().join("src").join("App.vue"),
+        "<template></template>\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("README.md"), "# Demo\n").unwrap();
+    fs::write(dir.path().join("Cargo.lock"), "# lock\n").unwrap();
+    fs::write(dir.path().join("logo.png"), [0x89, b'P', b'N', b'G', 0x00]).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["init", "--analyze"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy init --analyze");
+    assert!(output.status.success(), "sheafy init --analyze failed");
+    let config = fs::read_to_string(dir.path().join("sheafy.toml")).unwrap();
+    for expected in ["node_modules/", "Cargo.lock", "*.png", "vue = \"vue\""] {
+        assert!(
+            config.contains(expected),
+            "missing {} in:\n{}",
+            expected,
+            config
+        );
+    }
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+    let headers: Vec<&str> = bundle.lines().filter(|l| l.starts_with("## ")).collect();
+    // README and the entry point come first; lock file and binary are left out
+    assert_eq!(
+        headers,
+        ["## README.md", "## src/main.rs", "## src/App.vue"]
+    );
+    assert!(bundle.contains("## src/App.vue\n```vue\n"));
+
+    // A second run refuses to overwrite the config
+    let output = get_sheafy_cmd()
+        .args(["init", "--analyze"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy init --analyze");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_restore_on_conflict_skip_writes_report() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("bundle.md"),
+        "## same.txt\n```\nsame\n```\n\n## local.txt\n```\nfrom bundle\n```\n\n## new.txt\n```\nnew\n```\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("same.txt"), "same\n").unwrap();
+    fs::write(dir.path().join("local.txt"), "local edits\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--on-conflict", "skip"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("local.txt")).unwrap(),
+        "local edits\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+        "new\n"
+    );
+
+    let report_path = dir.path().join("sheafy-conflicts.md");
+    let report = fs::read_to_string(&report_path).unwrap();
+    assert!(report.starts_with("<!-- sheafy:v2 -->\n<!-- sheafy:conflicts -->\n"));
+    assert!(
+        report.contains("## local.txt\n```\nfrom bundle\n```\n"),
+        "report: {}",
+        report
+    );
+    assert!(
+        report.contains("```\nlocal edits\n```\n"),
+        "report: {}",
+        report
+    );
+    assert!(!report.contains("same.txt"));
+
+    // Resolving: restoring the report writes only the bundled version of the conflicting file
+    let output = get_sheafy_cmd()
+        .args(["restore", "sheafy-conflicts.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "restoring the conflict report failed"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("local.txt")).unwrap(),
+        "from bundle\n"
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("0 created, 1 overwritten, 0 unchanged, 0 skipped."),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_bundle_header_style_round_trip() {
+    for (style, expected) in [
+        ("title", "```rust title=\"src/main.rs\"\n"),
+        ("pandoc", "```{.rust filename=\"src/main.rs\"}\n"),
+    ] {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src").join("main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.path().join("notes.txt"), "plain\n").unwrap();
+
+        let output = get_sheafy_cmd()
+            .args(["bundle", "--header-style", style])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(
+            output.status.success(),
+            "sheafy bundle --header-style {} failed",
+            style
+        );
+        let bundle = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+        assert!(bundle.contains(expected), "bundle: {}", bundle);
+        assert!(!bundle.contains("\n## "), "bundle: {}", bundle);
+
+        fs::remove_dir_all(dir.path().join("src")).unwrap();
+        fs::remove_file(dir.path().join("notes.txt")).unwrap();
+        let output = get_sheafy_cmd()
+            .arg("restore")
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy restore");
+        assert!(output.status.success(), "sheafy restore failed");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("src").join("main.rs")).unwrap(),
+            "fn main() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+            "plain\n"
+        );
+    }
+}
+
+#[test]
+fn test_verify_reports_version_and_features() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "same\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "same\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--dedup"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+    assert!(bundle.contains("\n---\n<!-- sheafy:v2 -->\n"));
+
+    let output = get_sheafy_cmd()
+        .arg("verify")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy verify");
+    assert!(output.status.success(), "sheafy verify failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Format: v2\n"), "stdout: {}", stdout);
+    assert!(
+        stdout.contains("same-as references (1)"),
+        "stdout: {}",
+        stdout
+    );
+
+    // Unmarked bundles are v1; dangling references are reported as errors
+    fs::write(
+        dir.path().join("old.md"),
+        "## a.txt\n```\nx\n```\n\n## b.txt\n<!-- sheafy:same-as missing.txt -->\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["verify", "old.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy verify");
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Format: v1 (no version marker)"),
+        "stdout: {}",
+        stdout
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing.txt"), "stderr: {}", stderr);
+
+    // Bundles from a newer format are refused by both verify and restore
+    fs::write(
+        dir.path().join("new.md"),
+        "<!-- sheafy:v9 -->\n## c.txt\n```\nc\n```\n",
+    )
+    .unwrap();
+    for args in [["verify", "new.md"], ["restore", "new.md"]] {
+        let output = get_sheafy_cmd()
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy");
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("format v9"), "stderr: {}", stderr);
+    }
+    assert!(!dir.path().join("c.txt").exists());
+}
+
+#[test]
+fn test_clean_removes_marked_bundles_only() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    fs::write(dir.path().join("NOTES.md"), "# Hand-written\n").unwrap();
+    fs::create_dir(dir.path().join("out")).unwrap();
+    for output in ["project_bundle.md", "out/old.md"] {
+        let status = get_sheafy_cmd()
+            .args(["bundle", "-o", output])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle")
+            .status;
+        assert!(status.success());
+    }
+
+    let output = get_sheafy_cmd()
+        .args(["clean", "--dry-run"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy clean --dry-run");
+    assert!(output.status.success(), "sheafy clean --dry-run failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("2 file(s) would be removed"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(dir.path().join("project_bundle.md").exists());
+
+    let output = get_sheafy_cmd()
+        .arg("clean")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy clean");
+    assert!(output.status.success(), "sheafy clean failed");
+    assert!(!dir.path().join("project_bundle.md").exists());
+    assert!(!dir.path().join("out").join("old.md").exists());
+    assert!(dir.path().join("NOTES.md").exists());
+    assert!(dir.path().join("a.txt").exists());
+}
+
+#[test]
+fn test_bundle_front_matter_and_reproducible_mode() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .env("SOURCE_DATE_EPOCH", "1700000000")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+    assert!(
+        bundle.starts_with("---\ngenerator: sheafy "),
+        "bundle: {}",
+        bundle
+    );
+    assert!(
+        bundle.contains("\ncreated: 2023-11-14T22:13:20Z\nroot: \""),
+        "bundle: {}",
+        bundle
+    );
+    assert!(
+        bundle.contains("\nfiles: 1\nsource_bytes: 2\nscan_ms: "),
+        "bundle: {}",
+        bundle
+    );
+    assert!(
+        bundle.contains("\nlanguages:\n  text: { files: 1, bytes: 2 }\n---\n"),
+        "bundle: {}",
+        bundle
+    );
+
+    // Keep the bundles out of each other so the runs see the same tree
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"*.md\"\n",
+    )
+    .unwrap();
+    let mut outputs = Vec::new();
+    for name in ["one.md", "two.md"] {
+        let output = get_sheafy_cmd()
+            .args(["bundle", "--reproducible", "-o", name])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle --reproducible");
+        assert!(
+            output.status.success(),
+            "sheafy bundle --reproducible failed"
+        );
+        outputs.push(fs::read(dir.path().join(name)).unwrap());
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    let outputs: Vec<String> = outputs
+        .into_iter()
+        .map(|b| String::from_utf8(b).unwrap())
+        .collect();
+    assert!(!outputs[0].contains("created:"));
+    assert!(!outputs[0].contains("scan_ms:"));
+    assert!(outputs[0].contains("\nfiles: 1\n"));
+    assert!(!outputs[0].contains(&dir.path().to_string_lossy().into_owned()));
+
+    // Front matter does not get in the way of restoring
+    fs::remove_file(dir.path().join("a.txt")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "one.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "a\n");
+}
+
+#[test]
+fn test_bundle_output_template_placeholders() {
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("demo");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("a.txt"), "a\n").unwrap();
+    init_git_repo(&project);
+    git(&project, &["checkout", "-q", "-b", "feature/x"]);
+    let hash = String::from_utf8(
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .current_dir(&project)
+            .output()
+            .unwrap()
+            .stdout,
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args([
+            "bundle",
+            "-o",
+            "bundles/{project}-{branch}-{hash}-{date}.md",
+        ])
+        .env("SOURCE_DATE_EPOCH", "1700000000")
+        .current_dir(&project)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let expected = project
+        .join("bundles")
+        .join(format!("demo-feature-x-{}-2023-11-14.md", hash.trim()));
+    check_bundle_content(&expected, &["a.txt"], &[]);
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "{nope}.md"])
+        .current_dir(&project)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown placeholder {nope}"));
+}
+
+#[test]
+fn test_restore_finds_bundle_with_templated_bundle_name() {
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("demo");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("a.txt"), "a\n").unwrap();
+    fs::write(
+        project.join("sheafy.toml"),
+        "[sheafy]\nbundle_name = \"ctx-{project}.md\"\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(&project)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    assert!(project.join("ctx-demo.md").exists());
+
+    fs::write(project.join("a.txt"), "changed\n").unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .current_dir(&project)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read_to_string(project.join("a.txt")).unwrap(), "a\n");
+}
+
+#[test]
+fn test_config_lint_reports_pattern_problems() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("logs")).unwrap();
+    fs::write(dir.path().join("logs").join("keep.log"), "x\n").unwrap();
+    fs::write(dir.path().join("a.tmp"), "x\n").unwrap();
+    fs::write(dir.path().join("b.tmp"), "x\n").unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    let config = r#"[sheafy]
+ignore_patterns = """
+*.tmp
+!b.tmp
+logs/
+!logs/keep.log
+!main.rs
+*.nothing
+src/[a
+"""
+"#;
+    fs::write(dir.path().join("sheafy.toml"), config).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["config", "lint"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy config lint");
+    assert!(
+        !output.status.success(),
+        "invalid glob should fail the lint"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 7: 'src/[a' is not a valid pattern"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("line 6: '*.nothing' matches no files"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("line 4: '!logs/keep.log' can never apply"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("line 5: '!main.rs' has no effect"),
+        "stderr: {}",
+        stderr
+    );
+    // A negation that really re-includes a file is fine
+    assert!(!stderr.contains("'!b.tmp'"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_restore_check_mode() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    let check = || {
+        get_sheafy_cmd()
+            .args(["restore", "--check", "project_bundle.md"])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy restore --check")
+    };
+    let output = check();
+    assert!(output.status.success(), "in-sync tree should pass --check");
+
+    fs::write(dir.path().join("a.txt"), "changed\n").unwrap();
+    fs::remove_file(dir.path().join("b.txt")).unwrap();
+    let output = check();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("modified  a.txt"), "stdout: {}", stdout);
+    assert!(stdout.contains("new       b.txt"), "stdout: {}", stdout);
+    // Nothing was written
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+        "changed\n"
+    );
+    assert!(!dir.path().join("b.txt").exists());
+
+    // A real restore reports how many files were already in sync
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("1 created, 0 overwritten, 1 unchanged, 0 skipped."),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_restore_check_against_head() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+    init_git_repo(dir.path());
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    let check = |extra: &[&str]| {
+        get_sheafy_cmd()
+            .args(["restore", "--check", "project_bundle.md"])
+            .args(extra)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy restore --check")
+    };
+    // Uncommitted edits count against the working tree, not against HEAD
+    fs::write(dir.path().join("a.txt"), "dirty\n").unwrap();
+    fs::remove_file(dir.path().join("b.txt")).unwrap();
+    assert_eq!(check(&[]).status.code(), Some(1));
+    let output = check(&["--diff-base", "head"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {}", stdout);
+    assert!(stdout.contains("HEAD matches"), "stdout: {}", stdout);
+
+    // Once committed, the edit is what the bundle changes
+    git(dir.path(), &["commit", "-q", "-am", "edit"]);
+    let output = check(&["--diff-base", "head"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("modified  a.txt"), "stdout: {}", stdout);
+    assert!(stdout.contains("new       b.txt"), "stdout: {}", stdout);
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "--diff-base", "head"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success(), "--diff-base needs --check");
+
+    // Before the first commit, every file is new
+    let fresh = tempdir().unwrap();
+    fs::write(fresh.path().join("a.txt"), "a\n").unwrap();
+    git(fresh.path(), &["init", "-q"]);
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(fresh.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let output = get_sheafy_cmd()
+        .args(["restore", "--check", "--diff-base", "head"])
+        .current_dir(fresh.path())
+        .output()
+        .expect("Failed to execute sheafy restore --check");
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("new       a.txt"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_render_html_and_pdf() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(
+        dir.path().join("src/main.rs"),
+        "fn main() {\n    // greet\n    println!(\"<hi>\");\n}\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("notes.txt"), "plain\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    let output = get_sheafy_cmd()
+        .args(["render", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy render");
+    assert!(output.status.success(), "sheafy render failed");
+    let html = fs::read_to_string(dir.path().join("bundle.html")).unwrap();
+    assert!(html.contains("<nav>"));
+    assert!(
+        html.contains("<a href=\"#file-1\">notes.txt</a>"),
+        "html: {}",
+        html
+    );
+    assert!(html.contains("id=\"file-2\""));
+    assert!(html.contains("<span class=\"ln\">3</span>"));
+    assert!(html.contains("<span class=\"k\">fn</span>"));
+    assert!(html.contains("<span class=\"c\">// greet</span>"));
+    // Content is escaped
+    assert!(html.contains("&quot;&lt;hi&gt;&quot;"));
+
+    let output = get_sheafy_cmd()
+        .args(["render", "bundle.md", "--pdf"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy render --pdf");
+    assert!(output.status.success(), "sheafy render --pdf failed");
+    let pdf = fs::read(dir.path().join("bundle.pdf")).unwrap();
+    let text = String::from_utf8_lossy(&pdf);
+    assert!(text.starts_with("%PDF-1.4"));
+    assert!(text.trim_end().ends_with("%%EOF"));
+    assert!(text.contains("/Outlines"));
+    assert!(text.contains("(src/main.rs)"));
+}
+
+#[test]
+fn test_bundle_link_anchors_round_trip() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    let readme = "# Project\n\nSee the [guide](docs/guide.md) and [usage](#usage).\n";
+    let guide = "Back to [readme](../README.md#usage), [home](https://example.com/README.md)\n\
+                 and ![logo](../missing.png).\n";
+    fs::write(dir.path().join("README.md"), readme).unwrap();
+    fs::write(dir.path().join("docs/guide.md"), guide).unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"sheafy.toml\"\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--link-anchors", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains("See the [guide](#docsguidemd) and [usage](#usage)."),
+        "bundle: {}",
+        bundle
+    );
+    assert!(bundle.contains("Back to [readme](#readmemd), [home](https://example.com/README.md)"));
+    assert!(bundle.contains("![logo](../missing.png)"));
+    assert!(
+        bundle.contains("<!-- sheafy:links {\"0\":\"docs/guide.md\"} -->"),
+        "bundle: {}",
+        bundle
+    );
+    assert!(bundle.contains("<!-- sheafy:links {\"0\":\"../README.md#usage\"} -->"));
+
+    let output = get_sheafy_cmd()
+        .args(["verify", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy verify");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("rewritten links (2)"));
+
+    // Restore writes the original links back
+    fs::remove_file(dir.path().join("README.md")).unwrap();
+    fs::remove_dir_all(dir.path().join("docs")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("README.md")).unwrap(),
+        readme
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("docs/guide.md")).unwrap(),
+        guide
+    );
+}
+
+#[test]
+fn test_bundle_link_anchors_leave_links_to_skipped_files() {
+    let dir = tempdir().unwrap();
+    let readme = "See [data](blob.dat) and [notes](blobdat).\n";
+    fs::write(dir.path().join("README.md"), readme).unwrap();
+    fs::write(dir.path().join("blob.dat"), [0u8, 159, 146, 150, 0, 1]).unwrap();
+    fs::write(dir.path().join("blobdat"), "notes\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"sheafy.toml\"\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--link-anchors", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(!bundle.contains("## blob.dat\n"), "bundle: {}", bundle);
+    // The skipped binary has no section to point at, nor does it take the anchor
+    assert!(
+        bundle.contains("See [data](blob.dat) and [notes](#blobdat)."),
+        "bundle: {}",
+        bundle
+    );
+}
+
+const SAMPLE_NOTEBOOK: &str = r##"{
+ "cells": [
+  {
+   "cell_type": "markdown",
+   "id": "intro",
+   "metadata": {},
+   "source": [
+    "# Analysis\n",
+    "\n",
+    "Loads the data."
+   ]
+  },
+  {
+   "cell_type": "code",
+   "execution_count": null,
+   "id": "load",
+   "metadata": {
+    "tags": [
+     "setup"
+    ]
+   },
+   "outputs": [],
+   "source": [
+    "import json\n",
+    "data = json.load(open(\"data.json\"))"
+   ]
+  }
+ ],
+ "metadata": {
+  "kernelspec": {
+   "display_name": "Python 3",
+   "language": "python",
+   "name": "python3"
+  }
+ },
+ "nbformat": 4,
+ "nbformat_minor": 5
+}
+"##;
+
+#[test]
+fn test_bundle_file_handlers_round_trip() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("analysis.ipynb"), SAMPLE_NOTEBOOK).unwrap();
+    fs::write(dir.path().join("secret.txt"), "token=abc\nuser=me\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        r#"[sheafy]
+ignore_patterns = "sheafy.toml"
+
+[sheafy.handlers.notebook]
+patterns = "*.ipynb"
+
+[sheafy.handlers.tagged]
+patterns = "secret.txt"
+encode = "sed 's/^/ENC:/'"
+decode = "sed 's/^ENC://'"
+"#,
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains("## analysis.ipynb\n```python\n# sheafy:notebook {"),
+        "bundle: {}",
+        bundle
+    );
+    assert!(
+        bundle.contains("# %% [markdown] {\"id\":\"intro\"}\n# # Analysis\n#\n# Loads the data.\n")
+    );
+    assert!(bundle
+        .contains("# %% {\"id\":\"load\",\"metadata\":{\"tags\":[\"setup\"]}}\nimport json\n"));
+    assert!(bundle.contains("<!-- sheafy:handler notebook -->"));
+    assert!(bundle.contains("ENC:token=abc\nENC:user=me\n"));
+    assert!(bundle.contains("<!-- sheafy:handler tagged -->"));
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "--check", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore --check");
+    assert!(
+        output.status.success(),
+        "decoded sections should match: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    fs::remove_file(dir.path().join("analysis.ipynb")).unwrap();
+    fs::remove_file(dir.path().join("secret.txt")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("analysis.ipynb")).unwrap(),
+        SAMPLE_NOTEBOOK
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("secret.txt")).unwrap(),
+        "token=abc\nuser=me\n"
+    );
+}
+
+#[test]
+fn test_bundle_flattens_notebooks_by_default() {
+    let dir = tempdir().unwrap();
+    let notebook = SAMPLE_NOTEBOOK
+        .replace("\"language\": \"python\"", "\"language\": \"javascript\"")
+        .replace("import json\\n", "const fs = require('fs');\\n");
+    fs::write(dir.path().join("app.ipynb"), &notebook).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains("## app.ipynb\n```javascript\n// sheafy:notebook {"),
+        "bundle: {}",
+        bundle
+    );
+    assert!(bundle
+        .contains("// %% [markdown] {\"id\":\"intro\"}\n// # Analysis\n//\n// Loads the data.\n"));
+    assert!(bundle.contains("\nconst fs = require('fs');\n"));
+    assert!(!bundle.contains("\"nbformat_minor\": 5"));
+
+    fs::remove_file(dir.path().join("app.ipynb")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("app.ipynb")).unwrap(),
+        notebook
+    );
+
+    // notebooks = "raw" keeps the JSON
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nnotebooks = \"raw\"\nignore_patterns = \"*.md\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "raw.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("raw.md")).unwrap();
+    assert!(
+        bundle.contains("## app.ipynb\n```json\n{\n \"cells\": ["),
+        "bundle: {}",
+        bundle
+    );
+    assert!(!bundle.contains("sheafy:handler"));
+}
+
+#[test]
+fn test_restore_keeps_notebook_outputs_of_unchanged_cells() {
+    let dir = tempdir().unwrap();
+    let outputs = "\"outputs\": [\n    {\n     \"name\": \"stdout\",\n     \"output_type\": \"stream\",\n     \"text\": [\n      \"loaded\\n\"\n     ]\n    }\n   ],";
+    let notebook = SAMPLE_NOTEBOOK
+        .replace("\"execution_count\": null,", "\"execution_count\": 3,")
+        .replace("\"outputs\": [],", outputs);
+    fs::write(dir.path().join("app.ipynb"), &notebook).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(!bundle.contains("loaded"), "{}", bundle);
+
+    // An unedited notebook is left as it is
+    let output = get_sheafy_cmd()
+        .args(["restore", "--check", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore --check");
+    assert!(
+        output.status.success(),
+        "stdout: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("app.ipynb")).unwrap(),
+        notebook
+    );
+
+    // An edited cell loses its stale outputs
+    fs::write(
+        dir.path().join("bundle.md"),
+        bundle.replace("import json\n", "import csv\n"),
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    let restored = fs::read_to_string(dir.path().join("app.ipynb")).unwrap();
+    assert!(restored.contains("\"import csv\\n\""), "{}", restored);
+    assert!(restored.contains("\"outputs\": []"), "{}", restored);
+    assert!(
+        restored.contains("\"execution_count\": null"),
+        "{}",
+        restored
+    );
+}
+
+#[test]
+fn test_bundle_csv_preview_rows() {
+    let dir = tempdir().unwrap();
+    let mut data = String::from("id,name,notes\n1,a,\"spans\ntwo lines\"\n");
+    for i in 2..=50 {
+        data.push_str(&format!("{},n{},x\n", i, i));
+    }
+    fs::write(dir.path().join("data.csv"), &data).unwrap();
+    fs::write(dir.path().join("small.tsv"), "a\tb\n1\t2\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--csv-preview-rows", "3", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains("## data.csv\n```csv\nid,name,notes\n1,a,\"spans\ntwo lines\"\n2,n2,x\n3,n3,x\n```\n<!-- sheafy:preview first 3 of 50 rows -->\n"),
+        "bundle: {}",
+        bundle
+    );
+    assert!(!bundle.contains("4,n4,x"));
+    assert!(bundle.contains("## small.tsv\n```tsv\na\tb\n1\t2\n```\n"));
+
+    // Restore leaves previewed tables alone
+    let output = get_sheafy_cmd()
+        .args(["restore", "--check", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore --check");
+    assert!(
+        output.status.success(),
+        "previews should not count as differences"
+    );
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipping preview: data.csv"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("data.csv")).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn test_state_dir_is_not_bundled_and_cleaned() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    fs::create_dir_all(dir.path().join(".sheafy")).unwrap();
+    fs::write(dir.path().join(".sheafy/last-reply.md"), "## a.txt\n").unwrap();
+    fs::write(dir.path().join(".sheafy/descriptions.toml"), "# kept\n").unwrap();
+    // The socket of a daemon that was killed is left behind with nobody listening
+    #[cfg(unix)]
+    drop(std::os::unix::net::UnixListener::bind(dir.path().join(".sheafy/daemon.sock")).unwrap());
+
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+    assert!(bundle.contains("## a.txt"));
+    assert!(!bundle.contains(".sheafy"), "bundle: {}", bundle);
+
+    let output = get_sheafy_cmd()
+        .arg("clean")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy clean");
+    assert!(output.status.success(), "sheafy clean failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Removing: .sheafy/last-reply.md"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(!dir.path().join(".sheafy/last-reply.md").exists());
+    #[cfg(unix)]
+    assert!(!dir.path().join(".sheafy/daemon.sock").exists());
+    assert!(!dir.path().join("project_bundle.md").exists());
+    // User-authored files and the recorded paths stay
+    assert!(dir.path().join(".sheafy/descriptions.toml").exists());
+    assert!(dir.path().join(".sheafy/state.json").exists());
+
+    // With nothing else left in it, the directory goes too
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join(".sheafy")).unwrap();
+    fs::write(dir.path().join(".sheafy/last-reply.md"), "## a.txt\n").unwrap();
+    let output = get_sheafy_cmd()
+        .arg("clean")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy clean");
+    assert!(output.status.success(), "sheafy clean failed");
+    assert!(!dir.path().join(".sheafy").exists());
+}
+
+#[test]
+fn test_restore_flat_mode() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/util")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "pub mod util;\n").unwrap();
+    fs::write(dir.path().join("src/util/mod.rs"), "pub fn f() {}\n").unwrap();
+    fs::write(
+        dir.path().join("src__lib.rs"),
+        "// clashes once flattened\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--flat", "flat"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore --flat");
+    assert!(output.status.success(), "sheafy restore --flat failed");
+    let flat = dir.path().join("flat");
+    assert_eq!(
+        fs::read_to_string(flat.join("src__lib.rs")).unwrap(),
+        "pub mod util;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(flat.join("src__util__mod.rs")).unwrap(),
+        "pub fn f() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(flat.join("src__lib-2.rs")).unwrap(),
+        "// clashes once flattened\n"
+    );
+    let mapping: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(flat.join("sheafy-map.json")).unwrap()).unwrap();
+    assert_eq!(mapping["src__util__mod.rs"], "src/util/mod.rs");
+    assert_eq!(mapping["src__lib-2.rs"], "src__lib.rs");
+    assert_eq!(fs::read_dir(&flat).unwrap().count(), 4);
+}
+
+#[test]
+fn test_bundle_submodules_policy() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::create_dir_all(dir.path().join("vendor/lib/src")).unwrap();
+    // Submodules have a `.git` file pointing into the parent's git directory
+    fs::write(
+        dir.path().join("vendor/lib/.git"),
+        "gitdir: ../../.git/modules/lib\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("vendor/lib/README.txt"), "lib\n").unwrap();
+    fs::write(dir.path().join("vendor/lib/src/x.rs"), "pub fn x() {}\n").unwrap();
+
+    let bundle_with = |policy: Option<&str>| {
+        let mut config = String::from("[sheafy]\nignore_patterns = \"*.md\\nsheafy.toml\"\n");
+        if let Some(policy) = policy {
+            config.push_str(&format!("submodules = \"{}\"\n", policy));
+        }
+        fs::write(dir.path().join("sheafy.toml"), config).unwrap();
+        let output = get_sheafy_cmd()
+            .args(["bundle", "-o", "bundle.md"])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(output.status.success(), "sheafy bundle failed");
+        let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+        let has = |path: &str| bundle.contains(&format!("## {}\n", path));
+        assert!(has("main.rs"));
+        (has("vendor/lib/README.txt"), has("vendor/lib/src/x.rs"))
+    };
+
+    assert_eq!(bundle_with(None), (true, true));
+    assert_eq!(bundle_with(Some("include")), (true, true));
+    assert_eq!(bundle_with(Some("skip")), (false, false));
+    assert_eq!(bundle_with(Some("shallow")), (true, false));
+}
+
+#[test]
+fn test_bundle_show_excluded() {
+    let dir = tempdir().unwrap();
+    git(dir.path(), &["init", "-q"]);
+    fs::write(dir.path().join(".gitignore"), "build/\nsecret.rs\n").unwrap();
+    fs::write(dir.path().join(".env"), "TOKEN=x\n").unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("secret.rs"), "const KEY: &str = \"\";\n").unwrap();
+    fs::write(dir.path().join("debug.log"), "log\n").unwrap();
+    fs::create_dir_all(dir.path().join("build")).unwrap();
+    fs::write(dir.path().join("build/a.o"), "a").unwrap();
+    fs::write(dir.path().join("build/b.o"), "b").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"*.log\"\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--show-excluded"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle --show-excluded");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let section = |reason: &str| {
+        let start = stdout.find(&format!("  By {}", reason)).expect(reason);
+        let rest = &stdout[start..];
+        let end = rest[2..].find("  By ").map_or(rest.len(), |i| i + 2);
+        rest[..end].to_string()
+    };
+    assert!(stdout.contains("Excluded 8 file(s):"), "{}", stdout);
+    let hidden = section("hidden files");
+    assert!(hidden.contains("    .env\n") && hidden.contains("    .gitignore\n"));
+    let gitignored = section(".gitignore rules");
+    assert!(
+        gitignored.contains("    build/ (2 files)\n"),
+        "{}",
+        gitignored
+    );
+    assert!(gitignored.contains("    secret.rs\n"));
+    assert!(section("ignore_patterns").contains("    debug.log\n"));
+    let own = section("sheafy's own files");
+    assert!(own.contains("    sheafy.toml\n") && own.contains("    bundle.md\n"));
+    assert!(!stdout.contains("    main.rs\n"));
+}
+
+#[test]
+fn test_bundle_show_excluded_names_submodules() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::create_dir_all(dir.path().join("vendor/lib/src")).unwrap();
+    fs::write(
+        dir.path().join("vendor/lib/.git"),
+        "gitdir: ../../.git/modules/lib\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("vendor/lib/README.txt"), "lib\n").unwrap();
+    fs::write(dir.path().join("vendor/lib/src/x.rs"), "pub fn x() {}\n").unwrap();
+
+    let excluded = |policy: &str| {
+        fs::write(
+            dir.path().join("sheafy.toml"),
+            format!("[sheafy]\nsubmodules = \"{}\"\n", policy),
+        )
+        .unwrap();
+        let output = get_sheafy_cmd()
+            .args(["bundle", "-o", "bundle.md", "--show-excluded"])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle --show-excluded");
+        assert!(output.status.success(), "sheafy bundle failed");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        stdout[stdout.find("Excluded").expect(&stdout)..].to_string()
+    };
+
+    let skipped = excluded("skip");
+    assert!(
+        skipped.contains("  By submodules policy:\n    vendor/ (2 files)\n"),
+        "{}",
+        skipped
+    );
+    let shallow = excluded("shallow");
+    assert!(
+        shallow.contains("  By submodules policy:\n    vendor/lib/src"),
+        "{}",
+        shallow
+    );
+    assert!(!shallow.contains("README.txt"), "{}", shallow);
+    // Without submodules involved, nothing is blamed on them
+    let included = excluded("include");
+    assert!(!included.contains("submodules"), "{}", included);
+}
+
+#[test]
+fn test_section_spacing_and_tolerant_restore() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("b.py"), "b = 1\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nsection_spacing = 2\nignore_patterns = \"sheafy.toml\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(bundle.contains("```\n\n\n## b.py\n"), "{}", bundle);
+
+    // Hand-edited bundles: no or many blank lines, trailing whitespace on every delimiter
+    let mangled = bundle
+        .replacen("---\n", "--- \n", 1)
+        .replace("<!-- sheafy:v2 -->\n", "<!-- sheafy:v2 -->  \n\n")
+        .replace("## a.rs\n```rust\n", "## a.rs \t\n\n```rust  \n")
+        .replace("```\n\n\n## b.py\n", "``` \t\n## b.py\n")
+        .replace("b = 1\n```\n", "b = 1\n```   \n\n \n\n");
+    let restored = tempdir().unwrap();
+    fs::write(restored.path().join("bundle.md"), mangled).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(restored.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(restored.path().join("a.rs")).unwrap(),
+        "fn a() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(restored.path().join("b.py")).unwrap(),
+        "b = 1\n"
+    );
+
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nsection_spacing = 3\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("section_spacing must be 1 or 2"));
+}
+
+#[test]
+fn test_restore_parser_fences_and_error_positions() {
+    let dir = tempdir().unwrap();
+    let bundle = "<!-- sheafy:v2 -->\n\
+        # Notes\n\n\
+        ```markdown\n## not/a/file.rs\n```rust\nfn ignored() {}\n```\n\n\
+        ## doc.md\n\
+        ````markdown\nExample:\n```rust\nfn main() {}\n```\n````\n\n\
+        ## b.rs\n```rust\nfn b() {}\n```\n";
+    fs::write(dir.path().join("bundle.md"), bundle).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("doc.md")).unwrap(),
+        "Example:\n```rust\nfn main() {}\n```\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("b.rs")).unwrap(),
+        "fn b() {}\n"
+    );
+    assert!(!dir.path().join("not").exists());
+
+    // An unclosed fence is reported where it opens instead of swallowing later sections
+    let broken =
+        "<!-- sheafy:v2 -->\n\n## a.rs\n```rust\nfn a() {}\n\n## c.rs\n```rust\nfn c() {}\n";
+    fs::write(dir.path().join("broken.md"), broken).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "broken.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 4: code fence of 'a.rs' is never closed"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.path().join("a.rs").exists());
+}
+
+#[test]
+fn test_markdown_headings_round_trip_and_prose_headings() {
+    let dir = tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    // A README documenting the bundle format, headings and fences included
+    let readme = "# sheafy\n\n## Bundle format\n\nEach file is a section:\n\n\
+        ```markdown\n<!-- sheafy:v2 -->\n\n## src/main.rs\n```rust\nfn main() {}\n```\n```\n\n\
+        ## src/lib.rs\n\nHeadings like this one are prose here.\n\n\
+        ````\n## `my file.rs`\n```\n````\n";
+    fs::write(src.join("README.md"), readme).unwrap();
+    fs::write(src.join("main.rs"), "fn main() {}\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "../bundle.md"])
+        .current_dir(&src)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    let target = dir.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("bundle.md"))
+        .current_dir(&target)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("README.md")).unwrap(),
+        readme
+    );
+    let mut restored: Vec<_> = fs::read_dir(&target)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    restored.sort();
+    assert_eq!(restored, ["README.md", "main.rs"]);
+
+    // In a reply, a heading of prose followed by a fence is not a section
+    let reply = "<!-- sheafy:v2 -->\n\n## How to run\n```bash\ncargo run\n```\n\n\
+        ## Changes to `main.rs`:\n```rust\nfn main() {}\n```\n\n\
+        ## app.rs\n```rust\nfn app() {}\n```\n";
+    fs::write(dir.path().join("reply.md"), reply).unwrap();
+    let out = tempdir().unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("reply.md"))
+        .current_dir(out.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let restored: Vec<_> = fs::read_dir(out.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(restored, ["app.rs"]);
+
+    let output = get_sheafy_cmd()
+        .arg("inspect")
+        .arg(dir.path().join("reply.md"))
+        .output()
+        .expect("Failed to execute sheafy inspect");
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("heading '## How to run' does not name a path; treated as prose"));
+}
+
+#[test]
+fn test_restore_preserves_missing_final_newline() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("no_eol.txt"), "last line").unwrap();
+    fs::write(dir.path().join("empty.txt"), "").unwrap();
+    fs::write(dir.path().join("eol.txt"), "line\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(bundle.contains("## no_eol.txt\n```\nlast line\n```\n<!-- sheafy:no-eol -->\n"));
+    assert!(!bundle.contains("## eol.txt\n```\nline\n```\n<!-- sheafy:no-eol -->"));
+
+    let restore_into = |config: &str| {
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("sheafy.toml"), config).unwrap();
+        let output = get_sheafy_cmd()
+            .arg("restore")
+            .arg(dir.path().join("bundle.md"))
+            .current_dir(target.path())
+            .output()
+            .expect("Failed to execute sheafy restore");
+        assert!(output.status.success(), "sheafy restore failed");
+        ["no_eol.txt", "empty.txt", "eol.txt"]
+            .map(|name| fs::read_to_string(target.path().join(name)).unwrap())
+    };
+    assert_eq!(restore_into(""), ["last line", "", "line\n"]);
+    assert_eq!(
+        restore_into("[sheafy]\nnormalize_eof_newline = true\n"),
+        ["last line\n", "\n", "line\n"]
+    );
+}
+
+#[test]
+fn test_bundle_max_depth() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("top.rs"), "fn top() {}\n").unwrap();
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    fs::write(dir.path().join("a/mid.rs"), "fn mid() {}\n").unwrap();
+    fs::write(dir.path().join("a/b/deep.rs"), "fn deep() {}\n").unwrap();
+
+    let bundle_with = |args: &[&str], config: &str| {
+        fs::write(dir.path().join("sheafy.toml"), config).unwrap();
+        let output = get_sheafy_cmd()
+            .args(["bundle", "-o", "bundle.md"])
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(output.status.success(), "sheafy bundle failed");
+        let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+        ["top.rs", "a/mid.rs", "a/b/deep.rs"].map(|path| bundle.contains(&format!("## {}\n", path)))
+    };
+
+    assert_eq!(bundle_with(&[], ""), [true, true, true]);
+    assert_eq!(bundle_with(&["--max-depth", "1"], ""), [true, false, false]);
+    assert_eq!(
+        bundle_with(&[], "[sheafy]\nmax_depth = 2\n"),
+        [true, true, false]
+    );
+    // The flag overrides the config
+    assert_eq!(
+        bundle_with(&["--max-depth", "3"], "[sheafy]\nmax_depth = 1\n"),
+        [true, true, true]
+    );
+}
+
+#[test]
+fn test_bundle_files_from_list_and_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/b.rs"), "fn b() {}\n").unwrap();
+    fs::write(dir.path().join("src/a.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.path().join("unlisted.rs"), "fn c() {}\n").unwrap();
+    fs::write(dir.path().join(".gitignore"), "src/b.rs\n").unwrap();
+    fs::write(
+        dir.path().join("list.txt"),
+        "src/b.rs\n\n./src/a.rs\nsrc/b.rs\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--files-from", "list.txt"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle --files-from");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    // Listed order, even for ignored files; duplicates once; nothing else
+    let b = bundle.find("## src/b.rs\n").expect("src/b.rs missing");
+    let a = bundle.find("## src/a.rs\n").expect("src/a.rs missing");
+    assert!(b < a);
+    assert_eq!(bundle.matches("## src/b.rs\n").count(), 1);
+    assert!(!bundle.contains("unlisted.rs") && !bundle.contains("## list.txt"));
+
+    let mut child = get_sheafy_cmd()
+        .args(["bundle", "-o", "stdin.md", "--files-from", "-"])
+        .current_dir(dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to spawn sheafy bundle");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"unlisted.rs\n")
+        .unwrap();
+    assert!(
+        child.wait().unwrap().success(),
+        "sheafy bundle --files-from - failed"
+    );
+    let bundle = fs::read_to_string(dir.path().join("stdin.md")).unwrap();
+    assert!(bundle.contains("## unlisted.rs\n") && !bundle.contains("## src/a.rs"));
+
+    fs::write(dir.path().join("list.txt"), "missing.rs\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--files-from", "list.txt"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle --files-from");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'missing.rs' does not exist"));
+}
+
+#[test]
+fn test_header_prefixes_round_trip() {
+    let dir = tempdir().unwrap();
+    let config = "[sheafy]\nheader_strip_prefix = \"src\"\nheader_add_prefix = \"my-crate/\"\nignore_patterns = \"sheafy.toml\"\n";
+    fs::write(dir.path().join("sheafy.toml"), config).unwrap();
+    fs::create_dir_all(dir.path().join("src/util")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "pub mod util;\n").unwrap();
+    fs::write(dir.path().join("src/util/mod.rs"), "pub fn f() {}\n").unwrap();
+    fs::write(dir.path().join("README.md"), "# Demo\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(bundle.contains("## my-crate/lib.rs\n"), "{}", bundle);
+    assert!(bundle.contains("## my-crate/util/mod.rs\n"));
+    assert!(bundle.contains("## README.md\n"));
+
+    let target = tempdir().unwrap();
+    fs::write(target.path().join("sheafy.toml"), config).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("bundle.md"))
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(target.path().join("src/lib.rs")).unwrap(),
+        "pub mod util;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(target.path().join("src/util/mod.rs")).unwrap(),
+        "pub fn f() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(target.path().join("README.md")).unwrap(),
+        "# Demo\n"
+    );
+    assert!(!target.path().join("my-crate").exists());
+}
+
+#[test]
+fn test_header_strip_prefix_alone_keeps_other_files_in_place() {
+    let dir = tempdir().unwrap();
+    let config = "[sheafy]\nheader_strip_prefix = \"src\"\nignore_patterns = \"sheafy.toml\"\n";
+    fs::write(dir.path().join("sheafy.toml"), config).unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+    fs::write(dir.path().join("README.md"), "# Demo\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains("## README.md\n```markdown\n# Demo\n```\n<!-- sheafy:unmapped -->\n"),
+        "{}",
+        bundle
+    );
+    assert!(bundle.contains("## lib.rs\n"), "{}", bundle);
+
+    let target = tempdir().unwrap();
+    fs::write(target.path().join("sheafy.toml"), config).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("bundle.md"))
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(target.path().join("README.md")).unwrap(),
+        "# Demo\n"
+    );
+    assert!(target.path().join("src/lib.rs").exists());
+    assert!(!target.path().join("src/README.md").exists());
+}
+
+#[test]
+fn test_bundle_honors_parent_gitignore() {
+    let repo = tempdir().unwrap();
+    git(repo.path(), &["init", "-q"]);
+    fs::write(
+        repo.path().join(".gitignore"),
+        "target/\n/sub/build/\n*.log\n",
+    )
+    .unwrap();
+    let sub = repo.path().join("sub");
+    fs::create_dir_all(sub.join("src")).unwrap();
+    fs::create_dir_all(sub.join("target")).unwrap();
+    fs::create_dir_all(sub.join("build")).unwrap();
+    fs::write(sub.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(sub.join("target/out.rs"), "// generated\n").unwrap();
+    fs::write(sub.join("build/gen.rs"), "// generated\n").unwrap();
+    fs::write(sub.join("debug.log"), "log\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(&sub)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(
+        &sub.join("bundle.md"),
+        &["src/main.rs"],
+        &["target/out.rs", "build/gen.rs", "debug.log"],
+    );
+
+    fs::write(
+        sub.join("sheafy.toml"),
+        "[sheafy]\nparent_gitignore = false\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(&sub)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(
+        &sub.join("bundle.md"),
+        &["src/main.rs", "target/out.rs", "build/gen.rs", "debug.log"],
+        &[],
+    );
+}
+
+#[test]
+fn test_inspect_traces_accepted_and_rejected_blocks() {
+    let dir = tempdir().unwrap();
+    let bundle = "<!-- sheafy:v2 -->\n\n## src/a.rs\n```rust\nfn a() {}\n```\n\n## Notes\nJust prose.\n\n```\nloose\n```\n\n## b.rs\n<!-- sheafy:same-as gone.rs -->\n";
+    fs::write(dir.path().join("bundle.md"), bundle).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["inspect", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy inspect");
+    assert!(output.status.success(), "sheafy inspect failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("      3  heading '## src/a.rs'"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("      4  fence of 3 backticks opens 'src/a.rs'"));
+    assert!(stdout.contains("      6  section 'src/a.rs' from line 3 accepted"));
+    assert!(stdout.contains("      8  rejected: heading 'Notes' is followed by prose (line 9)"));
+    assert!(stdout.contains("     11  rejected: fence of 3 backticks without a heading"));
+    assert!(stdout.contains("     16  rejected: 'b.rs' refers to 'gone.rs'"));
+    assert!(stdout.contains("1 section(s) accepted, 3 rejected."));
+
+    fs::write(
+        dir.path().join("broken.md"),
+        "## a.rs\n````rust\nfn a() {}\n```\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["inspect", "broken.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy inspect");
+    assert!(
+        !output.status.success(),
+        "inspect should fail on an unclosed fence"
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("      2  fence of 4 backticks opens 'a.rs'"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("error: line 2: code fence of 'a.rs' is never closed"));
+}
+
+#[test]
+fn test_bundle_limits_per_pattern() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nlimits = { \"*.snap\" = \"2 files\", \"*.sql\" = \"1KB\" }\n",
+    )
+    .unwrap();
+    fs::create_dir_all(dir.path().join("snapshots")).unwrap();
+    for name in ["a", "b", "c", "d"] {
+        fs::write(
+            dir.path().join(format!("snapshots/{}.snap", name)),
+            format!("snapshot {}\n", name),
+        )
+        .unwrap();
+    }
+    fs::write(dir.path().join("big.sql"), "x".repeat(2000)).unwrap();
+    fs::write(dir.path().join("small.sql"), "select 1;\n").unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--show-excluded"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle_path = dir.path().join("bundle.md");
+    check_bundle_content(
+        &bundle_path,
+        &[
+            "main.rs",
+            "small.sql",
+            "snapshots/a.snap",
+            "snapshots/b.snap",
+        ],
+        &["big.sql", "snapshots/c.snap", "snapshots/d.snap"],
+    );
+    let bundle = fs::read_to_string(&bundle_path).unwrap();
+    assert!(
+        bundle.contains(
+            "> Left out by limits: 2 more file(s) matching `*.snap` (22 B; limit 2 file(s))."
+        ),
+        "{}",
+        bundle
+    );
+    assert!(bundle.contains(
+        "> Left out by limits: 1 more file(s) matching `*.sql` (2.0 KiB; limit 1.0 KiB)."
+    ));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("By limits in sheafy.toml:"), "{}", stdout);
+    assert!(stdout.contains("    big.sql\n"));
+
+    // The note is prose; restore reads the kept sections only
+    let target = tempdir().unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(&bundle_path)
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert!(target.path().join("snapshots/b.snap").exists());
+    assert!(!target.path().join("snapshots/c.snap").exists());
+}
+
+#[test]
+fn test_restore_tracked_only() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "// old\n").unwrap();
+    init_git_repo(dir.path());
+
+    let surprise = "<!-- sheafy:v2 -->\n\n## src/lib.rs\n```rust\n// new\n```\n\n## .cargo/config.toml\n```toml\n[build]\n```\n";
+    fs::write(dir.path().join("surprise.md"), surprise).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "surprise.md", "--tracked-only"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success(), "untracked path should be refused");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Not tracked by git: .cargo/config.toml"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("nothing was restored"));
+    assert!(!dir.path().join(".cargo").exists());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/lib.rs")).unwrap(),
+        "// old\n"
+    );
+
+    // An allowlisted path may be created
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nrestore_allow = \".cargo/\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "surprise.md", "--tracked-only"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "allowed paths should be restored");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/lib.rs")).unwrap(),
+        "// new\n"
+    );
+    assert!(dir.path().join(".cargo/config.toml").exists());
+}
+
+#[test]
+fn test_bundle_async_io_matches_sync() {
+    let dir = tempdir().unwrap();
+    for i in 0..40 {
+        fs::create_dir_all(dir.path().join(format!("d{}", i % 4))).unwrap();
+        fs::write(
+            dir.path().join(format!("d{}/f{}.txt", i % 4, i)),
+            format!("file {}\n", i),
+        )
+        .unwrap();
+    }
+    let bundle = |io: &str| {
+        let output = get_sheafy_cmd()
+            .args(["bundle", "--reproducible", "--io", io, "-o"])
+            .arg(format!(".out/{}.md", io))
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+    let (ok, _) = bundle("sync");
+    assert!(ok, "sheafy bundle --io sync failed");
+    let (ok, stderr) = bundle("async");
+    if cfg!(feature = "async-io") {
+        assert!(ok, "sheafy bundle --io async failed: {}", stderr);
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".out/sync.md")).unwrap(),
+            fs::read_to_string(dir.path().join(".out/async.md")).unwrap()
+        );
+    } else {
+        assert!(!ok && stderr.contains("async-io"), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_bundle_against_baseline() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("same.txt"), "same\n").unwrap();
+    fs::write(dir.path().join("edited.txt"), "before\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", ".bundles/round1.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+
+    fs::write(dir.path().join("edited.txt"), "after\n").unwrap();
+    fs::write(dir.path().join("added.txt"), "new\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args([
+            "bundle",
+            "-o",
+            ".bundles/round2.md",
+            "--baseline",
+            ".bundles/round1.md",
+        ])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle --baseline failed");
+    let round2 = dir.path().join(".bundles/round2.md");
+    check_bundle_content(&round2, &["edited.txt", "added.txt"], &["same.txt"]);
+    let content = fs::read_to_string(&round2).unwrap();
+    assert!(
+        content.contains("> Unchanged since round1.md and not repeated here: `same.txt`."),
+        "{}",
+        content
+    );
+
+    // Nothing changed since the last full bundle: it only lists the files
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", ".bundles/full.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let output = get_sheafy_cmd()
+        .args([
+            "bundle",
+            "-o",
+            ".bundles/round3.md",
+            "--baseline",
+            ".bundles/full.md",
+        ])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle --baseline failed");
+    let content = fs::read_to_string(dir.path().join(".bundles/round3.md")).unwrap();
+    assert!(!content.contains("\n## "), "{}", content);
+    assert!(
+        content.contains("`added.txt`, `edited.txt`, `same.txt`"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn test_header_escaping_and_normalization() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("my file (v2).rs"), "fn v2() {}\n").unwrap();
+    fs::write(dir.path().join("docs/#notes.md"), "notes\n").unwrap();
+    fs::write(dir.path().join("plain_name.rs"), "fn plain() {}\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(bundle.contains("\n## `my file (v2).rs`\n"), "{}", bundle);
+    assert!(bundle.contains("\n## `docs/#notes.md`\n"), "{}", bundle);
+    assert!(bundle.contains("\n## plain_name.rs\n"), "{}", bundle);
+
+    let target = tempdir().unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", dir.path().join("out.md").to_str().unwrap()])
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(target.path().join("my file (v2).rs")).unwrap(),
+        "fn v2() {}\n"
+    );
+    assert!(target.path().join("docs/#notes.md").exists());
+
+    // Legacy bare headings, a closing `#` sequence and decomposed accents
+    let legacy = "<!-- sheafy:v2 -->\n\n## src/a.rs ##\n```\na\n```\n\n## caf\u{65}\u{301}.txt\n```\ncafe\n```\n";
+    fs::write(target.path().join("legacy.md"), legacy).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "legacy.md"])
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert!(target.path().join("src/a.rs").exists());
+    assert!(target.path().join("caf\u{e9}.txt").exists());
+}
+
+#[test]
+fn test_restore_writes_over_nfd_named_file() {
+    let dir = tempdir().unwrap();
+    // Decomposed, as file names created on macOS are
+    let nfd = "caf\u{65}\u{301}.txt";
+    fs::write(dir.path().join(nfd), "before\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(bundle.contains("\n## caf\u{e9}.txt\n"), "{}", bundle);
+
+    fs::write(dir.path().join("out.md"), bundle.replace("before", "after")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "out.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read_to_string(dir.path().join(nfd)).unwrap(), "after\n");
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().starts_with("caf"))
+        .collect();
+    assert_eq!(names.len(), 1, "{:?}", names);
+}
+
+#[test]
+fn test_restore_truncated_final_fence() {
+    let dir = tempdir().unwrap();
+    let bundle = "<!-- sheafy:v2 -->\n\n## done.txt\n```\ncomplete\n```\n\n## src/cut.rs\n```rust\nfn main() {\n    println!(\"hel";
+    fs::write(dir.path().join("reply.md"), bundle).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        !output.status.success(),
+        "truncation should fail by default"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'src/cut.rs' is never closed"),
+        "{}",
+        stderr
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md", "--on-truncated", "skip"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: 'src/cut.rs' is truncated"),
+        "{}",
+        stderr
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 skipped."));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("done.txt")).unwrap(),
+        "complete\n"
+    );
+    assert!(!dir.path().join("src/cut.rs").exists());
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md", "--on-truncated", "partial"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert!(!dir.path().join("src/cut.rs").exists());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/cut.rs.partial")).unwrap(),
+        "fn main() {\n    println!(\"hel\n"
+    );
+}
+
+#[test]
+fn test_restore_refuses_paths_never_bundled() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let state = fs::read_to_string(dir.path().join(".sheafy/state.json")).unwrap();
+    assert!(state.contains("\"main.rs\""), "{}", state);
+
+    let reply = "<!-- sheafy:v2 -->\n\n## main.rs\n```rust\nfn main() { run() }\n```\n\n## src/invented.rs\n```rust\npub fn run() {}\n```\n";
+    fs::write(dir.path().join("reply.md"), reply).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success(), "unknown paths should be refused");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Never bundled from this project: src/invented.rs"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("--allow-new-paths"), "{}", stderr);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md", "--allow-new-paths"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert!(dir.path().join("src/invented.rs").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_restore_validate() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "fn old() {}\n").unwrap();
+    fs::write(dir.path().join("keep.txt"), "kept\n").unwrap();
+    // The check sees the whole tree, with the bundle's files in place
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"*.md\"\n\n[sheafy.restore]\nvalidate = true\n\n\
+         [sheafy.restore.checks]\nrust = \"test -f keep.txt && grep -q 'fn ok' src/lib.rs\"\n",
+    )
+    .unwrap();
+    let bundle = "## src/lib.rs\n```rust\nfn broken( {}\n```\n";
+    fs::write(dir.path().join("bad.md"), bundle).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bad.md", "--allow-new-paths"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Validation check"), "{}", stderr);
+    assert!(stderr.contains("nothing was restored"), "{}", stderr);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/lib.rs")).unwrap(),
+        "fn old() {}\n"
+    );
+
+    // --no-validate restores regardless
+    let output = get_sheafy_cmd()
+        .args(["restore", "bad.md", "--allow-new-paths", "--no-validate"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore --no-validate failed"
+    );
+    assert!(fs::read_to_string(dir.path().join("src/lib.rs"))
+        .unwrap()
+        .contains("fn broken"));
+
+    fs::write(
+        dir.path().join("good.md"),
+        bundle.replace("fn broken( {}", "fn ok() {}"),
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "good.md", "--allow-new-paths"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "sheafy restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Validation passed."));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/lib.rs")).unwrap(),
+        "fn ok() {}\n"
+    );
+}
+
+#[test]
+fn test_restore_allowed_paths() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy.restore]\nallowed_paths = [\"src/**\", \"tests/**\"]\n",
+    )
+    .unwrap();
+    let reply = "<!-- sheafy:v2 -->\n\n## src/lib.rs\n```rust\npub fn run() {}\n```\n\n## .github/workflows/deploy.yml\n```yaml\non: push\n```\n";
+    fs::write(dir.path().join("reply.md"), reply).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        !output.status.success(),
+        "disallowed paths should be refused"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Outside allowed_paths: .github/workflows/deploy.yml"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.path().join("src/lib.rs").exists());
+
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy.restore]\nallowed_paths = [\"src/**\"]\non_disallowed = \"skip\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "reply.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'.github/workflows/deploy.yml' is outside allowed_paths"));
+    assert!(dir.path().join("src/lib.rs").exists());
+    assert!(!dir.path().join(".github").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_bundle_copy_chunks() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempdir().unwrap();
+    let bin = tempdir().unwrap();
+    let copied = bin.path().join("copied.txt");
+    // A stand-in clipboard tool that records what it was given
+    let tool = bin.path().join("xclip");
+    fs::write(
+        &tool,
+        format!("#!/bin/sh\n/bin/cat > '{}'\n", copied.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--copy-chunks", "100000"])
+        .env("PATH", bin.path())
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert_eq!(fs::read_to_string(&copied).unwrap(), bundle);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Copied chunk 1/1"));
+
+    // Several chunks are copied one keypress at a time, which needs a terminal
+    let long: String = (0..300).map(|i| format!("line {}\n", i)).collect();
+    fs::write(dir.path().join("long.txt"), long).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--copy-chunks", "1000"])
+        .env("PATH", bin.path())
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("needs a terminal"), "{}", stderr);
+    assert!(dir.path().join("bundle.md").exists());
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--copy-chunks", "10"])
+        .env("PATH", bin.path())
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("too small"));
+}
+
+#[test]
+fn test_bundle_of_bundles_round_trip() {
+    let dir = tempdir().unwrap();
+    // A bundle held as a file, and a Markdown file quoting fences of its own
+    let inner = "---\ngenerator: sheafy 0.1.0\n---\n<!-- sheafy:v2 -->\n\n## src/a.rs\n```rust\nfn a() {}\n```\n\n## b.txt\n```\nb\n```\n";
+    let doc = "# Fences\n\n````markdown\n```rust\nfn main() {}\n```\n````\n  ```\n";
+    fs::create_dir(dir.path().join("fixtures")).unwrap();
+    fs::write(dir.path().join("fixtures/inner.md"), inner).unwrap();
+    fs::write(dir.path().join("doc.md"), doc).unwrap();
+    fs::write(dir.path().join("c.rs"), "fn c() {}\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out/bundle.md", "--include-bundles"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("out/bundle.md")).unwrap();
+    assert!(
+        bundle.contains("## fixtures/inner.md\n````markdown\n"),
+        "{}",
+        bundle
+    );
+    assert!(bundle.contains("## doc.md\n`````markdown\n"), "{}", bundle);
+    assert!(bundle.contains("## c.rs\n```rust\n"), "{}", bundle);
+
+    for path in ["fixtures/inner.md", "doc.md", "c.rs"] {
+        fs::remove_file(dir.path().join(path)).unwrap();
+    }
+    let output = get_sheafy_cmd()
+        .args(["restore", "out/bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("fixtures/inner.md")).unwrap(),
+        inner
+    );
+    assert_eq!(fs::read_to_string(dir.path().join("doc.md")).unwrap(), doc);
+    assert!(!dir.path().join("src/a.rs").exists());
+    assert!(!dir.path().join("b.txt").exists());
+
+    let output = get_sheafy_cmd()
+        .args(["verify", "out/bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy verify");
+    assert!(output.status.success(), "sheafy verify failed");
+}
+
+#[test]
+fn test_bundle_stub_sections() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("assets")).unwrap();
+    let logo = [0x89u8, b'P', b'N', b'G', 0, 1, 2, 3];
+    fs::write(dir.path().join("assets/logo.png"), logo).unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nstub_url = \"https://example.com/{path}\"\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--stub", "*.png"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains(
+            "## assets/logo.png\n<!-- sheafy:stub {\"size\":8,\"sha256\":\"\
+             a2f1fd24269194c781385faedf255eead2ddc66f0a8200d04bdaa040d42e2a22\","
+        ),
+        "{}",
+        bundle
+    );
+    assert!(bundle.contains("\"url\":\"https://example.com/assets/logo.png\"} -->"));
+    assert!(bundle.contains("fn main() {}"));
+
+    // The hash matches the file on disk
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--check"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "restore --check failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Stubs are skipped by default
+    let target = tempdir().unwrap();
+    let bundle_path = dir.path().join("bundle.md");
+    let output = get_sheafy_cmd()
+        .args(["restore", bundle_path.to_str().unwrap()])
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipping stub: assets/logo.png"));
+    assert!(target.path().join("main.rs").exists());
+    assert!(!target.path().join("assets/logo.png").exists());
+
+    // Placeholders are empty files, and never replace an existing one
+    fs::write(target.path().join("main.rs"), "changed").unwrap();
+    let output = get_sheafy_cmd()
+        .args([
+            "restore",
+            bundle_path.to_str().unwrap(),
+            "--stubs",
+            "placeholder",
+        ])
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(target.path().join("assets/logo.png")).unwrap(),
+        Vec::<u8>::new()
+    );
+    fs::write(target.path().join("assets/logo.png"), "real").unwrap();
+    get_sheafy_cmd()
+        .args([
+            "restore",
+            bundle_path.to_str().unwrap(),
+            "--stubs",
+            "placeholder",
+        ])
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert_eq!(
+        fs::read_to_string(target.path().join("assets/logo.png")).unwrap(),
+        "real"
+    );
+}
+
+#[test]
+fn test_bundle_directory_settings() {
+    let dir = tempdir().unwrap();
+    for (path, content) in [
+        ("README.md", "top\n"),
+        ("a/x.snap", "snapshot\n"),
+        ("a/keep.snap", "kept\n"),
+        ("a/lib.rs", "fn a() {}\n"),
+        ("b/page.tpl", "<p>hi</p>\n"),
+        ("b/fixtures/data.txt", "fixture\n"),
+        ("b/main.rs", "fn main() {}\n"),
+        ("top.snap", "not scoped\n"),
+    ] {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"sheafy.toml\"\n\
+         [sheafy.dir.\"a\"]\nignore_patterns = \"*.snap\"\n",
+    )
+    .unwrap();
+    // A nested config adds to the table for its directory
+    fs::write(
+        dir.path().join("a/sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"!keep.snap\"\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("b/sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"fixtures/\"\npriority_patterns = \"main.rs\"\n\
+         language_hints = { tpl = \"html\" }\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--show-excluded"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(!bundle.contains("## a/x.snap"), "{}", bundle);
+    assert!(bundle.contains("## a/keep.snap"), "{}", bundle);
+    assert!(bundle.contains("## top.snap"), "{}", bundle);
+    assert!(!bundle.contains("## b/fixtures/data.txt"), "{}", bundle);
+    assert!(!bundle.contains("sheafy.toml"), "{}", bundle);
+    assert!(bundle.contains("## b/page.tpl\n```html\n"), "{}", bundle);
+    assert!(
+        bundle.find("## b/main.rs").unwrap() < bundle.find("## README.md").unwrap(),
+        "{}",
+        bundle
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("ignore_patterns of a directory"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_bundle_file_stats() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.rs"), "fn a() {}\nfn b() {}\n").unwrap();
+
+    for style in ["heading", "title"] {
+        let output = get_sheafy_cmd()
+            .args([
+                "bundle",
+                "-o",
+                "bundle.md",
+                "--file-stats",
+                "--header-style",
+                style,
+            ])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(
+            output.status.success(),
+            "sheafy bundle failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+        let stats = "<!-- sheafy:stats lines=2 bytes=20 tokens=5 -->\n```rust";
+        assert!(bundle.contains(stats), "{}", bundle);
+        if style == "heading" {
+            assert!(bundle.contains("## a.rs\n<!-- sheafy:stats"), "{}", bundle);
+        }
+
+        let target = tempdir().unwrap();
+        let output = get_sheafy_cmd()
+            .args(["restore", dir.path().join("bundle.md").to_str().unwrap()])
+            .current_dir(target.path())
+            .output()
+            .expect("Failed to execute sheafy restore");
+        assert!(output.status.success());
+        assert_eq!(
+            fs::read_to_string(target.path().join("a.rs")).unwrap(),
+            "fn a() {}\nfn b() {}\n"
+        );
+    }
+}
+
+#[test]
+fn test_bundle_smart_order() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    for (path, content) in [
+        ("aaa.txt", "notes\n"),
+        ("Cargo.toml", "[package]\nname = \"demo\"\n"),
+        ("README.md", "# Demo\n"),
+        ("src/main.rs", "mod util;\nmod api;\nfn main() {}\n"),
+        ("src/api.rs", "pub fn call() {}\n"),
+        ("src/util.rs", "pub fn help() {}\n"),
+        ("src/zed.rs", "pub fn unused() {}\n"),
+    ] {
+        fs::write(dir.path().join(path), content).unwrap();
+    }
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--auto-order", "smart"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    let headings: Vec<&str> = bundle
+        .lines()
+        .filter_map(|line| line.strip_prefix("## "))
+        .collect();
+    assert_eq!(
+        headings,
+        [
+            "Cargo.toml",
+            "README.md",
+            "src/main.rs",
+            "src/util.rs",
+            "src/api.rs",
+            "aaa.txt",
+            "src/zed.rs"
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_daemon_socket() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "Content A").unwrap();
+    fs::write(dir.path().join("b.rs"), "fn b() {}\n").unwrap();
+
+    let mut child = get_sheafy_cmd()
+        .args(["daemon", "--socket"])
+        .current_dir(dir.path())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn sheafy daemon");
+    let socket = dir.path().join(".sheafy").join("daemon.sock");
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert!(socket.exists(), "daemon socket was not created");
+
+    for _ in 0..3 {
+        let output = get_sheafy_cmd()
+            .args(["bundle", "-o", "out.md"])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("via the daemon"), "stdout: {}", stdout);
+    }
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(bundle.contains("Content A"));
+    assert!(bundle.contains("fn b() {}"));
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .env("SHEAFY_NO_DAEMON", "1")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("daemon"));
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    writeln!(
+        stream,
+        r#"{{"jsonrpc":"2.0","id":1,"method":"stats","params":{{"output":"out.md"}}}}"#
+    )
+    .unwrap();
+    let mut responses = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    responses.read_line(&mut line).unwrap();
+    let stats: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(stats["result"]["files"], 2, "stats: {}", line);
+    writeln!(stream, r#"{{"jsonrpc":"2.0","id":2,"method":"shutdown"}}"#).unwrap();
+    line.clear();
+    responses.read_line(&mut line).unwrap();
+    assert!(line.contains(r#""id":2"#), "shutdown: {}", line);
+    drop(stream);
+
+    let status = child.wait().unwrap();
+    assert!(status.success());
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(
+        stderr.contains("reusing its file list"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(!socket.exists(), "daemon socket was not removed");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_daemon_socket_follows_outside_ignores_and_client_env() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    let project = dir.path().join("proj");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("a.txt"), "a\n").unwrap();
+    fs::write(project.join("x.log"), "log\n").unwrap();
+    fs::write(project.join("y.tmp"), "tmp\n").unwrap();
+    init_git_repo(dir.path());
+
+    let mut child = get_sheafy_cmd()
+        .args(["daemon", "--socket"])
+        .current_dir(&project)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to spawn sheafy daemon");
+    let socket = project.join(".sheafy").join("daemon.sock");
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert!(socket.exists(), "daemon socket was not created");
+    let bundle = |env: Option<(&str, &str)>| {
+        let mut cmd = get_sheafy_cmd();
+        cmd.args(["bundle", "-o", "out.md"]).current_dir(&project);
+        if let Some((name, value)) = env {
+            cmd.env(name, value);
+        }
+        let output = cmd.output().expect("Failed to execute sheafy bundle");
+        assert!(output.status.success(), "sheafy bundle failed");
+        (
+            String::from_utf8_lossy(&output.stdout).to_string(),
+            fs::read_to_string(project.join("out.md")).unwrap(),
+        )
+    };
+
+    let (stdout, content) = bundle(None);
+    assert!(stdout.contains("via the daemon"), "stdout: {}", stdout);
+    assert!(content.contains("## x.log") && content.contains("## y.tmp"));
+    // Rules outside the directories the walk enters apply on the next request
+    fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+    fs::write(dir.path().join(".git/info/exclude"), "*.tmp\n").unwrap();
+    let (stdout, content) = bundle(None);
+    assert!(stdout.contains("via the daemon"), "stdout: {}", stdout);
+    assert!(!content.contains("## x.log"), "bundle: {}", content);
+    assert!(!content.contains("## y.tmp"), "bundle: {}", content);
+
+    // The client's SOURCE_DATE_EPOCH is not the daemon's, so it bundles by itself
+    let (stdout, content) = bundle(Some(("SOURCE_DATE_EPOCH", "1700000000")));
+    assert!(!stdout.contains("via the daemon"), "stdout: {}", stdout);
+    assert!(
+        content.contains("created: 2023-11-14T22:13:20Z"),
+        "bundle: {}",
+        content
+    );
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    writeln!(stream, r#"{{"jsonrpc":"2.0","id":1,"method":"shutdown"}}"#).unwrap();
+    drop(stream);
+    assert!(child.wait().unwrap().success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_daemon_paranoid_cache_counts() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\nb").unwrap();
+    fs::write(dir.path().join("b.rs"), "fn b() {}\n").unwrap();
+    // Old enough that the modification times are trusted without --paranoid
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+    for name in ["a.txt", "b.rs"] {
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join(name))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+
+    let mut child = get_sheafy_cmd()
+        .args(["daemon", "--socket", "--paranoid", "--verbose"])
+        .current_dir(dir.path())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn sheafy daemon");
+    let socket = dir.path().join(".sheafy").join("daemon.sock");
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert!(socket.exists(), "daemon socket was not created");
+    let request = |method: &str| {
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        writeln!(
+            stream,
+            r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{{"output":"out.md"}}}}"#,
+            method
+        )
+        .unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        serde_json::from_str::<serde_json::Value>(&line).unwrap()
+    };
+
+    let first = request("stats");
+    assert_eq!(first["result"]["lines"], 3, "{}", first);
+    let second = request("stats");
+    let cache = &second["result"]["cache"];
+    assert_eq!(cache["file_hits"], 2, "{}", second);
+    assert_eq!(cache["verified"], 2, "{}", second);
+
+    // Same size and modification time, different content: only the hash tells
+    fs::write(dir.path().join("a.txt"), "ab\n").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(dir.path().join("a.txt"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    let third = request("stats");
+    assert_eq!(third["result"]["lines"], 2, "{}", third);
+    assert_eq!(third["result"]["cache"]["file_misses"], 3, "{}", third);
+    request("shutdown");
+
+    assert!(child.wait().unwrap().success());
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(
+        stderr.contains("files 3 hit(s) (3 checked by hash), 3 miss(es)"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_restore_collects_write_failures() {
+    let dir = tempdir().unwrap();
+    let bundle =
+        "## a.txt\n```\nA\n```\n\n## blocker/x.txt\n```\nX\n```\n\n## c.txt\n```\nC\n```\n";
+    fs::write(dir.path().join("bundle.md"), bundle).unwrap();
+    // A file where a directory is needed makes `blocker/x.txt` unwritable, even as root
+    fs::write(dir.path().join("blocker"), "not a directory").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 file(s) could not be restored:"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(stderr.contains("  blocker/x.txt: "), "stderr: {}", stderr);
+    assert!(stderr.contains("error code: E305"), "stderr: {}", stderr);
+    assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "A\n");
+    assert_eq!(fs::read_to_string(dir.path().join("c.txt")).unwrap(), "C\n");
+
+    fs::remove_file(dir.path().join("a.txt")).unwrap();
+    fs::remove_file(dir.path().join("c.txt")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--stop-on-error"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--stop-on-error"), "stderr: {}", stderr);
+    assert!(dir.path().join("a.txt").exists());
+    assert!(!dir.path().join("c.txt").exists());
+}
+
+#[test]
+fn test_bundle_always_include() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("LICENSE"), "MIT License\n").unwrap();
+    fs::write(dir.path().join(".env.example"), "TOKEN=\n").unwrap();
+    fs::write(dir.path().join(".env"), "TOKEN=secret\n").unwrap();
+    fs::create_dir_all(dir.path().join(".github/workflows")).unwrap();
+    fs::write(dir.path().join(".github/workflows/ci.yml"), "on: push\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"LICENSE\"\nalways_include = [\"LICENSE\", \".github/workflows/ci.yml\", \"./.env.example\", \"missing.txt\"]\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("always_include file 'missing.txt' does not exist"),
+        "stderr: {}",
+        stderr
+    );
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(bundle.contains("\n## main.rs\n"));
+    assert!(bundle.contains("\n## LICENSE\n"), "bundle: {}", bundle);
+    assert!(bundle.contains("\n## .github/workflows/ci.yml\n"));
+    assert!(bundle.contains("\n## .env.example\n"));
+    assert!(!bundle.contains("TOKEN=secret"));
+}
+
+#[test]
+fn test_bundle_long_lines_policies() {
+    let dir = tempdir().unwrap();
+    let minified = format!("{}\nshort\n{}", "var a=1;".repeat(30), "\u{e9}".repeat(25));
+    fs::write(dir.path().join("app.min.js"), &minified).unwrap();
+    fs::write(
+        dir.path().join("blob.b64"),
+        format!("{}\n", "QUJD".repeat(20)),
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("huge.txt"),
+        format!("{}\n", "x".repeat(100)),
+    )
+    .unwrap();
+    fs::write(dir.path().join("wide.txt"), format!("{}\n", "y".repeat(30))).unwrap();
+    fs::write(dir.path().join("ok.rs"), "fn main() {}\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nmax_line_length = 20\nlong_lines = { \"*.js\" = \"wrap\", \"*.b64\" = \"truncate\", \"huge.txt\" = \"skip\" }\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "out.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "'app.min.js' has 2 line(s) longer than 20 characters (line 1 has 240); wrapping them."
+        ),
+        "stderr: {}",
+        stderr
+    );
+    assert!(stderr.contains("'huge.txt' has 1 line(s)"));
+    assert!(stderr.contains(
+        "'wide.txt' has 1 line(s) longer than 20 characters (line 1 has 30); bundling it as is."
+    ));
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(!bundle.contains("## huge.txt"));
+    assert!(
+        bundle.contains("<!-- sheafy:wrap at=20 lines=1:12,3:2 -->"),
+        "bundle: {}",
+        bundle
+    );
+    assert!(bundle.contains("\nQUJDQUJDQUJDQUJDQUJD [... 60 more characters]\n```\n<!-- sheafy:preview long lines cut at 20 characters -->\n"));
+    assert!(bundle.contains(&"y".repeat(30)));
+
+    let restored = dir.path().join("restored");
+    fs::create_dir(&restored).unwrap();
+    fs::copy(dir.path().join("out.md"), restored.join("out.md")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "out.md"])
+        .current_dir(&restored)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(restored.join("app.min.js")).unwrap(),
+        minified
+    );
+    assert!(!restored.join("blob.b64").exists());
+}
+
+#[test]
+fn test_bundle_minified_policies() {
+    let dir = tempdir().unwrap();
+    let minified = "function(a,b){return a+b};".repeat(200);
+    fs::write(dir.path().join("app.js"), &minified).unwrap();
+    // Long, but ordinary prose
+    fs::write(
+        dir.path().join("notes.txt"),
+        "a line of ordinary words\n".repeat(100),
+    )
+    .unwrap();
+    let bundle_with = |policy: &str| {
+        let output = get_sheafy_cmd()
+            .args(["bundle", "-o", "bundle.md", "--minified", policy])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(stderr.contains("'app.js' looks minified"), "{}", stderr);
+        assert!(!stderr.contains("'notes.txt' looks minified"), "{}", stderr);
+        fs::read_to_string(dir.path().join("bundle.md")).unwrap()
+    };
+
+    let bundle = bundle_with("include");
+    assert!(bundle.contains(&minified));
+
+    let bundle = bundle_with("skip");
+    assert!(!bundle.contains("## app.js"));
+    assert!(bundle.contains("> Left out as minified: 1 file(s): `app.js` (5.1 KiB)."));
+    assert!(bundle.contains("## notes.txt"));
+
+    let bundle = bundle_with("truncate");
+    assert!(bundle.contains("## app.js"));
+    assert!(!bundle.contains(&minified));
+    assert!(
+        bundle.contains("<!-- sheafy:preview first 1000 of 5200 characters of a minified file -->")
+    );
+    // Restore leaves the real file alone
+    let target = tempdir().unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("bundle.md"))
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert!(!target.path().join("app.js").exists());
+    assert!(target.path().join("notes.txt").exists());
+}
+
+#[test]
+fn test_ask_streams_reply_and_applies_it() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    if std::process::Command::new("curl")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        eprintln!("curl not found; skipping");
+        return;
+    }
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+    // A stand-in for the Messages API that streams a fixed reply
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut headers = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            headers.push_str(&line.to_ascii_lowercase());
+        }
+        let mut stream = stream;
+        if headers.contains("expect: 100-continue") {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        }
+        let length: usize = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let reply = "Here you go.\n\n## hello.txt\n```\nHello!\n```\n";
+        let mut response = String::from(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n",
+        );
+        for piece in [&reply[..10], &reply[10..]] {
+            let event = serde_json::json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": { "type": "text_delta", "text": piece },
+            });
+            response.push_str(&format!("event: content_block_delta\ndata: {}\n\n", event));
+        }
+        response.push_str("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n");
+        stream.write_all(response.as_bytes()).unwrap();
+        (headers, String::from_utf8(body).unwrap())
+    });
+
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        format!(
+            "[sheafy]\n[sheafy.ask]\nmodel = \"claude-test\"\nbase_url = \"http://127.0.0.1:{}\"\n",
+            port
+        ),
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args([
+            "ask",
+            "--prompt",
+            "Add a greeting file.",
+            "--apply",
+            "--allow-new-paths",
+            "-o",
+            "bundle.md",
+        ])
+        .env("ANTHROPIC_API_KEY", "test-key")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy ask");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {}", stderr);
+    let (headers, body) = server.join().unwrap();
+    assert!(
+        headers.contains("x-api-key: test-key"),
+        "headers: {}",
+        headers
+    );
+    assert!(headers.contains("anthropic-version: "));
+    let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(request["model"], "claude-test");
+    assert_eq!(request["stream"], true);
+    let sent = request["messages"][0]["content"].as_str().unwrap();
+    assert!(sent.contains("Add a greeting file."));
+    assert!(sent.contains("fn main() {}"));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "Here you go.\n\n## hello.txt\n```\nHello!\n```\n");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("hello.txt")).unwrap(),
+        "Hello!\n"
+    );
+    assert!(dir.path().join(".sheafy/last-reply.md").exists());
+
+    let output = get_sheafy_cmd()
+        .args(["ask", "--prompt", "x", "-o", "bundle.md"])
+        .env_remove("ANTHROPIC_API_KEY")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy ask");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Set ANTHROPIC_API_KEY"));
+}
+
+#[test]
+fn test_bundle_file_stats_tokenizer() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "abab  ab\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "中文字\n").unwrap();
+    // a, b, space, newline, "ab" and two spaces
+    fs::write(
+        dir.path().join("ranks.tiktoken"),
+        "YQ== 0\nYg== 1\nIA== 2\nCg== 3\nYWI= 4\nICA= 5\n",
+    )
+    .unwrap();
+
+    let bundle = |config: &str| {
+        fs::write(dir.path().join("sheafy.toml"), config).unwrap();
+        let output = get_sheafy_cmd()
+            .args(["bundle", "-o", "bundle.md", "--file-stats"])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(
+            output.status.success(),
+            "sheafy bundle failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        (
+            fs::read_to_string(dir.path().join("bundle.md")).unwrap(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    // The estimate counts each CJK character as a token
+    let (estimated, _) = bundle("[sheafy]\nignore_patterns = \"*.tiktoken\"\n");
+    assert!(estimated.contains("## a.txt\n<!-- sheafy:stats lines=1 bytes=9 tokens=3 -->"));
+    assert!(estimated.contains("## b.txt\n<!-- sheafy:stats lines=1 bytes=10 tokens=4 -->"));
+
+    // "abab" "  " "ab" "\n" would be 4; the pre-tokenizer leaves the second space to
+    // " ab", which has no rank: "abab"=2, " "=1, " ab"=2, "\n"=1
+    let (exact, _) = bundle(
+        "[sheafy]\nignore_patterns = \"*.tiktoken\"\ntokenizer = \"cl100k\"\ntokenizer_file = \"ranks.tiktoken\"\n",
+    );
+    assert!(
+        exact.contains("## a.txt\n<!-- sheafy:stats lines=1 bytes=9 tokens=6 -->"),
+        "{}",
+        exact
+    );
+
+    let (fallback, stderr) = bundle(
+        "[sheafy]\nignore_patterns = \"*.tiktoken\"\ntokenizer = \"o200k\"\ntokenizer_file = \"missing.tiktoken\"\n",
+    );
+    assert!(
+        stderr.contains("Warning: tokenizer 'o200k' needs its rank file"),
+        "{}",
+        stderr
+    );
+    assert!(fallback.contains("## a.txt\n<!-- sheafy:stats lines=1 bytes=9 tokens=3 -->"));
+}
+
+#[test]
+fn test_bundle_script_formats_round_trip() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/it's here")).unwrap();
+    let files = [
+        ("src/main.rs", "fn main() {}\n"),
+        ("src/it's here/note.txt", "no final 'newline'\n$HOME `x`"),
+        ("eof.txt", "SHEAFY_EOF\n\n"),
+        ("crlf.txt", "a\r\nb\r\n"),
+        ("empty.txt", ""),
+    ];
+    for (path, content) in files {
+        fs::write(dir.path().join(path), content).unwrap();
+    }
+
+    for (format, script) in [("shell", "bundle.sh"), ("powershell", "bundle.ps1")] {
+        let output = get_sheafy_cmd()
+            .args(["bundle", "--format", format, "-o", script])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(
+            output.status.success(),
+            "sheafy bundle failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let text = fs::read_to_string(dir.path().join(script)).unwrap();
+        assert!(text.contains("# sheafy:script v1"), "{}", text);
+
+        let target = tempdir().unwrap();
+        let output = get_sheafy_cmd()
+            .args(["restore", dir.path().join(script).to_str().unwrap()])
+            .current_dir(target.path())
+            .output()
+            .expect("Failed to execute sheafy restore");
+        assert!(
+            output.status.success(),
+            "sheafy restore failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        for (path, content) in files {
+            assert_eq!(
+                fs::read_to_string(target.path().join(path)).unwrap(),
+                content,
+                "{} from {}",
+                path,
+                script
+            );
+        }
+    }
+
+    // The POSIX script recreates the tree by itself
+    #[cfg(unix)]
+    {
+        let target = tempdir().unwrap();
+        let status = std::process::Command::new("sh")
+            .arg(dir.path().join("bundle.sh"))
+            .current_dir(target.path())
+            .status()
+            .expect("Failed to run sh");
+        assert!(status.success());
+        for (path, content) in files {
+            assert_eq!(
+                fs::read_to_string(target.path().join(path)).unwrap(),
+                content,
+                "{}",
+                path
+            );
+        }
+    }
+}
+
+#[test]
+fn test_bundle_archive_formats_round_trip() {
+    let dir = tempdir().unwrap();
+    let out = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/deep/er")).unwrap();
+    let long_name = format!("src/deep/er/{}.txt", "n".repeat(120));
+    let binary: Vec<u8> = (0..=255u8).cycle().take(5000).collect();
+    let text = "fn main() {}\n".repeat(200);
+    let files: Vec<(&str, Vec<u8>)> = vec![
+        ("src/main.rs", text.into_bytes()),
+        ("data.bin", binary),
+        ("run.sh", b"#!/bin/sh\necho hi\n".to_vec()),
+        ("empty.txt", Vec::new()),
+        (long_name.as_str(), b"long\n".to_vec()),
+    ];
+    for (path, content) in &files {
+        fs::write(dir.path().join(path), content).unwrap();
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir.path().join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    for (format, name) in [("tar.gz", "bundle.tar.gz"), ("zip", "bundle.zip")] {
+        let archive = out.path().join(name);
+        let output = get_sheafy_cmd()
+            .args([
+                "bundle",
+                "--format",
+                format,
+                "-o",
+                archive.to_str().unwrap(),
+            ])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        assert!(
+            output.status.success(),
+            "sheafy bundle failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let target = tempdir().unwrap();
+        let output = get_sheafy_cmd()
+            .args(["restore", archive.to_str().unwrap()])
+            .current_dir(target.path())
+            .output()
+            .expect("Failed to execute sheafy restore");
+        assert!(
+            output.status.success(),
+            "sheafy restore failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        for (path, content) in &files {
+            assert_eq!(
+                &fs::read(target.path().join(path)).unwrap(),
+                content,
+                "{} from {}",
+                path,
+                format
+            );
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(target.path().join("run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_ne!(
+                mode & 0o111,
+                0,
+                "run.sh lost its executable bit in {}",
+                format
+            );
+        }
+    }
+
+    // Archives are not bundles for the other commands
+    let output = get_sheafy_cmd()
+        .args([
+            "restore",
+            "--check",
+            out.path().join("bundle.zip").to_str().unwrap(),
+        ])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is an archive"));
+}
+
+#[test]
+fn test_bundle_checksums_and_verify_on_restore() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("notes.txt"), "no final newline").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--checksums", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(bundle.contains("## sheafy:checksums"), "{}", bundle);
+    // Same as `sha256sum src/main.rs`
+    assert!(bundle
+        .contains("536e506bb90914c243a12b397b9a998f85ae2cbd9ba02dfd03a9e155ca5ca0f4  src/main.rs"));
+
+    // An intact bundle restores; a file edited on disk is only reported
+    fs::write(dir.path().join("notes.txt"), "edited locally").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--verify-checksums"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("edited on disk since bundling"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("sheafy:checksums"), "{}", stderr);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+        "no final newline"
+    );
+    assert!(!dir.path().join("sheafy:checksums").exists());
+
+    // A section edited in the bundle stops the restore
+    fs::write(
+        dir.path().join("bundle.md"),
+        bundle.replace("fn main() {}", "fn main() { evil() }"),
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--verify-checksums"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("changed   src/main.rs"), "{}", stdout);
+    assert!(stderr.contains("E306"), "{}", stderr);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+
+    // Without --verify-checksums, the edited bundle restores as usual
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/main.rs")).unwrap(),
+        "fn main() { evil() }\n"
+    );
+}
+
+#[test]
+fn test_bundle_profiles_and_all_profiles() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("docs")).unwrap();
+    fs::create_dir_all(dir.path().join("src/gen")).unwrap();
+    fs::write(dir.path().join("docs/guide.md"), "# Guide\n").unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("src/gen/out.rs"), "// generated\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        r#"[sheafy]
+[sheafy.profiles.docs]
+include = ["docs/**"]
+[sheafy.profiles.code]
+output = "code.md"
+include = ["src/"]
+exclude = ["src/gen/"]
+file_stats = true
+"#,
+    )
+    .unwrap();
+
+    // Run twice: the second run must not pick up the bundles of the first
+    for _ in 0..2 {
+        let output = get_sheafy_cmd()
+            .args(["bundle", "--all-profiles"])
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "sheafy bundle failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            stdout.contains("Bundled 2 profile(s) with 1 walk(s)"),
+            "{}",
+            stdout
+        );
+
+        let docs = fs::read_to_string(dir.path().join("project_bundle-docs.md")).unwrap();
+        assert!(docs.contains("## docs/guide.md"));
+        assert!(!docs.contains("## src/main.rs"));
+        assert!(!docs.contains("## code.md"));
+        let code = fs::read_to_string(dir.path().join("code.md")).unwrap();
+        assert!(code.contains("## src/main.rs"));
+        assert!(code.contains("<!-- sheafy:stats"));
+        assert!(!code.contains("## src/gen/out.rs"));
+        assert!(!code.contains("## docs/guide.md"));
+        assert!(!code.contains("project_bundle-docs.md"));
+    }
+
+    // One profile, with a command line option on top
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--profile", "code", "-o", "one.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    let one = fs::read_to_string(dir.path().join("one.md")).unwrap();
+    assert!(one.contains("## src/main.rs"));
+    assert!(one.contains("<!-- sheafy:stats"));
+    assert!(!one.contains("## docs/guide.md"));
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--profile", "nope"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("profiles: code, docs"));
+}
+
+#[test]
+fn test_bundle_append_from_several_dirs() {
+    let dir = tempdir().unwrap();
+    for (name, files) in [
+        ("a", ["shared.txt", "a.txt"]),
+        ("b", ["shared.txt", "b.txt"]),
+    ] {
+        let src = dir.path().join(name);
+        fs::create_dir_all(&src).unwrap();
+        for file in files {
+            fs::write(src.join(file), format!("{} from {}\n", file, name)).unwrap();
+        }
+    }
+    let combined = dir.path().join("combined.md");
+    // Both runs append at once; the lock keeps their sections apart
+    let children: Vec<_> = ["a", "b"]
+        .iter()
+        .map(|name| {
+            get_sheafy_cmd()
+                .args(["bundle", "--append", "-o"])
+                .arg(&combined)
+                .current_dir(dir.path().join(name))
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .expect("Failed to execute sheafy bundle")
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+    let content = fs::read_to_string(&combined).unwrap();
+    assert_eq!(
+        content.matches("<!-- sheafy:v2 -->").count(),
+        1,
+        "{}",
+        content
+    );
+    assert_eq!(content.matches("## shared.txt").count(), 1, "{}", content);
+    assert!(content.starts_with("---\n"));
+
+    // Appending again adds nothing
+    let output = get_sheafy_cmd()
+        .args(["bundle", "--append", "-o"])
+        .arg(&combined)
+        .current_dir(dir.path().join("a"))
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Appended 0 section(s)"));
+    assert_eq!(fs::read_to_string(&combined).unwrap(), content);
+
+    let target = dir.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(&combined)
+        .current_dir(&target)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("a.txt")).unwrap(),
+        "a.txt from a\n"
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("b.txt")).unwrap(),
+        "b.txt from b\n"
+    );
+    assert!(target.join("shared.txt").exists());
+}
+
+#[test]
+fn test_restore_template_vars() {
+    let dir = tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("{{name}}")).unwrap();
+    fs::write(
+        src.join("{{name}}/main.txt"),
+        "project {{ name }} keeps {{other}}\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .current_dir(&src)
+        .args(["bundle", "-o", "../template.md", "--template-vars", "name"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = dir.path().join("template.md");
+    assert!(fs::read_to_string(&bundle)
+        .unwrap()
+        .contains("vars: [name]"));
+
+    let target = dir.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let output = get_sheafy_cmd()
+        .current_dir(&target)
+        .args(["restore", bundle.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is a template"));
+    assert!(fs::read_dir(&target).unwrap().next().is_none());
+
+    let output = get_sheafy_cmd()
+        .current_dir(&target)
+        .args(["restore", bundle.to_str().unwrap(), "--var", "name=myapp"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("myapp/main.txt")).unwrap(),
+        "project myapp keeps {{other}}\n"
+    );
+}
+
+#[test]
+fn test_restore_refuses_readonly_bundles() {
+    let dir = tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("main.rs"), "fn main() {}\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "../docs.md", "--readonly"])
+        .current_dir(&src)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = dir.path().join("docs.md");
+    assert!(fs::read_to_string(&bundle)
+        .unwrap()
+        .contains("\nreadonly: true\n"));
+
+    let target = dir.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(&bundle)
+        .current_dir(&target)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--force-readonly"), "{}", stderr);
+    assert!(stderr.contains("error code: E208"), "{}", stderr);
+    assert!(!target.join("main.rs").exists());
+
+    // `restore: false` is honored too, and --force-readonly restores either
+    fs::write(
+        dir.path().join("context.md"),
+        "---\nrestore: false\n---\n<!-- sheafy:v2 -->\n\n## lib.rs\n```\npub fn f() {}\n```\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("context.md"))
+        .current_dir(&target)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    assert!(!target.join("lib.rs").exists());
+    for name in ["docs.md", "context.md"] {
+        let output = get_sheafy_cmd()
+            .arg("restore")
+            .arg(dir.path().join(name))
+            .arg("--force-readonly")
+            .current_dir(&target)
+            .output()
+            .expect("Failed to execute sheafy restore");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    assert!(target.join("main.rs").exists());
+    assert!(target.join("lib.rs").exists());
+}
+
+#[test]
+fn test_new_from_template_registry() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    fs::create_dir_all(source.join("src")).unwrap();
+    fs::write(
+        source.join("Cargo.toml"),
+        "[package]\nname = \"{{name}}\"\n",
+    )
+    .unwrap();
+    fs::write(
+        source.join("src/main.rs"),
+        "// by {{author}}\nfn main() {}\n",
+    )
+    .unwrap();
+    let registry = dir.path().join("registry");
+    fs::create_dir_all(&registry).unwrap();
+    let output = get_sheafy_cmd()
+        .current_dir(&source)
+        .args(["bundle", "-o", "../registry/rust-cli.md"])
+        .args(["--template-vars", "name,author"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let work = dir.path().join("work");
+    fs::create_dir_all(&work).unwrap();
+    fs::write(
+        work.join("sheafy.toml"),
+        "[sheafy]\ntemplate_registry = \"../registry\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .current_dir(&work)
+        .args(["new", "rust-cli", "myapp", "--var", "author=Jane"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(work.join("myapp/Cargo.toml")).unwrap(),
+        "[package]\nname = \"myapp\"\n"
+    );
+    assert_eq!(
+        fs::read_to_string(work.join("myapp/src/main.rs")).unwrap(),
+        "// by Jane\nfn main() {}\n"
+    );
+
+    // A missing variable, an unknown template and a non-empty directory are refused
+    let output = get_sheafy_cmd()
+        .current_dir(&work)
+        .args(["new", "rust-cli", "other"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("{{author}}"));
+    let output = get_sheafy_cmd()
+        .current_dir(&work)
+        .args(["new", "python", "other"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(templates: rust-cli)"));
+    let output = get_sheafy_cmd()
+        .current_dir(&work)
+        .args(["new", "rust-cli", "myapp", "--var", "author=Jane"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not empty"));
+
+    // A git repository works as a registry too
+    git(&registry, &["init", "-q"]);
+    git(&registry, &["add", "."]);
+    git(&registry, &["commit", "-q", "-m", "templates"]);
+    let url = format!("file://{}", registry.display());
+    fs::write(
+        work.join("sheafy.toml"),
+        format!("[sheafy]\ntemplate_registry = {:?}\n", url),
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .current_dir(&work)
+        .args(["new", "rust-cli", "cloned", "--var", "author=Jo"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(work.join("cloned/src/main.rs")).unwrap(),
+        "// by Jo\nfn main() {}\n"
+    );
+}
+
+#[test]
+fn test_bundle_watch_session_records_changes() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+    let mut child = get_sheafy_cmd()
+        .current_dir(dir.path())
+        .args(["bundle", "--watch", "--session", "session.md"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let session = dir.path().join("session.md");
+    let wait_for = |needle: &str| {
+        for _ in 0..100 {
+            if fs::read_to_string(&session).is_ok_and(|s| s.contains(needle)) {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        false
+    };
+    let bundled = wait_for("bundled 1 file(s)");
+    if bundled {
+        fs::write(
+            dir.path().join("src/main.rs"),
+            "fn main() {\n    run();\n}\n",
+        )
+        .unwrap();
+    }
+    let changed = bundled && wait_for("1 file(s) changed");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(changed, "{:?}", fs::read_to_string(&session));
+
+    let session = fs::read_to_string(&session).unwrap();
+    assert!(session.contains("### src/main.rs\n```diff\n@@ -1 +1,3 @@\n-fn main() {}\n+fn main() {\n+    run();\n+}\n```"));
+    let bundle = fs::read_to_string(dir.path().join("project_bundle.md")).unwrap();
+    assert!(bundle.contains("    run();"));
+    assert!(!bundle.contains("## session.md"));
+}
+
+#[test]
+fn test_bundle_excludes_output_named_through_missing_dir() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    // `nodir` doesn't exist, so the output path can't be canonicalized as written
+    for _ in 0..2 {
+        let output = get_sheafy_cmd()
+            .current_dir(dir.path())
+            .args(["bundle", "-o", "nodir/../out.md"])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(bundle.contains("## main.rs"));
+    assert!(!bundle.contains("## out.md"));
+    assert!(!dir.path().join("nodir").exists());
+}
+
+#[test]
+fn test_bundle_max_files_aborts_scan() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("vendor/pkg")).unwrap();
+    for i in 0..12 {
+        fs::write(dir.path().join(format!("vendor/pkg/f{}.js", i)), "x\n").unwrap();
+    }
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+    for jobs in ["1", "4"] {
+        let output = get_sheafy_cmd()
+            .current_dir(dir.path())
+            .args(["bundle", "--max-files", "10", "--jobs", jobs])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("more than max_files (10)"), "{}", stderr);
+        assert!(stderr.contains("error code: E207"));
+        assert!(stderr.contains("vendor/pkg/"));
+        assert!(stderr.contains("  vendor/ "));
+        assert!(!dir.path().join("project_bundle.md").exists());
+    }
+
+    // 0 lifts the limit, as does the config's max_files
+    let output = get_sheafy_cmd()
+        .current_dir(dir.path())
+        .args(["bundle", "--max-files", "0"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    fs::write(dir.path().join("sheafy.toml"), "[sheafy]\nmax_files = 13\n").unwrap();
+    let output = get_sheafy_cmd()
+        .current_dir(dir.path())
+        .args(["bundle"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_stats_compare_and_history() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::create_dir_all(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.path().join("docs/guide.md"), "guide\n").unwrap();
+    let bundle = |name: &str| {
+        let output = get_sheafy_cmd()
+            .current_dir(dir.path())
+            .args(["bundle", "-o", name])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    bundle("old.md");
+    fs::write(dir.path().join("src/lib.rs"), "x".repeat(400)).unwrap();
+    bundle("new.md");
+
+    let history = fs::read_to_string(dir.path().join(".sheafy/metrics.jsonl")).unwrap();
+    let runs: Vec<serde_json::Value> = history
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0]["output"], "old.md");
+    assert_eq!(runs[1]["dirs"]["src/"]["files"], 2);
+    assert_eq!(runs[1]["dirs"]["docs/"]["tokens"], 2);
+
+    let output = get_sheafy_cmd()
+        .current_dir(dir.path())
+        .args(["stats", "--compare", "old.md", "new.md"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let src = stdout.lines().find(|l| l.starts_with("src/")).unwrap();
+    assert!(src.contains("1 -> 2"), "{}", stdout);
+    assert!(src.contains("+100"), "{}", stdout);
+    // Unchanged directories are left out
+    assert!(
+        !stdout.lines().any(|l| l.starts_with("docs/")),
+        "{}",
+        stdout
+    );
+
+    let output = get_sheafy_cmd()
+        .current_dir(dir.path())
+        .args(["stats", "--history"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("old.md") && stdout.contains("new.md"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_bundle_split_into_parts_round_trip() {
+    let dir = tempdir().unwrap();
+    let long: String = (0..300).map(|i| format!("line {}\n", i)).collect();
+    fs::write(dir.path().join("long.txt"), &long).unwrap();
+    fs::write(dir.path().join("short.txt"), "short").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--split-size", "1KB"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert_eq!(bundle.matches("## long.txt").count(), 1);
+    assert!(
+        bundle.contains("<!-- sheafy:part 1/3 -->\n```"),
+        "{}",
+        bundle
+    );
+    assert!(bundle.contains("<!-- sheafy:part 3/3 -->"), "{}", bundle);
+    assert!(!bundle.contains("part 1/1"));
+
+    fs::remove_file(dir.path().join("long.txt")).unwrap();
+    fs::remove_file(dir.path().join("short.txt")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("long.txt")).unwrap(),
+        long
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("short.txt")).unwrap(),
+        "short"
+    );
+
+    // A missing part is reported instead of restoring half a file
+    let broken = bundle.replace("<!-- sheafy:part 2/3 -->", "");
+    fs::write(dir.path().join("broken.md"), broken).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "broken.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("expected part 2/3 of 'long.txt'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_error_codes() {
+    let dir = tempdir().unwrap();
+    let run = |args: &[&str]| {
+        let output = get_sheafy_cmd()
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy");
+        assert!(!output.status.success(), "sheafy {:?} should fail", args);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let stderr = run(&["restore", "missing.md"]);
+    assert!(stderr.contains("Bundle not found"), "{}", stderr);
+    assert!(stderr.contains("error code: E201"), "{}", stderr);
+
+    fs::write(dir.path().join("broken.md"), "## a.txt\n```\nunclosed\n").unwrap();
+    let stderr = run(&["restore", "broken.md"]);
+    assert!(stderr.contains("line 2: code fence"), "{}", stderr);
+    assert!(stderr.contains("error code: E202"), "{}", stderr);
+
+    // Paths leaving the working directory are refused before anything is written
+    fs::create_dir(dir.path().join("project")).unwrap();
+    fs::write(
+        dir.path().join("project/escape.md"),
+        "## inside.txt\n```\nin\n```\n\n## ../outside.txt\n```\nout\n```\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "escape.md", "--allow-new-paths"])
+        .current_dir(dir.path().join("project"))
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error code: E301"), "{}", stderr);
+    assert!(!dir.path().join("outside.txt").exists());
+    assert!(!dir.path().join("project/inside.txt").exists());
+
+    // Errors without a stable code print none
+    let stderr = run(&["bundle", "--use-gitignore", "--no-gitignore"]);
+    assert!(!stderr.contains("error code"), "{}", stderr);
+}
+
+#[test]
+fn test_restore_change_summary() {
+    let dir = tempdir().unwrap();
+    let bundle = "<!-- sheafy:v2 -->\n\n## grown.txt\n```\nhello world\n```\n\n## same.txt\n```\nsame\n```\n\n## src/new.rs\n```\nfn main() {}\n```\n\n## kept.txt\n```\nbundled\n```\n";
+    fs::write(dir.path().join("bundle.md"), bundle).unwrap();
+    fs::write(dir.path().join("grown.txt"), "hi\n").unwrap();
+    fs::write(dir.path().join("same.txt"), "same\n").unwrap();
+    fs::write(dir.path().join("kept.txt"), "local\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--on-conflict", "skip"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("1 created, 0 overwritten, 1 unchanged, 2 skipped."),
+        "{}",
+        stdout
+    );
+
+    fs::write(
+        dir.path().join("src/new.rs"),
+        "fn main() { println!(\"old\"); }\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("0 created, 3 overwritten, 1 unchanged, 0 skipped."),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("  overwritten  grown.txt (+9 B)\n"));
+    assert!(stdout.contains("  overwritten  kept.txt (+2 B)\n"));
+    assert!(stdout.contains("  overwritten  src/new.rs (-18 B)\n"));
+}
+
+#[test]
+fn test_restore_report_file() {
+    let dir = tempdir().unwrap();
+    let bundle = "<!-- sheafy:v2 -->\n\n## new.txt\n```\nnew\n```\n\n## same.txt\n```\nsame\n```\n\n## kept.txt\n```\nbundled\n```\n";
+    fs::write(dir.path().join("bundle.md"), bundle).unwrap();
+    fs::write(dir.path().join("same.txt"), "same\n").unwrap();
+    fs::write(dir.path().join("kept.txt"), "local\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--on-conflict", "skip", "--report"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let report: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(dir.path().join("sheafy-restore-report.json")).unwrap(),
+    )
+    .unwrap();
+    let entries = report["entries"].as_array().unwrap();
+    let actions: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|e| {
+            (
+                e["section"].as_str().unwrap(),
+                e["action"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("new.txt", "created"),
+            ("same.txt", "unchanged"),
+            ("kept.txt", "skipped")
+        ]
+    );
+    assert!(entries[0]["sha256_before"].is_null());
+    assert_eq!(
+        entries[0]["sha256_after"],
+        "7aa7a5359173d05b63cfd682e3c38487f3cb4f7f1d60659fe59fab1505977d4c"
+    );
+    assert_eq!(entries[1]["sha256_before"], entries[1]["sha256_after"]);
+    assert_eq!(entries[2]["sha256_before"], entries[2]["sha256_after"]);
+    assert!(entries[2]["reason"].as_str().unwrap().contains("conflict"));
+    assert!(entries[0]["target"].as_str().unwrap().ends_with("new.txt"));
+
+    // Without --report, no report is written
+    fs::remove_file(dir.path().join("sheafy-restore-report.json")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert!(!dir.path().join("sheafy-restore-report.json").exists());
+}
+
+#[test]
+fn test_bundle_cargo_package() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let write = |path: &str, content: &str| {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/scratch\"]\n\n[workspace.dependencies]\nutil = { path = \"crates/util\" }\n",
+    );
+    write("README.md", "# Workspace\n");
+    write(
+        "crates/app/Cargo.toml",
+        "[package]\nname = \"app\"\n\n[dependencies]\nutil = { workspace = true }\ncore = { path = \"../core\" }\nserde = \"1\"\n",
+    );
+    write("crates/app/src/main.rs", "fn main() {}\n");
+    write(
+        "crates/core/Cargo.toml",
+        "[package]\nname = \"core\"\n\n[dev-dependencies]\nfixtures = { path = \"../../fixtures\" }\n",
+    );
+    write("crates/core/src/lib.rs", "pub fn core() {}\n");
+    write("crates/util/Cargo.toml", "[package]\nname = \"util\"\n");
+    write("crates/util/src/lib.rs", "pub fn util() {}\n");
+    write("crates/other/Cargo.toml", "[package]\nname = \"other\"\n");
+    write("crates/other/src/lib.rs", "pub fn other() {}\n");
+    write("fixtures/Cargo.toml", "[package]\nname = \"fixtures\"\n");
+    write("fixtures/src/lib.rs", "pub fn fixture() {}\n");
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--package", "app"])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(
+        &root.join("bundle.md"),
+        &[
+            "Cargo.toml",
+            "crates/app/Cargo.toml",
+            "crates/app/src/main.rs",
+            "crates/core/src/lib.rs",
+            "crates/util/src/lib.rs",
+            "fixtures/src/lib.rs",
+        ],
+        &["README.md", "crates/other/src/lib.rs"],
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--package", "missing"])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("No workspace member named 'missing' (members: app, core, other, util)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_bundle_project_presets_without_gitignore() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let write = |path: &str, content: &str| {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    write("package.json", "{\"name\": \"app\"}\n");
+    write("pyproject.toml", "[project]\nname = \"tool\"\n");
+    write("src/index.js", "console.log(1);\n");
+    write("node_modules/left-pad/index.js", "module.exports = 1;\n");
+    write("packages/ui/node_modules/react/index.js", "// react\n");
+    write("dist/bundle.js", "// built\n");
+    write("tool/__pycache__/mod.cpython-312.pyc", "bytecode");
+    write(".venv/lib/site.py", "# venv\n");
+    write("tool/mod.py", "x = 1\n");
+    write("coverage/lcov.info", "TN:\n");
+
+    let output = get_sheafy_cmd()
+        .args([
+            "bundle",
+            "-o",
+            "bundle.md",
+            "--no-gitignore",
+            "--show-excluded",
+        ])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Detected node + python project"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("By built-in project presets"), "{}", stdout);
+    check_bundle_content(
+        &root.join("bundle.md"),
+        &["package.json", "src/index.js", "tool/mod.py"],
+        &[
+            "node_modules/left-pad/index.js",
+            "packages/ui/node_modules/react/index.js",
+            "dist/bundle.js",
+            "tool/__pycache__/mod.cpython-312.pyc",
+            ".venv/lib/site.py",
+            "coverage/lcov.info",
+        ],
+    );
+
+    // ignore_patterns can bring a preset-ignored file back, and presets can be turned off
+    fs::write(
+        root.join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"!dist/\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--no-gitignore"])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(
+        &root.join("bundle.md"),
+        &["dist/bundle.js"],
+        &["node_modules/left-pad/index.js"],
+    );
+
+    fs::write(root.join("sheafy.toml"), "[sheafy]\npresets = false\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--no-gitignore"])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(
+        &root.join("bundle.md"),
+        &["node_modules/left-pad/index.js", "coverage/lcov.info"],
+        &[],
+    );
+}
+
+#[test]
+fn test_bundle_prompt_injection() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    // A prompt quoting a heading and a fence must not become a file on restore
+    let task = "Fix the bug.\n\n## example.rs\n```rust\nfn broken() {}\n```\n";
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--prompt", task])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    let prompt = bundle
+        .find("<!-- sheafy:prompt -->\n## Task\n\nFix the bug.")
+        .unwrap();
+    assert!(prompt < bundle.find("## main.rs").unwrap(), "{}", bundle);
+
+    let target = tempdir().unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(dir.path().join("bundle.md"))
+        .current_dir(target.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success(), "sheafy restore failed");
+    assert!(target.path().join("main.rs").exists());
+    assert!(!target.path().join("example.rs").exists());
+
+    // `{{prompt}}` in the epilogue places it after the files
+    fs::write(dir.path().join("task.md"), "Review the code.\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"task.md\"\nprologue = \"# Project\"\nepilogue = \"{{prompt}}\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--prompt-file", "task.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    let prompt = bundle
+        .find("## Task\n\nReview the code.\n<!-- sheafy:prompt-end -->")
+        .unwrap();
+    assert!(prompt > bundle.find("## main.rs").unwrap(), "{}", bundle);
+    assert!(!bundle.contains("{{prompt}}"));
+}
+
+#[test]
+fn test_restore_strip_components_and_prefix() {
+    let dir = tempdir().unwrap();
+    let bundle = dir.path().join("bundle.md");
+    fs::write(
+        &bundle,
+        "<!-- sheafy:v2 -->\n\n## backend/src/main.rs\n```rust\nfn main() {}\n```\n\n## backend/Cargo.toml\n```toml\n[package]\n```\n\n## README.md\n```\n# top\n```\n",
+    )
+    .unwrap();
+
+    let stripped = dir.path().join("stripped");
+    fs::create_dir_all(&stripped).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(&bundle)
+        .args(["--strip-components", "1"])
+        .current_dir(&stripped)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(stripped.join("src/main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+    assert!(stripped.join("Cargo.toml").exists());
+    // Nothing is left of README.md after one component
+    assert!(!stripped.join("README.md").exists());
+    assert!(!stripped.join("backend").exists());
+
+    // --prefix applies after stripping
+    let moved = dir.path().join("moved");
+    fs::create_dir_all(&moved).unwrap();
+    let output = get_sheafy_cmd()
+        .arg("restore")
+        .arg(&bundle)
+        .args(["--strip-components", "1", "--prefix", "server/"])
+        .current_dir(&moved)
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert!(moved.join("server/src/main.rs").exists());
+    assert!(moved.join("server/Cargo.toml").exists());
+    assert!(!moved.join("server/README.md").exists());
+}
+
+#[test]
+fn test_bundle_include_config() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("main.py"), "print('hello')").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nbundle_name = \"out.md\"\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    check_bundle_content(&dir.path().join("out.md"), &["main.py"], &["sheafy.toml"]);
+
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nbundle_name = \"out.md\"\ninclude_config = true\n",
+    )
+    .unwrap();
+    let output = get_sheafy_cmd()
+        .arg("bundle")
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success(), "sheafy bundle failed");
+    let bundle = fs::read_to_string(dir.path().join("out.md")).unwrap();
+    assert!(bundle.contains("## sheafy.toml\n"), "{}", bundle);
+    assert!(bundle.contains("include_config = true"));
+}
+
+#[test]
+fn test_bundle_summarize_command() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("docs")).unwrap();
+    fs::write(
+        dir.path().join("docs/design.md"),
+        "# Design\n\nA long story.\n\nMore of it.\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("docs/broken.md"), "# Broken\n").unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        r#"[sheafy]
+[[sheafy.summarize]]
+pattern = "docs/broken.md"
+command = "exit 1"
+
+[[sheafy.summarize]]
+pattern = "docs/**/*.md"
+command = "head -n 1 | sed \"s|^|Summary of $SHEAFY_PATH: |\""
+language = "markdown"
+"#,
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    assert!(
+        bundle.contains(
+            "## docs/design.md\n```markdown\nSummary of docs/design.md: # Design\n```\n<!-- sheafy:preview summary by `head -n 1"
+        ),
+        "{}",
+        bundle
+    );
+    assert!(!bundle.contains("A long story."));
+    // A failing summarizer leaves the file as it is
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Summarizer `exit 1` failed on 'docs/broken.md'"));
+    assert!(bundle.contains("## docs/broken.md\n```markdown\n# Broken\n```\n"));
+    assert!(bundle.contains("fn main() {}"));
+
+    // Restore never writes the summary over the file
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--on-conflict", "overwrite"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipping preview: docs/design.md"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("docs/design.md")).unwrap(),
+        "# Design\n\nA long story.\n\nMore of it.\n"
+    );
+}
+
+#[test]
+fn test_bundle_recent_first_order() {
+    let dir = tempdir().unwrap();
+    git(dir.path(), &["init", "-q"]);
+    for (file, date) in [
+        ("b_old.txt", "2020-01-01T00:00:00Z"),
+        ("a_newer.txt", "2022-01-01T00:00:00Z"),
+    ] {
+        fs::write(dir.path().join(file), "content\n").unwrap();
+        git(dir.path(), &["add", file]);
+        let status = Command::new("git")
+            .args([
+                "-c",
+                "user.name=sheafy",
+                "-c",
+                "user.email=sheafy@example.com",
+            ])
+            .args(["commit", "-q", "-m", file, "--date", date])
+            .env("GIT_COMMITTER_DATE", date)
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    // Not committed, so its modification time (now) counts
+    fs::write(dir.path().join("c_untracked.txt"), "new\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--auto-order", "recent-first"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    let position = |file: &str| bundle.find(&format!("## {}\n", file)).unwrap();
+    assert!(position("c_untracked.txt") < position("a_newer.txt"));
+    assert!(position("a_newer.txt") < position("b_old.txt"));
+}
+
+#[test]
+fn test_ignore_patterns_are_anchored_at_working_dir() {
+    let dir = tempdir().unwrap();
+    for file in [
+        "build/out.txt",
+        "src/build/keep.txt",
+        "docs/a.md",
+        "sub/docs/b.md",
+        "keep.log",
+        "other.log",
+    ] {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "content\n").unwrap();
+    }
+    fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\nignore_patterns = \"\"\"\n/build\ndocs/*.md\n!keep.log\n\"\"\"\n",
+    )
+    .unwrap();
+    git(dir.path(), &["init", "-q"]);
+
+    // No temporary files are involved, so an unusable temp directory doesn't matter
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .env("TMPDIR", dir.path().join("missing"))
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    check_bundle_content(
+        &dir.path().join("bundle.md"),
+        &["src/build/keep.txt", "sub/docs/b.md", "keep.log"],
+        &["build/out.txt", "docs/a.md", "other.log"],
+    );
+}
+
+#[test]
+fn test_timings_and_profile_out() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args([
+            "--timings",
+            "bundle",
+            "-o",
+            "bundle.md",
+            "--profile-out",
+            "flame.json",
+        ])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Timings (total "), "{}", stderr);
+    for phase in ["walk", "read", "transform", "write"] {
+        assert!(stderr.contains(&format!("  {} ", phase)), "{}", stderr);
+    }
+    assert!(stderr.contains("read ") && stderr.contains("(2 spans)"));
+
+    let profile: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join("flame.json")).unwrap()).unwrap();
+    assert_eq!(
+        profile["$schema"],
+        "https://www.speedscope.app/file-format-schema.json"
+    );
+    let frames: Vec<&str> = profile["shared"]["frames"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|frame| frame["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(frames, ["walk", "read", "transform", "write"]);
+    let events = profile["profiles"][0]["events"].as_array().unwrap();
+    // Every span opens and closes, in time order
+    assert_eq!(events.len() % 2, 0);
+    let times: Vec<u64> = events.iter().map(|e| e["at"].as_u64().unwrap()).collect();
+    assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    // Restore records its own phases
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--timings"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("  parse "), "{}", stderr);
+}
+
+#[test]
+fn test_bundle_skips_other_bundles() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("history")).unwrap();
+    fs::write(
+        dir.path().join("history/old-context.md"),
+        "---\ngenerator: sheafy 0.1.0\n---\n<!-- sheafy:v2 -->\n\n## a.rs\n```rust\nfn a() {}\n```\n",
+    )
+    .unwrap();
+    // Mentioning the marker further down doesn't make a document a bundle
+    fs::write(
+        dir.path().join("notes.md"),
+        "# Notes\n\nBundles start with `<!-- sheafy:v2 -->`.\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Skipping sheafy bundle: history/old-context.md"));
+    check_bundle_content(
+        &dir.path().join("bundle.md"),
+        &["a.rs", "notes.md"],
+        &["history/old-context.md"],
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md", "--include-bundles"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(output.status.success());
+    check_bundle_content(
+        &dir.path().join("bundle.md"),
+        &["a.rs", "notes.md", "history/old-context.md"],
+        &[],
+    );
+}
+
+#[test]
+fn test_restore_keeps_line_endings_of_existing_files() {
+    let dir = tempdir().unwrap();
+    let bundle = dir.path().join("bundle.md");
+    fs::write(
+        &bundle,
+        "<!-- sheafy:v2 -->\n\n## win.txt\n```\nfirst\nsecond\n```\n\n## bom.txt\n```\nnew\n```\n\n## new.txt\n```\nfresh\n```\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("win.txt"), "old\r\nlines\r\n").unwrap();
+    fs::write(dir.path().join("bom.txt"), "\u{feff}old\n").unwrap();
+
+    // Files differing only in line endings are not reported as modified
+    fs::write(dir.path().join("new.txt"), "fresh\r\n").unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--check"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore --check");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("new.txt"), "{}", stdout);
+    fs::remove_file(dir.path().join("new.txt")).unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(dir.path().join("win.txt")).unwrap(),
+        b"first\r\nsecond\r\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("bom.txt")).unwrap(),
+        "\u{feff}new\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+        "fresh\n"
+    );
+
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md", "--as-bundled"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("win.txt")).unwrap(),
+        "first\nsecond\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("bom.txt")).unwrap(),
+        "new\n"
+    );
+}
+
+#[test]
+fn test_bundle_check_reports_stale_bundle() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "alpha\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "beta\n").unwrap();
+    let bundle = |extra: &[&str]| {
+        get_sheafy_cmd()
+            .args(["bundle", "-o", "bundle.md"])
+            .args(extra)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy bundle")
+    };
+
+    // A missing bundle is never current
+    let output = bundle(&["--check"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error code: E201"), "{}", stderr);
+    assert!(!dir.path().join("bundle.md").exists());
+
+    assert!(bundle(&[]).status.success());
+    let written = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    let output = bundle(&["--check"]);
+    assert!(
+        output.status.success(),
+        "fresh bundle reported stale: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is up to date"));
+
+    fs::write(dir.path().join("a.txt"), "alpha, edited\n").unwrap();
+    fs::remove_file(dir.path().join("b.txt")).unwrap();
+    fs::write(dir.path().join("c.txt"), "gamma\n").unwrap();
+    let output = bundle(&["--check"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error code: E209"), "{}", stderr);
+    assert!(stdout.contains("changed a.txt"), "{}", stdout);
+    assert!(stdout.contains("removed b.txt"), "{}", stdout);
+    assert!(stdout.contains("added   c.txt"), "{}", stdout);
+    // The committed bundle is left alone
+    assert_eq!(
+        fs::read_to_string(dir.path().join("bundle.md")).unwrap(),
+        written
+    );
+}
+
+#[test]
+fn test_bundle_groups_sections_by_tag() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::create_dir_all(dir.path().join("tests")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+    fs::write(dir.path().join("tests/it.rs"), "#[test]\nfn t() {}\n").unwrap();
+    fs::write(dir.path().join("README.md"), "Demo\n").unwrap();
+    fs::write(dir.path().join("build.sh"), "echo hi\n").unwrap();
+    fs::write(
+        dir.path().join("sheafy.toml"),
+        "[sheafy]\ntag_order = [\"tests\"]\n\n[sheafy.tags]\ncore = [\"src/**\"]\ndocs = [\"*.md\"]\ntests = [\"tests/**\"]\n",
+    )
+    .unwrap();
+
+    let output = get_sheafy_cmd()
+        .args(["bundle", "-o", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "sheafy bundle failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("bundle.md")).unwrap();
+    let order: Vec<&str> = bundle
+        .lines()
+        .filter(|line| line.starts_with("# ") || line.starts_with("## "))
+        .collect();
+    assert_eq!(
+        order,
+        [
+            "# Tests",
+            "## tests/it.rs",
+            "# Core",
+            "## src/lib.rs",
+            "# Docs",
+            "## README.md",
+            "# Other",
+            "## build.sh",
+        ],
+        "{}",
+        bundle
+    );
+
+    // The group headings are prose to restore
+    fs::remove_dir_all(dir.path().join("src")).unwrap();
+    let output = get_sheafy_cmd()
+        .args(["restore", "bundle.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy restore");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("src/lib.rs")).unwrap(),
+        "pub fn f() {}\n"
+    );
+}
+
+#[test]
+fn test_tui_needs_a_terminal() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "alpha\n").unwrap();
+    for args in [&["tui"][..], &["tui", "--restore"][..]] {
+        let output = get_sheafy_cmd()
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("Failed to execute sheafy tui");
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("needs a terminal"), "{}", stderr);
+    }
+}
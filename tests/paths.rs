@@ -0,0 +1,84 @@
+//! Tests of the path helpers bundle and restore compare and resolve paths with.
+
+use sheafy::paths::{absolute, from_slash, normalize, same, strip_verbatim, to_slash};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+#[test]
+fn normalize_resolves_dots_lexically() {
+    assert_eq!(normalize(Path::new("a/./b/../c")), PathBuf::from("a/c"));
+    assert_eq!(
+        normalize(Path::new("../a/../../b")),
+        PathBuf::from("../../b")
+    );
+    assert_eq!(normalize(Path::new("/a/../../b")), PathBuf::from("/b"));
+    assert_eq!(normalize(Path::new("a/..")), PathBuf::new());
+}
+
+#[test]
+fn strip_verbatim_keeps_plain_paths() {
+    assert_eq!(
+        strip_verbatim(Path::new(r"\\?\C:\x\y")),
+        PathBuf::from(r"C:\x\y")
+    );
+    assert_eq!(
+        strip_verbatim(Path::new(r"\\?\UNC\server\share\x")),
+        PathBuf::from(r"\\server\share\x")
+    );
+    // Verbatim paths that aren't drive or UNC paths have no plain spelling
+    assert_eq!(
+        strip_verbatim(Path::new(r"\\?\Volume{1}\x")),
+        PathBuf::from(r"\\?\Volume{1}\x")
+    );
+    assert_eq!(strip_verbatim(Path::new("/tmp/x")), PathBuf::from("/tmp/x"));
+}
+
+#[test]
+fn absolute_spells_existing_and_missing_paths_alike() {
+    let dir = tempdir().unwrap();
+    let base = dir.path().canonicalize().unwrap();
+    fs::create_dir_all(base.join("out")).unwrap();
+
+    // A file that doesn't exist yet, below a directory that doesn't either
+    let missing = absolute(Path::new("nodir/../out/bundle.md"), &base);
+    assert_eq!(missing, base.join("out").join("bundle.md"));
+    fs::write(base.join("out/bundle.md"), "").unwrap();
+    let existing = absolute(Path::new("out/bundle.md"), &base);
+    assert!(same(&missing, &existing));
+
+    // Absolute paths ignore the base
+    assert_eq!(
+        absolute(&base.join("out"), Path::new("/elsewhere")),
+        base.join("out")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn absolute_follows_symlinks_of_existing_ancestors() {
+    let dir = tempdir().unwrap();
+    let base = dir.path().canonicalize().unwrap();
+    fs::create_dir_all(base.join("real")).unwrap();
+    std::os::unix::fs::symlink(base.join("real"), base.join("link")).unwrap();
+    assert_eq!(
+        absolute(Path::new("link/new.md"), &base),
+        base.join("real").join("new.md")
+    );
+}
+
+#[test]
+fn same_follows_the_platform_case_rules() {
+    let a = Path::new("/p/Bundle.md");
+    let b = Path::new("/p/bundle.md");
+    assert!(same(a, a));
+    assert_eq!(same(a, b), cfg!(any(windows, target_os = "macos")));
+}
+
+#[test]
+fn slash_paths_round_trip() {
+    let base = Path::new("base");
+    let path = from_slash(base, "src/main.rs");
+    assert_eq!(path, base.join("src").join("main.rs"));
+    assert_eq!(to_slash(&path), "base/src/main.rs");
+}
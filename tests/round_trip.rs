@@ -0,0 +1,189 @@
+//! Property-based round-trip tests: random file trees are bundled and restored
+//! in-process, and the restored bytes are compared with the originals.
+//!
+//! Known lossy behaviors are spelled out as properties of their own rather than
+//! filtered away silently.
+
+use proptest::prelude::*;
+use sheafy::bundle::run_bundle;
+use sheafy::cli::{BundleArgs, RestoreArgs};
+use sheafy::config::{Config, SheafyConfig};
+use sheafy::restore::{parse_sections, run_restore};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+/// One line of file content, mixing the characters that are hard on the format.
+fn line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[ -~]{0,40}",
+        "[a-z`]{0,12}",
+        Just("## not a heading".to_string()),
+        Just("<!-- sheafy:same-as elsewhere -->".to_string()),
+        Just("    ```".to_string()),
+        Just("héllo wörld, 日本語, 🦀".to_string()),
+        "[a-z]{1000,3000}",
+        Just(String::new()),
+    ]
+}
+
+/// File content: lines joined by LF or CRLF, with or without a final newline.
+fn content() -> impl Strategy<Value = String> {
+    (
+        prop::collection::vec(line(), 0..12),
+        any::<bool>(),
+        any::<bool>(),
+    )
+        .prop_map(|(lines, crlf, final_newline)| {
+            let eol = if crlf { "\r\n" } else { "\n" };
+            let mut content = lines.join(eol);
+            if final_newline && !lines.is_empty() {
+                content.push_str(eol);
+            }
+            content
+        })
+}
+
+fn path() -> impl Strategy<Value = String> {
+    (
+        // Spaces, `#`, backticks and the like must survive the section heading
+        prop::collection::vec("[a-z][a-z0-9_ #()`*é]{0,5}", 1..4),
+        prop::sample::select(vec!["rs", "py", "md", "txt", "json", "toml", ""]),
+    )
+        .prop_map(|(parts, ext)| {
+            let path = parts.join("/");
+            if ext.is_empty() {
+                path
+            } else {
+                format!("{}.{}", path, ext)
+            }
+        })
+}
+
+/// Paths where no file is also the parent directory of another.
+fn tree() -> impl Strategy<Value = BTreeMap<String, String>> {
+    prop::collection::btree_map(path(), content(), 1..8).prop_map(|mut files| {
+        let paths: Vec<String> = files.keys().cloned().collect();
+        files.retain(|path, _| {
+            !paths
+                .iter()
+                .any(|other| other.starts_with(&format!("{}/", path)))
+        });
+        files
+    })
+}
+
+fn config_for(dir: &Path) -> Config {
+    Config {
+        sheafy: SheafyConfig {
+            working_dir: Some(dir.to_string_lossy().into_owned()),
+            // Generated trees may contain `setup.py` next to `build/`
+            presets: Some(false),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Bundles `files` and restores the bundle into a fresh directory, returning every
+/// restored file by path.
+fn bundle_and_restore(files: &BTreeMap<String, String>) -> BTreeMap<String, Vec<u8>> {
+    let source = tempdir().unwrap();
+    for (path, content) in files {
+        let path = source.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    let out = tempdir().unwrap();
+    let bundle_path = out.path().join("bundle.md");
+    run_bundle(
+        config_for(source.path()),
+        BundleArgs {
+            output: Some(bundle_path.to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let target = tempdir().unwrap();
+    run_restore(
+        &config_for(target.path()),
+        &RestoreArgs {
+            input_file: Some(bundle_path.to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut restored = BTreeMap::new();
+    collect(target.path(), target.path(), &mut restored);
+    restored
+}
+
+fn collect(root: &Path, dir: &Path, files: &mut BTreeMap<String, Vec<u8>>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(root, &path, files);
+        } else {
+            let rel = path.strip_prefix(root).unwrap().to_string_lossy();
+            files.insert(rel.replace('\\', "/"), fs::read(&path).unwrap());
+        }
+    }
+}
+
+/// Lines starting with a fence would close the section early
+fn has_fence_line(content: &str) -> bool {
+    content.lines().any(|line| line.starts_with("```"))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn bundle_restore_round_trip(files in tree()) {
+        prop_assume!(!files.values().any(|content| has_fence_line(content)));
+        // The config file is never bundled
+        prop_assume!(!files.contains_key("sheafy.toml"));
+        let restored = bundle_and_restore(&files);
+        let expected: BTreeMap<String, Vec<u8>> = files
+            .iter()
+            .map(|(path, content)| (path.clone(), content.as_bytes().to_vec()))
+            .collect();
+        prop_assert_eq!(restored, expected);
+    }
+
+    #[test]
+    fn parser_never_panics(text in "(## [a-z./]{0,8}\n|```[a-z]{0,4}\n|<!-- sheafy:[a-z -]{0,20}-->\n|---\n|[ -~]{0,30}\n){0,40}") {
+        let _ = parse_sections(&text);
+    }
+
+    #[test]
+    fn dangling_same_as_is_skipped(
+        sections in prop::collection::btree_map("[a-z]{1,8}\\.rs", "[a-z ]{0,20}\n", 1..6),
+        dangling in "[a-z]{1,8}\\.txt",
+    ) {
+        let mut bundle = String::from("<!-- sheafy:v2 -->\n");
+        for (path, content) in &sections {
+            bundle.push_str(&format!("\n## {}\n```rust\n{}```\n", path, content));
+        }
+        bundle.push_str(&format!("\n## {}\n<!-- sheafy:same-as missing.rs -->\n", dangling));
+        let parsed = parse_sections(&bundle).unwrap();
+        let parsed: BTreeMap<String, String> =
+            parsed.into_iter().map(|s| (s.path, s.content)).collect();
+        prop_assert_eq!(parsed, sections);
+    }
+
+    #[test]
+    fn preview_sections_are_parsed_but_flagged(rows in prop::collection::vec("[a-z0-9,]{1,20}\n", 1..10)) {
+        let content: String = rows.concat();
+        let bundle = format!(
+            "<!-- sheafy:v2 -->\n\n## data.csv\n```csv\n{}```\n<!-- sheafy:preview first 1 of 99 rows -->\n",
+            content
+        );
+        let parsed = parse_sections(&bundle).unwrap();
+        prop_assert_eq!(parsed.len(), 1);
+        prop_assert!(parsed[0].preview);
+        prop_assert_eq!(&parsed[0].content, &content);
+    }
+}
@@ -0,0 +1,183 @@
+//! Tests of the patterns `sheafy tui` bundles a selection of files with, and of saving
+//! them as a profile.
+
+#![cfg(feature = "tui")]
+
+use sheafy::bundle::select_files;
+use sheafy::cli::BundleArgs;
+use sheafy::config::{Config, SheafyConfig};
+use sheafy::restore::parse_sections;
+use sheafy::settings::save_profile_at;
+use sheafy::tui::selection_patterns;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+
+/// Files with names that are glob syntax in patterns, next to plain ones.
+const FILES: &[&str] = &[
+    "README.md",
+    "docs/guide.md",
+    "docs/[draft].md",
+    "src/lib.rs",
+    "src/main.rs",
+    "src/util/a.rs",
+    "src/util/b.rs",
+    "weird/star*.md",
+    "weird/q?.txt",
+    "weird/back\\slash.txt",
+    "weird/a[1]/x.txt",
+    "weird/!bang.txt",
+    "weird/end ",
+];
+
+fn config(dir: &Path) -> Config {
+    Config {
+        sheafy: SheafyConfig {
+            working_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn selected(dir: &Path, include: Vec<String>, exclude: Vec<String>) -> Vec<PathBuf> {
+    let args = BundleArgs {
+        include,
+        exclude,
+        ..Default::default()
+    };
+    let mut files = select_files(&config(dir), &args).unwrap().files;
+    files.sort();
+    files
+}
+
+#[test]
+fn patterns_select_exactly_the_checked_files() {
+    let dir = tempdir().unwrap();
+    for file in FILES {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("{}\n", file)).unwrap();
+    }
+    let files = selected(dir.path(), Vec::new(), Vec::new());
+    assert_eq!(files.len(), FILES.len(), "{:?}", files);
+
+    let mut include_used = false;
+    let mut exclude_used = false;
+    // Every nonempty subset would take too long; a stride visits a spread of them
+    for mask in (1u32..1 << files.len()).step_by(37) {
+        let checked: Vec<bool> = (0..files.len()).map(|i| mask & (1 << i) != 0).collect();
+        let (include, exclude) = selection_patterns(&files, &checked);
+        include_used |= !include.is_empty();
+        exclude_used |= !exclude.is_empty();
+        let expected: Vec<PathBuf> = files
+            .iter()
+            .zip(&checked)
+            .filter(|(_, &checked)| checked)
+            .map(|(file, _)| file.clone())
+            .collect();
+        assert_eq!(
+            selected(dir.path(), include.clone(), exclude.clone()),
+            expected,
+            "include {:?}, exclude {:?}",
+            include,
+            exclude
+        );
+    }
+    assert!(include_used && exclude_used);
+}
+
+#[test]
+fn patterns_cover_whole_directories_with_one_pattern() {
+    let files: Vec<PathBuf> = FILES.iter().map(PathBuf::from).collect();
+    let checked: Vec<bool> = FILES.iter().map(|file| file.starts_with("src/")).collect();
+    assert_eq!(
+        selection_patterns(&files, &checked),
+        (vec!["/src/".to_string()], Vec::new())
+    );
+
+    let checked: Vec<bool> = FILES.iter().map(|file| *file != "weird/q?.txt").collect();
+    assert_eq!(
+        selection_patterns(&files, &checked),
+        (Vec::new(), vec!["/weird/q\\?.txt".to_string()])
+    );
+
+    // Every file checked needs no pattern
+    assert_eq!(
+        selection_patterns(&files, &vec![true; files.len()]),
+        (Vec::new(), Vec::new())
+    );
+}
+
+#[test]
+fn saved_profile_bundles_the_selection() {
+    let dir = tempdir().unwrap();
+    for file in FILES {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("{}\n", file)).unwrap();
+    }
+    let original = "# Project settings\n\
+                    [sheafy]\n\
+                    # Keep the config out of the bundle\n\
+                    ignore_patterns = \"sheafy.toml\" # one pattern per line\n\
+                    \n\
+                    # Only the docs\n\
+                    [sheafy.profiles.docs]\n\
+                    include = [\"/docs/\"]\n";
+    let config_path = dir.path().join("sheafy.toml");
+    fs::write(&config_path, original).unwrap();
+
+    let files = selected(dir.path(), Vec::new(), Vec::new());
+    let checked: Vec<bool> = files
+        .iter()
+        .map(|file| file.starts_with("src") || file.starts_with("weird/a[1]"))
+        .collect();
+    let (include, exclude) = selection_patterns(&files, &checked);
+    save_profile_at(&config_path, "picked", &include, &exclude).unwrap();
+
+    // The new table goes after everything that was there
+    let saved = fs::read_to_string(&config_path).unwrap();
+    assert!(saved.starts_with(original), "{}", saved);
+    assert!(
+        saved.ends_with("\n[sheafy.profiles.picked]\ninclude = [\"/src/\", '/weird/a\\[1\\]/']\n"),
+        "{}",
+        saved
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sheafy"))
+        .args(["bundle", "--profile", "picked", "-o", "picked.md"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to execute sheafy bundle");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bundle = fs::read_to_string(dir.path().join("picked.md")).unwrap();
+    let mut bundled: Vec<PathBuf> = parse_sections(&bundle)
+        .unwrap()
+        .into_iter()
+        .map(|section| PathBuf::from(section.path))
+        .collect();
+    bundled.sort();
+    let expected: Vec<PathBuf> = files
+        .iter()
+        .zip(&checked)
+        .filter(|(_, &checked)| checked)
+        .map(|(file, _)| file.clone())
+        .collect();
+    assert_eq!(bundled, expected);
+
+    // Saving over a profile replaces its patterns and keeps its comment
+    save_profile_at(&config_path, "docs", &[], &["/docs/guide.md".to_string()]).unwrap();
+    let saved = fs::read_to_string(&config_path).unwrap();
+    assert!(
+        saved.contains("# Only the docs\n[sheafy.profiles.docs]\nexclude = [\"/docs/guide.md\"]\n"),
+        "{}",
+        saved
+    );
+    assert!(!saved.contains("include = [\"/docs/\"]"), "{}", saved);
+}